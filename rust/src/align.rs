@@ -0,0 +1,476 @@
+//! `align-check` command: can one copy of the data seed two torrents?
+//!
+//! Cross-seeding adds a second torrent (another tracker's upload of the
+//! same release) on top of data already on disk. That works when its files
+//! are byte-identical to files of the first. Files are paired by path and
+//! size, then the rest by size alone where a size is unique on both sides
+//! (a renamed file, to be linked or renamed before adding). Empty files need
+//! no data and are left out.
+//!
+//! Sizes cannot prove equal content. When both torrents lay out the same
+//! files with the same piece size, their piece hashes can, and a piece that
+//! differs means different data. Otherwise a piece of one torrent is only
+//! complete when every file it touches is paired, so a missing file also
+//! costs the pieces it shares with its neighbours; the report gives, for
+//! each direction, the bytes and pieces the other torrent's data serves.
+//!
+//! Exits with code 1 unless each torrent's data serves the other in full.
+//! `--format json` prints the report.
+
+use crate::bencode::{self, TorrentFile, TorrentMeta};
+use crate::json::Json;
+use crate::logger::{Level, Record};
+use crate::piecemap::PieceMap;
+
+use std::path::{Path, PathBuf};
+
+/// How two torrents' data relate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Same info hash.
+    Identical,
+    /// Every file has a counterpart: one copy seeds both.
+    Aligned,
+    /// One torrent's files all have counterparts in the other.
+    Subset,
+    /// Some files pair up.
+    Partial,
+    /// Nothing pairs up.
+    Unrelated,
+}
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Identical => "identical",
+            Verdict::Aligned => "aligned",
+            Verdict::Subset => "subset",
+            Verdict::Partial => "partial",
+            Verdict::Unrelated => "unrelated",
+        }
+    }
+
+    /// Whether one copy of the data serves both torrents in full.
+    pub fn serves_both(self) -> bool {
+        matches!(self, Verdict::Identical | Verdict::Aligned)
+    }
+}
+
+/// What one torrent's data provides for the other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub bytes: u64,
+    pub total_bytes: u64,
+    /// Pieces whose every byte is in paired files.
+    pub pieces: usize,
+    pub total_pieces: usize,
+}
+
+impl Coverage {
+    fn to_json(self) -> Json {
+        Json::object()
+            .with("bytes", self.bytes as i64)
+            .with("total_bytes", self.total_bytes as i64)
+            .with("pieces", self.pieces as i64)
+            .with("total_pieces", self.total_pieces as i64)
+    }
+
+    fn describe(self) -> String {
+        let percent = match self.total_bytes {
+            0 => 100.0,
+            total => self.bytes as f64 * 100.0 / total as f64,
+        };
+        format!(
+            "{:.1}% of the bytes, {} of {} pieces",
+            percent, self.pieces, self.total_pieces
+        )
+    }
+}
+
+/// Result of comparing torrent A with torrent B.
+#[derive(Debug, Clone)]
+pub struct AlignReport {
+    pub verdict: Verdict,
+    /// Files paired by path and size.
+    pub same_path: usize,
+    /// Files paired by size alone, as (path in A, path in B).
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// Paths in both torrents whose sizes differ.
+    pub size_differs: Vec<PathBuf>,
+    /// Files of A without a counterpart in B.
+    pub only_a: Vec<PathBuf>,
+    /// Files of B without a counterpart in A.
+    pub only_b: Vec<PathBuf>,
+    /// B's data as served by A's files.
+    pub b_from_a: Coverage,
+    /// A's data as served by B's files.
+    pub a_from_b: Coverage,
+    /// Pieces with equal hashes, when both torrents have the same layout
+    /// and piece size.
+    pub equal_pieces: Option<usize>,
+    /// Whether the top-level names (folder or single file) are equal.
+    pub same_name: bool,
+}
+
+impl AlignReport {
+    /// Result object for `--format json`.
+    pub fn to_json(&self) -> Json {
+        let paths = |list: &[PathBuf]| {
+            Json::Array(
+                list.iter()
+                    .map(|p| Json::from(p.to_string_lossy().into_owned()))
+                    .collect(),
+            )
+        };
+        let renamed = self.renamed.iter().map(|(a, b)| {
+            Json::object()
+                .with("a", a.to_string_lossy().into_owned())
+                .with("b", b.to_string_lossy().into_owned())
+        });
+        Json::object()
+            .with("verdict", self.verdict.as_str())
+            .with("same_name", self.same_name)
+            .with("same_path", self.same_path as i64)
+            .with("renamed", Json::Array(renamed.collect()))
+            .with("size_differs", paths(&self.size_differs))
+            .with("only_a", paths(&self.only_a))
+            .with("only_b", paths(&self.only_b))
+            .with("b_from_a", self.b_from_a.to_json())
+            .with("a_from_b", self.a_from_b.to_json())
+            .with("equal_pieces", self.equal_pieces.map(|n| n as i64))
+    }
+}
+
+/// Whether two torrent paths name the same file on Windows.
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+/// Pair the non-empty files of `a` and `b`: same path and size first, then
+/// by size where it is unique among the files still unpaired on both sides.
+fn pair_files(a: &[TorrentFile], b: &[TorrentFile]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    let mut paired_a = vec![false; a.len()];
+    let mut paired_b = vec![false; b.len()];
+    for (j, fb) in b.iter().enumerate().filter(|(_, f)| f.length > 0) {
+        let hit = (0..a.len())
+            .find(|&i| !paired_a[i] && a[i].length == fb.length && same_path(&a[i].path, &fb.path));
+        if let Some(i) = hit {
+            paired_a[i] = true;
+            paired_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    let unique = |files: &[TorrentFile], paired: &[bool], length: u64| {
+        let mut found = files
+            .iter()
+            .enumerate()
+            .filter(|(k, f)| !paired[*k] && f.length == length)
+            .map(|(k, _)| k);
+        match (found.next(), found.next()) {
+            (Some(k), None) => Some(k),
+            _ => None,
+        }
+    };
+    for j in 0..b.len() {
+        if paired_b[j] || b[j].length == 0 {
+            continue;
+        }
+        let Some(i) = unique(a, &paired_a, b[j].length) else {
+            continue;
+        };
+        if unique(b, &paired_b, b[j].length) == Some(j) {
+            paired_a[i] = true;
+            paired_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+/// How much of `meta` the files flagged in `paired` serve.
+fn coverage(meta: &TorrentMeta, paired: &[bool]) -> Coverage {
+    let bytes = meta
+        .files
+        .iter()
+        .zip(paired)
+        .filter(|(_, &p)| p)
+        .map(|(f, _)| f.length)
+        .sum();
+    let (pieces, total_pieces) = match PieceMap::new(meta) {
+        Some(map) => {
+            let complete = (0..map.piece_count())
+                .filter(|&piece| map.spans(piece).iter().all(|s| paired[s.file]))
+                .count();
+            (complete, map.piece_count())
+        }
+        None => (0, 0),
+    };
+    Coverage {
+        bytes,
+        total_bytes: meta.total_size,
+        pieces,
+        total_pieces,
+    }
+}
+
+/// Pieces with equal hashes, if `a` and `b` lay out the same files in the
+/// same order with the same piece size.
+fn equal_pieces(a: &TorrentMeta, b: &TorrentMeta) -> Option<usize> {
+    let non_empty = |m: &TorrentMeta| {
+        m.files
+            .iter()
+            .filter(|f| f.length > 0)
+            .map(|f| (f.path.to_string_lossy().to_lowercase(), f.length))
+            .collect::<Vec<_>>()
+    };
+    if a.piece_length == 0 || a.piece_length != b.piece_length || non_empty(a) != non_empty(b) {
+        return None;
+    }
+    let equal = a
+        .piece_hashes
+        .iter()
+        .zip(&b.piece_hashes)
+        .filter(|(x, y)| x == y)
+        .count();
+    Some(equal)
+}
+
+/// Compare two parsed torrents.
+pub fn align(a: &TorrentMeta, b: &TorrentMeta) -> AlignReport {
+    let pairs = pair_files(&a.files, &b.files);
+    let mut paired_a: Vec<bool> = a.files.iter().map(|f| f.length == 0).collect();
+    let mut paired_b: Vec<bool> = b.files.iter().map(|f| f.length == 0).collect();
+    let mut report = AlignReport {
+        verdict: Verdict::Unrelated,
+        same_path: 0,
+        renamed: Vec::new(),
+        size_differs: Vec::new(),
+        only_a: Vec::new(),
+        only_b: Vec::new(),
+        b_from_a: Coverage::default(),
+        a_from_b: Coverage::default(),
+        equal_pieces: equal_pieces(a, b),
+        same_name: a.name == b.name,
+    };
+    for &(i, j) in &pairs {
+        paired_a[i] = true;
+        paired_b[j] = true;
+        if same_path(&a.files[i].path, &b.files[j].path) {
+            report.same_path += 1;
+        } else {
+            report
+                .renamed
+                .push((a.files[i].path.clone(), b.files[j].path.clone()));
+        }
+    }
+    let unpaired = |files: &[TorrentFile], paired: &[bool]| -> Vec<PathBuf> {
+        files
+            .iter()
+            .zip(paired)
+            .filter(|(_, &p)| !p)
+            .map(|(f, _)| f.path.clone())
+            .collect()
+    };
+    report.only_a = unpaired(&a.files, &paired_a);
+    report.only_b = unpaired(&b.files, &paired_b);
+    report.size_differs = report
+        .only_b
+        .iter()
+        .filter(|pb| report.only_a.iter().any(|pa| same_path(pa, pb)))
+        .cloned()
+        .collect();
+    report.b_from_a = coverage(b, &paired_b);
+    report.a_from_b = coverage(a, &paired_a);
+
+    report.verdict = if a.info_hash == b.info_hash && a.info_hash != [0; 20] {
+        Verdict::Identical
+    } else if report.only_a.is_empty() && report.only_b.is_empty() {
+        Verdict::Aligned
+    } else if report.only_a.is_empty() || report.only_b.is_empty() {
+        Verdict::Subset
+    } else if pairs.is_empty() {
+        Verdict::Unrelated
+    } else {
+        Verdict::Partial
+    };
+    // Same layout, yet some pieces hash differently: the content differs
+    if let Some(equal) = report.equal_pieces {
+        if equal < b.piece_hashes.len().max(a.piece_hashes.len()) {
+            report.b_from_a.pieces = equal;
+            report.a_from_b.pieces = equal;
+            if report.verdict == Verdict::Aligned {
+                report.verdict = Verdict::Partial;
+            }
+        }
+    }
+    report
+}
+
+/// Run the `align-check` command. Errors are aborts, already logged.
+pub fn run(torrent_a: &str, torrent_b: &str) -> Result<AlignReport, String> {
+    let load = |torrent: &str| {
+        bencode::parse_torrent_file(Path::new(torrent)).inspect_err(|e| {
+            Record::new(Level::Error, "ALIGN", torrent, "abort")
+                .message(e.as_str())
+                .emit();
+        })
+    };
+    let (a, b) = (load(torrent_a)?, load(torrent_b)?);
+    let report = align(&a, &b);
+
+    for (from, to) in &report.renamed {
+        Record::new(Level::Info, "ALIGN", torrent_b, "renamed")
+            .path(to)
+            .message(format!("{:?} matches {:?} of A by size", to, from))
+            .emit();
+    }
+    for path in &report.size_differs {
+        Record::new(Level::Warn, "ALIGN", torrent_b, "size-differs")
+            .path(path)
+            .message(format!(
+                "{:?} is in both torrents with different sizes",
+                path
+            ))
+            .emit();
+    }
+    for path in report
+        .only_b
+        .iter()
+        .filter(|p| !report.size_differs.contains(p))
+    {
+        Record::new(Level::Warn, "ALIGN", torrent_b, "missing")
+            .path(path)
+            .message(format!("{:?} has no counterpart in A", path))
+            .emit();
+    }
+    for path in report
+        .only_a
+        .iter()
+        .filter(|p| !report.size_differs.contains(p))
+    {
+        Record::new(Level::Info, "ALIGN", torrent_a, "extra")
+            .path(path)
+            .message(format!("{:?} has no counterpart in B", path))
+            .emit();
+    }
+    if let Some(equal) = report.equal_pieces {
+        Record::new(Level::Info, "ALIGN", torrent_b, "pieces")
+            .message(format!(
+                "same layout and piece size: {} of {} piece hashes equal",
+                equal,
+                b.piece_hashes.len()
+            ))
+            .emit();
+    }
+    if !report.same_name {
+        Record::new(Level::Info, "ALIGN", torrent_b, "name")
+            .message(format!(
+                "top-level names differ ({:?} vs {:?}): add B with A's name or a link",
+                a.name, b.name
+            ))
+            .emit();
+    }
+    let level = if report.verdict.serves_both() {
+        Level::Info
+    } else {
+        Level::Warn
+    };
+    Record::new(level, "ALIGN", torrent_b, "summary")
+        .message(format!(
+            "{}: {} paired by path, {} by size; A serves {} of B, B serves {} of A",
+            report.verdict.as_str(),
+            report.same_path,
+            report.renamed.len(),
+            report.b_from_a.describe(),
+            report.a_from_b.describe()
+        ))
+        .emit();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(name: &str, files: &[(&str, u64)], piece_length: u64) -> TorrentMeta {
+        let files: Vec<TorrentFile> = files
+            .iter()
+            .map(|&(path, length)| TorrentFile {
+                path: PathBuf::from(path),
+                length,
+                skip: false,
+            })
+            .collect();
+        TorrentMeta {
+            name: name.to_string(),
+            total_size: files.iter().map(|f| f.length).sum(),
+            files,
+            piece_length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_aligned_and_renamed() {
+        let a = meta(
+            "Show.S01",
+            &[("e1.mkv", 100), ("e2.mkv", 120), ("a.nfo", 0)],
+            16,
+        );
+        let b = meta("Show.S01.x", &[("E1.mkv", 100), ("Show.e2.mkv", 120)], 32);
+        let report = align(&a, &b);
+        assert_eq!(report.verdict, Verdict::Aligned);
+        assert_eq!(report.same_path, 1);
+        assert_eq!(
+            report.renamed,
+            vec![(PathBuf::from("e2.mkv"), PathBuf::from("Show.e2.mkv"))]
+        );
+        assert!(!report.same_name);
+        assert_eq!(report.equal_pieces, None);
+        assert_eq!(report.b_from_a.pieces, report.b_from_a.total_pieces);
+    }
+
+    #[test]
+    fn test_partial_costs_boundary_pieces() {
+        // B = 3 files of 10 bytes in 8-byte pieces; A lacks the middle one
+        let a = meta("t", &[("x", 10), ("z", 10), ("extra", 5)], 8);
+        let b = meta("t", &[("x", 10), ("y", 10), ("z", 10)], 8);
+        let report = align(&a, &b);
+        assert_eq!(report.verdict, Verdict::Partial);
+        assert_eq!(report.only_b, vec![PathBuf::from("y")]);
+        assert_eq!(report.only_a, vec![PathBuf::from("extra")]);
+        // Pieces 0..8 and 24..30 only; 8..16 and 16..24 touch y
+        assert_eq!(report.b_from_a.bytes, 20);
+        assert_eq!(
+            (report.b_from_a.pieces, report.b_from_a.total_pieces),
+            (2, 4)
+        );
+
+        // Repeated sizes are ambiguous and stay unpaired
+        let a = meta("t", &[("p", 7), ("q", 7)], 8);
+        let b = meta("t", &[("r", 7), ("s", 7)], 8);
+        assert_eq!(align(&a, &b).verdict, Verdict::Unrelated);
+        let c = meta("t", &[("p", 7), ("q", 9)], 8);
+        assert_eq!(align(&a, &c).verdict, Verdict::Partial);
+        assert_eq!(
+            align(&c, &meta("t", &[("p", 7)], 8)).verdict,
+            Verdict::Subset
+        );
+    }
+
+    #[test]
+    fn test_equal_pieces() {
+        let mut a = meta("t", &[("x", 16), ("y", 8)], 8);
+        a.piece_hashes = vec![[1; 20], [2; 20], [3; 20]];
+        let mut b = a.clone();
+        b.info_hash = [9; 20];
+        assert_eq!(align(&a, &b).equal_pieces, Some(3));
+        assert_eq!(align(&a, &b).verdict, Verdict::Aligned);
+        b.piece_hashes[2] = [4; 20];
+        let report = align(&a, &b);
+        assert_eq!(report.equal_pieces, Some(2));
+        assert_eq!(report.verdict, Verdict::Partial);
+        assert_eq!(report.b_from_a.pieces, 2);
+    }
+}
@@ -0,0 +1,546 @@
+//! `serve` command: localhost HTTP API for queuing sync jobs.
+//!
+//! Scripts (Sonarr/Radarr custom scripts, schedulers) queue a cleanup and
+//! poll for its result instead of starting the exe themselves:
+//!
+//! ```text
+//! POST /jobs       {"type": "sync", "torrent": "C:\\t\\x.torrent", "dir": "E:\\x"}
+//!                  -> 202 {"id": 1, "status": "queued"}
+//! GET  /jobs/<id>  -> 200 {"id": 1, "status": "done", "deleted": 3, ...}
+//! ```
+//!
+//! Every request needs `Authorization: Bearer <--api-token>`. The listener
+//! only binds loopback addresses. Jobs run one at a time on a worker thread
+//! with the sync options given to `serve` (a shallow `dir` fails the job
+//! like it aborts a sync); results are kept in memory until the process
+//! exits. With a profile `root`, a `dir` outside it is refused with 403,
+//! as the CLI refuses it. A job that fails, even by panicking, is marked `failed` and the
+//! queue moves on to the next one. With `--job-timeout`, a job still
+//! running after that long (a hung share, a dying disk) is marked `failed`
+//! too and its thread is told to stop: walks and deletions check a cancel
+//! flag, and files it already staged are put back. The next job starts
+//! right away, but a job for the same directory fails while the old
+//! thread is still running.
+
+use crate::bencode;
+use crate::crash;
+use crate::json::Json;
+use crate::logger::{Level, Record};
+use crate::metrics;
+use crate::paths;
+use crate::safety;
+use crate::sync;
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Default `--listen` address.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8765";
+/// Largest request body accepted.
+const MAX_BODY: usize = 64 * 1024;
+/// Largest request line plus headers accepted, read before auth runs.
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// State of a queued job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Done => "done",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Job {
+    id: u64,
+    torrent: String,
+    dir: String,
+    status: Status,
+    deleted: usize,
+    errors: usize,
+    /// Abort reason of a failed job.
+    error: Option<String>,
+}
+
+impl Job {
+    fn to_json(&self) -> Json {
+        Json::object()
+            .with("id", self.id as i64)
+            .with("type", "sync")
+            .with("torrent", self.torrent.as_str())
+            .with("dir", self.dir.as_str())
+            .with("status", self.status.as_str())
+            .with("deleted", self.deleted as i64)
+            .with("errors", self.errors as i64)
+            .with("error", self.error.clone())
+    }
+}
+
+type Jobs = Arc<Mutex<BTreeMap<u64, Job>>>;
+
+/// A parsed request: method, path, headers and body.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: impl Read) -> Result<Request, String> {
+        let mut reader = BufReader::new(stream);
+        let mut head = (&mut reader).take(MAX_HEAD);
+        let mut next_line = |line: &mut String| {
+            line.clear();
+            head.read_line(line).map_err(|e| e.to_string())?;
+            match line.ends_with('\n') {
+                true => Ok(()),
+                false => Err("request head too large or cut short".to_string()),
+            }
+        };
+        let mut line = String::new();
+        next_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err("bad request line".to_string());
+        };
+        let (method, path) = (method.to_string(), path.to_string());
+
+        let mut headers = Vec::new();
+        loop {
+            next_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+        let length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .map_or(Ok(0), |(_, v)| v.parse::<usize>())
+            .map_err(|_| "bad Content-Length".to_string())?;
+        if length > MAX_BODY {
+            return Err("request body too large".to_string());
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+        Ok(Request {
+            method,
+            path,
+            headers,
+            body,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Compare without stopping at the first difference.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Route one request; returns the status code and JSON body. Job
+/// directories must lie inside `root` when one is set.
+fn handle(
+    request: &Request,
+    token: &str,
+    root: Option<&str>,
+    jobs: &Jobs,
+    queue: &mpsc::Sender<u64>,
+) -> (u16, Json) {
+    let error = |status: u16, message: &str| (status, Json::object().with("error", message));
+    let authorized = request
+        .header("authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|given| same_token(given, token));
+    if !authorized {
+        return error(401, "missing or wrong bearer token");
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/jobs") => {
+            let body = String::from_utf8_lossy(&request.body);
+            let Ok(json) = Json::parse(&body) else {
+                return error(400, "body is not JSON");
+            };
+            let field = |name: &str| json.get(name).and_then(Json::as_str);
+            if field("type") != Some("sync") {
+                return error(400, "type must be \"sync\"");
+            }
+            let (Some(torrent), Some(dir)) = (field("torrent"), field("dir")) else {
+                return error(400, "torrent and dir are required");
+            };
+            if torrent == bencode::STDIN {
+                return error(400, "torrent must be a file path, not stdin");
+            }
+            if root.is_some_and(|root| !paths::within(root, dir)) {
+                return error(403, "dir is outside the profile root");
+            }
+            let Ok(mut jobs) = jobs.lock() else {
+                return error(500, "job table unavailable");
+            };
+            let id = jobs.keys().next_back().map_or(1, |last| last + 1);
+            jobs.insert(
+                id,
+                Job {
+                    id,
+                    torrent: torrent.to_string(),
+                    dir: dir.to_string(),
+                    status: Status::Queued,
+                    deleted: 0,
+                    errors: 0,
+                    error: None,
+                },
+            );
+            drop(jobs);
+            let _ = queue.send(id);
+            (
+                202,
+                Json::object()
+                    .with("id", id as i64)
+                    .with("status", "queued"),
+            )
+        }
+        ("GET", path) if path.starts_with("/jobs/") => {
+            let job = path["/jobs/".len()..]
+                .parse::<u64>()
+                .ok()
+                .and_then(|id| jobs.lock().ok()?.get(&id).cloned());
+            match job {
+                Some(job) => (200, job.to_json()),
+                None => error(404, "no such job"),
+            }
+        }
+        (_, "/jobs") => error(405, "use POST"),
+        _ => error(404, "unknown path"),
+    }
+}
+
+fn respond(mut stream: &TcpStream, status: u16, body: &Json) {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+}
+
+type Outcome = thread::Result<Result<sync::SyncReport, String>>;
+
+/// A timed-out job whose thread may still be running.
+struct Stale {
+    dir: String,
+    finished: mpsc::Receiver<Outcome>,
+}
+
+impl Stale {
+    fn running(&self) -> bool {
+        matches!(self.finished.try_recv(), Err(TryRecvError::Empty))
+    }
+}
+
+/// Whether two job directories name the same folder.
+fn same_dir(a: &str, b: &str) -> bool {
+    let trim = |dir: &str| dir.trim_end_matches(['\\', '/']).to_lowercase();
+    trim(a) == trim(b)
+}
+
+/// Run `job` on its own thread; `Err` once `timeout` passes without it
+/// finishing, after telling the thread to stop and adding it to `stale`.
+/// A job whose directory a stale thread still works on fails at once.
+fn run_job(
+    job: &Job,
+    options: &Arc<sync::Options>,
+    timeout: Option<Duration>,
+    stale: &mut Vec<Stale>,
+) -> Result<sync::SyncReport, String> {
+    stale.retain(Stale::running);
+    if stale.iter().any(|s| same_dir(&s.dir, &job.dir)) {
+        let message = "a timed-out job is still stopping in this directory".to_string();
+        Record::new(Level::Error, "SERVE", &job.dir, "abort")
+            .message(message.as_str())
+            .emit();
+        return Err(message);
+    }
+    let (done, finished) = mpsc::channel();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (torrent, dir, options) = (job.torrent.clone(), job.dir.clone(), Arc::clone(options));
+    thread::spawn({
+        let cancel = Arc::clone(&cancel);
+        move || {
+            safety::set_cancel(cancel);
+            let _ = done.send(panic::catch_unwind(AssertUnwindSafe(|| {
+                sync::run(&torrent, &dir, &options)
+            })));
+        }
+    });
+    let outcome = match timeout {
+        Some(timeout) => finished.recv_timeout(timeout).map_err(|_| {
+            cancel.store(true, Ordering::Relaxed);
+            format!("job timed out after {}s, told to stop", timeout.as_secs())
+        }),
+        None => finished.recv().map_err(|_| "job thread vanished".to_string()),
+    };
+    // The panic hook has written the crash report; keep serving
+    let message = match outcome {
+        Ok(Ok(result)) => return result,
+        Ok(Err(_)) => format!("job panicked, see {}", crash::FILE_NAME),
+        Err(message) => message,
+    };
+    if cancel.load(Ordering::Relaxed) {
+        stale.push(Stale {
+            dir: job.dir.clone(),
+            finished,
+        });
+    }
+    Record::new(Level::Error, "SERVE", &job.dir, "abort")
+        .message(message.as_str())
+        .emit();
+    Err(message)
+}
+
+/// Run queued jobs one at a time.
+fn worker(
+    jobs: Jobs,
+    queue: mpsc::Receiver<u64>,
+    options: sync::Options,
+    timeout: Option<Duration>,
+) {
+    let options = Arc::new(options);
+    let mut stale = Vec::new();
+    for id in queue {
+        let Some(job) = jobs.lock().ok().and_then(|mut jobs| {
+            let job = jobs.get_mut(&id)?;
+            job.status = Status::Running;
+            Some(job.clone())
+        }) else {
+            continue;
+        };
+        let result = run_job(&job, &options, timeout, &mut stale);
+        metrics::record_sync(&result);
+        if let Ok(mut jobs) = jobs.lock() {
+            if let Some(job) = jobs.get_mut(&id) {
+                match &result {
+                    Ok(report) => {
+                        job.status = Status::Done;
+                        job.deleted = report.deleted.len();
+                        job.errors = report.errors.len();
+                    }
+                    Err(e) => {
+                        job.status = Status::Failed;
+                        job.error = Some(e.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Serve the API on `listen` until the process is stopped; jobs are
+/// limited to `root` like CLI syncs.
+pub fn run(
+    listen: &str,
+    token: &str,
+    root: Option<&str>,
+    options: sync::Options,
+    job_timeout: Option<Duration>,
+) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "SERVE", listen, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let addr: SocketAddr = listen
+        .parse()
+        .map_err(|_| abort(format!("'{}' is not an IP:port address", listen)))?;
+    if !addr.ip().is_loopback() {
+        return Err(abort(format!("{} is not a loopback address", addr.ip())));
+    }
+    if token.is_empty() {
+        return Err(abort("--api-token must not be empty".to_string()));
+    }
+    let listener = TcpListener::bind(addr).map_err(|e| abort(format!("cannot listen: {}", e)))?;
+
+    let jobs: Jobs = Arc::new(Mutex::new(BTreeMap::new()));
+    let (queue, receiver) = mpsc::channel();
+    let worker_jobs = Arc::clone(&jobs);
+    thread::spawn(move || worker(worker_jobs, receiver, options, job_timeout));
+
+    Record::new(Level::Info, "SERVE", listen, "listen")
+        .message(format!("API listening on http://{}", addr))
+        .emit();
+    for stream in listener.incoming().flatten() {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+        let (status, body) = match Request::read(&stream) {
+            Ok(request) => {
+                let (status, body) = handle(&request, token, root, &jobs, &queue);
+                Record::new(Level::Debug, "SERVE", listen, "request")
+                    .message(format!("{} {} -> {}", request.method, request.path, status))
+                    .write();
+                (status, body)
+            }
+            Err(e) => (400, Json::object().with("error", e)),
+        };
+        respond(&stream, status, &body);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, auth: &str, body: &str) -> Request {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nAuthorization: {}\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            auth,
+            body.len(),
+            body
+        );
+        Request::read(raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_handle_jobs() {
+        let jobs: Jobs = Arc::new(Mutex::new(BTreeMap::new()));
+        let (queue, receiver) = mpsc::channel();
+        let body = r#"{"type": "sync", "torrent": "C:\\t\\x.torrent", "dir": "E:\\Online\\TV\\x"}"#;
+
+        let (status, _) = handle(
+            &request("POST", "/jobs", "Bearer nope", body),
+            "s3cret",
+            None,
+            &jobs,
+            &queue,
+        );
+        assert_eq!(status, 401);
+
+        let (status, json) = handle(
+            &request("POST", "/jobs", "Bearer s3cret", body),
+            "s3cret",
+            None,
+            &jobs,
+            &queue,
+        );
+        assert_eq!(status, 202);
+        assert_eq!(json.get("id").and_then(Json::as_i64), Some(1));
+        assert_eq!(receiver.try_recv(), Ok(1));
+
+        let (status, json) = handle(
+            &request("GET", "/jobs/1", "Bearer s3cret", ""),
+            "s3cret",
+            None,
+            &jobs,
+            &queue,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(json.get("status").and_then(Json::as_str), Some("queued"));
+        assert_eq!(
+            json.get("dir").and_then(Json::as_str),
+            Some("E:\\Online\\TV\\x")
+        );
+
+        let (status, _) = handle(
+            &request("GET", "/jobs/9", "Bearer s3cret", ""),
+            "s3cret",
+            None,
+            &jobs,
+            &queue,
+        );
+        assert_eq!(status, 404);
+        let unknown = r#"{"type": "wipe", "torrent": "a", "dir": "b"}"#;
+        let (status, _) = handle(
+            &request("POST", "/jobs", "Bearer s3cret", unknown),
+            "s3cret",
+            None,
+            &jobs,
+            &queue,
+        );
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn test_root_and_limits() {
+        let jobs: Jobs = Arc::new(Mutex::new(BTreeMap::new()));
+        let (queue, _receiver) = mpsc::channel();
+        let post = |dir: &str| {
+            let body = format!(
+                r#"{{"type": "sync", "torrent": "C:\\t\\x.torrent", "dir": "{}"}}"#,
+                dir.replace('\\', "\\\\")
+            );
+            let request = request("POST", "/jobs", "Bearer s3cret", &body);
+            handle(&request, "s3cret", Some("E:\\Online"), &jobs, &queue).0
+        };
+        assert_eq!(post("E:\\Online\\TV\\x"), 202);
+        assert_eq!(post("C:\\Windows"), 403);
+        assert_eq!(post("E:\\Online\\..\\Windows"), 403);
+
+        let endless = format!("GET /{} HTTP/1.1\r\n\r\n", "x".repeat(MAX_HEAD as usize));
+        assert!(Request::read(endless.as_bytes()).is_err());
+        let cut = "GET /jobs/1 HTTP/1.1\r\nAuthorization: Bearer";
+        assert!(Request::read(cut.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_same_dir() {
+        assert!(same_dir("E:\\Online\\Show\\", "e:\\online\\show"));
+        assert!(!same_dir("E:\\Online\\Show", "E:\\Online\\Show 2"));
+    }
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("abc", "abc"));
+        assert!(!same_token("abd", "abc"));
+        assert!(!same_token("ab", "abc"));
+    }
+}
@@ -0,0 +1,212 @@
+//! Tamper-evident manifest of actions taken (`--audit DIR`).
+//!
+//! Every file deleted, kept, created, renamed or purged, every directory
+//! removed, snapshot taken and process terminated is appended to
+//! `DIR\<run id>.jsonl`, one JSON object per line with the user and host
+//! that ran it. Each line carries `prev`, the `hash` of the line before it
+//! (the last line of the previous run's manifest for the first one), and
+//! its own `hash`: the SHA-256 of the line without the `hash` field. An
+//! edited, removed or reordered line breaks the chain, which `audit-verify`
+//! reports. This detects tampering; it is not a signature, so keep `DIR`
+//! somewhere only admins can write.
+
+use crate::json::Json;
+use crate::logger::{self, Level, Record};
+use crate::sha;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// `prev` of the very first line.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+static CHAIN: Mutex<Option<Chain>> = Mutex::new(None);
+
+/// Where this run's manifest goes and how far its chain got.
+struct Chain {
+    file: PathBuf,
+    prev: String,
+    seq: u64,
+}
+
+/// Enable auditing into `dir` (`--audit`).
+pub fn set_dir(dir: PathBuf) {
+    let _ = DIR.set(dir);
+}
+
+/// Manifests in `dir`, oldest first (run IDs sort by start time).
+fn manifests(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    files.retain(|p| p.extension().is_some_and(|e| e == "jsonl"));
+    files.sort();
+    files
+}
+
+/// `hash` of the last line of `text`, if it has one.
+fn last_hash(text: &str) -> Option<String> {
+    let line = text.lines().rev().find(|l| !l.trim().is_empty())?;
+    Some(split_hash(line)?.1.to_string())
+}
+
+/// Split a manifest line into the hashed part (closed again with `}`) and
+/// its `hash` value.
+fn split_hash(line: &str) -> Option<(String, &str)> {
+    let (body, rest) = line.trim_end().rsplit_once(",\"hash\":\"")?;
+    let hash = rest.strip_suffix("\"}")?;
+    Some((format!("{}}}", body), hash))
+}
+
+/// Render `entry` (which has `prev` but no `hash`) as a manifest line;
+/// returns the line and its hash.
+fn seal(entry: &Json) -> (String, String) {
+    let body = entry.to_string();
+    let hash = sha::hex(&sha::sha256(body.as_bytes()));
+    let line = format!("{},\"hash\":\"{}\"}}", &body[..body.len() - 1], hash);
+    (line, hash)
+}
+
+fn start(dir: &Path) -> Result<Chain, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("cannot create {:?}: {}", dir, e))?;
+    let prev = manifests(dir)
+        .last()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .and_then(|text| last_hash(&text))
+        .unwrap_or_else(|| GENESIS.to_string());
+    Ok(Chain {
+        file: dir.join(format!("{}.jsonl", logger::run_id())),
+        prev,
+        seq: 0,
+    })
+}
+
+fn append(chain: &mut Chain, entry: Json) -> Result<(), String> {
+    let entry = entry
+        .with("seq", chain.seq as i64)
+        .with("prev", chain.prev.as_str());
+    let (line, hash) = seal(&entry);
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&chain.file)
+        .and_then(|mut f| f.write_all(format!("{}\n", line).as_bytes()))
+        .map_err(|e| format!("cannot write {:?}: {}", chain.file, e))?;
+    chain.prev = hash;
+    chain.seq += 1;
+    Ok(())
+}
+
+/// Record an action taken on `path` (or on `target` itself) by `command`.
+pub fn record(command: &str, target: &str, action: &str, path: Option<&Path>, detail: &str) {
+    let Some(dir) = DIR.get() else {
+        return;
+    };
+    let entry = Json::object()
+        .with("ts", logger::iso_timestamp())
+        .with("run", logger::run_id())
+        .with("user", std::env::var("USERNAME").unwrap_or_default())
+        .with("host", std::env::var("COMPUTERNAME").unwrap_or_default())
+        .with("command", command)
+        .with("target", target)
+        .with("action", action)
+        .with("path", path.map(|p| p.to_string_lossy().into_owned()))
+        .with("detail", detail);
+    let result = match CHAIN.lock() {
+        Ok(mut chain) => match chain.as_mut() {
+            Some(chain) => append(chain, entry),
+            None => start(dir).and_then(|started| append(chain.insert(started), entry)),
+        },
+        Err(_) => Err("audit chain unavailable".to_string()),
+    };
+    if let Err(e) = result {
+        Record::new(Level::Warn, command, target, "audit")
+            .message(format!("action not audited: {}", e))
+            .emit();
+    }
+}
+
+/// Check the chain of `text`, continuing from `prev`; returns the last hash
+/// or the first broken line (1-based) with the reason.
+fn verify_text(text: &str, mut prev: String) -> Result<String, (usize, String)> {
+    for (index, line) in text.lines().enumerate() {
+        let broken = |reason: &str| (index + 1, reason.to_string());
+        let (body, hash) = split_hash(line).ok_or_else(|| broken("no hash field"))?;
+        let json = Json::parse(&body).map_err(|_| broken("not JSON"))?;
+        if json.get("prev").and_then(Json::as_str) != Some(prev.as_str()) {
+            return Err(broken("prev does not match the line before"));
+        }
+        if json.get("seq").and_then(Json::as_i64) != Some(index as i64) {
+            return Err(broken("seq out of order"));
+        }
+        if sha::hex(&sha::sha256(body.as_bytes())) != hash {
+            return Err(broken("hash does not match the line"));
+        }
+        prev = hash.to_string();
+    }
+    Ok(prev)
+}
+
+/// `audit-verify` command: check every manifest in `dir`, oldest first.
+pub fn verify(dir: &Path) -> Result<(), String> {
+    let target = dir.to_string_lossy();
+    let fail = |message: String| {
+        Record::new(Level::Error, "AUDIT", &target, "tampered")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let files = manifests(dir);
+    let mut prev = GENESIS.to_string();
+    let mut lines = 0;
+    for file in &files {
+        let text = fs::read_to_string(file).map_err(|e| fail(format!("{:?}: {}", file, e)))?;
+        prev = verify_text(&text, prev)
+            .map_err(|(line, reason)| fail(format!("{:?} line {}: {}", file, line, reason)))?;
+        lines += text.lines().count();
+    }
+    Record::new(Level::Info, "AUDIT", &target, "summary")
+        .message(format!(
+            "chain intact: {} actions in {} manifests",
+            lines,
+            files.len()
+        ))
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(prev: &str, seq: i64, action: &str) -> (String, String) {
+        seal(
+            &Json::object()
+                .with("action", action)
+                .with("path", "E:\\x\\a.nfo")
+                .with("seq", seq)
+                .with("prev", prev),
+        )
+    }
+
+    #[test]
+    fn test_chain() {
+        let (first, hash1) = line(GENESIS, 0, "delete");
+        let (second, hash2) = line(&hash1, 1, "rmdir");
+        let text = format!("{}\n{}\n", first, second);
+        assert_eq!(last_hash(&text), Some(hash2.clone()));
+        assert_eq!(verify_text(&text, GENESIS.to_string()), Ok(hash2));
+
+        // An edited line, a removed line and a reordered pair all break it
+        let edited = text.replace("a.nfo", "b.nfo");
+        assert_eq!(verify_text(&edited, GENESIS.to_string()).unwrap_err().0, 1);
+        assert_eq!(verify_text(&second, GENESIS.to_string()).unwrap_err().0, 1);
+        let swapped = format!("{}\n{}\n", second, first);
+        assert!(verify_text(&swapped, GENESIS.to_string()).is_err());
+        // A manifest must continue where the previous one ended
+        assert!(verify_text(&text, "f".repeat(64)).is_err());
+    }
+}
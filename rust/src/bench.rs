@@ -0,0 +1,191 @@
+//! `bench` command: measure the throughput `verify` depends on.
+//!
+//! Three numbers: directory-walk rate, sequential read speed over the files
+//! in the directory (first `READ_LIMIT` bytes, `READ_BUFFER` at a time), and
+//! SHA-1 speed per hasher thread. Verify is bound by the slower of reading
+//! and hashing, so the recommended `--threads` is the fewest hashers that
+//! keep up with the disk. Run it twice to see the effect of the OS cache on
+//! the read figure.
+
+use crate::hashing;
+use crate::logger::{Level, Record};
+use crate::safety;
+use crate::sha;
+use crate::volume;
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Stop the read test after this many bytes.
+const READ_LIMIT: u64 = 1 << 30;
+/// Read size, about one piece of a large torrent.
+const READ_BUFFER: usize = 4 << 20;
+/// Data each hasher thread digests in the SHA-1 test.
+const HASH_BYTES: usize = 64 << 20;
+
+fn mb_per_sec(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1_048_576.0 / elapsed.as_secs_f64().max(1e-9)
+}
+
+/// Collect every file under `dir` with its size, within the walk limits.
+fn walk(
+    dir: &Path,
+    depth: usize,
+    entries: &mut usize,
+    files: &mut Vec<(PathBuf, u64)>,
+) -> Result<(), String> {
+    safety::check_walk(*entries, depth)?;
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in read_dir.flatten() {
+        *entries += 1;
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            walk(&entry.path(), depth + 1, entries, files)?;
+        } else if file_type.is_file() {
+            let size = entry.metadata().map_or(0, |m| m.len());
+            files.push((entry.path(), size));
+        }
+    }
+    Ok(())
+}
+
+/// Read the files in order until `READ_LIMIT`; returns the bytes read.
+fn read_sequential(files: &[(PathBuf, u64)]) -> u64 {
+    let mut buf = vec![0u8; READ_BUFFER];
+    let mut total = 0u64;
+    for (path, _) in files {
+        let Ok(mut file) = File::open(path) else {
+            continue;
+        };
+        while let Ok(n) = file.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            total += n as u64;
+            if total >= READ_LIMIT {
+                return total;
+            }
+        }
+    }
+    total
+}
+
+/// Combined SHA-1 speed of `threads` hashers, in MB/s.
+fn hash_speed(threads: usize) -> f64 {
+    let data = vec![0x5au8; HASH_BYTES];
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for chunk in data.chunks(READ_BUFFER) {
+                    std::hint::black_box(sha::sha1(chunk));
+                }
+            });
+        }
+    });
+    mb_per_sec((HASH_BYTES * threads) as u64, start.elapsed())
+}
+
+/// Fewest hashers (at most `cpus`) whose combined speed keeps up with the
+/// disk.
+fn recommend_threads(read_mbps: f64, per_thread_mbps: f64, cpus: usize) -> usize {
+    if per_thread_mbps <= 0.0 {
+        return cpus;
+    }
+    ((read_mbps / per_thread_mbps).ceil() as usize).clamp(1, cpus.max(1))
+}
+
+pub fn run(dir_path: &str) {
+    let dir = Path::new(dir_path);
+    if !dir.is_dir() {
+        Record::new(Level::Error, "BENCH", dir_path, "abort")
+            .message("directory does not exist, aborted")
+            .emit();
+        std::process::exit(1);
+    }
+
+    // Directory walk
+    let start = Instant::now();
+    let mut entries = 0;
+    let mut files = Vec::new();
+    if let Err(e) = walk(dir, 0, &mut entries, &mut files) {
+        Record::new(Level::Error, "BENCH", dir_path, "abort")
+            .message(e)
+            .emit();
+        std::process::exit(1);
+    }
+    let elapsed = start.elapsed();
+    Record::new(Level::Info, "BENCH", dir_path, "walk")
+        .message(format!(
+            "walked {} entries in {:.2}s ({:.0} entries/s)",
+            entries,
+            elapsed.as_secs_f64(),
+            entries as f64 / elapsed.as_secs_f64().max(1e-9)
+        ))
+        .emit();
+
+    // Sequential read
+    let start = Instant::now();
+    let read = read_sequential(&files);
+    let read_mbps = mb_per_sec(read, start.elapsed());
+    if read == 0 {
+        Record::new(Level::Warn, "BENCH", dir_path, "read")
+            .message("no readable file data, read speed not measured")
+            .emit();
+    } else {
+        Record::new(Level::Info, "BENCH", dir_path, "read")
+            .message(format!(
+                "read {} MiB sequentially at {:.0} MB/s ({} KiB reads)",
+                read >> 20,
+                read_mbps,
+                READ_BUFFER >> 10
+            ))
+            .emit();
+    }
+
+    // Hashing, one thread and then all cores
+    let cpus = hashing::default_threads();
+    let single = hash_speed(1);
+    let all = hash_speed(cpus);
+    Record::new(Level::Info, "BENCH", dir_path, "sha1")
+        .message(format!(
+            "SHA-1 {:.0} MB/s on 1 thread, {:.0} MB/s on {} threads",
+            single, all, cpus
+        ))
+        .emit();
+
+    let kind = volume::detect(dir);
+    let threads = if read == 0 {
+        kind.hash_threads(cpus)
+    } else {
+        recommend_threads(read_mbps, single, cpus)
+    };
+    Record::new(Level::Info, "BENCH", dir_path, "recommend")
+        .message(format!(
+            "{} volume: recommended --threads {}",
+            kind, threads
+        ))
+        .emit();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommend_threads() {
+        // Disk faster than one hasher: enough threads to keep up
+        assert_eq!(recommend_threads(1500.0, 400.0, 8), 4);
+        // Capped at the CPU count
+        assert_eq!(recommend_threads(5000.0, 400.0, 8), 8);
+        // Slow HDD: one hasher suffices
+        assert_eq!(recommend_threads(150.0, 400.0, 8), 1);
+    }
+}
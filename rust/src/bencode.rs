@@ -1,10 +1,21 @@
-//! Bencode parser — recursive descent from byte slice.
+//! Bencode parser — iterative, from byte slice.
 //!
 //! Supports all four Bencode types: Integer, ByteString, List, Dictionary.
 //! Ported from BencodeSerializer.java.
+//!
+//! Torrents from public trackers are untrusted: open lists and
+//! dictionaries live on an explicit stack, nesting deeper than `MAX_DEPTH`
+//! or more than `MAX_ITEMS` values are refused (`Limits`), and a string
+//! length is checked against the data left before anything is allocated.
+//! With the `fuzz` feature, `parse_fuzz` runs one input through every
+//! parser for an external fuzzer (`fuzz-bencode` command).
+
+#[cfg(feature = "client-apis")]
+use crate::http;
+use crate::sha;
 
-use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
 
 /// A Bencode value.
 #[derive(Debug, Clone)]
@@ -25,6 +36,14 @@ impl BValue {
         }
     }
 
+    /// Get as integer.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            BValue::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
     /// Get as list.
     pub fn as_list(&self) -> Option<&[BValue]> {
         match self {
@@ -58,13 +77,116 @@ impl std::fmt::Display for ParseError {
     }
 }
 
-/// Parse a Bencode value from a byte slice.
+/// Deepest nesting of lists and dictionaries accepted by default; real
+/// torrents use a handful of levels.
+pub const MAX_DEPTH: usize = 256;
+/// Most values (of any type, containers included) one parse builds by
+/// default; a torrent with 100 000 files has well under a million.
+pub const MAX_ITEMS: usize = 10_000_000;
+
+/// Bounds on what one parse may build.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Lists and dictionaries open at once.
+    pub max_depth: usize,
+    /// Values parsed in total.
+    pub max_items: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_depth: MAX_DEPTH,
+            max_items: MAX_ITEMS,
+        }
+    }
+}
+
+/// A list or dictionary still being filled.
+enum Frame {
+    List(Vec<BValue>),
+    /// Entries so far and a key waiting for its value.
+    Dict(BTreeMap<Vec<u8>, BValue>, Option<Vec<u8>>),
+}
+
+/// Parse a Bencode value from a byte slice, within the default `Limits`.
 /// Returns the parsed value and the remaining unparsed bytes.
 pub fn parse(data: &[u8]) -> Result<(BValue, &[u8]), ParseError> {
-    if data.is_empty() {
-        return Err(ParseError("Unexpected end of data".to_string()));
+    parse_with(data, &Limits::default())
+}
+
+/// `parse` within `limits`. Open containers are kept on an explicit stack,
+/// so nesting costs heap, bounded by `max_depth`, not call stack.
+pub fn parse_with<'a>(data: &'a [u8], limits: &Limits) -> Result<(BValue, &'a [u8]), ParseError> {
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut rest = data;
+    let mut items = 0usize;
+    loop {
+        let value = match rest.first() {
+            None => {
+                return Err(ParseError(
+                    match stack.last() {
+                        None => "Unexpected end of data",
+                        Some(Frame::List(_)) => "List: missing 'e'",
+                        Some(Frame::Dict(_, Some(_))) => "Dictionary: missing value",
+                        Some(Frame::Dict(_, None)) => "Dictionary: missing 'e'",
+                    }
+                    .to_string(),
+                ))
+            }
+            Some(b'e') if !stack.is_empty() => {
+                rest = &rest[1..];
+                match stack.pop() {
+                    Some(Frame::List(list)) => BValue::List(list),
+                    Some(Frame::Dict(map, None)) => BValue::Dict(map),
+                    _ => return Err(ParseError("Dictionary: missing value".to_string())),
+                }
+            }
+            Some(&open @ (b'l' | b'd')) => {
+                if stack.len() >= limits.max_depth {
+                    return Err(ParseError(format!(
+                        "nested deeper than {} levels",
+                        limits.max_depth
+                    )));
+                }
+                stack.push(match open {
+                    b'l' => Frame::List(Vec::new()),
+                    _ => Frame::Dict(BTreeMap::new(), None),
+                });
+                rest = &rest[1..];
+                continue;
+            }
+            Some(_) => {
+                let (value, remaining) = parse_scalar(rest)?;
+                rest = remaining;
+                value
+            }
+        };
+
+        items += 1;
+        if items > limits.max_items {
+            return Err(ParseError(format!("more than {} values", limits.max_items)));
+        }
+        match stack.last_mut() {
+            None => return Ok((value, rest)),
+            Some(Frame::List(list)) => list.push(value),
+            Some(Frame::Dict(map, pending)) => match (pending.take(), value) {
+                (Some(key), value) => {
+                    map.insert(key, value);
+                }
+                (None, BValue::Bytes(key)) => *pending = Some(key),
+                (None, _) => {
+                    return Err(ParseError(
+                        "Dictionary: key must be byte string".to_string(),
+                    ))
+                }
+            },
+        }
     }
+}
 
+/// An integer or byte string at the start of `data`.
+fn parse_scalar(data: &[u8]) -> Result<(BValue, &[u8]), ParseError> {
     match data[0] {
         // Integer: i<number>e
         b'i' => {
@@ -81,48 +203,6 @@ pub fn parse(data: &[u8]) -> Result<(BValue, &[u8]), ParseError> {
             Ok((BValue::Integer(num), &rest[end + 1..]))
         }
 
-        // List: l<items>e
-        b'l' => {
-            let mut rest = &data[1..];
-            let mut items = Vec::new();
-            while !rest.is_empty() && rest[0] != b'e' {
-                let (val, remaining) = parse(rest)?;
-                items.push(val);
-                rest = remaining;
-            }
-            if rest.is_empty() {
-                return Err(ParseError("List: missing 'e'".to_string()));
-            }
-            Ok((BValue::List(items), &rest[1..]))
-        }
-
-        // Dictionary: d<key><value>...e
-        b'd' => {
-            let mut rest = &data[1..];
-            let mut map = BTreeMap::new();
-            while !rest.is_empty() && rest[0] != b'e' {
-                let (key, remaining) = parse(rest)?;
-                let key_bytes = match key {
-                    BValue::Bytes(b) => b,
-                    _ => {
-                        return Err(ParseError(
-                            "Dictionary: key must be byte string".to_string(),
-                        ))
-                    }
-                };
-                if remaining.is_empty() {
-                    return Err(ParseError("Dictionary: missing value".to_string()));
-                }
-                let (val, remaining) = parse(remaining)?;
-                map.insert(key_bytes, val);
-                rest = remaining;
-            }
-            if rest.is_empty() {
-                return Err(ParseError("Dictionary: missing 'e'".to_string()));
-            }
-            Ok((BValue::Dict(map), &rest[1..]))
-        }
-
         // Byte string: <length>:<data>
         b'0'..=b'9' => {
             let colon = data
@@ -135,7 +215,7 @@ pub fn parse(data: &[u8]) -> Result<(BValue, &[u8]), ParseError> {
                 .parse::<usize>()
                 .map_err(|_| ParseError(format!("String: bad length '{}'", len_str)))?;
             let start = colon + 1;
-            if start + len > data.len() {
+            if len > data.len() - start {
                 return Err(ParseError(format!(
                     "String: expected {} bytes but only {} available",
                     len,
@@ -153,23 +233,151 @@ pub fn parse(data: &[u8]) -> Result<(BValue, &[u8]), ParseError> {
     }
 }
 
-/// Extract file paths from a torrent's Bencode data.
+/// Encode a value canonically (dictionary keys sorted, as `BTreeMap` keeps
+/// them).
+pub fn encode(value: &BValue) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(value, &mut out);
+    out
+}
+
+fn encode_into(value: &BValue, out: &mut Vec<u8>) {
+    match value {
+        BValue::Integer(n) => out.extend_from_slice(format!("i{}e", n).as_bytes()),
+        BValue::Bytes(bytes) => {
+            out.extend_from_slice(format!("{}:", bytes.len()).as_bytes());
+            out.extend_from_slice(bytes);
+        }
+        BValue::List(items) => {
+            out.push(b'l');
+            items.iter().for_each(|item| encode_into(item, out));
+            out.push(b'e');
+        }
+        BValue::Dict(map) => {
+            out.push(b'd');
+            for (key, value) in map {
+                encode_into(&BValue::Bytes(key.clone()), out);
+                encode_into(value, out);
+            }
+            out.push(b'e');
+        }
+    }
+}
+
+/// One file listed in a torrent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentFile {
+    /// Path relative to the download directory, using OS separators.
+    pub path: PathBuf,
+    /// Size in bytes.
+    pub length: u64,
+    /// Set to "don't download" in the client or a `--priorities` file.
+    pub skip: bool,
+}
+
+/// Metadata extracted from a torrent's `info` dictionary.
+#[derive(Debug, Clone, Default)]
+pub struct TorrentMeta {
+    /// SHA-1 of the raw bencoded `info` dictionary (v1 infohash).
+    pub info_hash: [u8; 20],
+    /// `info.name` — the suggested directory (multi-file) or file name.
+    pub name: String,
+    /// Files in torrent order. Single-file torrents have one entry named `name`.
+    pub files: Vec<TorrentFile>,
+    /// Bytes per piece (`info.piece length`), 0 if absent.
+    pub piece_length: u64,
+    /// Number of SHA-1 hashes in `info.pieces`.
+    pub pieces: usize,
+    /// The SHA-1 hashes themselves, one per piece.
+    pub piece_hashes: Vec<[u8; 20]>,
+    /// `info.private` is set to 1.
+    pub private: bool,
+    /// Sum of all file lengths.
+    pub total_size: u64,
+    /// Pairs of `files` indices `(first, later)` whose paths are equal or
+    /// differ only by case. Both entries are kept so piece offsets stay right.
+    pub duplicates: Vec<(usize, usize)>,
+}
+
+/// Find entries whose paths collide on a case-insensitive file system.
+pub fn find_duplicates(files: &[TorrentFile]) -> Vec<(usize, usize)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut duplicates = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let key = file.path.to_string_lossy().to_lowercase();
+        match seen.get(&key) {
+            Some(&first) => duplicates.push((first, i)),
+            None => {
+                seen.insert(key, i);
+            }
+        }
+    }
+    duplicates
+}
+
+/// Raw bytes of the top-level `info` value, exactly as they appear in `data`.
 ///
-/// Reads `info.files[].path` for multi-file torrents, or `info.name` for single-file.
-/// Returns relative paths using OS path separators.
-pub fn torrent_files(data: &[u8]) -> Result<Vec<PathBuf>, ParseError> {
+/// The infohash must be computed over the original encoding, not a
+/// re-encoding of the parsed value (key order or integer format may differ).
+fn raw_info(data: &[u8]) -> Option<&[u8]> {
+    if data.first() != Some(&b'd') {
+        return None;
+    }
+    let mut rest = &data[1..];
+    while !rest.is_empty() && rest[0] != b'e' {
+        let (key, after_key) = parse(rest).ok()?;
+        let (_, after_value) = parse(after_key).ok()?;
+        if key.as_bytes() == Some(b"info") {
+            return Some(&after_key[..after_key.len() - after_value.len()]);
+        }
+        rest = after_value;
+    }
+    None
+}
+
+/// Read a non-negative integer field as `u64`.
+fn non_negative(value: &BValue, what: &str) -> Result<u64, ParseError> {
+    value
+        .as_int()
+        .and_then(|n| u64::try_from(n).ok())
+        .ok_or_else(|| ParseError(format!("'{}' is not a non-negative integer", what)))
+}
+
+/// Check one `path` component or a single-file `name`: it must name one
+/// entry inside the payload directory, not step out of it or replace it.
+fn check_component(name: &str) -> Result<(), ParseError> {
+    let plain = !matches!(name, "" | "." | "..")
+        && !name.contains(['/', '\\', ':'])
+        && matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        );
+    if !plain {
+        return Err(ParseError(format!("unsafe path component {:?}", name)));
+    }
+    Ok(())
+}
+
+/// Extract torrent metadata from Bencode data.
+///
+/// Reads `info.files[].path` / `length` for multi-file torrents, or
+/// `info.name` / `length` for single-file. Paths are relative and use OS
+/// path separators.
+pub fn torrent_meta(data: &[u8]) -> Result<TorrentMeta, ParseError> {
     let (root, _) = parse(data)?;
     let info = root
         .field(b"info")
         .ok_or_else(|| ParseError("Missing 'info' dictionary".to_string()))?;
 
+    let name = info.field(b"name").and_then(|n| n.as_str_lossy());
+
     // Multi-file torrent: info.files
-    if let Some(files) = info.field(b"files") {
+    let files = if let Some(files) = info.field(b"files") {
         let file_list = files
             .as_list()
             .ok_or_else(|| ParseError("'files' is not a list".to_string()))?;
 
-        let mut paths = Vec::with_capacity(file_list.len());
+        let mut entries = Vec::with_capacity(file_list.len());
 
         for file_entry in file_list {
             let path_list = file_entry
@@ -182,27 +390,171 @@ pub fn torrent_files(data: &[u8]) -> Result<Vec<PathBuf>, ParseError> {
                 let name = component
                     .as_str_lossy()
                     .ok_or_else(|| ParseError("Path component is not a string".to_string()))?;
+                check_component(&name)?;
                 file_path.push(&name);
             }
-            paths.push(file_path);
+
+            let length = file_entry
+                .field(b"length")
+                .ok_or_else(|| ParseError("File entry missing 'length'".to_string()))?;
+            let length = non_negative(length, "length")?;
+
+            // Some creators emit zero-length placeholder entries with an
+            // empty path; they name nothing on disk, so drop them.
+            if file_path.as_os_str().is_empty() {
+                if length == 0 {
+                    continue;
+                }
+                return Err(ParseError("File entry has an empty 'path'".to_string()));
+            }
+
+            entries.push(TorrentFile {
+                path: file_path,
+                length,
+                skip: false,
+            });
         }
 
-        Ok(paths)
+        entries
     }
     // Single-file torrent: info.name
-    else if let Some(name) = info.field(b"name").and_then(|n| n.as_str_lossy()) {
-        Ok(vec![PathBuf::from(name)])
+    else if let Some(name) = &name {
+        let length = info
+            .field(b"length")
+            .ok_or_else(|| ParseError("Single-file torrent missing 'length'".to_string()))?;
+        check_component(name)?;
+        vec![TorrentFile {
+            path: PathBuf::from(name),
+            length: non_negative(length, "length")?,
+            skip: false,
+        }]
     } else {
-        Err(ParseError(
+        return Err(ParseError(
             "No 'files' or 'name' found in torrent info".to_string(),
-        ))
+        ));
+    };
+
+    let piece_length = match info.field(b"piece length") {
+        Some(v) => non_negative(v, "piece length")?,
+        None => 0,
+    };
+    let piece_hashes: Vec<[u8; 20]> = info
+        .field(b"pieces")
+        .and_then(|p| p.as_bytes())
+        .unwrap_or_default()
+        .chunks_exact(20)
+        .map(|h| {
+            let mut hash = [0u8; 20];
+            hash.copy_from_slice(h);
+            hash
+        })
+        .collect();
+    let pieces = piece_hashes.len();
+    let private = info.field(b"private").and_then(|p| p.as_int()) == Some(1);
+    let total_size = files
+        .iter()
+        .try_fold(0u64, |total, f| total.checked_add(f.length))
+        .ok_or_else(|| ParseError("file lengths add up past 2^64 bytes".to_string()))?;
+    let duplicates = find_duplicates(&files);
+
+    let info_hash = raw_info(data).map(sha::sha1).unwrap_or_default();
+
+    Ok(TorrentMeta {
+        info_hash,
+        name: name.unwrap_or_default(),
+        files,
+        piece_length,
+        pieces,
+        piece_hashes,
+        private,
+        total_size,
+        duplicates,
+    })
+}
+
+/// Run `data` through every bencode reader: the parser with a re-encoding
+/// round trip, torrent metadata, and the lenient repair parser. Bad input
+/// is fine; a panic here is a bug.
+#[cfg(feature = "fuzz")]
+pub fn parse_fuzz(data: &[u8]) {
+    if let Ok((value, _)) = parse(data) {
+        let encoded = encode(&value);
+        let (again, rest) = parse(&encoded).expect("canonical encoding parses");
+        assert!(rest.is_empty(), "canonical encoding has trailing bytes");
+        assert!(
+            encode(&again) == encoded,
+            "canonical encoding is not stable"
+        );
     }
+    let _ = torrent_meta(data);
+    let _ = crate::repair::repair(data);
+}
+
+/// Torrent path meaning "read the torrent from stdin".
+pub const STDIN: &str = "-";
+
+/// Read all of stdin, for a piped torrent.
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut data)?;
+    Ok(data)
 }
 
-/// Parse a torrent file from disk and extract file paths.
-pub fn parse_torrent_file(path: &Path) -> Result<Vec<PathBuf>, String> {
-    let data = std::fs::read(path).map_err(|e| format!("Cannot read torrent file: {}", e))?;
-    torrent_files(&data).map_err(|e| e.to_string())
+/// Largest torrent downloaded from a URL.
+#[cfg(feature = "client-apis")]
+const MAX_DOWNLOAD: usize = 32 * 1024 * 1024;
+
+/// Whether a torrent argument is an `http://` or `https://` URL.
+#[cfg(feature = "client-apis")]
+fn is_url(source: &str) -> bool {
+    let lower = source.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Whether a download's `Content-Type` can be a torrent; trackers differ,
+/// but an HTML or JSON body is a login or error page.
+#[cfg(feature = "client-apis")]
+fn torrent_content_type(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    !(mime.starts_with("text/") || mime.ends_with("json") || mime.ends_with("xml"))
+}
+
+/// Download the torrent at `url`.
+#[cfg(feature = "client-apis")]
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    let headers = [("Accept", "application/x-bittorrent")];
+    let response = http::get_limited(&http::Url::parse(url)?, &headers, MAX_DOWNLOAD)
+        .map_err(|e| format!("Cannot download torrent: {}", e))?;
+    if response.status != 200 {
+        return Err(format!("Cannot download torrent: HTTP {}", response.status));
+    }
+    let content_type = response.header("Content-Type");
+    if !torrent_content_type(content_type) {
+        return Err(format!(
+            "Cannot download torrent: got {} instead",
+            content_type.unwrap_or_default()
+        ));
+    }
+    Ok(response.body)
+}
+
+/// Parse a torrent file from disk (from stdin for `-`, downloaded for an
+/// http(s) URL) and extract its metadata.
+pub fn parse_torrent_file(path: &Path) -> Result<TorrentMeta, String> {
+    #[cfg(feature = "client-apis")]
+    if is_url(&path.to_string_lossy()) {
+        return torrent_meta(&download(&path.to_string_lossy())?).map_err(|e| e.to_string());
+    }
+    let data = if path == Path::new(STDIN) {
+        read_stdin()
+    } else {
+        std::fs::read(path)
+    }
+    .map_err(|e| format!("Cannot read torrent file: {}", e))?;
+    torrent_meta(&data).map_err(|e| e.to_string())
 }
 
 #[cfg(test)]
@@ -241,19 +593,124 @@ mod tests {
 
     #[test]
     fn test_multi_file_torrent() {
-        let data = b"d4:infod5:filesld6:lengthi100e4:pathl9:file1.txteed6:lengthi200e4:pathl6:SubDir9:file2.txteeeee";
-        let files = torrent_files(data).unwrap();
-        assert_eq!(files.len(), 2);
-        assert_eq!(files[0], PathBuf::from("file1.txt"));
-        assert_eq!(files[1], PathBuf::from("SubDir").join("file2.txt"));
+        let data = b"d4:infod5:filesld6:lengthi100e4:pathl9:file1.txteed6:lengthi200e4:pathl6:SubDir9:file2.txteee4:name3:Dir12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaa7:privatei1eee";
+        let meta = torrent_meta(data).unwrap();
+        assert_eq!(meta.name, "Dir");
+        assert_eq!(meta.files.len(), 2);
+        assert_eq!(meta.files[0].path, PathBuf::from("file1.txt"));
+        assert_eq!(meta.files[0].length, 100);
+        assert_eq!(meta.files[1].path, PathBuf::from("SubDir").join("file2.txt"));
+        assert_eq!(meta.total_size, 300);
+        assert_eq!(meta.piece_length, 16384);
+        assert_eq!(meta.pieces, 1);
+        assert!(meta.private);
     }
 
     #[test]
     fn test_single_file_torrent() {
-        let data = b"d4:infod4:name9:file1.txtee";
-        let files = torrent_files(data).unwrap();
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0], PathBuf::from("file1.txt"));
+        let data = b"d4:infod6:lengthi42e4:name9:file1.txtee";
+        let meta = torrent_meta(data).unwrap();
+        assert_eq!(meta.files.len(), 1);
+        assert_eq!(meta.files[0].path, PathBuf::from("file1.txt"));
+        assert_eq!(meta.total_size, 42);
+        assert!(!meta.private);
+    }
+
+    #[test]
+    fn test_zero_length_entries() {
+        let data = b"d4:infod5:filesld6:lengthi0e4:pathl9:empty.txteed6:lengthi0e4:pathleed6:lengthi5e4:pathl1:aeee4:name1:Dee";
+        let meta = torrent_meta(data).unwrap();
+        assert_eq!(meta.files.len(), 2);
+        assert_eq!(meta.files[0].path, PathBuf::from("empty.txt"));
+        assert_eq!(meta.files[0].length, 0);
+        assert_eq!(meta.total_size, 5);
+
+        let data = b"d4:infod5:filesld6:lengthi3e4:pathleee4:name1:Dee";
+        assert!(torrent_meta(data).is_err());
+    }
+
+    #[test]
+    fn test_unsafe_components() {
+        let multi = |component: &str| {
+            let data = format!(
+                "d4:infod5:filesld6:lengthi0e4:pathl1:a{}:{}eee4:name1:Dee",
+                component.len(),
+                component
+            );
+            torrent_meta(data.as_bytes())
+        };
+        assert!(multi("b.txt").is_ok());
+        for bad in ["", ".", "..", "x/y", "x\\y", "C:", "C:\\Windows", "/etc", "a:b"] {
+            assert!(multi(bad).is_err(), "{:?}", bad);
+        }
+        assert!(torrent_meta(b"d4:infod6:lengthi1e4:name2:..ee").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_paths() {
+        let data = b"d4:infod5:filesld6:lengthi1e4:pathl1:a5:x.txteed6:lengthi2e4:pathl1:beed6:lengthi3e4:pathl1:A5:X.TXTeed6:lengthi4e4:pathl1:beee4:name1:Dee";
+        let meta = torrent_meta(data).unwrap();
+        assert_eq!(meta.files.len(), 4);
+        assert_eq!(meta.files[2].length, 3);
+        assert_eq!(meta.duplicates, vec![(0, 2), (1, 3)]);
     }
-}
 
+    #[test]
+    fn test_info_hash_uses_raw_bytes() {
+        let data = b"d8:announce3:url4:infod6:lengthi1e4:name1:aee";
+        let meta = torrent_meta(data).unwrap();
+        assert_eq!(meta.info_hash, sha::sha1(b"d6:lengthi1e4:name1:ae"));
+    }
+
+    #[test]
+    fn test_negative_length_rejected() {
+        let data = b"d4:infod6:lengthi-1e4:name1:aee";
+        assert!(torrent_meta(data).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "client-apis")]
+    fn test_download_checks() {
+        assert!(is_url("HTTPS://tracker.example/dl/1.torrent"));
+        assert!(!is_url("C:\\t\\http.torrent"));
+        assert!(torrent_content_type(None));
+        assert!(torrent_content_type(Some("application/x-bittorrent")));
+        assert!(torrent_content_type(Some("application/octet-stream")));
+        assert!(!torrent_content_type(Some("text/html; charset=utf-8")));
+        assert!(!torrent_content_type(Some("application/json")));
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let deep = |n: usize| [vec![b'l'; n], vec![b'e'; n]].concat();
+        assert!(parse(&deep(MAX_DEPTH)).is_ok());
+        assert!(parse(&deep(MAX_DEPTH + 1)).is_err());
+        assert!(parse(&deep(1_000_000)).is_err());
+        assert!(parse(b"18446744073709551615:x").is_err());
+        let file = |name: &str| format!("d6:lengthi{}e4:pathl1:{}ee", i64::MAX, name);
+        let files = ["a", "b", "c"].map(file).concat();
+        let huge = format!("d4:infod5:filesl{}e4:name1:xee", files);
+        assert!(torrent_meta(huge.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            max_depth: 2,
+            max_items: 4,
+        };
+        assert!(parse_with(b"lli1eee", &limits).is_ok());
+        assert!(parse_with(b"llli1eeee", &limits).is_err());
+        assert!(parse_with(b"li1ei2ei3ee", &limits).is_ok());
+        assert!(parse_with(b"li1ei2ei3ei4ee", &limits).is_err());
+        // Errors name the container left open
+        let error = |data: &[u8]| parse(data).unwrap_err().0;
+        assert_eq!(error(b"li1e"), "List: missing 'e'");
+        assert_eq!(error(b"d1:a"), "Dictionary: missing value");
+        assert_eq!(error(b"d1:ai1e"), "Dictionary: missing 'e'");
+        assert_eq!(error(b"di1ei2ee"), "Dictionary: key must be byte string");
+        let (value, rest) = parse(b"d1:ald1:bi2eeee4:tail").unwrap();
+        assert_eq!(encode(&value), b"d1:ald1:bi2eeee");
+        assert_eq!(rest, b"4:tail");
+    }
+}
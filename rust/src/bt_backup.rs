@@ -0,0 +1,165 @@
+//! qBittorrent's `BT_backup` folder as a torrent source (`--bt-backup`).
+//!
+//! qBittorrent keeps a copy of every torrent as `<infohash>.torrent` next
+//! to libtorrent's `<infohash>.fastresume`, which records where the
+//! download is saved. Pairing the two gives the directory of each torrent
+//! without the WebUI, so `sync-all` works with qBittorrent stopped.
+//!
+//! A multi-file torrent's directory is its save path joined with the
+//! torrent name. Single-file torrents sit directly in the save path among
+//! other downloads, so they are skipped rather than synced, as are
+//! unfinished downloads. Deselected and renamed files are taken from the
+//! fastresume file (see `fastresume`).
+
+use crate::bencode::{self, TorrentMeta};
+use crate::fastresume::Resume;
+use crate::pathmap::PathMap;
+use crate::priorities::Priorities;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A torrent, the directory it downloads into, and the client's file
+/// settings from its fastresume file.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub torrent: PathBuf,
+    pub dir: PathBuf,
+    /// Files set to "don't download".
+    pub priorities: Priorities,
+    /// Files renamed in the client.
+    pub path_map: PathMap,
+}
+
+/// A torrent left out, with the reason.
+pub type Skipped = (PathBuf, String);
+
+/// Result of scanning a `BT_backup` folder, sorted by file name.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Torrents that can be synced.
+    pub entries: Vec<Entry>,
+    /// Torrents that cannot, with the reason.
+    pub skipped: Vec<Skipped>,
+}
+
+/// A torrent with its fastresume data.
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub torrent: PathBuf,
+    pub meta: TorrentMeta,
+    pub resume: Resume,
+    /// The save path from the fastresume file.
+    pub save: PathBuf,
+}
+
+impl Pair {
+    /// Whether the torrent is a single file saved directly in `save`.
+    pub fn single_file(&self) -> bool {
+        self.meta.files.len() == 1 && self.meta.files[0].path == Path::new(&self.meta.name)
+    }
+
+    /// Directory the torrent's file paths are relative to.
+    pub fn content_dir(&self) -> PathBuf {
+        if self.single_file() {
+            self.save.clone()
+        } else {
+            self.save.join(&self.meta.name)
+        }
+    }
+}
+
+/// Pair `torrent` with its fastresume file.
+fn pair(torrent: &Path) -> Result<Pair, String> {
+    let resume_file = torrent.with_extension("fastresume");
+    if !resume_file.is_file() {
+        return Err("no fastresume file".to_string());
+    }
+    let resume = Resume::load(&resume_file)?;
+    let save = resume
+        .save_path
+        .clone()
+        .ok_or("fastresume has no save path")?;
+    Ok(Pair {
+        torrent: torrent.to_path_buf(),
+        meta: bencode::parse_torrent_file(torrent)?,
+        resume,
+        save: PathBuf::from(save),
+    })
+}
+
+/// Check that `pair` can be synced and take the client's file settings.
+fn entry(pair: &Pair) -> Result<Entry, String> {
+    if pair.single_file() || pair.meta.name.is_empty() {
+        return Err("single-file torrent, its save path holds other downloads".to_string());
+    }
+    // Unfinished files may carry a temporary name (`.!qB`) sync would delete
+    if !pair.resume.complete(&pair.meta) {
+        return Err("download not complete".to_string());
+    }
+    Ok(Entry {
+        torrent: pair.torrent.clone(),
+        dir: pair.content_dir(),
+        priorities: pair.resume.file_priorities(&pair.meta)?,
+        path_map: pair.resume.path_map(&pair.meta)?,
+    })
+}
+
+/// Every torrent in `dir` paired with its fastresume data, sorted by file
+/// name; `Err` with the reason for those that cannot be.
+pub fn pairs(dir: &Path) -> Result<Vec<Result<Pair, Skipped>>, String> {
+    let mut torrents: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("cannot read {:?}: {}", dir, e))?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("torrent"))
+        })
+        .collect();
+    torrents.sort();
+    Ok(torrents
+        .into_iter()
+        .map(|torrent| pair(&torrent).map_err(|reason| (torrent, reason)))
+        .collect())
+}
+
+/// Pair every torrent in `dir` with its directory.
+pub fn scan(dir: &Path) -> Result<Scan, String> {
+    let mut scan = Scan::default();
+    for pair in pairs(dir)? {
+        match pair.and_then(|pair| entry(&pair).map_err(|reason| (pair.torrent, reason))) {
+            Ok(entry) => scan.entries.push(entry),
+            Err(skipped) => scan.skipped.push(skipped),
+        }
+    }
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_scan() {
+        let dir = TempDir::new("bt-backup");
+        let multi = b"d4:infod5:filesld6:lengthi1e4:pathl5:a.mkveee4:name4:Show\
+                      12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let single = b"d4:infod6:lengthi1e4:name5:f.iso\
+                       12:piece lengthi16384e6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        fs::write(dir.join("aa.torrent"), multi).unwrap();
+        fs::write(dir.join("aa.fastresume"), b"d9:save_path5:E:\\TVe").unwrap();
+        fs::write(dir.join("bb.torrent"), single).unwrap();
+        fs::write(dir.join("bb.fastresume"), b"d12:qBt-savePath5:E:\\TVe").unwrap();
+        fs::write(dir.join("cc.torrent"), multi).unwrap();
+
+        let scan = scan(&dir).unwrap();
+        assert_eq!(scan.entries.len(), 1);
+        assert_eq!(scan.entries[0].torrent, dir.join("aa.torrent"));
+        assert_eq!(scan.entries[0].dir, PathBuf::from("E:\\TV").join("Show"));
+        let skipped: Vec<&str> = scan.skipped.iter().map(|(_, r)| r.as_str()).collect();
+        assert!(skipped[0].starts_with("single-file"));
+        assert!(skipped[1].starts_with("no fastresume"));
+    }
+}
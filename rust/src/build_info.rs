@@ -0,0 +1,59 @@
+//! Identity of this build, for `version`, log run headers, JSON log
+//! records and crash reports.
+//!
+//! The git commit comes from `build.rs` (`unknown` when built outside a git
+//! checkout); the features are the Cargo features compiled in.
+
+/// Crate version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short git commit hash of the build.
+pub const GIT_HASH: &str = env!("ZDC_GIT_HASH");
+
+/// Enabled Cargo features, in `Cargo.toml` order.
+pub fn features() -> Vec<&'static str> {
+    [
+        ("client-apis", cfg!(feature = "client-apis")),
+        ("verify", cfg!(feature = "verify")),
+        ("https", cfg!(feature = "https")),
+        ("tui", cfg!(feature = "tui")),
+        ("gui", cfg!(feature = "gui")),
+        ("service", cfg!(feature = "service")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// Target platform, e.g. `windows-x86_64`.
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// `1.0.0+<git hash>`, the build field of JSON log records.
+pub fn build_id() -> String {
+    format!("{}+{}", VERSION, GIT_HASH)
+}
+
+/// One line: version, commit, platform and features.
+pub fn summary() -> String {
+    format!(
+        "zDirComp {} ({}, {}, features: {})",
+        VERSION,
+        GIT_HASH,
+        platform(),
+        features().join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let summary = summary();
+        assert!(summary.starts_with(&format!("zDirComp {} (", VERSION)));
+        assert!(summary.contains(&platform()));
+        assert_eq!(build_id(), format!("{}+{}", VERSION, GIT_HASH));
+    }
+}
@@ -0,0 +1,157 @@
+//! Results of clean runs, for `--skip-unchanged`.
+//!
+//! After a sync or verify that found nothing wrong, a cheap fingerprint of
+//! the directories (file count, total size, newest mtime of any file or
+//! directory) is stored in `state`, keyed by command, infohash and
+//! directory. A later run with `--skip-unchanged` that finds the same
+//! fingerprint stops early, so batch runs over hundreds of torrents only
+//! work on the ones that changed. Changing options such as `--subpath` or
+//! keep patterns is not detected; run once without the flag after that.
+
+#[cfg(any(feature = "verify", test))]
+use crate::dir_index::DirIndex;
+use crate::safety;
+use crate::sha;
+use crate::state;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Summary of a directory tree that changes when files are added, removed,
+/// resized, written or renamed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub files: u64,
+    pub bytes: u64,
+    /// Newest modification time, in seconds since the Unix epoch.
+    pub newest: u64,
+}
+
+impl Fingerprint {
+    fn to_value(self) -> String {
+        format!("{} {} {}", self.files, self.bytes, self.newest)
+    }
+
+    fn parse(s: &str) -> Option<Fingerprint> {
+        let mut parts = s.split(' ').map(str::parse::<u64>);
+        let fingerprint = Fingerprint {
+            files: parts.next()?.ok()?,
+            bytes: parts.next()?.ok()?,
+            newest: parts.next()?.ok()?,
+        };
+        parts.next().is_none().then_some(fingerprint)
+    }
+
+    fn add(&mut self, metadata: &fs::Metadata) {
+        self.add_entry(metadata.is_file(), metadata.len(), metadata.modified().ok());
+    }
+
+    fn add_entry(&mut self, is_file: bool, size: u64, modified: Option<SystemTime>) {
+        let modified = modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        self.newest = self.newest.max(modified);
+        if is_file {
+            self.files += 1;
+            self.bytes += size;
+        }
+    }
+}
+
+fn walk(dir: &Path, depth: usize, entries: &mut usize, fp: &mut Fingerprint) -> Result<(), String> {
+    safety::check_walk(*entries, depth)?;
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in read_dir.flatten() {
+        *entries += 1;
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        fp.add(&metadata);
+        if metadata.is_dir() {
+            walk(&entry.path(), depth + 1, entries, fp)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fingerprint of the trees under `dirs`, within the walk limits.
+pub fn fingerprint(dirs: &[PathBuf]) -> Result<Fingerprint, String> {
+    let mut fp = Fingerprint::default();
+    let mut entries = 0;
+    for dir in dirs {
+        if let Ok(metadata) = fs::metadata(dir) {
+            fp.add(&metadata);
+        }
+        walk(dir, 0, &mut entries, &mut fp)?;
+    }
+    Ok(fp)
+}
+
+/// Fingerprint of the tree under `dir` from an index of all of it, without
+/// walking it again.
+#[cfg(any(feature = "verify", test))]
+pub fn fingerprint_index(dir: &Path, index: &DirIndex) -> Fingerprint {
+    let mut fp = Fingerprint::default();
+    if let Ok(metadata) = fs::metadata(dir) {
+        fp.add(&metadata);
+    }
+    for (_, meta) in index.entries() {
+        fp.add_entry(!meta.is_dir, meta.size, meta.modified);
+    }
+    fp
+}
+
+fn key(command: &str, info_hash: &[u8], dir: &Path) -> String {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    format!(
+        "clean {} {} {}",
+        command,
+        sha::hex(info_hash),
+        dir.to_string_lossy().to_lowercase()
+    )
+}
+
+/// Whether the last clean `command` run for this torrent and directory saw
+/// the same fingerprint.
+pub fn is_clean(command: &str, info_hash: &[u8], dir: &Path, fp: Fingerprint) -> bool {
+    state::get(&key(command, info_hash, dir)).and_then(|v| Fingerprint::parse(&v)) == Some(fp)
+}
+
+/// Record a clean run (`Some`) or forget the last one (`None`).
+pub fn record(
+    command: &str,
+    info_hash: &[u8],
+    dir: &Path,
+    fp: Option<Fingerprint>,
+) -> Result<(), String> {
+    let value = fp.map(Fingerprint::to_value);
+    state::set(&key(command, info_hash, dir), value.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = TempDir::new("fp");
+        fs::create_dir_all(dir.join("Sub")).unwrap();
+        fs::write(dir.join("a"), b"abc").unwrap();
+        fs::write(dir.join("Sub").join("b"), b"de").unwrap();
+
+        let fp = fingerprint(&[dir.to_path_buf()]).unwrap();
+        assert_eq!((fp.files, fp.bytes), (2, 5));
+        assert!(fp.newest > 0);
+        assert_eq!(Fingerprint::parse(&fp.to_value()), Some(fp));
+        assert_eq!(Fingerprint::parse("1 2"), None);
+        let index = DirIndex::build(&dir, Path::new("")).unwrap();
+        assert_eq!(fingerprint_index(&dir, &index), fp);
+
+        fs::write(dir.join("Sub").join("c"), b"").unwrap();
+        assert_ne!(fingerprint(&[dir.to_path_buf()]).unwrap(), fp);
+    }
+}
@@ -0,0 +1,127 @@
+//! `--fix-case`: give files and folders the exact letter case of the torrent.
+//!
+//! NTFS finds `movie.MKV` when asked for `Movie.mkv`, so a client seeds
+//! from it happily, but tools that compare case (rsync to Linux, WSL
+//! mounts, case-sensitive folders) see another name. Sync would also count
+//! the file as an extra. With `--fix-case`, sync renames each such entry
+//! before planning. The rename goes through a temporary name, as a direct
+//! case-only rename is not honoured by every file system.
+
+use crate::dir_index::DirIndex;
+use crate::retry;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Entries of `index` whose path matches an expected file, or a folder of
+/// one, except for letter case: (current, wanted) paths relative to the
+/// index root. Only the last component of each pair differs, and children
+/// come before their folders, so renaming in order keeps the current paths
+/// valid. Names the torrent spells two ways, and names whose exact spelling
+/// also exists, are left alone.
+pub fn mismatches(index: &DirIndex, expected: &HashSet<PathBuf>) -> Vec<(PathBuf, PathBuf)> {
+    // Lowercased path -> exact spelling, `None` if the torrent has several
+    let mut wanted: HashMap<String, Option<&Path>> = HashMap::new();
+    for path in expected {
+        for prefix in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            let key = prefix.to_string_lossy().to_lowercase();
+            wanted
+                .entry(key)
+                .and_modify(|exact| {
+                    if *exact != Some(prefix) {
+                        *exact = None;
+                    }
+                })
+                .or_insert(Some(prefix));
+        }
+    }
+    let on_disk: HashSet<&Path> = index.entries().map(|(relative, _)| relative).collect();
+
+    let mut renames = Vec::new();
+    for (relative, _) in index.entries() {
+        let key = relative.to_string_lossy().to_lowercase();
+        let Some(Some(exact)) = wanted.get(&key) else {
+            continue;
+        };
+        let (Some(name), Some(exact_name)) = (relative.file_name(), exact.file_name()) else {
+            continue;
+        };
+        if name == exact_name {
+            continue;
+        }
+        let target = relative.with_file_name(exact_name);
+        if !on_disk.contains(target.as_path()) {
+            renames.push((relative.to_path_buf(), target));
+        }
+    }
+    renames
+}
+
+/// Rename `dir/from` to `dir/to` by way of a temporary name in the same
+/// folder; on failure the entry keeps its old name.
+pub fn rename(dir: &Path, from: &Path, to: &Path) -> io::Result<()> {
+    let source = dir.join(from);
+    let target = dir.join(to);
+    let mut temp = source.clone().into_os_string();
+    temp.push(".zdc_case");
+    let temp = PathBuf::from(temp);
+    if fs::symlink_metadata(&temp).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} is in the way", temp),
+        ));
+    }
+    retry::rename(&source, &temp)?;
+    // With the source moved away, anything found here is another entry
+    let result = match fs::symlink_metadata(&target) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", target),
+        )),
+        Err(_) => retry::rename(&temp, &target),
+    };
+    result.inspect_err(|_| {
+        let _ = retry::rename(&temp, &source);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_fix_case() {
+        let dir = TempDir::new("case");
+        fs::create_dir_all(dir.join("season 1")).unwrap();
+        fs::write(dir.join("season 1").join("EP1.mkv"), b"x").unwrap();
+        fs::write(dir.join("Extra.nfo"), b"x").unwrap();
+
+        let expected: HashSet<PathBuf> = [Path::new("Season 1").join("ep1.mkv")].into();
+        let index = DirIndex::build(&dir, Path::new("")).unwrap();
+        let renames = mismatches(&index, &expected);
+        assert_eq!(
+            renames,
+            vec![
+                (
+                    Path::new("season 1").join("EP1.mkv"),
+                    Path::new("season 1").join("ep1.mkv")
+                ),
+                (PathBuf::from("season 1"), PathBuf::from("Season 1")),
+            ]
+        );
+        for (from, to) in &renames {
+            rename(&dir, from, to).unwrap();
+        }
+        assert!(dir.join("Season 1").join("ep1.mkv").is_file());
+        let index = DirIndex::build(&dir, Path::new("")).unwrap();
+        assert!(mismatches(&index, &expected).is_empty());
+
+        // Spelled two ways by the torrent: ambiguous, left alone
+        let both: HashSet<PathBuf> =
+            [PathBuf::from("extra.nfo"), PathBuf::from("EXTRA.nfo")].into();
+        assert!(mismatches(&index, &both).is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+//! Checksum files: `.sfv` (CRC32), `md5sum` and `sha1sum` formats.
+//!
+//! `verify --export FORMAT` writes one for the files that passed, so other
+//! tools (QuickSFV, `sha1sum -c`, tracker checkers) can use the result. One
+//! file per torrent at the top of the directory, or with `--export-per-dir`
+//! one per directory listing only that directory's files. Either way it is
+//! named after the torrent: `<name>.sfv`, `<name>.md5` or `<name>.sha1`.
+//! Sync deletes files the torrent does not list, so keep these with a
+//! `.zdirignore` pattern.
+//!
+//! `verify --trust-sfv` reads such files back (ours or any other `.sfv`,
+//! `.md5`, `.sha1` next to the torrent's files): a listed file that has not
+//! been modified since the checksum file was written is taken as good
+//! without hashing.
+
+use crate::sha;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Checksum file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Sfv,
+    Md5,
+    Sha1,
+}
+
+impl Format {
+    /// Parse an `--export` value.
+    pub fn parse(s: &str) -> Option<Format> {
+        match s.to_lowercase().as_str() {
+            "sfv" | "crc32" => Some(Format::Sfv),
+            "md5" | "md5sum" => Some(Format::Md5),
+            "sha1" | "sha1sum" => Some(Format::Sha1),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Sfv => "sfv",
+            Format::Md5 => "md5",
+            Format::Sha1 => "sha1",
+        }
+    }
+
+    /// Format of a checksum file, by extension.
+    fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "sfv" => Some(Format::Sfv),
+            "md5" => Some(Format::Md5),
+            "sha1" => Some(Format::Sha1),
+            _ => None,
+        }
+    }
+
+    /// Paths listed in a checksum file's text, relative to its directory.
+    fn entries(self, text: &str) -> Vec<PathBuf> {
+        let names = text.lines().filter_map(|line| {
+            let line = line.trim_end();
+            match self {
+                _ if line.is_empty() => None,
+                Format::Sfv if line.starts_with(';') => None,
+                Format::Sfv => line.rsplit_once(' ').map(|(name, _)| name.trim_end()),
+                // `<hash>  <name>` (text mode) or `<hash> *<name>` (binary)
+                Format::Md5 | Format::Sha1 => line
+                    .split_once(' ')
+                    .map(|(_, name)| name.strip_prefix([' ', '*']).unwrap_or(name)),
+            }
+        });
+        names
+            .map(|name| name.split(['/', '\\']).filter(|p| !p.is_empty()).collect())
+            .collect()
+    }
+
+    /// One line of the checksum file. SFV is a Windows format and uses
+    /// backslashes; the `*sum` formats use forward slashes.
+    fn line(self, path: &Path, checksum: &str) -> String {
+        let parts: Vec<String> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        match self {
+            Format::Sfv => format!("{} {}", parts.join("\\"), checksum),
+            Format::Md5 | Format::Sha1 => format!("{}  {}", checksum, parts.join("/")),
+        }
+    }
+}
+
+/// CRC-32 (IEEE, as used by SFV) lookup table.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Streaming CRC-32.
+struct Crc32(u32);
+
+impl Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = CRC_TABLE[((self.0 ^ u32::from(b)) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// Streaming MD5.
+struct Md5 {
+    state: [u32; 4],
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Md5 {
+    fn new() -> Md5 {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let full = self.pending.len() / 64 * 64;
+        for block in self.pending[..full].chunks_exact(64) {
+            md5_block(&mut self.state, block);
+        }
+        self.pending.drain(..full);
+    }
+
+    fn finish(mut self) -> [u8; 16] {
+        let bit_len = self.len.wrapping_mul(8);
+        let mut tail = std::mem::take(&mut self.pending);
+        tail.push(0x80);
+        while tail.len() % 64 != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bit_len.to_le_bytes());
+        for block in tail.chunks_exact(64) {
+            md5_block(&mut self.state, block);
+        }
+        let mut out = [0u8; 16];
+        for (chunk, v) in out.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+}
+
+/// Process one 64-byte MD5 block.
+fn md5_block(state: &mut [u32; 4], block: &[u8]) {
+    let mut m = [0u32; 16];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        m[i] = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    let [mut a, mut b, mut c, mut d] = *state;
+    for i in 0..64 {
+        let (f, g) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16),
+        };
+        let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+        a = d;
+        d = c;
+        c = b;
+        b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[(i / 16) * 4 + i % 4]));
+    }
+    for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+        *s = s.wrapping_add(v);
+    }
+}
+
+/// Checksum of a whole file in `format`, read in chunks.
+fn file_checksum(path: &Path, format: Format) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; 1 << 20];
+    let mut crc = Crc32(!0);
+    let mut md5 = Md5::new();
+    let mut sha1 = sha::Sha1::new();
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        match format {
+            Format::Sfv => crc.update(&buf[..n]),
+            Format::Md5 => md5.update(&buf[..n]),
+            Format::Sha1 => sha1.update(&buf[..n]),
+        }
+    }
+    Ok(match format {
+        Format::Sfv => format!("{:08X}", crc.finish()),
+        Format::Md5 => sha::hex(&md5.finish()),
+        Format::Sha1 => sha::hex(&sha1.finish()),
+    })
+}
+
+/// Write checksum files for `files` (paths relative to `dir`), named
+/// `<name>.<ext>`. Returns the files written.
+pub fn export(
+    dir: &Path,
+    files: &[PathBuf],
+    format: Format,
+    per_dir: bool,
+    name: &str,
+) -> Result<Vec<PathBuf>, String> {
+    // Checksum file location (relative to `dir`) → entries relative to it
+    let mut groups: BTreeMap<PathBuf, Vec<(PathBuf, String)>> = BTreeMap::new();
+    for relative in files {
+        let checksum = file_checksum(&dir.join(relative), format)
+            .map_err(|e| format!("cannot read {:?}: {}", relative, e))?;
+        let (base, entry) = match (per_dir, relative.parent(), relative.file_name()) {
+            (true, Some(parent), Some(file_name)) => {
+                (parent.to_path_buf(), PathBuf::from(file_name))
+            }
+            _ => (PathBuf::new(), relative.clone()),
+        };
+        groups.entry(base).or_default().push((entry, checksum));
+    }
+
+    let file_name = format!("{}.{}", name, format.extension());
+    let mut written = Vec::new();
+    for (base, entries) in groups {
+        let mut text = String::new();
+        if format == Format::Sfv {
+            text.push_str(&format!(
+                "; Generated by zDirComp {}\r\n",
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        for (entry, checksum) in &entries {
+            text.push_str(&format.line(entry, checksum));
+            text.push_str(if format == Format::Sfv { "\r\n" } else { "\n" });
+        }
+        let path = dir.join(&base).join(&file_name);
+        fs::write(&path, text).map_err(|e| format!("cannot write {:?}: {}", path, e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Files in `files` (relative to `dir`) listed in a checksum file next to
+/// them or above them that was written after the file last changed.
+pub fn trusted(dir: &Path, files: &[PathBuf]) -> HashSet<PathBuf> {
+    let wanted: HashSet<&PathBuf> = files.iter().collect();
+    let mut bases: Vec<PathBuf> = Vec::new();
+    for file in files {
+        let mut parent = file.parent();
+        while let Some(base) = parent {
+            if !bases.iter().any(|b| b == base) {
+                bases.push(base.to_path_buf());
+            }
+            parent = base.parent();
+        }
+    }
+
+    let mut trusted = HashSet::new();
+    for base in bases {
+        let Ok(read_dir) = fs::read_dir(dir.join(&base)) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            let Some(format) = Format::from_path(&path) else {
+                continue;
+            };
+            let (Ok(text), Ok(written)) = (
+                fs::read_to_string(&path),
+                entry.metadata().and_then(|m| m.modified()),
+            ) else {
+                continue;
+            };
+            for listed in format.entries(&text) {
+                let relative = base.join(listed);
+                let unchanged = fs::metadata(dir.join(&relative))
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| modified <= written);
+                if unchanged && wanted.contains(&relative) {
+                    trusted.insert(relative);
+                }
+            }
+        }
+    }
+    trusted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = Crc32(!0);
+        crc.update(data);
+        crc.finish()
+    }
+
+    fn md5(data: &[u8]) -> String {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        sha::hex(&md5.finish())
+    }
+
+    #[test]
+    fn test_vectors() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        let long = [b'a'; 1000];
+        let mut split = Md5::new();
+        split.update(&long[..100]);
+        split.update(&long[100..]);
+        assert_eq!(sha::hex(&split.finish()), md5(&long));
+    }
+
+    #[test]
+    fn test_export_per_dir() {
+        let dir = TempDir::new("export");
+        fs::create_dir_all(dir.join("CD1")).unwrap();
+        fs::write(dir.join("CD1").join("a.bin"), b"123456789").unwrap();
+        fs::write(dir.join("top.bin"), b"abc").unwrap();
+
+        let files = [Path::new("CD1").join("a.bin"), PathBuf::from("top.bin")];
+        let written = export(&dir, &files, Format::Sfv, true, "Album").unwrap();
+        assert_eq!(written.len(), 2);
+        let sfv = fs::read_to_string(dir.join("CD1").join("Album.sfv")).unwrap();
+        assert!(sfv.ends_with("a.bin CBF43926\r\n"));
+
+        export(&dir, &files, Format::Md5, false, "Album").unwrap();
+        let sums = fs::read_to_string(dir.join("Album.md5")).unwrap();
+        assert!(sums.contains("900150983cd24fb0d6963f7d28e17f72  top.bin\n"));
+        assert!(sums.contains("  CD1/a.bin\n"));
+
+        // Both checksum files list both files, written after them
+        let trusted = trusted(&dir, &files);
+        assert_eq!(trusted.len(), 2);
+    }
+
+    #[test]
+    fn test_entries() {
+        let sfv = "; comment\r\nCD1\\a b.bin CBF43926\r\n\r\n";
+        assert_eq!(
+            Format::Sfv.entries(sfv),
+            vec![Path::new("CD1").join("a b.bin")]
+        );
+        let sums = "900150983cd24fb0d6963f7d28e17f72  top.bin\nabc *CD1/x y\n";
+        assert_eq!(
+            Format::Md5.entries(sums),
+            vec![PathBuf::from("top.bin"), Path::new("CD1").join("x y")]
+        );
+    }
+}
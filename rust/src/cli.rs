@@ -0,0 +1,375 @@
+//! Command-line argument splitting.
+//!
+//! Separates `--option value` / `--flag` arguments from positional arguments
+//! so each mode can keep reading its positionals by index as before.
+
+/// Options that consume the following argument as their value.
+const VALUE_OPTIONS: &[&str] = &[
+    "log-format",
+    "log",
+    "audit",
+    "threads",
+    "post-action",
+    "client",
+    "client-url",
+    "client-user",
+    "client-pass",
+    "http-timeout",
+    "pid",
+    "name",
+    "session",
+    "profile",
+    "label",
+    "order",
+    "max-files",
+    "max-depth",
+    "max-memory",
+    "cpu-affinity",
+    "priority",
+    "dir",
+    "map",
+    "subpath",
+    "priorities",
+    "export",
+    "retention",
+    "snapshot-over",
+    "pre-delete-hook",
+    "post-run-hook",
+    "approve-kill-hook",
+    "hook-timeout",
+    "kill-default",
+    "max-delete-percent",
+    "every",
+    "args",
+    "task-name",
+    "update-url",
+    "channel",
+    "listen",
+    "api-token",
+    "job-timeout",
+    "library",
+    "bt-backup",
+    "sample",
+    "torrents",
+    "older-than",
+    "delete-rate",
+    "min-size",
+    "protect",
+    "root-tag",
+    "out",
+    "rules",
+    "media-server",
+    "media-url",
+    "media-token",
+    "format",
+];
+
+/// Options that take no value; config files may set them with `true`.
+const FLAG_OPTIONS: &[&str] = &[
+    "quiet",
+    "verbose",
+    "tui",
+    "kill-tree",
+    "clear-motw",
+    "include-system",
+    "fix-case",
+    "rename-root",
+    "preserve-dir-times",
+    "touch-expected",
+    "who-details",
+    "delete-skipped",
+    "export-per-dir",
+    "trust-sfv",
+    "incremental",
+    "skip-unchanged",
+    "allow-copy",
+    "import-safe",
+    "allow-running",
+    "force-large-delete",
+    "quarantine",
+    "purge",
+];
+
+/// Every option name, value-taking first (for shell completion).
+pub fn option_names() -> impl Iterator<Item = &'static str> {
+    VALUE_OPTIONS.iter().chain(FLAG_OPTIONS).copied()
+}
+
+/// Undo the Windows `"...\"` quoting trap: clients pass `"%D\"`, the C
+/// runtime reads `\"` as an escaped quote and glues the following
+/// arguments onto the path. Split such an argument back into the path (with
+/// its trailing backslash) and the arguments after it.
+///
+/// Only positional arguments that end in `"` or hold the glued `" `
+/// separator are split; option values (hook command lines) keep their
+/// quotes. Returns the repaired arguments and, for logging, each mangled
+/// argument with what it was split into.
+pub fn repair_quoting(raw: &[String]) -> (Vec<String>, Vec<(String, Vec<String>)>) {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut repairs = Vec::new();
+    let (mut after_dashes, mut takes_value) = (false, false);
+    for arg in raw {
+        let value = std::mem::take(&mut takes_value);
+        if !after_dashes && !value {
+            if arg == "--" {
+                after_dashes = true;
+            } else if let Some(name) = arg.strip_prefix("--") {
+                takes_value = VALUE_OPTIONS.contains(&name.to_lowercase().as_str());
+            }
+        }
+        let positional = !value && (after_dashes || !arg.starts_with("--"));
+        let glued = arg.ends_with('"') || arg.contains("\" ");
+        match arg.split_once('"') {
+            Some((path, rest)) if positional && glued => {
+                let mut split = vec![format!("{}\\", path.trim_end_matches('\\'))];
+                split.extend(
+                    rest.split_whitespace()
+                        .map(|a| a.trim_matches('"').to_string())
+                        .filter(|a| !a.is_empty()),
+                );
+                out.extend(split.iter().cloned());
+                repairs.push((arg.clone(), split));
+            }
+            _ => out.push(arg.clone()),
+        }
+    }
+    (out, repairs)
+}
+
+/// Characters that cannot appear in a Windows path argument; one left
+/// after `repair_quoting` means the command line is still mangled.
+pub fn invalid_path_char(arg: &str) -> Option<char> {
+    arg.chars().find(|c| matches!(c, '"' | '<' | '>' | '|') || c.is_control())
+}
+
+/// Parsed command line: positionals in order, options by name.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    /// Split raw arguments (without the program name).
+    ///
+    /// Accepts both `--name value` (for names listed in `VALUE_OPTIONS`) and
+    /// `--name=value`. A bare `--` ends option parsing.
+    pub fn parse(raw: &[String]) -> Result<Args, String> {
+        let mut args = Args::default();
+        let mut iter = raw.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                args.positional.extend(iter.by_ref().cloned());
+                break;
+            }
+            let Some(name) = arg.strip_prefix("--") else {
+                args.positional.push(arg.clone());
+                continue;
+            };
+            if let Some((name, value)) = name.split_once('=') {
+                args.options
+                    .push((name.to_lowercase(), Some(value.to_string())));
+            } else if VALUE_OPTIONS.contains(&name.to_lowercase().as_str()) {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("option --{} requires a value", name))?;
+                args.options
+                    .push((name.to_lowercase(), Some(value.clone())));
+            } else {
+                args.options.push((name.to_lowercase(), None));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Last value given for an option, if any.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    /// All values given for a repeatable option, in order.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(n, _)| n == name)
+            .filter_map(|(_, v)| v.as_deref())
+            .collect()
+    }
+
+    /// Whether a flag (option without a value) was given.
+    pub fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, v)| n == name && v.is_none())
+    }
+
+    /// Rewrite every positional argument and option value.
+    pub fn map_values(&mut self, f: impl Fn(&str) -> String) {
+        for p in &mut self.positional {
+            *p = f(p);
+        }
+        self.map_option_values(f);
+    }
+
+    /// Rewrite option values only; positionals stay literal.
+    pub fn map_option_values(&mut self, f: impl Fn(&str) -> String) {
+        for value in self.options.iter_mut().filter_map(|(_, v)| v.as_mut()) {
+            *value = f(value);
+        }
+    }
+
+    /// Add an option from the config file (`None` for a flag) unless the
+    /// command line already gave it. Unknown names are an error.
+    pub fn set_default(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        let known = match value {
+            Some(_) => name != "profile" && VALUE_OPTIONS.contains(&name),
+            None => FLAG_OPTIONS.contains(&name),
+        };
+        if !known {
+            return Err(format!("unknown setting '{}'", name));
+        }
+        if !self.options.iter().any(|(n, _)| n == name) {
+            self.options.insert(0, (name.to_string(), value));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_options() {
+        let args = Args::parse(&strings(&[
+            "sync",
+            "--log-format",
+            "jsonl",
+            "a.torrent",
+            "D:\\x",
+        ]))
+        .unwrap();
+        assert_eq!(args.positional, strings(&["sync", "a.torrent", "D:\\x"]));
+        assert_eq!(args.value("log-format"), Some("jsonl"));
+    }
+
+    #[test]
+    fn test_repeated_values() {
+        let args = Args::parse(&strings(&["sync", "--dir", "F:\\b", "--dir=G:\\c"])).unwrap();
+        assert_eq!(args.values("dir"), vec!["F:\\b", "G:\\c"]);
+        assert!(args.values("label").is_empty());
+    }
+
+    #[test]
+    fn test_equals_and_terminator() {
+        let args = Args::parse(&strings(&["--log-format=text", "--", "--odd-name"])).unwrap();
+        assert_eq!(args.value("log-format"), Some("text"));
+        assert_eq!(args.positional, strings(&["--odd-name"]));
+    }
+
+    #[test]
+    fn test_flags() {
+        let args = Args::parse(&strings(&["unlock", "--Quiet", "D:\\x"])).unwrap();
+        assert!(args.flag("quiet"));
+        assert!(!args.flag("verbose"));
+        assert_eq!(args.positional, strings(&["unlock", "D:\\x"]));
+    }
+
+    #[test]
+    fn test_map_option_values() {
+        let mut args = Args::parse(&strings(&["sync", "100% done", "--dir", "x"])).unwrap();
+        args.map_option_values(|v| v.to_uppercase());
+        assert_eq!(args.positional, strings(&["sync", "100% done"]));
+        assert_eq!(args.value("dir"), Some("X"));
+    }
+
+    #[test]
+    fn test_set_default() {
+        let mut args = Args::parse(&strings(&["sync", "--threads", "2"])).unwrap();
+        args.set_default("threads", Some("8".into())).unwrap();
+        args.set_default("post-action", Some("pause".into())).unwrap();
+        args.set_default("tui", None).unwrap();
+        assert_eq!(args.value("threads"), Some("2"));
+        assert_eq!(args.value("post-action"), Some("pause"));
+        assert!(args.flag("tui"));
+        assert!(args.set_default("bogus", None).is_err());
+        assert!(args.set_default("tui", Some("x".into())).is_err());
+    }
+
+    #[test]
+    fn test_repair_quoting() {
+        // "E:\Online\Show\" --quiet  as split by the C runtime
+        let raw = strings(&["sync", "a.torrent", "E:\\Online\\Show\" --quiet"]);
+        let (args, repairs) = repair_quoting(&raw);
+        assert_eq!(
+            args,
+            strings(&["sync", "a.torrent", "E:\\Online\\Show\\", "--quiet"])
+        );
+        assert_eq!(repairs.len(), 1);
+        assert_eq!(repairs[0].0, raw[2]);
+
+        // Drive root: "D:\" arrives as D:"
+        let (args, _) = repair_quoting(&strings(&["unlock", "D:\""]));
+        assert_eq!(args, strings(&["unlock", "D:\\"]));
+
+        let (args, repairs) = repair_quoting(&strings(&["D:\\x"]));
+        assert_eq!(args, strings(&["D:\\x"]));
+        assert!(repairs.is_empty());
+
+        // Hook command lines keep their quotes
+        let raw = strings(&[
+            "--pre-delete-hook",
+            "\"C:\\My Hooks\\a.bat\" x",
+            "--post-run-hook=\"b.bat\"",
+        ]);
+        let (args, repairs) = repair_quoting(&raw);
+        assert_eq!(args, raw);
+        assert!(repairs.is_empty());
+
+        // Even as the value of an option outside the hooks
+        let raw = strings(&[
+            "--pre-delete-hook",
+            "\"C:\\x\\y.bat\" --db",
+            "--label",
+            "say \"hi\" twice",
+            "--",
+            "E:\\--odd\" --quiet",
+        ]);
+        let (args, repairs) = repair_quoting(&raw);
+        assert_eq!(args[..5], raw[..5]);
+        assert_eq!(args[5..], strings(&["E:\\--odd\\", "--quiet"]));
+        assert_eq!(repairs.len(), 1);
+
+        // A quote without the glued separator is left for the path check
+        let (args, repairs) = repair_quoting(&strings(&["E:\\a\"b"]));
+        assert_eq!(args, strings(&["E:\\a\"b"]));
+        assert!(repairs.is_empty());
+
+        // So does the command line of a scheduled task
+        let raw = strings(&[
+            "install-task",
+            "--args",
+            "sync-all --bt-backup \"C:\\BT backup\"",
+        ]);
+        let (args, repairs) = repair_quoting(&raw);
+        assert_eq!(args, raw);
+        assert!(repairs.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_path_char() {
+        assert_eq!(invalid_path_char("E:\\Online\\x"), None);
+        assert_eq!(invalid_path_char("E:\\a|b"), Some('|'));
+    }
+
+    #[test]
+    fn test_missing_value() {
+        assert!(Args::parse(&strings(&["sync", "--log-format"])).is_err());
+    }
+}
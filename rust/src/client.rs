@@ -0,0 +1,694 @@
+//! Torrent client Web APIs for post-sync actions.
+//!
+//! After sync deletes payload files the client still believes it has every
+//! piece and keeps seeding data that no longer exists. `--post-action`
+//! tells the client to recheck (or pause) the torrent, identified by the
+//! infohash of the .torrent file that was synced.
+//!
+//! Backends: qBittorrent (WebUI API v2), Transmission (RPC), Deluge (Web
+//! JSON-RPC), uTorrent/BitTorrent (WebUI, token auth). The uTorrent backend
+//! can also list a torrent's files, which `sync-client` uses instead of a
+//! .torrent file.
+
+use crate::bencode::{self, TorrentFile, TorrentMeta};
+use crate::http::{self, Url};
+use crate::json::Json;
+use crate::sha;
+
+use std::path::PathBuf;
+
+/// What to do in the client after sync changed the directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Recheck,
+    Pause,
+}
+
+impl Action {
+    /// Parse a `--post-action` value.
+    pub fn parse(s: &str) -> Option<Action> {
+        match s.to_lowercase().as_str() {
+            "none" => Some(Action::None),
+            "recheck" => Some(Action::Recheck),
+            "pause" => Some(Action::Pause),
+            _ => None,
+        }
+    }
+}
+
+/// Supported client APIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    QBittorrent,
+    Transmission,
+    Deluge,
+    UTorrent,
+}
+
+impl Kind {
+    /// Parse a `--client` value.
+    pub fn parse(s: &str) -> Option<Kind> {
+        match s.to_lowercase().as_str() {
+            "qbittorrent" | "qbt" => Some(Kind::QBittorrent),
+            "transmission" => Some(Kind::Transmission),
+            "deluge" => Some(Kind::Deluge),
+            "utorrent" | "bittorrent" => Some(Kind::UTorrent),
+            _ => None,
+        }
+    }
+
+    /// WebUI address used when `--client-url` is not given.
+    fn default_url(self) -> &'static str {
+        match self {
+            Kind::QBittorrent => "http://127.0.0.1:8080",
+            Kind::Transmission => "http://127.0.0.1:9091/transmission/rpc",
+            Kind::Deluge => "http://127.0.0.1:8112/json",
+            Kind::UTorrent => "http://127.0.0.1:8080/gui/",
+        }
+    }
+}
+
+/// How to reach the client.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub kind: Kind,
+    pub url: Url,
+    pub user: String,
+    pub pass: String,
+}
+
+impl Config {
+    /// Build from CLI values; `url` falls back to the client's default port.
+    pub fn new(kind: Kind, url: Option<&str>, user: &str, pass: &str) -> Result<Config, String> {
+        Ok(Config {
+            kind,
+            url: Url::parse(url.unwrap_or(kind.default_url()))?,
+            user: user.to_string(),
+            pass: pass.to_string(),
+        })
+    }
+
+    /// Create a backend. No network traffic happens until the first call.
+    pub fn connect(&self) -> Box<dyn Client> {
+        match self.kind {
+            Kind::QBittorrent => Box::new(QBittorrent {
+                config: self.clone(),
+                cookie: None,
+            }),
+            Kind::Transmission => Box::new(Transmission {
+                config: self.clone(),
+                session_id: String::new(),
+            }),
+            Kind::Deluge => Box::new(Deluge {
+                config: self.clone(),
+                cookie: None,
+                id: 0,
+            }),
+            Kind::UTorrent => Box::new(UTorrent {
+                config: self.clone(),
+                session: None,
+            }),
+        }
+    }
+}
+
+/// `--post-action` together with the client it applies to.
+#[derive(Debug, Clone)]
+pub struct PostAction {
+    pub action: Action,
+    pub config: Option<Config>,
+}
+
+impl Default for PostAction {
+    /// No post-action configured.
+    fn default() -> PostAction {
+        PostAction {
+            action: Action::None,
+            config: None,
+        }
+    }
+}
+
+impl PostAction {
+    /// Apply the action to the torrent with this infohash.
+    pub fn apply(&self, info_hash: &[u8; 20]) -> Result<(), String> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let hash = sha::hex(info_hash);
+        let mut client = config.connect();
+        match self.action {
+            Action::None => Ok(()),
+            Action::Recheck => client.recheck(&hash),
+            Action::Pause => client.pause(&hash),
+        }
+    }
+}
+
+/// Operations every backend supports. `hash` is the lowercase hex infohash.
+pub trait Client {
+    /// Force a full hash check of the torrent.
+    fn recheck(&mut self, hash: &str) -> Result<(), String>;
+    /// Stop the torrent (no seeding until resumed).
+    fn pause(&mut self, hash: &str) -> Result<(), String>;
+
+    /// The torrent's name and file list as the client reports it.
+    fn files(&mut self, hash: &str) -> Result<TorrentMeta, String> {
+        let _ = hash;
+        Err("this client cannot list torrent files".to_string())
+    }
+
+    /// Where every torrent of the client keeps its data (folder or file).
+    fn content_paths(&mut self) -> Result<Vec<PathBuf>, String> {
+        Err("this client cannot list its torrents".to_string())
+    }
+}
+
+/// Parse a 40-character hex infohash.
+pub fn parse_info_hash(s: &str) -> Option<[u8; 20]> {
+    if s.len() != 40 || !s.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// `name=value` part of a `Set-Cookie` header.
+fn cookie_pair(set_cookie: &str) -> String {
+    set_cookie
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_string()
+}
+
+/// Fail on any non-2xx status.
+fn expect_ok(resp: &http::Response, what: &str) -> Result<(), String> {
+    if (200..300).contains(&resp.status) {
+        Ok(())
+    } else {
+        Err(format!("{} failed: HTTP {}", what, resp.status))
+    }
+}
+
+// ============================================================
+// qBittorrent WebUI API v2
+// ============================================================
+
+struct QBittorrent {
+    config: Config,
+    cookie: Option<String>,
+}
+
+impl QBittorrent {
+    fn url(&self, endpoint: &str) -> Url {
+        let base = self.config.url.path.trim_end_matches('/');
+        self.config
+            .url
+            .with_path(&format!("{}/api/v2/{}", base, endpoint))
+    }
+
+    /// POST a form, logging in first if needed.
+    fn post(&mut self, endpoint: &str, form: &str) -> Result<http::Response, String> {
+        let referer = self.config.url.origin();
+        if self.cookie.is_none() {
+            let body = format!(
+                "username={}&password={}",
+                http::form_encode(&self.config.user),
+                http::form_encode(&self.config.pass)
+            );
+            let resp = http::post(
+                &self.url("auth/login"),
+                &[
+                    ("Content-Type", "application/x-www-form-urlencoded"),
+                    ("Referer", &referer),
+                ],
+                body.as_bytes(),
+            )?;
+            expect_ok(&resp, "qBittorrent login")?;
+            // Localhost auth bypass answers without a cookie; that is fine.
+            self.cookie = Some(
+                resp.headers("Set-Cookie")
+                    .map(cookie_pair)
+                    .find(|c| c.starts_with("SID="))
+                    .unwrap_or_default(),
+            );
+            if resp.text().trim() == "Fails." {
+                return Err("qBittorrent login failed: wrong username or password".to_string());
+            }
+        }
+        let cookie = self.cookie.clone().unwrap_or_default();
+        http::post(
+            &self.url(endpoint),
+            &[
+                ("Content-Type", "application/x-www-form-urlencoded"),
+                ("Referer", &referer),
+                ("Cookie", &cookie),
+            ],
+            form.as_bytes(),
+        )
+    }
+}
+
+impl Client for QBittorrent {
+    fn recheck(&mut self, hash: &str) -> Result<(), String> {
+        let resp = self.post("torrents/recheck", &format!("hashes={}", hash))?;
+        expect_ok(&resp, "qBittorrent recheck")
+    }
+
+    fn pause(&mut self, hash: &str) -> Result<(), String> {
+        let form = format!("hashes={}", hash);
+        let resp = self.post("torrents/pause", &form)?;
+        // qBittorrent 5 renamed pause/resume to stop/start
+        if resp.status == 404 {
+            let resp = self.post("torrents/stop", &form)?;
+            return expect_ok(&resp, "qBittorrent stop");
+        }
+        expect_ok(&resp, "qBittorrent pause")
+    }
+
+    /// File names from `torrents/files` include renames made in the client,
+    /// so renamed files are expected under their new names.
+    fn files(&mut self, hash: &str) -> Result<TorrentMeta, String> {
+        let form = format!("hashes={}", hash);
+        let resp = self.post("torrents/info", &form)?;
+        expect_ok(&resp, "qBittorrent torrent info")?;
+        let info = Json::parse(&resp.text())?;
+        let torrent = info
+            .as_array()
+            .and_then(|list| list.first())
+            .ok_or_else(|| format!("torrent {} not found in qBittorrent", hash))?;
+        let field = |key: &str| torrent.get(key).and_then(Json::as_str).unwrap_or_default();
+        let name = field("name").to_string();
+        let root = content_root(field("save_path"), field("content_path"));
+
+        let resp = self.post("torrents/files", &format!("hash={}", hash))?;
+        expect_ok(&resp, "qBittorrent file list")?;
+        let entries = Json::parse(&resp.text())?;
+        let entries = entries
+            .as_array()
+            .ok_or("qBittorrent torrents/files: unexpected response")?;
+
+        let mut names = Vec::with_capacity(entries.len());
+        let mut details = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let path = entry.get("name").and_then(Json::as_str);
+            let length = entry.get("size").and_then(Json::as_i64);
+            let (Some(path), Some(length)) = (path, length) else {
+                return Err("qBittorrent torrents/files: malformed file entry".to_string());
+            };
+            names.push(path.replace('\\', "/"));
+            // Priority 0 is "Do not download"
+            let skip = entry.get("priority").and_then(Json::as_i64) == Some(0);
+            details.push((length.max(0) as u64, skip));
+        }
+        let files: Vec<TorrentFile> = strip_root(&names, &root)
+            .into_iter()
+            .zip(details)
+            .map(|(path, (length, skip))| TorrentFile {
+                path: path.split('/').collect::<PathBuf>(),
+                length,
+                skip,
+            })
+            .collect();
+
+        Ok(TorrentMeta {
+            info_hash: parse_info_hash(hash).unwrap_or_default(),
+            name,
+            total_size: files.iter().map(|f| f.length).sum(),
+            duplicates: bencode::find_duplicates(&files),
+            files,
+            ..Default::default()
+        })
+    }
+
+    fn content_paths(&mut self) -> Result<Vec<PathBuf>, String> {
+        let resp = self.post("torrents/info", "")?;
+        expect_ok(&resp, "qBittorrent torrent list")?;
+        let list = Json::parse(&resp.text())?;
+        let list = list
+            .as_array()
+            .ok_or("qBittorrent torrents/info: unexpected response")?;
+        list.iter()
+            .map(|torrent| {
+                torrent
+                    .get("content_path")
+                    .and_then(Json::as_str)
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+                    .ok_or_else(|| "qBittorrent torrents/info: no content_path".to_string())
+            })
+            .collect()
+    }
+}
+
+/// The torrent's top folder relative to its save path (`content_path` of a
+/// multi-file torrent), `/`-separated; empty for a single file.
+fn content_root(save_path: &str, content_path: &str) -> String {
+    let normalize = |p: &str| p.replace('\\', "/").trim_end_matches('/').to_string();
+    let (save, content) = (normalize(save_path), normalize(content_path));
+    match content.get(..save.len()) {
+        Some(prefix) if !save.is_empty() && prefix.eq_ignore_ascii_case(&save) => {
+            content[save.len()..].trim_start_matches('/').to_string()
+        }
+        _ => String::new(),
+    }
+}
+
+/// qBittorrent file names start with the top folder; sync paths are
+/// relative to it. Names are kept whole unless all of them are inside it.
+fn strip_root(names: &[String], root: &str) -> Vec<String> {
+    let prefix = format!("{}/", root);
+    let inside = !root.is_empty() && names.iter().all(|n| n.starts_with(&prefix));
+    names
+        .iter()
+        .map(|n| if inside { n[prefix.len()..].to_string() } else { n.clone() })
+        .collect()
+}
+
+// ============================================================
+// Transmission RPC
+// ============================================================
+
+struct Transmission {
+    config: Config,
+    session_id: String,
+}
+
+impl Transmission {
+    /// Call an RPC method on one torrent, handling the 409 session-id handshake.
+    fn call(&mut self, method: &str, hash: &str) -> Result<(), String> {
+        let body = format!(
+            "{{\"method\":\"{}\",\"arguments\":{{\"ids\":[\"{}\"]}}}}",
+            method, hash
+        );
+        let auth = (!self.config.user.is_empty())
+            .then(|| http::basic_auth(&self.config.user, &self.config.pass));
+        for _ in 0..2 {
+            let mut headers = vec![
+                ("Content-Type", "application/json"),
+                ("X-Transmission-Session-Id", self.session_id.as_str()),
+            ];
+            if let Some(auth) = &auth {
+                headers.push(("Authorization", auth.as_str()));
+            }
+            let resp = http::post(&self.config.url, &headers, body.as_bytes())?;
+            if resp.status == 409 {
+                self.session_id = resp
+                    .header("X-Transmission-Session-Id")
+                    .unwrap_or_default()
+                    .to_string();
+                continue;
+            }
+            expect_ok(&resp, &format!("Transmission {}", method))?;
+            return if resp.text().contains("\"result\":\"success\"") {
+                Ok(())
+            } else {
+                Err(format!("Transmission {} failed: {}", method, resp.text()))
+            };
+        }
+        Err("Transmission rejected the session id".to_string())
+    }
+}
+
+impl Client for Transmission {
+    fn recheck(&mut self, hash: &str) -> Result<(), String> {
+        self.call("torrent-verify", hash)
+    }
+
+    fn pause(&mut self, hash: &str) -> Result<(), String> {
+        self.call("torrent-stop", hash)
+    }
+}
+
+// ============================================================
+// Deluge Web JSON-RPC
+// ============================================================
+
+struct Deluge {
+    config: Config,
+    cookie: Option<String>,
+    id: u32,
+}
+
+impl Deluge {
+    /// Call a JSON-RPC method; `params` is the JSON array of arguments.
+    fn call(&mut self, method: &str, params: &str) -> Result<http::Response, String> {
+        self.id += 1;
+        let body = format!(
+            "{{\"method\":\"{}\",\"params\":{},\"id\":{}}}",
+            method, params, self.id
+        );
+        let cookie = self.cookie.clone().unwrap_or_default();
+        let resp = http::post(
+            &self.config.url,
+            &[("Content-Type", "application/json"), ("Cookie", &cookie)],
+            body.as_bytes(),
+        )?;
+        expect_ok(&resp, &format!("Deluge {}", method))?;
+        if !resp.text().contains("\"error\": null") && !resp.text().contains("\"error\":null") {
+            return Err(format!("Deluge {} failed: {}", method, resp.text()));
+        }
+        Ok(resp)
+    }
+
+    fn login(&mut self) -> Result<(), String> {
+        if self.cookie.is_some() {
+            return Ok(());
+        }
+        let params = format!("[{}]", crate::json::Json::from(self.config.pass.as_str()));
+        let resp = self.call("auth.login", &params)?;
+        if !resp.text().contains("\"result\": true") && !resp.text().contains("\"result\":true") {
+            return Err("Deluge login failed: wrong password".to_string());
+        }
+        self.cookie = resp
+            .headers("Set-Cookie")
+            .map(cookie_pair)
+            .find(|c| c.starts_with("_session_id="));
+        Ok(())
+    }
+}
+
+impl Client for Deluge {
+    fn recheck(&mut self, hash: &str) -> Result<(), String> {
+        self.login()?;
+        self.call("core.force_recheck", &format!("[[\"{}\"]]", hash))
+            .map(drop)
+    }
+
+    fn pause(&mut self, hash: &str) -> Result<(), String> {
+        self.login()?;
+        self.call("core.pause_torrents", &format!("[[\"{}\"]]", hash))
+            .map(drop)
+    }
+}
+
+// ============================================================
+// uTorrent / BitTorrent WebUI
+// ============================================================
+
+struct UTorrent {
+    config: Config,
+    /// (token, GUID cookie) from `token.html`.
+    session: Option<(String, String)>,
+}
+
+impl UTorrent {
+    fn auth(&self) -> String {
+        http::basic_auth(&self.config.user, &self.config.pass)
+    }
+
+    /// Fetch the CSRF token; it is bound to the GUID cookie set alongside it.
+    fn login(&mut self) -> Result<(), String> {
+        let base = self.config.url.path.trim_end_matches('/');
+        let url = self.config.url.with_path(&format!("{}/token.html", base));
+        let resp = http::get(&url, &[("Authorization", &self.auth())])?;
+        expect_ok(&resp, "uTorrent token")?;
+        let token = extract_token(&resp.text()).ok_or("uTorrent token.html has no token")?;
+        let cookie = resp
+            .headers("Set-Cookie")
+            .map(cookie_pair)
+            .find(|c| c.starts_with("GUID="))
+            .unwrap_or_default();
+        self.session = Some((token, cookie));
+        Ok(())
+    }
+
+    /// GET `/gui/?token=…&{query}`, refreshing an expired token once.
+    fn get(&mut self, query: &str) -> Result<http::Response, String> {
+        for _ in 0..2 {
+            if self.session.is_none() {
+                self.login()?;
+            }
+            let (token, cookie) = self.session.clone().unwrap_or_default();
+            let base = self.config.url.path.trim_end_matches('/');
+            let url = self.config.url.with_path(&format!(
+                "{}/?token={}&{}",
+                base,
+                http::form_encode(&token),
+                query
+            ));
+            let resp = http::get(
+                &url,
+                &[("Authorization", &self.auth()), ("Cookie", &cookie)],
+            )?;
+            // 400 "invalid request" is what uTorrent answers for a stale token
+            if resp.status == 400 {
+                self.session = None;
+                continue;
+            }
+            expect_ok(&resp, "uTorrent WebUI")?;
+            return Ok(resp);
+        }
+        Err("uTorrent rejected the WebUI token".to_string())
+    }
+
+    fn get_json(&mut self, query: &str) -> Result<Json, String> {
+        let resp = self.get(query)?;
+        Json::parse(&resp.text()).map_err(|e| format!("uTorrent response: {}", e))
+    }
+}
+
+/// Token from `<div id='token' style='display:none;'>TOKEN</div>`.
+fn extract_token(html: &str) -> Option<String> {
+    let start = html
+        .find("id='token'")
+        .or_else(|| html.find("id=\"token\""))?;
+    let rest = &html[start..];
+    let open = rest.find('>')? + 1;
+    let close = rest[open..].find('<')? + open;
+    Some(rest[open..close].trim().to_string()).filter(|t| !t.is_empty())
+}
+
+impl Client for UTorrent {
+    fn recheck(&mut self, hash: &str) -> Result<(), String> {
+        self.get(&format!("action=recheck&hash={}", hash.to_uppercase()))
+            .map(drop)
+    }
+
+    fn pause(&mut self, hash: &str) -> Result<(), String> {
+        // "pause" keeps peer connections open; "stop" actually stops seeding
+        self.get(&format!("action=stop&hash={}", hash.to_uppercase()))
+            .map(drop)
+    }
+
+    fn files(&mut self, hash: &str) -> Result<TorrentMeta, String> {
+        let hash = hash.to_uppercase();
+        let list = self.get_json("list=1")?;
+        let name = list
+            .get("torrents")
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Json::as_array)
+            .find(|t| t.first().and_then(Json::as_str) == Some(hash.as_str()))
+            .and_then(|t| t.get(2).and_then(Json::as_str))
+            .ok_or_else(|| format!("torrent {} not found in uTorrent", hash))?
+            .to_string();
+
+        let reply = self.get_json(&format!("action=getfiles&hash={}", hash))?;
+        // "files": ["HASH", [[name, size, downloaded, priority, ...], ...]];
+        // priority 0 is "don't download"
+        let entries = reply
+            .get("files")
+            .and_then(Json::as_array)
+            .and_then(|f| f.get(1))
+            .and_then(Json::as_array)
+            .ok_or("uTorrent getfiles: unexpected response")?;
+
+        let mut files = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let fields = entry.as_array().unwrap_or_default();
+            let path = fields.first().and_then(Json::as_str);
+            let length = fields.get(1).and_then(Json::as_i64);
+            let (Some(path), Some(length)) = (path, length) else {
+                return Err("uTorrent getfiles: malformed file entry".to_string());
+            };
+            files.push(TorrentFile {
+                path: path.split(['\\', '/']).collect::<PathBuf>(),
+                length: length.max(0) as u64,
+                skip: fields.get(3).and_then(Json::as_i64) == Some(0),
+            });
+        }
+
+        Ok(TorrentMeta {
+            info_hash: parse_info_hash(&hash).unwrap_or_default(),
+            name,
+            total_size: files.iter().map(|f| f.length).sum(),
+            duplicates: bencode::find_duplicates(&files),
+            files,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(Action::parse("Recheck"), Some(Action::Recheck));
+        assert_eq!(Action::parse("resume"), None);
+        assert_eq!(Kind::parse("qbt"), Some(Kind::QBittorrent));
+        assert_eq!(Kind::parse("rtorrent"), None);
+    }
+
+    #[test]
+    fn test_default_url_and_qbt_paths() {
+        let config = Config::new(Kind::QBittorrent, None, "", "").unwrap();
+        assert_eq!(config.url.port, 8080);
+        let qbt = QBittorrent {
+            config: Config::new(Kind::QBittorrent, Some("http://nas:8081/qbt/"), "", "").unwrap(),
+            cookie: None,
+        };
+        assert_eq!(
+            qbt.url("torrents/recheck").path,
+            "/qbt/api/v2/torrents/recheck"
+        );
+    }
+
+    #[test]
+    fn test_extract_token() {
+        let html = "<html><div id='token' style='display:none;'>abc-123</div></html>";
+        assert_eq!(extract_token(html).as_deref(), Some("abc-123"));
+        assert_eq!(extract_token("<html></html>"), None);
+    }
+
+    #[test]
+    fn test_parse_info_hash() {
+        let hash = parse_info_hash("0123456789ABCDEF0123456789abcdef01234567").unwrap();
+        assert_eq!(hash[0], 0x01);
+        assert_eq!(hash[19], 0x67);
+        assert!(parse_info_hash("0123").is_none());
+        assert!(parse_info_hash("zz23456789ABCDEF0123456789abcdef01234567").is_none());
+    }
+
+    #[test]
+    fn test_qbt_renamed_names() {
+        let root = content_root("D:\\Downloads\\", "D:\\Downloads\\Show Renamed");
+        assert_eq!(root, "Show Renamed");
+        let names = vec![
+            "Show Renamed/S01/Episode 1.mkv".to_string(),
+            "Show Renamed/cover.jpg".to_string(),
+        ];
+        assert_eq!(
+            strip_root(&names, &root),
+            vec!["S01/Episode 1.mkv".to_string(), "cover.jpg".to_string()]
+        );
+        // Single-file torrent: content_path is the file itself
+        let single = vec!["movie.mkv".to_string()];
+        let root = content_root("D:/Downloads", "D:/Downloads/movie.mkv");
+        assert_eq!(strip_root(&single, &root), single);
+    }
+
+    #[test]
+    fn test_cookie_pair() {
+        assert_eq!(cookie_pair("SID=abc; HttpOnly; path=/"), "SID=abc");
+    }
+}
@@ -0,0 +1,107 @@
+//! `completion powershell`: tab completion for commands and options.
+//!
+//! Prints a `Register-ArgumentCompleter` script built from the option table
+//! in `cli`, so it never falls behind the parser. Load it for the session
+//! with `zDirComp completion powershell | Out-String | Invoke-Expression`,
+//! or append it to `$PROFILE`.
+
+use crate::cli;
+
+/// Commands offered in first position.
+fn commands() -> Vec<&'static str> {
+    let mut commands = vec![
+        "sync",
+        "sync-all",
+        "unlock",
+        "doctor",
+        "purge",
+        "orphans",
+        "report",
+        "repair-torrent",
+        "selftest",
+        "lint",
+        "align-check",
+        "metrics",
+        "stats",
+        "exclusions",
+        "install",
+        "uninstall",
+        "install-task",
+        "uninstall-task",
+        "audit-verify",
+        "completion",
+        "version",
+    ];
+    if cfg!(feature = "verify") {
+        commands.extend(["verify", "bench", "audit-complete"]);
+    }
+    if cfg!(feature = "client-apis") {
+        commands.extend(["sync-client", "self-update"]);
+    }
+    if cfg!(feature = "service") {
+        commands.push("serve");
+    }
+    if cfg!(feature = "fuzz") {
+        commands.push("fuzz-bencode");
+    }
+    commands
+}
+
+/// PowerShell array literal of `items`.
+fn ps_array<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    let quoted: Vec<String> = items.map(|item| format!("'{}'", item)).collect();
+    format!("@({})", quoted.join(", "))
+}
+
+/// The completion script.
+fn powershell() -> String {
+    let options = cli::option_names().map(|name| format!("--{}", name));
+    let options: Vec<String> = options.collect();
+    format!(
+        "\
+Register-ArgumentCompleter -Native -CommandName zDirComp, zDirComp.exe -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $commands = {}
+    $options = {}
+    $position = $commandAst.CommandElements.Count - [int]($wordToComplete -ne '')
+    $candidates = if ($position -le 1 -and -not $wordToComplete.StartsWith('-')) {{
+        $commands
+    }} else {{
+        $options
+    }}
+    $candidates | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+",
+        ps_array(commands().into_iter()),
+        ps_array(options.iter().map(String::as_str))
+    )
+}
+
+/// `completion` command; only `powershell` is supported.
+pub fn run(shell: &str) -> Result<(), String> {
+    match shell.to_lowercase().as_str() {
+        "powershell" | "pwsh" => {
+            print!("{}", powershell());
+            Ok(())
+        }
+        _ => Err(format!("Unknown shell '{}'. Use 'powershell'.", shell)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_powershell_script() {
+        let script = powershell();
+        assert!(script.starts_with("Register-ArgumentCompleter -Native"));
+        assert!(script.contains("'sync', 'sync-all'"));
+        assert!(script.contains("'--format'"));
+        assert!(script.contains("'--tui'"));
+        // Balanced braces, or PowerShell rejects the whole block
+        assert_eq!(script.matches('{').count(), script.matches('}').count());
+    }
+}
@@ -0,0 +1,228 @@
+//! Config file `zDirComp.toml` next to the executable (no external crates).
+//!
+//! Reads the small TOML subset the tool needs: `[section]` / `[a.b]`
+//! headers, `key = value` with strings, booleans, integers and arrays of
+//! strings, and `#` comments. Named profiles live in `[profile.<name>]`
+//! sections and are selected with `--profile <name>`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file name, looked up next to the executable.
+pub const FILE_NAME: &str = "zDirComp.toml";
+
+/// A config value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Str(String),
+    Bool(bool),
+    Int(i64),
+    List(Vec<String>),
+}
+
+/// Parsed config: sections in file order, keys in section order.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    sections: Vec<(String, Vec<(String, Value)>)>,
+}
+
+impl Config {
+    /// Parse config text; errors name the offending line.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut current = String::new();
+        for (number, raw) in text.lines().enumerate() {
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |what: &str| format!("{} line {}: {}", FILE_NAME, number + 1, what);
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| error("unterminated section header"))?;
+                current = name.trim().to_lowercase();
+                if !config.sections.iter().any(|(n, _)| *n == current) {
+                    config.sections.push((current.clone(), Vec::new()));
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected 'key = value'"))?;
+            let key = key.trim().trim_matches('"').to_lowercase();
+            let value = parse_value(value.trim()).map_err(|e| error(&e))?;
+            if !config.sections.iter().any(|(n, _)| *n == current) {
+                config.sections.push((current.clone(), Vec::new()));
+            }
+            let section = config
+                .sections
+                .iter_mut()
+                .find(|(n, _)| *n == current)
+                .map(|(_, entries)| entries)
+                .ok_or_else(|| error("internal: missing section"))?;
+            section.retain(|(k, _)| *k != key);
+            section.push((key, value));
+        }
+        Ok(config)
+    }
+
+    /// Entries of a section (`""` is the top level), if present.
+    pub fn section(&self, name: &str) -> Option<&[(String, Value)]> {
+        let name = name.to_lowercase();
+        self.sections
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, entries)| entries.as_slice())
+    }
+
+    /// Entries of `[profile.<name>]`.
+    pub fn profile(&self, name: &str) -> Option<&[(String, Value)]> {
+        self.section(&format!("profile.{}", name))
+    }
+}
+
+/// Path of the config file (next to the executable).
+pub fn path() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(|d| d.join(FILE_NAME)))
+}
+
+/// Load the config file; a missing file is an empty config.
+pub fn load() -> Result<Config, String> {
+    match path() {
+        Some(path) => load_from(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+fn load_from(path: &Path) -> Result<Config, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Config::parse(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(format!("cannot read {}: {}", path.display(), e)),
+    }
+}
+
+/// Drop a `#` comment that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or("unterminated array (arrays must fit on one line)")?;
+        let mut items = Vec::new();
+        let mut rest = inner.trim();
+        while !rest.is_empty() {
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(r) => r.trim_start(),
+                None if rest.is_empty() => rest,
+                None => return Err("expected ',' between array items".to_string()),
+            };
+        }
+        return Ok(Value::List(items));
+    }
+    if text.starts_with('"') || text.starts_with('\'') {
+        let (s, rest) = parse_string(text)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after string".to_string());
+        }
+        return Ok(Value::Str(s));
+    }
+    text.parse::<i64>()
+        .map(Value::Int)
+        .map_err(|_| format!("unsupported value '{}'", text))
+}
+
+/// Parse a basic (`"..."`, with escapes) or literal (`'...'`) string at the
+/// start of `text`; returns it and the remaining text.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, q @ ('"' | '\''))) => q,
+        _ => return Err("expected a quoted string".to_string()),
+    };
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((out, &text[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, e)| e) {
+                Some('\\') => out.push('\\'),
+                Some('"') => out.push('"'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                _ => return Err("bad escape in string (use '...' for Windows paths)".to_string()),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = Config::parse(
+            r#"
+# global
+log-format = "jsonl"
+
+[profile.tv]
+root = 'E:\Online\TV'   # literal string, no escapes
+keep = ["*.jpg", 'Extras/']
+post-action = "recheck"
+threads = 4
+tui = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.section(""),
+            Some(&[("log-format".to_string(), Value::Str("jsonl".into()))][..])
+        );
+        let tv = config.profile("TV").unwrap();
+        assert_eq!(tv[0].1, Value::Str("E:\\Online\\TV".into()));
+        assert_eq!(tv[1].1, Value::List(vec!["*.jpg".into(), "Extras/".into()]));
+        assert_eq!(tv[3].1, Value::Int(4));
+        assert_eq!(tv[4].1, Value::Bool(false));
+        assert!(config.profile("movies").is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("[profile.tv").is_err());
+        assert!(Config::parse("root").is_err());
+        assert!(Config::parse(r#"root = "E:\Online""#).is_err());
+        assert!(Config::parse("keep = [\"a\" \"b\"]").is_err());
+        let e = Config::parse("\n\nx = maybe").unwrap_err();
+        assert!(e.contains("line 3"), "{}", e);
+    }
+}
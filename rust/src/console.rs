@@ -0,0 +1,173 @@
+//! Console reporting, separate from the log file.
+//!
+//! Every significant event goes through `Record::emit`, which prints a short
+//! line here and then writes the full record to the log. How much reaches the
+//! console is controlled by `--quiet` / `--verbose`:
+//! - quiet: errors only
+//! - normal (default): info and above
+//! - verbose: everything, including debug records
+//!
+//! Warnings and errors go to stderr, the rest to stdout. Level tags are
+//! colorized only when the stream is a terminal and `NO_COLOR` is unset.
+//!
+//! With `--format json` every line goes to stderr instead, and stdout
+//! carries only the run's result as one compact JSON object, so
+//! `zDirComp ... | ConvertFrom-Json` sees nothing else.
+
+use crate::json::Json;
+use crate::logger::Level;
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+/// How much console output to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Select the console verbosity for the rest of the process.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        2 => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print results as JSON on stdout (`--format json`).
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Whether results go out as JSON.
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Lowest level that is printed at the given verbosity.
+fn threshold(verbosity: Verbosity) -> Level {
+    match verbosity {
+        Verbosity::Quiet => Level::Error,
+        Verbosity::Normal => Level::Info,
+        Verbosity::Verbose => Level::Debug,
+    }
+}
+
+/// Tag and ANSI color for a level (`None` for plain info lines).
+fn tag(level: Level) -> Option<(&'static str, &'static str)> {
+    match level {
+        Level::Debug => Some(("debug", "\x1b[2m")),
+        Level::Info => None,
+        Level::Warn => Some(("warning", "\x1b[33m")),
+        Level::Error => Some(("error", "\x1b[31m")),
+    }
+}
+
+/// Build the console line for `text` at `level`.
+fn render(level: Level, text: &str, color: bool) -> String {
+    match tag(level) {
+        None => text.to_string(),
+        Some((name, ansi)) if color => format!("{}{}:\x1b[0m {}", ansi, name, text),
+        Some((name, _)) => format!("{}: {}", name, text),
+    }
+}
+
+/// Print `text` at `level`, if the current verbosity allows it.
+pub fn print(level: Level, text: &str) {
+    if level < threshold(verbosity()) {
+        return;
+    }
+    if level >= Level::Warn || JSON.load(Ordering::Relaxed) {
+        let stderr = io::stderr();
+        let color = use_color(stderr.is_terminal());
+        let _ = writeln!(stderr.lock(), "{}", render(level, text, color));
+    } else {
+        let stdout = io::stdout();
+        let color = use_color(stdout.is_terminal());
+        let _ = writeln!(stdout.lock(), "{}", render(level, text, color));
+    }
+}
+
+/// Print a run's result on stdout, with `--format json` only.
+pub fn result(json: &Json) {
+    if JSON.load(Ordering::Relaxed) {
+        let _ = writeln!(io::stdout().lock(), "{}", json);
+    }
+}
+
+/// Decide whether to emit ANSI colors on a stream.
+fn use_color(is_terminal: bool) -> bool {
+    is_terminal && std::env::var_os("NO_COLOR").is_none() && ansi()
+}
+
+/// Enable ANSI escape processing once; true if the console supports it.
+pub fn ansi() -> bool {
+    static ANSI: OnceLock<bool> = OnceLock::new();
+    *ANSI.get_or_init(enable_ansi)
+}
+
+/// Turn on virtual terminal processing so the Windows console renders ANSI
+/// escapes. Returns false if the console refuses (e.g. legacy conhost).
+fn enable_ansi() -> bool {
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    extern "system" {
+        fn GetStdHandle(nStdHandle: u32) -> *mut std::ffi::c_void;
+        fn GetConsoleMode(hConsoleHandle: *mut std::ffi::c_void, lpMode: *mut u32) -> i32;
+        fn SetConsoleMode(hConsoleHandle: *mut std::ffi::c_void, dwMode: u32) -> i32;
+    }
+
+    let mut ok = true;
+    for std_handle in [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE] {
+        unsafe {
+            let handle = GetStdHandle(std_handle);
+            let mut mode = 0u32;
+            if GetConsoleMode(handle, &mut mode) == 0 {
+                continue;
+            }
+            if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING == 0
+                && SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) == 0
+            {
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        assert_eq!(threshold(Verbosity::Quiet), Level::Error);
+        assert!(Level::Warn < threshold(Verbosity::Quiet));
+        assert!(Level::Debug < threshold(Verbosity::Normal));
+        assert!(Level::Debug >= threshold(Verbosity::Verbose));
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render(Level::Info, "done", true), "done");
+        assert_eq!(render(Level::Warn, "slow", false), "warning: slow");
+        assert_eq!(
+            render(Level::Error, "bad", true),
+            "\x1b[31merror:\x1b[0m bad"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Crash reports: a panic hook that writes `zDirComp.crash.log`.
+//!
+//! Clients run zDirComp without a console, so a panic message on stderr is
+//! lost. The report has the command line, run ID, panic message and
+//! location, a backtrace and the last log records before the crash.
+
+use crate::build_info;
+use crate::logger;
+
+use std::backtrace::Backtrace;
+use std::fmt::Write as _;
+use std::panic::{self, PanicHookInfo};
+
+/// Crash report file name, in the same places as the log.
+pub const FILE_NAME: &str = "zDirComp.crash.log";
+
+/// Install the panic hook; the default hook still runs afterwards.
+pub fn install() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        write_report(&report(info, &Backtrace::force_capture()));
+        default_hook(info);
+    }));
+}
+
+fn report(info: &PanicHookInfo<'_>, backtrace: &Backtrace) -> String {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "(non-string panic payload)".to_string());
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+        .unwrap_or_default();
+    let args: Vec<String> = std::env::args().collect();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "=== {} crash, run {}",
+        build_info::summary(),
+        logger::run_id()
+    );
+    let _ = writeln!(out, "command line: {:?}", args);
+    let _ = writeln!(out, "panic: {} at {}", message, location);
+    let _ = writeln!(out, "backtrace:\n{}", backtrace);
+    let _ = writeln!(out, "last log records:");
+    for line in logger::recent() {
+        let _ = writeln!(out, "  {}", line);
+    }
+    out
+}
+
+/// Append the report; best effort, like the log files.
+fn write_report(report: &str) {
+    logger::append_first(&logger::log_paths(FILE_NAME), &format!("{}\n", report));
+}
@@ -0,0 +1,170 @@
+//! Metadata of a directory tree, collected once and shared by later steps.
+//!
+//! `DirIndex::build` walks the tree a single time and keeps each entry's
+//! size, write time and attributes, which the walk gets with the listing
+//! (see `walk`). Planning, sorting, the delete-share check and verify then
+//! look entries up here instead of asking the file system per file. Lookups
+//! ignore case, as NTFS does. An index that could not be built in full
+//! (`partial`) answers misses from the file system.
+
+use crate::paths;
+use crate::walk::Walker;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What the index knows of one file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meta {
+    pub is_dir: bool,
+    /// Size in bytes; 0 for directories.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Win32 file attributes, if the walk returned them.
+    pub attributes: Option<u32>,
+}
+
+impl Meta {
+    fn from_metadata(metadata: &fs::Metadata) -> Meta {
+        Meta {
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+            attributes: None,
+        }
+    }
+}
+
+/// Entries under one root, keyed by their path relative to it.
+#[derive(Debug, Clone)]
+pub struct DirIndex {
+    root: PathBuf,
+    /// In walk order: children before parents.
+    entries: Vec<(PathBuf, Meta)>,
+    by_key: HashMap<String, usize>,
+    complete: bool,
+}
+
+fn key(relative: &Path) -> String {
+    relative.to_string_lossy().to_lowercase()
+}
+
+impl DirIndex {
+    fn new(root: &Path, complete: bool) -> DirIndex {
+        DirIndex {
+            root: root.to_path_buf(),
+            entries: Vec::new(),
+            by_key: HashMap::new(),
+            complete,
+        }
+    }
+
+    fn insert(&mut self, relative: PathBuf, meta: Meta) {
+        self.by_key.insert(key(&relative), self.entries.len());
+        self.entries.push((relative, meta));
+    }
+
+    /// Index everything under `root/scope`, within the walk limits. Entry
+    /// paths are compared in long form, so `root` may be an 8.3 short name;
+    /// an entry that still cannot be placed makes the index partial.
+    pub fn build(root: &Path, scope: &Path) -> Result<DirIndex, String> {
+        let mut index = DirIndex::new(root, true);
+        let long_root = paths::long_path(root);
+        Walker::new(&root.join(scope)).on_entry(|entry| {
+            let Ok(relative) = paths::long_path(&entry.path)
+                .strip_prefix(&long_root)
+                .map(Path::to_path_buf)
+            else {
+                index.complete = false;
+                return Ok(());
+            };
+            let meta = Meta {
+                is_dir: entry.is_dir,
+                size: entry.size,
+                modified: entry.modified,
+                attributes: entry.attributes,
+            };
+            index.insert(relative, meta);
+            Ok(())
+        })?;
+        Ok(index)
+    }
+
+    /// An empty index whose lookups all go to the file system, for when
+    /// `build` failed.
+    #[cfg(any(feature = "verify", test))]
+    pub fn partial(root: &Path) -> DirIndex {
+        DirIndex::new(root, false)
+    }
+
+    /// Whether the whole tree was indexed.
+    #[cfg(feature = "verify")]
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Metadata of `relative`; `None` if it does not exist.
+    pub fn get(&self, relative: &Path) -> Option<Meta> {
+        match self.by_key.get(&key(relative)) {
+            Some(&i) => Some(self.entries[i].1),
+            None if self.complete => None,
+            None => fs::metadata(self.root.join(relative))
+                .ok()
+                .map(|m| Meta::from_metadata(&m)),
+        }
+    }
+
+    /// Files and directories, relative to the root, in walk order.
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, &Meta)> {
+        self.entries
+            .iter()
+            .map(|(relative, meta)| (relative.as_path(), meta))
+    }
+
+    /// Files only, in walk order.
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &Meta)> {
+        self.entries().filter(|(_, meta)| !meta.is_dir)
+    }
+
+    /// Whether `relative` is hidden+system.
+    pub fn is_hidden_system(&self, relative: &Path) -> bool {
+        match self.get(relative).and_then(|meta| meta.attributes) {
+            Some(attributes) => paths::hidden_system(attributes),
+            None => paths::is_hidden_system(&self.root.join(relative)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_index() {
+        let root = TempDir::new("index");
+        fs::create_dir_all(root.join("Sub")).unwrap();
+        fs::write(root.join("Sub").join("a.txt"), b"abc").unwrap();
+        fs::write(root.join("b.txt"), b"x").unwrap();
+
+        let index = DirIndex::build(&root, Path::new("")).unwrap();
+        let files: Vec<(&Path, u64)> = index.files().map(|(p, m)| (p, m.size)).collect();
+        let sub_a = Path::new("Sub").join("a.txt");
+        assert_eq!(files, vec![(sub_a.as_path(), 3), (Path::new("b.txt"), 1)]);
+        assert!(index.get(Path::new("Sub")).unwrap().is_dir);
+        assert_eq!(index.get(&Path::new("SUB").join("A.TXT")).unwrap().size, 3);
+
+        // Written after the walk: only a partial index sees it
+        fs::write(root.join("c.txt"), b"xy").unwrap();
+        assert_eq!(index.get(Path::new("c.txt")), None);
+        assert_eq!(
+            DirIndex::partial(&root)
+                .get(Path::new("c.txt"))
+                .unwrap()
+                .size,
+            2
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! `--preserve-dir-times`: directory modification times as before a sync
+//! (raw FFI, no external crates).
+//!
+//! Deleting, creating or renaming an entry updates the write time of its
+//! directory. Media managers that sort folders by that time ("recently
+//! added" in Plex, Jellyfin, Kodi) then show a cleaned-up season as new.
+//! `DirTimes::capture` records the time of every directory under a root
+//! before sync touches it; `restore` puts back those that changed. Folders
+//! sync removed are gone and ones it created have no earlier time; the
+//! trash and kept folders are left alone.
+
+use crate::paths;
+use crate::policy;
+use crate::walk::Walker;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const FILE_WRITE_ATTRIBUTES: DWORD = 0x100;
+const FILE_SHARE_ALL: DWORD = 0x7;
+const OPEN_EXISTING: DWORD = 3;
+const FILE_FLAG_BACKUP_SEMANTICS: DWORD = 0x0200_0000;
+/// 100 ns ticks from 1601-01-01 (FILETIME) to the Unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+extern "system" {
+    fn CreateFileW(
+        lpFileName: *const u16,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: *const std::ffi::c_void,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn SetFileTime(
+        hFile: HANDLE,
+        lpCreationTime: *const [DWORD; 2],
+        lpLastAccessTime: *const [DWORD; 2],
+        lpLastWriteTime: *const [DWORD; 2],
+    ) -> i32;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// `time` as a FILETIME (low, high); `None` before 1970.
+fn filetime(time: SystemTime) -> Option<[DWORD; 2]> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    let ticks = since_epoch.as_secs() * 10_000_000
+        + u64::from(since_epoch.subsec_nanos() / 100)
+        + FILETIME_UNIX_EPOCH;
+    Some([ticks as DWORD, (ticks >> 32) as DWORD])
+}
+
+/// Set the last write time of `path`, a file or a directory. Needs only
+/// attribute access, so files a client holds open can be set too.
+pub fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    let Some(time) = filetime(time) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "time before 1970",
+        ));
+    };
+    let wide = paths::to_wide(path);
+    unsafe {
+        let handle = CreateFileW(
+            wide.as_ptr(),
+            FILE_WRITE_ATTRIBUTES,
+            FILE_SHARE_ALL,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let ok = SetFileTime(handle, std::ptr::null(), std::ptr::null(), &time) != 0;
+        let error = io::Error::last_os_error();
+        CloseHandle(handle);
+        if ok {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Modification times of the directories of one tree.
+#[derive(Debug, Clone, Default)]
+pub struct DirTimes {
+    times: Vec<(PathBuf, SystemTime)>,
+}
+
+/// What `DirTimes::restore` did.
+#[derive(Debug, Default)]
+pub struct Restored {
+    /// Directories whose time was put back.
+    pub restored: usize,
+    /// Directories whose time could not be put back, with the error.
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+impl DirTimes {
+    /// Record the times of `root` and the directories below it, junctions
+    /// not followed.
+    pub fn capture(root: &Path) -> DirTimes {
+        let mut times = Vec::new();
+        // A walk stopped by the limits still leaves what it recorded
+        let _ = Walker::new(root).skip_reparse_points().on_entry(|entry| {
+            let internal = entry.path.strip_prefix(root).is_ok_and(policy::is_internal);
+            if let Some(modified) = entry.modified.filter(|_| entry.is_dir && !internal) {
+                times.push((entry.path.clone(), modified));
+            }
+            Ok(())
+        });
+        if let Ok(modified) = fs::metadata(root).and_then(|m| m.modified()) {
+            times.push((root.to_path_buf(), modified));
+        }
+        DirTimes { times }
+    }
+
+    /// Put back the times of recorded directories that still exist and
+    /// have changed.
+    pub fn restore(&self) -> Restored {
+        let mut restored = Restored::default();
+        for (path, before) in &self.times {
+            let Ok(now) = fs::metadata(path).and_then(|m| m.modified()) else {
+                continue;
+            };
+            if now == *before {
+                continue;
+            }
+            match set_modified(path, *before) {
+                Ok(()) => restored.restored += 1,
+                Err(e) => restored.errors.push((path.clone(), e)),
+            }
+        }
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use crate::trash::TRASH_DIR;
+
+    #[test]
+    fn test_capture() {
+        let dir = TempDir::new("dirtimes");
+        fs::create_dir_all(dir.join("S01").join("Subs")).unwrap();
+        fs::create_dir_all(dir.join(TRASH_DIR)).unwrap();
+        fs::write(dir.join("S01").join("a.mkv"), b"x").unwrap();
+
+        let times = DirTimes::capture(&dir);
+        let mut paths: Vec<&PathBuf> = times.times.iter().map(|(p, _)| p).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![&dir.to_path_buf(), &dir.join("S01"), &dir.join("S01").join("Subs")]
+        );
+        // Nothing changed, nothing to put back
+        let restored = times.restore();
+        assert_eq!(restored.restored, 0);
+        assert!(restored.errors.is_empty());
+
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1);
+        assert_eq!(filetime(time), Some([0xd5d7_1680, 0x019d_b1de]));
+    }
+}
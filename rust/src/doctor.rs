@@ -0,0 +1,64 @@
+//! `doctor` command: report the environment zDirComp would run in.
+//!
+//! Prints what the other commands detect on their own (config file, volume
+//! type and the I/O defaults derived from it) so a misbehaving setup can be
+//! checked without running a sync.
+
+use crate::config;
+use crate::logger::{self, Level, Record};
+use crate::volume;
+
+use std::path::Path;
+
+pub fn run(dir_path: &str) {
+    let dir = Path::new(dir_path);
+
+    match config::path() {
+        Some(path) if path.is_file() => {
+            let message = match config::load() {
+                Ok(_) => "loaded".to_string(),
+                Err(e) => e,
+            };
+            Record::new(Level::Info, "DOCTOR", dir_path, "config")
+                .path(&path)
+                .message(message)
+                .emit();
+        }
+        _ => Record::new(Level::Info, "DOCTOR", dir_path, "config")
+            .message("no config file")
+            .emit(),
+    }
+
+    match logger::check() {
+        Ok(path) => Record::new(Level::Info, "DOCTOR", dir_path, "log")
+            .path(&path)
+            .message(format!("logging to {}", path.display()))
+            .emit(),
+        Err(e) => Record::new(Level::Warn, "DOCTOR", dir_path, "log")
+            .message(e)
+            .emit(),
+    }
+
+    if !dir.is_dir() {
+        Record::new(Level::Warn, "DOCTOR", dir_path, "directory")
+            .message("directory does not exist")
+            .emit();
+    }
+
+    let kind = volume::detect(dir);
+    let root = volume::volume_root(dir).unwrap_or_else(|| "?".to_string());
+    Record::new(Level::Info, "DOCTOR", dir_path, "volume")
+        .message(match volume::free_bytes(dir) {
+            Some(free) => format!("{} volume at {}, {} MiB free", kind, root, free >> 20),
+            None => format!("{} volume at {}", kind, root),
+        })
+        .emit();
+
+    #[cfg(feature = "verify")]
+    Record::new(Level::Info, "DOCTOR", dir_path, "threads")
+        .message(format!(
+            "verify default: {} hasher thread(s)",
+            kind.hash_threads(crate::hashing::default_threads())
+        ))
+        .emit();
+}
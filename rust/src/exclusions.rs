@@ -0,0 +1,180 @@
+//! `exclusions add` / `exclusions remove`: stop the usual lockers at the
+//! source (raw FFI, no external crates).
+//!
+//! Most files unlock has to free are held by Windows Defender's real-time
+//! scan or by the Windows Search indexer. `add` registers the directory as
+//! a Defender exclusion (`Add-MpPreference -ExclusionPath`, through
+//! PowerShell; needs an elevated prompt) and sets the "not content indexed"
+//! attribute on it and everything below, which the indexer honours and new
+//! files inherit. `remove` undoes both. A drive root is refused: excluding
+//! a whole volume from the virus scanner is never what a seed folder needs.
+
+use crate::logger::{Level, Record};
+use crate::paths;
+use crate::safety;
+use crate::walk::Walker;
+
+use std::path::Path;
+use std::process::Command;
+
+const FILE_ATTRIBUTE_NOT_CONTENT_INDEXED: u32 = 0x2000;
+const INVALID_FILE_ATTRIBUTES: u32 = u32::MAX;
+
+extern "system" {
+    fn GetFileAttributesW(lpFileName: *const u16) -> u32;
+    fn SetFileAttributesW(lpFileName: *const u16, dwFileAttributes: u32) -> i32;
+}
+
+/// `s` as a PowerShell single-quoted string literal.
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Run a Defender preference cmdlet (`Add-MpPreference`,
+/// `Remove-MpPreference`) for `dir`.
+fn defender(cmdlet: &str, dir: &Path) -> Result<(), String> {
+    let script = format!(
+        "{} -ExclusionPath {} -ErrorAction Stop",
+        cmdlet,
+        ps_quote(&dir.to_string_lossy())
+    );
+    let output = Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()
+        .map_err(|e| format!("cannot run powershell.exe: {}", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr.lines().map(str::trim).find(|l| !l.is_empty());
+    Err(format!(
+        "{} failed (run from an elevated prompt?): {}",
+        cmdlet,
+        reason.unwrap_or("no details")
+    ))
+}
+
+/// Set or clear the not-content-indexed attribute on `path`; whether it
+/// changed.
+fn set_indexed(path: &Path, indexed: bool) -> Result<bool, String> {
+    let wide = paths::to_wide(path);
+    let attributes = unsafe { GetFileAttributesW(wide.as_ptr()) };
+    if attributes == INVALID_FILE_ATTRIBUTES {
+        return Err(format!("{:?}: {}", path, std::io::Error::last_os_error()));
+    }
+    let wanted = if indexed {
+        attributes & !FILE_ATTRIBUTE_NOT_CONTENT_INDEXED
+    } else {
+        attributes | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED
+    };
+    if wanted == attributes {
+        return Ok(false);
+    }
+    if unsafe { SetFileAttributesW(wide.as_ptr(), wanted) } == 0 {
+        return Err(format!("{:?}: {}", path, std::io::Error::last_os_error()));
+    }
+    Ok(true)
+}
+
+/// Set the indexing attribute on `dir` and every entry below it, junctions
+/// not followed. Returns how many changed and the failures.
+fn set_indexed_tree(dir: &Path, indexed: bool) -> (usize, Vec<String>) {
+    let mut changed = 0;
+    let mut errors = Vec::new();
+    let mut apply = |path: &Path| match set_indexed(path, indexed) {
+        Ok(true) => changed += 1,
+        Ok(false) => {}
+        Err(e) => errors.push(e),
+    };
+    apply(dir);
+    if let Err(e) = Walker::new(dir).skip_reparse_points().on_entry(|entry| {
+        apply(&entry.path);
+        Ok(())
+    }) {
+        errors.push(e);
+    }
+    (changed, errors)
+}
+
+/// Add (`exclude`) or remove the exclusions for `dir_path`. Every failure
+/// is logged; the first one is returned after both steps ran.
+fn run(dir_path: &str, exclude: bool) -> Result<(), String> {
+    let abort = |message: &str| {
+        Record::new(Level::Error, "EXCL", dir_path, "abort")
+            .message(message)
+            .emit();
+        message.to_string()
+    };
+    let dir = Path::new(dir_path);
+    if !dir.is_dir() {
+        return Err(abort("not a directory"));
+    }
+    if !safety::check_depth(dir, 2) {
+        return Err(abort("refusing a drive root, aborted"));
+    }
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let mut failed = None;
+
+    let (cmdlet, done) = if exclude {
+        (
+            "Add-MpPreference",
+            "added to the Windows Defender exclusions",
+        )
+    } else {
+        (
+            "Remove-MpPreference",
+            "removed from the Windows Defender exclusions",
+        )
+    };
+    match defender(cmdlet, &dir) {
+        Ok(()) => Record::new(Level::Info, "EXCL", dir_path, "defender")
+            .message(done)
+            .emit(),
+        Err(e) => {
+            Record::new(Level::Error, "EXCL", dir_path, "defender")
+                .message(e.as_str())
+                .emit();
+            failed.get_or_insert(e);
+        }
+    }
+
+    let (changed, errors) = set_indexed_tree(&dir, !exclude);
+    for e in &errors {
+        Record::new(Level::Warn, "EXCL", dir_path, "index")
+            .message(e.as_str())
+            .emit();
+    }
+    Record::new(Level::Info, "EXCL", dir_path, "index")
+        .message(format!(
+            "content indexing {} for {} entries, {} failed",
+            if exclude { "turned off" } else { "turned on" },
+            changed,
+            errors.len()
+        ))
+        .emit();
+    if let Some(e) = errors.into_iter().next() {
+        failed.get_or_insert(e);
+    }
+    failed.map_or(Ok(()), Err)
+}
+
+/// `exclusions add <dir>`.
+pub fn add(dir_path: &str) -> Result<(), String> {
+    run(dir_path, true)
+}
+
+/// `exclusions remove <dir>`.
+pub fn remove(dir_path: &str) -> Result<(), String> {
+    run(dir_path, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ps_quote() {
+        assert_eq!(ps_quote("E:\\Online"), "'E:\\Online'");
+        assert_eq!(ps_quote("E:\\Bob's $(x)"), "'E:\\Bob''s $(x)'");
+    }
+}
@@ -0,0 +1,132 @@
+//! `%VAR%` / `${VAR}` and client token expansion.
+//!
+//! Clients start "run on completion" programs without a shell, so
+//! environment variables arrive unexpanded. Config values can also use the
+//! tokens uTorrent and qBittorrent know (`%D` save directory, `%F` torrent
+//! file or content path, `%N` name, `%L` label/category, `%I` infohash),
+//! filled from this invocation's arguments. Unknown variables and tokens
+//! are left as they are, so a literal `%` in a file name survives; `%%` is
+//! a literal `%`. Only option and config values are expanded: positional
+//! paths are taken literally, so `100%% done` or `%TEMP%backup` folders are
+//! found as named.
+
+/// Values for the single-letter client tokens.
+#[derive(Debug, Clone, Default)]
+pub struct Tokens(Vec<(char, String)>);
+
+impl Tokens {
+    /// Set token `%<letter>` (case-insensitive).
+    pub fn set(&mut self, letter: char, value: &str) {
+        let letter = letter.to_ascii_uppercase();
+        self.0.retain(|(l, _)| *l != letter);
+        self.0.push((letter, value.to_string()));
+    }
+
+    fn get(&self, letter: char) -> Option<&str> {
+        let letter = letter.to_ascii_uppercase();
+        self.0
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Expand variables from the process environment and `tokens`.
+pub fn expand(text: &str, tokens: &Tokens) -> String {
+    expand_with(text, tokens, |name| std::env::var(name).ok())
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '(' | ')')
+}
+
+fn expand_with(text: &str, tokens: &Tokens, env: impl Fn(&str) -> Option<String>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '%' {
+            if chars.get(i + 1) == Some(&'%') {
+                out.push('%');
+                i += 2;
+                continue;
+            }
+            // %VAR% — only if the variable exists
+            let name_len = chars[i + 1..]
+                .iter()
+                .take_while(|&&c| is_var_char(c))
+                .count();
+            if name_len > 0 && chars.get(i + 1 + name_len) == Some(&'%') {
+                let name: String = chars[i + 1..i + 1 + name_len].iter().collect();
+                if let Some(value) = env(&name) {
+                    out.push_str(&value);
+                    i += name_len + 2;
+                    continue;
+                }
+            }
+            // %X client token
+            if let Some(value) = chars.get(i + 1).and_then(|&l| tokens.get(l)) {
+                out.push_str(value);
+                i += 2;
+                continue;
+            }
+        } else if c == '$' && chars.get(i + 1) == Some(&'{') {
+            if let Some(len) = chars[i + 2..].iter().position(|&c| c == '}') {
+                let name: String = chars[i + 2..i + 2 + len].iter().collect();
+                if let Some(value) = env(&name) {
+                    out.push_str(&value);
+                    i += len + 3;
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "USERPROFILE" => Some("C:\\Users\\me".to_string()),
+            "ProgramFiles(x86)" => Some("C:\\Program Files (x86)".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let t = Tokens::default();
+        assert_eq!(
+            expand_with("%USERPROFILE%\\Downloads", &t, env),
+            "C:\\Users\\me\\Downloads"
+        );
+        assert_eq!(expand_with("${USERPROFILE}/x", &t, env), "C:\\Users\\me/x");
+        assert_eq!(
+            expand_with("%ProgramFiles(x86)%", &t, env),
+            "C:\\Program Files (x86)"
+        );
+        // Unknown names and lone percent signs stay literal
+        assert_eq!(expand_with("100%Pure%Fun", &t, env), "100%Pure%Fun");
+        assert_eq!(expand_with("${NOPE} 50%", &t, env), "${NOPE} 50%");
+        assert_eq!(expand_with("a%%b", &t, env), "a%b");
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut t = Tokens::default();
+        t.set('D', "E:\\Online\\Show");
+        t.set('l', "tv");
+        assert_eq!(
+            expand_with("%D\\extras", &t, env),
+            "E:\\Online\\Show\\extras"
+        );
+        assert_eq!(expand_with("label-%L", &t, env), "label-tv");
+        assert_eq!(expand_with("%N", &t, env), "%N");
+    }
+}
@@ -0,0 +1,209 @@
+//! libtorrent `.fastresume` files (bencoded), as qBittorrent writes them.
+//!
+//! Read for what the `.torrent` cannot say about a download:
+//! - `save_path` (or qBittorrent's `qBt-savePath`): where it is saved
+//! - `file_priority`: per file in torrent order, 0 = "don't download";
+//!   the list may stop early, later files keep the default
+//! - `mapped_files`: per file, its renamed path relative to the save path
+//!   (empty = not renamed)
+//! - `pieces`: one byte per piece, bit 0 set when the client has it
+//!
+//! Priorities and renames become a `Priorities` and a `PathMap`, so sync
+//! treats them like `--priorities` and `--map` files; the bitfield tells
+//! finished downloads from unfinished ones.
+
+use crate::bencode::{self, BValue, TorrentMeta};
+use crate::pathmap::{self, PathMap};
+use crate::piecemap::PieceMap;
+use crate::priorities::Priorities;
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Parsed resume data.
+#[derive(Debug, Clone, Default)]
+pub struct Resume {
+    pub save_path: Option<String>,
+    /// `file_priority`, by file index.
+    pub priorities: Vec<i64>,
+    /// `mapped_files`, by file index; `None` where not renamed.
+    pub renamed: Vec<Option<String>>,
+    /// `pieces`, by piece index; empty when the file has no bitfield.
+    pub have: Vec<bool>,
+}
+
+impl Resume {
+    /// Parse fastresume bytes.
+    pub fn parse(data: &[u8]) -> Result<Resume, String> {
+        let (root, _) = bencode::parse(data).map_err(|e| format!("fastresume: {}", e))?;
+        let list = |key: &[u8]| {
+            root.field(key)
+                .and_then(BValue::as_list)
+                .unwrap_or_default()
+        };
+        Ok(Resume {
+            save_path: [&b"save_path"[..], b"qBt-savePath"]
+                .iter()
+                .find_map(|key| root.field(key)?.as_str_lossy())
+                .filter(|path| !path.is_empty()),
+            priorities: list(b"file_priority")
+                .iter()
+                .map(|p| p.as_int().unwrap_or(1))
+                .collect(),
+            renamed: list(b"mapped_files")
+                .iter()
+                .map(|p| p.as_str_lossy().filter(|p| !p.is_empty()))
+                .collect(),
+            have: root
+                .field(b"pieces")
+                .and_then(BValue::as_bytes)
+                .unwrap_or_default()
+                .iter()
+                .map(|b| b & 1 != 0)
+                .collect(),
+        })
+    }
+
+    /// Load a fastresume file.
+    pub fn load(path: &Path) -> Result<Resume, String> {
+        let data = fs::read(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+        Resume::parse(&data)
+    }
+
+    /// Refuse lists longer than the torrent's file list: the resume data
+    /// belongs to another torrent (or a different file order).
+    fn check_files(&self, meta: &TorrentMeta) -> Result<(), String> {
+        let files = meta.files.len();
+        if self.priorities.len() > files || self.renamed.len() > files {
+            return Err(format!(
+                "fastresume lists more files than the torrent's {}",
+                files
+            ));
+        }
+        Ok(())
+    }
+
+    /// Files set to "don't download", as a `--priorities` list.
+    pub fn file_priorities(&self, meta: &TorrentMeta) -> Result<Priorities, String> {
+        self.check_files(meta)?;
+        let entries = meta
+            .files
+            .iter()
+            .zip(&self.priorities)
+            .map(|(file, &priority)| (file.path.clone(), priority))
+            .collect();
+        Ok(Priorities::new(entries))
+    }
+
+    /// Renamed files, as a `--map` relative to the torrent's directory
+    /// (save path + name). Renames leaving that directory are refused.
+    pub fn path_map(&self, meta: &TorrentMeta) -> Result<PathMap, String> {
+        self.check_files(meta)?;
+        let mut entries = Vec::new();
+        for (file, renamed) in meta.files.iter().zip(&self.renamed) {
+            let Some(renamed) = renamed else {
+                continue;
+            };
+            let inside = strip_root(&pathmap::relative_path(renamed)?, &meta.name);
+            let to = inside.ok_or_else(|| {
+                format!(
+                    "{:?} was renamed to {:?}, outside {:?}",
+                    file.path, renamed, meta.name
+                )
+            })?;
+            entries.push((file.path.clone(), to));
+        }
+        Ok(PathMap::new(entries))
+    }
+
+    /// Whether the client has every piece of the files it downloads; true
+    /// without a bitfield.
+    pub fn complete(&self, meta: &TorrentMeta) -> bool {
+        let Some(map) = PieceMap::new(meta) else {
+            return true;
+        };
+        if self.have.is_empty() {
+            return true;
+        }
+        let wanted = |index: usize| self.priorities.get(index).is_none_or(|&p| p != 0);
+        (0..meta.files.len())
+            .filter(|&index| wanted(index))
+            .flat_map(|index| map.file_pieces(index))
+            .all(|piece| self.have.get(piece).copied().unwrap_or(false))
+    }
+}
+
+/// `path` below the torrent's root folder `name`, if it starts there.
+fn strip_root(path: &Path, name: &str) -> Option<PathBuf> {
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Normal(first)) if first.eq_ignore_ascii_case(name) => {
+            let rest: PathBuf = components.collect();
+            (!rest.as_os_str().is_empty()).then_some(rest)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta() -> TorrentMeta {
+        // Two 1-byte files sharing one 16 KiB piece, then a third in piece 2
+        bencode::torrent_meta(
+            b"d4:infod5:filesld6:lengthi1e4:pathl5:a.mkveed6:lengthi16383e4:pathl5:b.nfoee\
+              d6:lengthi1e4:pathl5:c.srteee4:name4:Show12:piece lengthi16384e\
+              6:pieces40:aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbbbbbbbbbbee",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let resume = Resume::parse(
+            b"d13:file_priorityli4ei0ee12:mapped_filesl0:13:Show/x/b2.nfoe\
+              6:pieces2:\x01\x0012:qBt-savePath5:E:\\TVe",
+        )
+        .unwrap();
+        assert_eq!(resume.save_path.as_deref(), Some("E:\\TV"));
+        assert_eq!(resume.priorities, vec![4, 0]);
+        assert_eq!(
+            resume.renamed,
+            vec![None, Some("Show/x/b2.nfo".to_string())]
+        );
+        assert_eq!(resume.have, vec![true, false]);
+
+        let meta = meta();
+        let mut files = meta.files.clone();
+        assert_eq!(resume.file_priorities(&meta).unwrap().apply(&mut files), 1);
+        assert!(files[1].skip);
+        let map = resume.path_map(&meta).unwrap();
+        assert_eq!(
+            map.map(Path::new("b.nfo")),
+            Some(Path::new("x").join("b2.nfo"))
+        );
+        // c.srt lies in the missing second piece
+        assert!(!resume.complete(&meta));
+    }
+
+    #[test]
+    fn test_refused() {
+        let meta = meta();
+        let outside = Resume {
+            renamed: vec![Some("Other/a.mkv".to_string())],
+            ..Resume::default()
+        };
+        assert!(outside.path_map(&meta).is_err());
+        let longer = Resume {
+            priorities: vec![1; 4],
+            ..Resume::default()
+        };
+        assert!(longer.file_priorities(&meta).is_err());
+        let done = Resume {
+            have: vec![true, true],
+            ..Resume::default()
+        };
+        assert!(done.complete(&meta));
+    }
+}
@@ -0,0 +1,202 @@
+//! File and folder pickers for double-click usage.
+//!
+//! When zDirComp is started from Explorer without arguments there is no
+//! command line to read, so the torrent and directory are picked with the
+//! native common dialogs and the sync is confirmed in a message box.
+//! Uses comdlg32/shell32/user32 via raw FFI (no external crates).
+
+#![allow(clippy::upper_case_acronyms)]
+
+use crate::logger::{Level, Record};
+use crate::metrics;
+use crate::paths;
+use crate::sync;
+
+use std::ffi::c_void;
+use std::ptr;
+
+type HWND = *mut c_void;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct OPENFILENAMEW {
+    lStructSize: u32,
+    hwndOwner: HWND,
+    hInstance: *mut c_void,
+    lpstrFilter: *const u16,
+    lpstrCustomFilter: *mut u16,
+    nMaxCustFilter: u32,
+    nFilterIndex: u32,
+    lpstrFile: *mut u16,
+    nMaxFile: u32,
+    lpstrFileTitle: *mut u16,
+    nMaxFileTitle: u32,
+    lpstrInitialDir: *const u16,
+    lpstrTitle: *const u16,
+    Flags: u32,
+    nFileOffset: u16,
+    nFileExtension: u16,
+    lpstrDefExt: *const u16,
+    lCustData: isize,
+    lpfnHook: *mut c_void,
+    lpTemplateName: *const u16,
+    pvReserved: *mut c_void,
+    dwReserved: u32,
+    FlagsEx: u32,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct BROWSEINFOW {
+    hwndOwner: HWND,
+    pidlRoot: *const c_void,
+    pszDisplayName: *mut u16,
+    lpszTitle: *const u16,
+    ulFlags: u32,
+    lpfn: *mut c_void,
+    lParam: isize,
+    iImage: i32,
+}
+
+#[link(name = "comdlg32")]
+extern "system" {
+    fn GetOpenFileNameW(lpofn: *mut OPENFILENAMEW) -> i32;
+}
+
+#[link(name = "shell32")]
+extern "system" {
+    fn SHBrowseForFolderW(lpbi: *mut BROWSEINFOW) -> *mut c_void;
+    fn SHGetPathFromIDListW(pidl: *const c_void, pszPath: *mut u16) -> i32;
+}
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoTaskMemFree(pv: *mut c_void);
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn MessageBoxW(hWnd: HWND, lpText: *const u16, lpCaption: *const u16, uType: u32) -> i32;
+}
+
+extern "system" {
+    fn GetConsoleProcessList(lpdwProcessList: *mut u32, dwProcessCount: u32) -> u32;
+}
+
+const OFN_NOCHANGEDIR: u32 = 0x0000_0008;
+const OFN_PATHMUSTEXIST: u32 = 0x0000_0800;
+const OFN_FILEMUSTEXIST: u32 = 0x0000_1000;
+const OFN_EXPLORER: u32 = 0x0008_0000;
+const BIF_RETURNONLYFSDIRS: u32 = 0x0001;
+const MB_YESNO: u32 = 0x0004;
+const MB_ICONWARNING: u32 = 0x0030;
+const MB_ICONINFORMATION: u32 = 0x0040;
+const MB_DEFBUTTON2: u32 = 0x0100;
+const IDYES: i32 = 6;
+
+const CAPTION: &str = "zDirComp";
+
+/// UTF-16 buffer up to the first NUL.
+fn from_wide(buf: &[u16]) -> String {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+/// True if the console was created for this process alone, i.e. the exe
+/// was double-clicked rather than run from a shell.
+pub fn launched_from_explorer() -> bool {
+    let mut pids = [0u32; 2];
+    unsafe { GetConsoleProcessList(pids.as_mut_ptr(), pids.len() as u32) == 1 }
+}
+
+fn pick_torrent() -> Option<String> {
+    // Filter pairs are NUL-separated and end with a double NUL
+    let filter = paths::to_wide("Torrent files\0*.torrent\0All files\0*.*\0");
+    let title = paths::to_wide("Select the .torrent file");
+    let mut file = vec![0u16; 32768];
+    let mut ofn: OPENFILENAMEW = unsafe { std::mem::zeroed() };
+    ofn.lStructSize = std::mem::size_of::<OPENFILENAMEW>() as u32;
+    ofn.lpstrFilter = filter.as_ptr();
+    ofn.nFilterIndex = 1;
+    ofn.lpstrFile = file.as_mut_ptr();
+    ofn.nMaxFile = file.len() as u32;
+    ofn.lpstrTitle = title.as_ptr();
+    ofn.Flags = OFN_EXPLORER | OFN_FILEMUSTEXIST | OFN_PATHMUSTEXIST | OFN_NOCHANGEDIR;
+    if unsafe { GetOpenFileNameW(&mut ofn) } == 0 {
+        return None;
+    }
+    Some(from_wide(&file))
+}
+
+fn pick_folder() -> Option<String> {
+    let title = paths::to_wide("Select the download folder to clean up");
+    let mut display = [0u16; 260];
+    let mut info = BROWSEINFOW {
+        hwndOwner: ptr::null_mut(),
+        pidlRoot: ptr::null(),
+        pszDisplayName: display.as_mut_ptr(),
+        lpszTitle: title.as_ptr(),
+        ulFlags: BIF_RETURNONLYFSDIRS,
+        lpfn: ptr::null_mut(),
+        lParam: 0,
+        iImage: 0,
+    };
+    unsafe {
+        let pidl = SHBrowseForFolderW(&mut info);
+        if pidl.is_null() {
+            return None;
+        }
+        let mut path = [0u16; 260];
+        let ok = SHGetPathFromIDListW(pidl, path.as_mut_ptr()) != 0;
+        CoTaskMemFree(pidl);
+        ok.then(|| from_wide(&path))
+    }
+}
+
+fn message(text: &str, flags: u32) -> i32 {
+    let text = paths::to_wide(text);
+    let caption = paths::to_wide(CAPTION);
+    unsafe { MessageBoxW(ptr::null_mut(), text.as_ptr(), caption.as_ptr(), flags) }
+}
+
+/// Pick a torrent and a folder, confirm, then run sync with default options.
+pub fn run() {
+    let Some(torrent) = pick_torrent() else {
+        return;
+    };
+    let Some(dir) = pick_folder() else {
+        return;
+    };
+    let prompt = format!(
+        "Delete every file in\n\n{}\n\nthat is not part of\n\n{}\n\nContinue?",
+        dir, torrent
+    );
+    if message(&prompt, MB_YESNO | MB_ICONWARNING | MB_DEFBUTTON2) != IDYES {
+        Record::new(Level::Info, "SYNC", &dir, "abort")
+            .message("confirmation declined, nothing deleted")
+            .write();
+        return;
+    }
+    let result = sync::run(&torrent, &dir, &sync::Options::default());
+    metrics::record_sync(&result);
+    match result {
+        Ok(_) => message(
+            "Sync finished. See the log file for details.",
+            MB_ICONINFORMATION,
+        ),
+        Err(e) => message(&format!("Sync aborted: {}", e), MB_ICONWARNING),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wide_roundtrip() {
+        let w = paths::to_wide("E:\\Online\\é");
+        assert_eq!(w.last(), Some(&0));
+        assert_eq!(from_wide(&w), "E:\\Online\\é");
+        assert_eq!(from_wide(&[0x41, 0, 0x42]), "A");
+    }
+}
@@ -0,0 +1,226 @@
+//! Open file handles of other processes (raw FFI, no external crates).
+//!
+//! Restart Manager only says *which* processes lock files; `unlock
+//! --who-details` also shows *which* files. The system handle table
+//! (`NtQuerySystemInformation`) lists every handle; each one owned by a
+//! locking process is duplicated into this process and, if it is a disk
+//! file, resolved with `GetFinalPathNameByHandleW`. Other handle types are
+//! skipped before any name query, since querying a pipe can block.
+
+use std::path::{Path, PathBuf};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const SYSTEM_EXTENDED_HANDLE_INFORMATION: i32 = 64;
+const STATUS_INFO_LENGTH_MISMATCH: i32 = 0xC000_0004_u32 as i32;
+const PROCESS_DUP_HANDLE: DWORD = 0x0040;
+const DUPLICATE_SAME_ACCESS: DWORD = 0x2;
+const FILE_TYPE_DISK: DWORD = 1;
+/// Pseudo handle returned by `GetCurrentProcess`.
+const CURRENT_PROCESS: HANDLE = -1isize as HANDLE;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct SYSTEM_HANDLE_TABLE_ENTRY_INFO_EX {
+    Object: *mut std::ffi::c_void,
+    UniqueProcessId: usize,
+    HandleValue: usize,
+    GrantedAccess: u32,
+    CreatorBackTraceIndex: u16,
+    ObjectTypeIndex: u16,
+    HandleAttributes: u32,
+    Reserved: u32,
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQuerySystemInformation(
+        SystemInformationClass: i32,
+        SystemInformation: *mut std::ffi::c_void,
+        SystemInformationLength: u32,
+        ReturnLength: *mut u32,
+    ) -> i32;
+}
+
+extern "system" {
+    fn OpenProcess(dwDesiredAccess: DWORD, bInheritHandle: i32, dwProcessId: DWORD) -> HANDLE;
+    fn DuplicateHandle(
+        hSourceProcessHandle: HANDLE,
+        hSourceHandle: HANDLE,
+        hTargetProcessHandle: HANDLE,
+        lpTargetHandle: *mut HANDLE,
+        dwDesiredAccess: DWORD,
+        bInheritHandle: i32,
+        dwOptions: DWORD,
+    ) -> i32;
+    fn GetFileType(hFile: HANDLE) -> DWORD;
+    fn GetFinalPathNameByHandleW(
+        hFile: HANDLE,
+        lpszFilePath: *mut u16,
+        cchFilePath: DWORD,
+        dwFlags: DWORD,
+    ) -> DWORD;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// A file opened by another process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenFile {
+    pub pid: u32,
+    pub path: PathBuf,
+}
+
+/// Snapshot of the system handle table: (pid, handle value) pairs.
+fn handle_table() -> Result<Vec<(u32, usize)>, String> {
+    // usize elements keep the buffer aligned for the entry structs
+    let mut buf: Vec<usize> = vec![0; 1 << 16];
+    loop {
+        let bytes = (buf.len() * std::mem::size_of::<usize>()) as u32;
+        let mut needed = 0u32;
+        let status = unsafe {
+            NtQuerySystemInformation(
+                SYSTEM_EXTENDED_HANDLE_INFORMATION,
+                buf.as_mut_ptr() as *mut std::ffi::c_void,
+                bytes,
+                &mut needed,
+            )
+        };
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            // The table grows between calls; leave some headroom
+            let words = (needed as usize).max(bytes as usize * 2) / std::mem::size_of::<usize>();
+            buf = vec![0; words + words / 4];
+            continue;
+        }
+        if status < 0 {
+            return Err(format!(
+                "NtQuerySystemInformation failed (status {:#010X})",
+                status as u32
+            ));
+        }
+        // Header: NumberOfHandles, Reserved; then the entries
+        let count = buf[0];
+        let max = (buf.len() - 2) * std::mem::size_of::<usize>()
+            / std::mem::size_of::<SYSTEM_HANDLE_TABLE_ENTRY_INFO_EX>();
+        let entries = unsafe {
+            std::slice::from_raw_parts(
+                buf.as_ptr().add(2) as *const SYSTEM_HANDLE_TABLE_ENTRY_INFO_EX,
+                count.min(max),
+            )
+        };
+        return Ok(entries
+            .iter()
+            .map(|e| (e.UniqueProcessId as u32, e.HandleValue))
+            .collect());
+    }
+}
+
+/// Path of a disk file handle owned by `process`, if it is one.
+fn file_path(process: HANDLE, handle: usize) -> Option<PathBuf> {
+    unsafe {
+        let mut dup: HANDLE = std::ptr::null_mut();
+        let ok = DuplicateHandle(
+            process,
+            handle as HANDLE,
+            CURRENT_PROCESS,
+            &mut dup,
+            0,
+            0,
+            DUPLICATE_SAME_ACCESS,
+        );
+        if ok == 0 {
+            return None;
+        }
+        let mut path = None;
+        if GetFileType(dup) == FILE_TYPE_DISK {
+            let mut buf = [0u16; 1024];
+            let len = GetFinalPathNameByHandleW(dup, buf.as_mut_ptr(), buf.len() as DWORD, 0);
+            if len > 0 && (len as usize) < buf.len() {
+                let text = String::from_utf16_lossy(&buf[..len as usize]);
+                path = Some(PathBuf::from(strip_verbatim(&text)));
+            }
+        }
+        CloseHandle(dup);
+        path
+    }
+}
+
+/// `\\?\E:\x` → `E:\x`, `\\?\UNC\server\share` → `\\server\share`.
+fn strip_verbatim(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix("\\\\?\\UNC\\") {
+        format!("\\\\{}", rest)
+    } else {
+        path.strip_prefix("\\\\?\\").unwrap_or(path).to_string()
+    }
+}
+
+/// Disk files opened by any of `pids`. Processes that cannot be opened
+/// for handle duplication (other users, protected) are skipped.
+pub fn open_files(pids: &[u32]) -> Result<Vec<OpenFile>, String> {
+    let table = handle_table()?;
+    let mut files = Vec::new();
+    for &pid in pids {
+        let process = unsafe { OpenProcess(PROCESS_DUP_HANDLE, 0, pid) };
+        if process.is_null() {
+            continue;
+        }
+        for &(_, handle) in table.iter().filter(|(owner, _)| *owner == pid) {
+            if let Some(path) = file_path(process, handle) {
+                files.push(OpenFile { pid, path });
+            }
+        }
+        unsafe { CloseHandle(process) };
+    }
+    Ok(files)
+}
+
+/// `path` relative to `dir`, compared case-insensitively as Windows does.
+pub fn relative_to(path: &Path, dir: &Path) -> Option<PathBuf> {
+    let path = path.to_string_lossy().replace('/', "\\");
+    let dir = dir.to_string_lossy().replace('/', "\\");
+    let dir = dir.trim_end_matches('\\');
+    let mut chars = path.char_indices();
+    for d in dir.chars() {
+        let (_, p) = chars.next()?;
+        if !p.to_lowercase().eq(d.to_lowercase()) {
+            return None;
+        }
+    }
+    match chars.next() {
+        Some((i, '\\')) if i + 1 < path.len() => Some(PathBuf::from(&path[i + 1..])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_verbatim() {
+        assert_eq!(
+            strip_verbatim("\\\\?\\E:\\Online\\a.mkv"),
+            "E:\\Online\\a.mkv"
+        );
+        assert_eq!(
+            strip_verbatim("\\\\?\\UNC\\nas\\tv\\a.mkv"),
+            "\\\\nas\\tv\\a.mkv"
+        );
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let dir = Path::new("E:\\Online\\Show\\");
+        assert_eq!(
+            relative_to(Path::new("e:\\online\\show\\S01\\a.mkv"), dir),
+            Some(PathBuf::from("S01\\a.mkv"))
+        );
+        assert_eq!(
+            relative_to(Path::new("E:\\Online\\Shows\\a.mkv"), dir),
+            None
+        );
+        assert_eq!(relative_to(Path::new("E:\\Online\\Show"), dir), None);
+    }
+}
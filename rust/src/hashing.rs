@@ -0,0 +1,203 @@
+//! Multi-threaded piece hashing pipeline.
+//!
+//! ```text
+//! reader ──(bounded channel of pieces)──► N hashers ──► collector
+//! ```
+//!
+//! - One reader thread per disk streams piece-sized buffers in piece order.
+//!   Reading sequentially keeps HDDs from seeking between threads.
+//! - N hasher threads pull buffers from the shared channel and digest them.
+//! - The collector (the calling thread) stores each digest at its piece
+//!   index, so results come back in order regardless of which hasher ran.
+//!
+//! The channel is bounded to a few buffers per hasher so memory stays at
+//! roughly `threads * 2 * piece_length` even on slow CPUs.
+
+use crate::bencode::TorrentMeta;
+use crate::piecemap::PieceMap;
+use crate::resources;
+use crate::sha;
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Digest used for pieces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// BitTorrent v1 piece hashes.
+    Sha1,
+}
+
+impl Algorithm {
+    /// Digest `data`.
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Sha1 => sha::sha1(data).to_vec(),
+        }
+    }
+}
+
+/// Default number of hasher threads: one per logical CPU, or per CPU
+/// allowed by `--cpu-affinity`.
+pub fn default_threads() -> usize {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    resources::cpus().map_or(cpus, |allowed| allowed.min(cpus))
+}
+
+/// Reads piece data from the files under `dir`, keeping the last file open.
+struct PieceReader<'a> {
+    dir: &'a Path,
+    meta: &'a TorrentMeta,
+    open: Option<(usize, File)>,
+}
+
+impl PieceReader<'_> {
+    /// Read piece `piece`. Returns `None` if any of its bytes are missing
+    /// (file absent, too short, or unreadable).
+    fn read(&mut self, map: &PieceMap, piece: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; map.piece_size(piece) as usize];
+        let mut pos = 0usize;
+        for span in map.spans(piece) {
+            let file = self.file(span.file)?;
+            file.seek(SeekFrom::Start(span.offset)).ok()?;
+            let end = pos + span.length as usize;
+            file.read_exact(&mut buf[pos..end]).ok()?;
+            pos = end;
+        }
+        Some(buf)
+    }
+
+    fn file(&mut self, index: usize) -> Option<&mut File> {
+        if self.open.as_ref().map(|(i, _)| *i) != Some(index) {
+            let file = File::open(self.dir.join(&self.meta.files[index].path)).ok()?;
+            self.open = Some((index, file));
+        }
+        self.open.as_mut().map(|(_, f)| f)
+    }
+}
+
+/// Hash the pieces of `meta` selected in `selected` from the files under
+/// `dir`.
+///
+/// Returns one entry per piece in piece order: the digest, or `None` if the
+/// piece was not selected or could not be read completely.
+pub fn hash_pieces(
+    dir: &Path,
+    meta: &TorrentMeta,
+    map: &PieceMap,
+    algorithm: Algorithm,
+    threads: usize,
+    selected: &[bool],
+) -> Vec<Option<Vec<u8>>> {
+    let threads = threads.max(1);
+    let count = map.piece_count();
+    let mut results: Vec<Option<Vec<u8>>> = vec![None; count];
+
+    thread::scope(|scope| {
+        let (piece_tx, piece_rx) = mpsc::sync_channel::<(usize, Option<Vec<u8>>)>(threads * 2);
+        let (digest_tx, digest_rx) = mpsc::channel::<(usize, Option<Vec<u8>>)>();
+        let piece_rx = Arc::new(Mutex::new(piece_rx));
+
+        // Reader: stream pieces in order
+        scope.spawn(move || {
+            let mut reader = PieceReader {
+                dir,
+                meta,
+                open: None,
+            };
+            for piece in (0..count).filter(|&p| selected[p]) {
+                if piece_tx.send((piece, reader.read(map, piece))).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Hashers
+        for _ in 0..threads {
+            let piece_rx = Arc::clone(&piece_rx);
+            let digest_tx = digest_tx.clone();
+            scope.spawn(move || loop {
+                let job = piece_rx.lock().map(|rx| rx.recv());
+                let Ok(Ok((piece, data))) = job else { break };
+                let digest = data.map(|d| algorithm.digest(&d));
+                if digest_tx.send((piece, digest)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(digest_tx);
+
+        // Collector: place digests by index
+        for (piece, digest) in digest_rx {
+            results[piece] = digest;
+        }
+    });
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use crate::bencode::TorrentFile;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_pipeline_matches_single_threaded() {
+        let dir = TempDir::new("hash");
+        let a: Vec<u8> = (0..100u8).collect();
+        let b: Vec<u8> = (100..=255u8).collect();
+        fs::write(dir.join("a"), &a).unwrap();
+        fs::write(dir.join("b"), &b).unwrap();
+
+        let meta = TorrentMeta {
+            name: "t".to_string(),
+            files: vec![
+                TorrentFile {
+                    path: PathBuf::from("a"),
+                    length: 100,
+                    skip: false,
+                },
+                TorrentFile {
+                    path: PathBuf::from("missing"),
+                    length: 0,
+                    skip: false,
+                },
+                TorrentFile {
+                    path: PathBuf::from("b"),
+                    length: 156,
+                    skip: false,
+                },
+            ],
+            piece_length: 64,
+            pieces: 4,
+            total_size: 256,
+            ..Default::default()
+        };
+        let map = PieceMap::new(&meta).unwrap();
+        let all: Vec<u8> = a.iter().chain(&b).copied().collect();
+
+        let digests = hash_pieces(&dir, &meta, &map, Algorithm::Sha1, 3, &[true; 4]);
+        assert_eq!(digests.len(), 4);
+        for (i, digest) in digests.iter().enumerate() {
+            let expected = sha::sha1(&all[i * 64..(i + 1) * 64]).to_vec();
+            assert_eq!(digest.as_ref(), Some(&expected));
+        }
+
+        fs::write(dir.join("b"), &b[..10]).unwrap();
+        let digests = hash_pieces(&dir, &meta, &map, Algorithm::Sha1, 2, &[true; 4]);
+        assert!(digests[0].is_some());
+        assert!(digests[1..].iter().all(Option::is_none));
+
+        // Pieces not selected are not read
+        let selected = [false, true, true, true];
+        let digests = hash_pieces(&dir, &meta, &map, Algorithm::Sha1, 2, &selected);
+        assert!(digests.iter().all(Option::is_none));
+    }
+}
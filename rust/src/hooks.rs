@@ -0,0 +1,152 @@
+//! `--pre-delete-hook` and `--post-run-hook`: user commands sync consults.
+//!
+//! A hook gets a JSON object on stdin: the deletion plan of a root before
+//! anything in it is deleted, or the summary of a completed run. Exit code 0
+//! lets the run go on. Any other code, or a hook that cannot be started,
+//! vetoes it: the plan is not deleted, or the run ends as failed. The
+//! command line is split like a Windows one (double quotes group words) and
+//! run without a shell; `.bat` and `.cmd` files work, other shell syntax
+//! needs `cmd /c`. Whatever the hook prints is logged. A hook still
+//! running after `--hook-timeout` (5 minutes by default) is killed, which
+//! vetoes like a refusal.
+//!
+//! unlock runs `--approve-kill-hook` the same way for each `ask` process it
+//! cannot ask about at a console, `--kill-tree` descendants included (their
+//! input has a `parent` PID); exit code 0 lets it be terminated.
+
+use crate::json::Json;
+use crate::logger::{Level, Record};
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(300);
+
+/// Set how long a hook may run, for the rest of the process.
+pub fn set_timeout(timeout: Duration) {
+    TIMEOUT_SECS.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+/// Read a child's output pipe to the end on its own thread.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.map(|mut pipe| pipe.read_to_end(&mut buf));
+        buf
+    })
+}
+
+/// Split a command line into words; double quotes group and are removed.
+fn split(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let (mut quoted, mut started) = (false, false);
+    for c in command.chars() {
+        match c {
+            '"' => (quoted, started) = (!quoted, true),
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    words.push(std::mem::take(&mut word));
+                }
+                started = false;
+            }
+            c => {
+                word.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        words.push(word);
+    }
+    words
+}
+
+/// Run hook `name` (`pre-delete`, `post-run`) with `input` on stdin. `Err`
+/// holds why the run is vetoed.
+pub fn run(name: &str, command: &str, target: &str, input: &Json) -> Result<(), String> {
+    let words = split(command);
+    let Some((program, args)) = words.split_first() else {
+        return Err(format!("{} hook is empty", name));
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot start {} hook {:?}: {}", name, program, e))?;
+
+    // Written from a thread: a hook filling its output pipe before reading
+    // all input would otherwise block both sides
+    let stdin = child.stdin.take();
+    let payload = format!("{}\n", input);
+    let writer = thread::spawn(move || {
+        // A hook that ignores its input closes the pipe early; that is fine
+        let _ = stdin.map(|mut stdin| stdin.write_all(payload.as_bytes()));
+    });
+    let (stdout, stderr) = (drain(child.stdout.take()), drain(child.stderr.take()));
+    let timeout = Duration::from_secs(TIMEOUT_SECS.load(Ordering::Relaxed));
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                // Its pipes may stay open in grandchildren; the readers are left behind
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "{} hook {:?} still running after {}s, killed",
+                    name,
+                    program,
+                    timeout.as_secs()
+                ));
+            }
+            Err(e) => return Err(format!("{} hook {:?} failed: {}", name, program, e)),
+        }
+    };
+    let _ = writer.join();
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    let stderr = String::from_utf8_lossy(&stderr);
+    for line in String::from_utf8_lossy(&stdout)
+        .lines()
+        .chain(stderr.lines())
+    {
+        if !line.trim().is_empty() {
+            Record::new(Level::Info, "HOOK", target, name)
+                .message(line.trim())
+                .emit();
+        }
+    }
+    if status.success() {
+        return Ok(());
+    }
+    let status = match status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => "no exit code".to_string(),
+    };
+    Err(match stderr.lines().rfind(|l| !l.trim().is_empty()) {
+        Some(reason) => format!("{} hook refused ({}): {}", name, status, reason.trim()),
+        None => format!("{} hook refused ({})", name, status),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split(r#""C:\My Hooks\check.bat" --db  "a b"c """#),
+            vec![r"C:\My Hooks\check.bat", "--db", "a bc", ""]
+        );
+        assert!(split("  ").is_empty());
+    }
+}
@@ -0,0 +1,558 @@
+//! Minimal HTTP/1.1 client (no external crates).
+//!
+//! Enough for torrent client WebUIs and webhooks:
+//! - `http://` goes over `std::net`, one request per connection
+//!   (`Connection: close`), with `Content-Length` or chunked response bodies.
+//! - `https://` goes through WinHTTP (system DLL, uses the Windows
+//!   certificate store and proxy settings). Built only with the `https`
+//!   feature, which is on by default.
+//!
+//! Timeouts apply to connect, send and receive separately and are set once
+//! at startup with `--http-timeout`.
+
+#[cfg(feature = "https")]
+use crate::paths;
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(10);
+
+/// Set the per-phase timeout for the rest of the process.
+pub fn set_timeout(timeout: Duration) {
+    TIMEOUT_SECS.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+fn timeout() -> Duration {
+    Duration::from_secs(TIMEOUT_SECS.load(Ordering::Relaxed))
+}
+
+/// A parsed `http[s]://host[:port]/path` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    /// `https://` (TLS via WinHTTP).
+    pub secure: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub path: String,
+}
+
+impl Url {
+    /// Parse an `http://` or `https://` URL.
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            return Err(format!(
+                "unsupported URL '{}' (use http:// or https://)",
+                url
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((h, p)) => (
+                h,
+                p.parse::<u16>()
+                    .map_err(|_| format!("bad port in URL '{}'", url))?,
+            ),
+            None => (authority, if secure { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL '{}'", url));
+        }
+        Ok(Url {
+            secure,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// `scheme://host:port`, e.g. for `Referer` / `Origin` headers.
+    pub fn origin(&self) -> String {
+        let scheme = if self.secure { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, self.host, self.port)
+    }
+
+    /// Same host and port, different path.
+    pub fn with_path(&self, path: &str) -> Url {
+        Url {
+            path: path.to_string(),
+            ..self.clone()
+        }
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// First header value with this name (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// All values of a header (e.g. several `Set-Cookie`).
+    pub fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Body as UTF-8 (lossy).
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// GET `url`.
+pub fn get(url: &Url, headers: &[(&str, &str)]) -> Result<Response, String> {
+    request("GET", url, headers, b"")
+}
+
+/// GET `url`, failing once the body passes `max_body` bytes.
+pub fn get_limited(
+    url: &Url,
+    headers: &[(&str, &str)],
+    max_body: usize,
+) -> Result<Response, String> {
+    send("GET", url, headers, b"", max_body)
+}
+
+/// POST `body` to `url`.
+pub fn post(url: &Url, headers: &[(&str, &str)], body: &[u8]) -> Result<Response, String> {
+    request("POST", url, headers, body)
+}
+
+/// Send a request and read the whole response.
+pub fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, String> {
+    send(method, url, headers, body, usize::MAX)
+}
+
+fn too_large(url: &Url, max_body: usize) -> String {
+    format!("response from {} is larger than {} bytes", url.host, max_body)
+}
+
+fn send(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_body: usize,
+) -> Result<Response, String> {
+    if url.secure {
+        return https_request(method, url, headers, body, max_body);
+    }
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", url.host, e))?
+        .next()
+        .ok_or_else(|| format!("cannot resolve {}", url.host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout())
+        .map_err(|e| format!("cannot connect to {}:{}: {}", url.host, url.port, e))?;
+    let _ = stream.set_read_timeout(Some(timeout()));
+    let _ = stream.set_write_timeout(Some(timeout()));
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        url.path,
+        url.host,
+        url.port,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|()| stream.write_all(body))
+        .map_err(|e| format!("request to {} failed: {}", url.host, e))?;
+
+    // Room for the headers on top of the body limit
+    let mut raw = Vec::new();
+    (&mut stream)
+        .take((max_body as u64).saturating_add(64 * 1024))
+        .read_to_end(&mut raw)
+        .map_err(|e| format!("response from {} failed: {}", url.host, e))?;
+    let response = parse_response(&raw)?;
+    if response.body.len() > max_body {
+        return Err(too_large(url, max_body));
+    }
+    Ok(response)
+}
+
+/// Split a raw response into status, headers and (de-chunked) body.
+fn parse_response(raw: &[u8]) -> Result<Response, String> {
+    let split = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed HTTP response")?;
+    let mut response = parse_head(&String::from_utf8_lossy(&raw[..split]))?;
+    response.body = raw[split + 4..].to_vec();
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        response.body = dechunk(&response.body)?;
+    }
+    Ok(response)
+}
+
+/// Status line and headers (CRLF-separated) into a body-less response.
+fn parse_head(head: &str) -> Result<Response, String> {
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or("malformed HTTP status line")?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+    Ok(Response {
+        status,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+#[cfg(not(feature = "https"))]
+fn https_request(
+    _: &str,
+    url: &Url,
+    _: &[(&str, &str)],
+    _: &[u8],
+    _: usize,
+) -> Result<Response, String> {
+    Err(format!(
+        "https://{} requested but this build has no HTTPS support (feature 'https')",
+        url.host
+    ))
+}
+
+/// HTTPS through WinHTTP, which handles TLS, redirects, proxies and chunking.
+#[cfg(feature = "https")]
+fn https_request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    max_body: usize,
+) -> Result<Response, String> {
+    use std::ffi::c_void;
+
+    type Handle = *mut c_void;
+
+    const WINHTTP_ACCESS_TYPE_DEFAULT_PROXY: u32 = 0;
+    const WINHTTP_FLAG_SECURE: u32 = 0x0080_0000;
+    const WINHTTP_QUERY_RAW_HEADERS_CRLF: u32 = 22;
+
+    #[link(name = "winhttp")]
+    extern "system" {
+        fn WinHttpOpen(
+            agent: *const u16,
+            access: u32,
+            proxy: *const u16,
+            bypass: *const u16,
+            flags: u32,
+        ) -> Handle;
+        fn WinHttpSetTimeouts(
+            h: Handle,
+            resolve: i32,
+            connect: i32,
+            send: i32,
+            receive: i32,
+        ) -> i32;
+        fn WinHttpConnect(h: Handle, server: *const u16, port: u16, reserved: u32) -> Handle;
+        fn WinHttpOpenRequest(
+            h: Handle,
+            verb: *const u16,
+            object: *const u16,
+            version: *const u16,
+            referrer: *const u16,
+            accept: *const *const u16,
+            flags: u32,
+        ) -> Handle;
+        fn WinHttpSendRequest(
+            h: Handle,
+            headers: *const u16,
+            headers_len: u32,
+            optional: *const c_void,
+            optional_len: u32,
+            total_len: u32,
+            context: usize,
+        ) -> i32;
+        fn WinHttpReceiveResponse(h: Handle, reserved: *mut c_void) -> i32;
+        fn WinHttpQueryHeaders(
+            h: Handle,
+            info: u32,
+            name: *const u16,
+            buffer: *mut c_void,
+            len: *mut u32,
+            index: *mut u32,
+        ) -> i32;
+        fn WinHttpReadData(h: Handle, buffer: *mut c_void, len: u32, read: *mut u32) -> i32;
+        fn WinHttpCloseHandle(h: Handle) -> i32;
+    }
+
+    /// Closes a WinHTTP handle on drop.
+    struct Guard(Handle);
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                unsafe {
+                    WinHttpCloseHandle(self.0);
+                }
+            }
+        }
+    }
+
+    let fail = |what: &str| {
+        format!(
+            "{} failed for https://{}: {}",
+            what,
+            url.host,
+            std::io::Error::last_os_error()
+        )
+    };
+    let ms = i32::try_from(timeout().as_millis()).unwrap_or(i32::MAX);
+    let extra: String = headers
+        .iter()
+        .map(|(n, v)| format!("{}: {}\r\n", n, v))
+        .collect();
+
+    unsafe {
+        let session = Guard(WinHttpOpen(
+            paths::to_wide("zDirComp").as_ptr(),
+            WINHTTP_ACCESS_TYPE_DEFAULT_PROXY,
+            std::ptr::null(),
+            std::ptr::null(),
+            0,
+        ));
+        if session.0.is_null() {
+            return Err(fail("WinHttpOpen"));
+        }
+        WinHttpSetTimeouts(session.0, ms, ms, ms, ms);
+
+        let connect = Guard(WinHttpConnect(
+            session.0,
+            paths::to_wide(&url.host).as_ptr(),
+            url.port,
+            0,
+        ));
+        if connect.0.is_null() {
+            return Err(fail("WinHttpConnect"));
+        }
+        let request = Guard(WinHttpOpenRequest(
+            connect.0,
+            paths::to_wide(method).as_ptr(),
+            paths::to_wide(&url.path).as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            std::ptr::null(),
+            WINHTTP_FLAG_SECURE,
+        ));
+        if request.0.is_null() {
+            return Err(fail("WinHttpOpenRequest"));
+        }
+
+        let extra_wide = paths::to_wide(&extra);
+        let body_len = u32::try_from(body.len()).map_err(|_| "request body too large")?;
+        if WinHttpSendRequest(
+            request.0,
+            if extra.is_empty() {
+                std::ptr::null()
+            } else {
+                extra_wide.as_ptr()
+            },
+            u32::MAX, // null-terminated
+            body.as_ptr() as *const c_void,
+            body_len,
+            body_len,
+            0,
+        ) == 0
+        {
+            return Err(fail("request"));
+        }
+        if WinHttpReceiveResponse(request.0, std::ptr::null_mut()) == 0 {
+            return Err(fail("response"));
+        }
+
+        // Raw headers: query the size, then fetch (length is in bytes)
+        let mut len = 0u32;
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_RAW_HEADERS_CRLF,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            &mut len,
+            std::ptr::null_mut(),
+        );
+        let mut raw = vec![0u16; len as usize / 2 + 1];
+        if WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_RAW_HEADERS_CRLF,
+            std::ptr::null(),
+            raw.as_mut_ptr() as *mut c_void,
+            &mut len,
+            std::ptr::null_mut(),
+        ) == 0
+        {
+            return Err(fail("reading headers"));
+        }
+        raw.truncate(len as usize / 2);
+        let mut response = parse_head(&String::from_utf16_lossy(&raw))?;
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let mut read = 0u32;
+            if WinHttpReadData(
+                request.0,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as u32,
+                &mut read,
+            ) == 0
+            {
+                return Err(fail("reading body"));
+            }
+            if read == 0 {
+                break;
+            }
+            if response.body.len() + read as usize > max_body {
+                return Err(too_large(url, max_body));
+            }
+            response.body.extend_from_slice(&buf[..read as usize]);
+        }
+        Ok(response)
+    }
+}
+
+/// Decode a chunked transfer-encoded body.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("malformed chunked body")?;
+        let size_str = String::from_utf8_lossy(&data[..line_end]);
+        let size_str = size_str.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| "bad chunk size")?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        if data.len() < size {
+            return Err("truncated chunked body".to_string());
+        }
+        out.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// `Authorization` header value for HTTP basic auth.
+pub fn basic_auth(user: &str, pass: &str) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let input = format!("{}:{}", user, pass);
+    let mut out = String::from("Basic ");
+    for chunk in input.as_bytes().chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Percent-encode a value for `application/x-www-form-urlencoded` or a query.
+pub fn form_encode(value: &str) -> String {
+    let mut out = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url = Url::parse("http://127.0.0.1:8080/api/v2").unwrap();
+        assert_eq!(url.host, "127.0.0.1");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/api/v2");
+        assert_eq!(Url::parse("http://nas").unwrap().path, "/");
+        let url = Url::parse("https://example.org/hook?x=1").unwrap();
+        assert!(url.secure);
+        assert_eq!(url.port, 443);
+        assert_eq!(url.origin(), "https://example.org:443");
+        assert!(Url::parse("ftp://nas").is_err());
+        assert!(Url::parse("http://nas:x/").is_err());
+    }
+
+    #[test]
+    fn test_parse_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.text(), "abcde");
+        assert_eq!(
+            resp.headers("set-cookie").collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        assert_eq!(
+            basic_auth("Aladdin", "open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(basic_auth("a", ""), "Basic YTo=");
+    }
+
+    #[test]
+    fn test_form_encode() {
+        assert_eq!(form_encode("a b&c=é"), "a%20b%26c%3D%C3%A9");
+    }
+}
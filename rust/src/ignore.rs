@@ -0,0 +1,275 @@
+//! Per-directory `.zdirignore` files: glob patterns sync must never delete.
+//!
+//! Each `.zdirignore` applies to its own directory and everything below it.
+//! One pattern per line; blank lines and `#` comments are skipped. A pattern
+//! without `/` matches a file name at any depth, one with `/` matches the
+//! path relative to the ignore file's directory. `*` and `?` stay within a
+//! path component, `**` spans components. Matching is case-insensitive, as
+//! on NTFS. The `.zdirignore` files themselves are always kept.
+//!
+//! `--rules` files add root-level exclude and companion rules (see `rules`).
+
+use crate::rules::Rules;
+use crate::walk::Walker;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the per-directory ignore file.
+pub const FILE_NAME: &str = ".zdirignore";
+
+/// One pattern and the directory (relative to the sync root) it came from.
+#[derive(Debug, Clone)]
+struct Rule {
+    base: Vec<String>,
+    pattern: String,
+    anchored: bool,
+}
+
+impl Rule {
+    /// Parse one pattern line of an ignore file in `base`.
+    fn parse(base: &[String], line: &str) -> Option<Rule> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let pattern = line.replace('\\', "/");
+        let anchored = pattern.trim_end_matches('/').contains('/');
+        Some(Rule {
+            base: base.to_vec(),
+            pattern: pattern
+                .trim_start_matches('/')
+                .trim_end_matches('/')
+                .to_lowercase(),
+            anchored,
+        })
+    }
+
+    /// Whether the rule matches a path given as lowercased components.
+    fn matches(&self, parts: &[String]) -> bool {
+        let Some(rest) = parts.strip_prefix(self.base.as_slice()) else {
+            return false;
+        };
+        if self.anchored {
+            // A directory pattern also covers everything inside it
+            (1..=rest.len()).any(|n| glob_match(&self.pattern, &rest[..n].join("/")))
+        } else {
+            rest.iter().any(|part| glob_match(&self.pattern, part))
+        }
+    }
+}
+
+/// All ignore rules found under a sync root.
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+    /// `exclude` rules, overriding all others.
+    exclude: Vec<Rule>,
+    /// `companion` rules.
+    companions: Vec<Rule>,
+    /// Ignore files that were read, relative to the root.
+    pub files: Vec<PathBuf>,
+}
+
+/// Lowercased normal components of a path, for matching.
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().to_lowercase()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Ignore {
+    /// Read every `.zdirignore` under `root`, junctions not followed.
+    /// `Err` at the `safety` walk limits or on cancel, like the index walk.
+    pub fn load(root: &Path) -> Result<Ignore, String> {
+        let mut ignore = Ignore::default();
+        Walker::new(root).skip_reparse_points().on_entry(|entry| {
+            let name = entry.path.file_name().unwrap_or_default();
+            if entry.is_dir || !name.eq_ignore_ascii_case(FILE_NAME) {
+                return Ok(());
+            }
+            if let Ok(text) = fs::read_to_string(&entry.path) {
+                let relative = entry.path.strip_prefix(root).unwrap_or(&entry.path);
+                ignore.add(relative.parent().unwrap_or(Path::new("")), &text);
+                ignore.files.push(relative.to_path_buf());
+            }
+            Ok(())
+        })?;
+        ignore.files.sort();
+        Ok(ignore)
+    }
+
+    /// Add root-level patterns from elsewhere (a profile's `keep` list).
+    pub fn add_patterns(&mut self, patterns: &[String]) {
+        self.add(Path::new(""), &patterns.join("\n"));
+    }
+
+    /// Add the rules of `--rules` files.
+    pub fn add_rules(&mut self, rules: &Rules) {
+        self.add_patterns(&rules.keep);
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .filter_map(|p| Rule::parse(&[], p))
+                .collect::<Vec<_>>()
+        };
+        self.exclude.extend(parse(&rules.exclude));
+        self.companions.extend(parse(&rules.companion));
+    }
+
+    /// Add the patterns of one ignore file located in `base`.
+    fn add(&mut self, base: &Path, text: &str) {
+        let base = components(base);
+        self.rules
+            .extend(text.lines().filter_map(|line| Rule::parse(&base, line)));
+    }
+
+    /// Whether `relative` (a file path relative to the sync root) must be kept.
+    pub fn is_ignored(&self, relative: &Path) -> bool {
+        let parts = components(relative);
+        if parts
+            .last()
+            .is_some_and(|name| name.eq_ignore_ascii_case(FILE_NAME))
+        {
+            return true;
+        }
+        if self.exclude.iter().any(|rule| rule.matches(&parts)) {
+            return false;
+        }
+        self.rules.iter().any(|rule| rule.matches(&parts))
+    }
+
+    /// Whether `relative` is kept by a companion rule: one of the
+    /// `expected` files in its folder shares its name up to a dot.
+    pub fn is_companion(&self, relative: &Path, expected: &HashSet<PathBuf>) -> bool {
+        let parts = components(relative);
+        let Some((name, folder)) = parts.split_last() else {
+            return false;
+        };
+        if !self.companions.iter().any(|rule| rule.matches(&parts))
+            || self.exclude.iter().any(|rule| rule.matches(&parts))
+        {
+            return false;
+        }
+        expected.iter().any(|file| {
+            let file = components(file);
+            let Some((file_name, file_folder)) = file.split_last() else {
+                return false;
+            };
+            let stem = file_name.rsplit_once('.').map_or(file_name.as_str(), |(s, _)| s);
+            file_folder == folder
+                && name
+                    .strip_prefix(stem)
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+}
+
+/// Match `text` against a glob: `*` and `?` within a component, `**` across.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    glob_at(&p, &t)
+}
+
+fn glob_at(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            // `**/` may also match zero directories
+            let rest = &p[2..];
+            if rest.first() == Some(&'/') && glob_at(&rest[1..], t) {
+                return true;
+            }
+            (0..=t.len()).any(|i| glob_at(rest, &t[i..]))
+        }
+        Some('*') => {
+            let rest = &p[1..];
+            for i in 0..=t.len() {
+                if glob_at(rest, &t[i..]) {
+                    return true;
+                }
+                if t.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => t.first().is_some_and(|&c| c != '/') && glob_at(&p[1..], &t[1..]),
+        Some(&c) => t.first() == Some(&c) && glob_at(&p[1..], &t[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.jpg", "cover.jpg"));
+        assert!(!glob_match("*.jpg", "art/cover.jpg"));
+        assert!(glob_match("art/*.jpg", "art/cover.jpg"));
+        assert!(glob_match("**/*.jpg", "cover.jpg"));
+        assert!(glob_match("**/*.jpg", "a/b/cover.jpg"));
+        assert!(glob_match("cd?", "cd1"));
+        assert!(!glob_match("cd?", "cd10"));
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let mut ignore = Ignore::default();
+        ignore.add(Path::new(""), "# custom artwork\n*.JPG\n\nExtras/\n");
+        ignore.add(Path::new("Season 1"), "notes/*.txt\n");
+
+        assert!(ignore.is_ignored(Path::new("Season 1/cover.jpg")));
+        assert!(ignore.is_ignored(Path::new("Extras/a/b.nfo")));
+        assert!(ignore.is_ignored(Path::new("Season 1/notes/x.txt")));
+        assert!(!ignore.is_ignored(Path::new("notes/x.txt")));
+        assert!(!ignore.is_ignored(Path::new("Season 1/x.txt")));
+        assert!(ignore.is_ignored(Path::new("Season 2/.zdirignore")));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TempDir::new("ignore");
+        fs::create_dir_all(dir.join("Season 1")).unwrap();
+        fs::write(dir.join(FILE_NAME), "*.jpg\n").unwrap();
+        fs::write(dir.join("Season 1").join(FILE_NAME), "*.txt\n").unwrap();
+
+        let ignore = Ignore::load(&dir).unwrap();
+        assert_eq!(
+            ignore.files,
+            vec![PathBuf::from(FILE_NAME), Path::new("Season 1").join(FILE_NAME)]
+        );
+        assert!(ignore.is_ignored(Path::new("Season 1/x.txt")));
+        assert!(!ignore.is_ignored(Path::new("x.txt")));
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut ignore = Ignore::default();
+        ignore.add(Path::new(""), "*.nfo\n");
+        ignore.add_rules(&Rules {
+            keep: vec!["Sample/".to_string()],
+            exclude: vec!["junk.nfo".to_string(), "*.forced.srt".to_string()],
+            companion: vec!["*.srt".to_string()],
+        });
+        assert!(ignore.is_ignored(Path::new("info.nfo")));
+        assert!(ignore.is_ignored(Path::new("Sample/a.mkv")));
+        assert!(!ignore.is_ignored(Path::new("junk.nfo")));
+
+        let expected: HashSet<PathBuf> = [Path::new("S1").join("Ep.01.mkv")].into();
+        let companion = |path: &str| ignore.is_companion(Path::new(path), &expected);
+        assert!(companion("S1/Ep.01.en.srt"));
+        assert!(companion("S1/ep.01.srt"));
+        assert!(!companion("S1/Ep.02.srt"));
+        assert!(!companion("Ep.01.srt"));
+        assert!(!companion("S1/Ep.01.forced.srt"));
+        assert!(!companion("S1/Ep.01.txt"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Import-safe sync (`--import-safe`) for Sonarr/Radarr setups (raw FFI).
+//!
+//! The *arr apps import finished downloads into a media library by hard
+//! link or copy. A planned deletion is deferred while the file
+//! - has a hard link inside a `--library` path (any second link when no
+//!   library is configured), or
+//! - is open in another process, e.g. an import still copying it.
+//!
+//! Deferred files stay in place and are reconsidered by the next sync. The
+//! link check runs as a deletion policy (`policy::HardlinkGuard`), the lock
+//! check as one query over the whole plan.
+
+use crate::paths;
+use crate::restart_manager;
+use crate::volume;
+
+use std::path::{Path, PathBuf};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+/// Longest link name `FindFirstFileNameW` can return, in UTF-16 units.
+const LINK_BUFFER: usize = 32_768;
+
+extern "system" {
+    fn FindFirstFileNameW(
+        lpFileName: *const u16,
+        dwFlags: DWORD,
+        StringLength: *mut DWORD,
+        LinkName: *mut u16,
+    ) -> HANDLE;
+    fn FindNextFileNameW(hFindStream: HANDLE, StringLength: *mut DWORD, LinkName: *mut u16) -> i32;
+    fn FindClose(hFindFile: HANDLE) -> i32;
+}
+
+/// Every name of the file at `path`, as full paths; empty if unknown.
+fn hard_links(path: &Path) -> Vec<PathBuf> {
+    let Some(root) = volume::volume_root(path) else {
+        return Vec::new();
+    };
+    let root = root.trim_end_matches('\\');
+    let wide = paths::to_wide(path);
+    let mut buf = vec![0u16; LINK_BUFFER];
+    let mut links = Vec::new();
+    unsafe {
+        let mut len = buf.len() as DWORD;
+        let handle = FindFirstFileNameW(wide.as_ptr(), 0, &mut len, buf.as_mut_ptr());
+        if handle == INVALID_HANDLE_VALUE {
+            return links;
+        }
+        loop {
+            // `len` counts the terminating NUL; names are relative to the volume
+            let name = String::from_utf16_lossy(&buf[..(len as usize).saturating_sub(1)]);
+            links.push(PathBuf::from(format!("{}{}", root, name)));
+            len = buf.len() as DWORD;
+            if FindNextFileNameW(handle, &mut len, buf.as_mut_ptr()) == 0 {
+                break;
+            }
+        }
+        FindClose(handle);
+    }
+    links
+}
+
+/// Whether `path` lies below `dir`, ignoring case as NTFS does.
+pub fn inside(path: &Path, dir: &Path) -> bool {
+    let path = path.to_string_lossy().to_lowercase();
+    let dir = dir.to_string_lossy().to_lowercase();
+    path.strip_prefix(dir.trim_end_matches(['\\', '/']))
+        .is_some_and(|rest| rest.starts_with(['\\', '/']))
+}
+
+/// The other name of `path` among `links` that should defer its deletion:
+/// one inside `library`, or any when no library is configured.
+fn library_link(path: &Path, links: &[PathBuf], library: &[PathBuf]) -> Option<PathBuf> {
+    let own = path.to_string_lossy().to_lowercase();
+    links
+        .iter()
+        .filter(|link| link.to_string_lossy().to_lowercase() != own)
+        .find(|link| library.is_empty() || library.iter().any(|dir| inside(link, dir)))
+        .cloned()
+}
+
+/// Another name of the file at `path` that should defer its deletion: one
+/// inside `library`, or any when no library is configured.
+pub fn linked(path: &Path, library: &[PathBuf]) -> Option<PathBuf> {
+    library_link(path, &hard_links(path), library)
+}
+
+/// Files among `planned` (relative to `dir`) open in another process, with
+/// the names of the processes.
+pub fn in_use(dir: &Path, planned: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    // One Restart Manager query for all files; narrowed down only if any is held
+    let files: Vec<Vec<String>> = planned
+        .iter()
+        .map(|r| vec![dir.join(r).to_string_lossy().into_owned()])
+        .collect();
+    restart_manager::locked(&files)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(i, processes)| {
+            let names: Vec<String> = processes.into_iter().map(|p| p.name).collect();
+            (planned[i].clone(), names.join(", "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library_link() {
+        let file = Path::new("E:\\Online\\Show\\ep1.mkv");
+        let links = vec![
+            PathBuf::from("E:\\Online\\Show\\ep1.mkv"),
+            PathBuf::from("E:\\Media\\TV\\Show\\Show - S01E01.mkv"),
+        ];
+        let library = vec![PathBuf::from("e:\\media\\tv")];
+        assert_eq!(library_link(file, &links, &library), Some(links[1].clone()));
+        // Linked elsewhere: only deferred when no library is configured
+        let movies = vec![PathBuf::from("E:\\Media\\Movies")];
+        assert_eq!(library_link(file, &links, &movies), None);
+        assert_eq!(library_link(file, &links, &[]), Some(links[1].clone()));
+        // A single name is never deferred
+        assert_eq!(library_link(file, &links[..1], &[]), None);
+        assert!(!inside(
+            Path::new("E:\\Media\\TV2\\x"),
+            Path::new("E:\\Media\\TV")
+        ));
+    }
+}
@@ -0,0 +1,339 @@
+//! `install` / `uninstall`: per-user setup (raw FFI).
+//!
+//! `install` copies the running executable to
+//! `%LOCALAPPDATA%\Programs\zDirComp`, writes a commented `zDirComp.toml`
+//! there if none exists, and adds the directory to the user's `Path`
+//! (`HKCU\Environment`). Logs and state go beside the executable or to
+//! `%LOCALAPPDATA%\zDirComp`, both inside the user profile, so they inherit
+//! its owner-only permissions and need no elevation. `uninstall` takes the
+//! directory off `Path` and deletes the executable and an unedited config
+//! skeleton; edited settings, logs and state stay, and so does the folder
+//! if they are there. `uninstall --purge` deletes the whole folder. When it
+//! runs from that directory itself, the files are left for the user.
+
+use crate::config;
+use crate::logger::{Level, Record};
+use crate::paths;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HKEY = *mut std::ffi::c_void;
+
+/// Sign-extended, as in the SDK headers.
+const HKEY_CURRENT_USER: HKEY = 0x8000_0001_u32 as i32 as isize as HKEY;
+const KEY_READ: DWORD = 0x2_0019;
+const KEY_WRITE: DWORD = 0x2_0006;
+const REG_EXPAND_SZ: DWORD = 2;
+const ERROR_FILE_NOT_FOUND: i32 = 2;
+const HWND_BROADCAST: isize = 0xFFFF;
+const WM_SETTINGCHANGE: u32 = 0x001A;
+const SMTO_ABORTIFHUNG: u32 = 0x0002;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegOpenKeyExW(
+        hKey: HKEY,
+        lpSubKey: *const u16,
+        ulOptions: DWORD,
+        samDesired: DWORD,
+        phkResult: *mut HKEY,
+    ) -> i32;
+    fn RegQueryValueExW(
+        hKey: HKEY,
+        lpValueName: *const u16,
+        lpReserved: *mut DWORD,
+        lpType: *mut DWORD,
+        lpData: *mut u8,
+        lpcbData: *mut DWORD,
+    ) -> i32;
+    fn RegSetValueExW(
+        hKey: HKEY,
+        lpValueName: *const u16,
+        Reserved: DWORD,
+        dwType: DWORD,
+        lpData: *const u8,
+        cbData: DWORD,
+    ) -> i32;
+    fn RegCloseKey(hKey: HKEY) -> i32;
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn SendMessageTimeoutW(
+        hWnd: isize,
+        Msg: u32,
+        wParam: usize,
+        lParam: isize,
+        fuFlags: u32,
+        uTimeout: u32,
+        lpdwResult: *mut usize,
+    ) -> isize;
+}
+
+/// Written as `zDirComp.toml` by `install` when there is none.
+const CONFIG_SKELETON: &str = "\
+# zDirComp settings. Command-line options override these; keys are option
+# names without the leading --.
+#
+# log-format = \"jsonl\"
+# retention = \"14d\"
+#
+# Selected with --profile tv:
+# [profile.tv]
+# root = \"F:\\\\TV\"
+# keep = [\"*.srt\"]
+";
+
+/// `%LOCALAPPDATA%\Programs\zDirComp`.
+fn install_dir() -> Result<PathBuf, String> {
+    std::env::var_os("LOCALAPPDATA")
+        .map(|d| PathBuf::from(d).join("Programs").join("zDirComp"))
+        .ok_or_else(|| "LOCALAPPDATA is not set".to_string())
+}
+
+/// Whether two `Path` entries name the same directory.
+fn same_entry(entry: &str, dir: &str) -> bool {
+    let norm = |s: &str| s.trim().trim_end_matches('\\').to_lowercase();
+    norm(entry) == norm(dir)
+}
+
+/// `path` with `dir` appended, or `None` if it is already listed.
+fn with_entry(path: &str, dir: &str) -> Option<String> {
+    if path.split(';').any(|entry| same_entry(entry, dir)) {
+        return None;
+    }
+    let path = path.trim_end_matches(';');
+    Some(if path.is_empty() {
+        dir.to_string()
+    } else {
+        format!("{};{}", path, dir)
+    })
+}
+
+/// `path` without `dir`, or `None` if it is not listed.
+fn without_entry(path: &str, dir: &str) -> Option<String> {
+    let kept: Vec<&str> = path
+        .split(';')
+        .filter(|entry| !same_entry(entry, dir))
+        .collect();
+    (kept.len() != path.split(';').count()).then(|| kept.join(";"))
+}
+
+/// Rewrite the user's `Path` with `change`; no write if it returns `None`.
+/// Returns whether it changed.
+fn update_user_path(change: impl Fn(&str) -> Option<String>) -> Result<bool, String> {
+    let name = paths::to_wide("Path");
+    unsafe {
+        let mut key: HKEY = std::ptr::null_mut();
+        let status = RegOpenKeyExW(
+            HKEY_CURRENT_USER,
+            paths::to_wide("Environment").as_ptr(),
+            0,
+            KEY_READ | KEY_WRITE,
+            &mut key,
+        );
+        if status != 0 {
+            return Err(format!("cannot open HKCU\\Environment (error {})", status));
+        }
+        let mut size: DWORD = 0;
+        let status = RegQueryValueExW(
+            key,
+            name.as_ptr(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut size,
+        );
+        let mut buf = vec![0u16; size as usize / 2 + 1];
+        let current = if status == ERROR_FILE_NOT_FOUND {
+            String::new()
+        } else {
+            let status = RegQueryValueExW(
+                key,
+                name.as_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                buf.as_mut_ptr().cast(),
+                &mut size,
+            );
+            if status != 0 {
+                RegCloseKey(key);
+                return Err(format!("cannot read the user Path (error {})", status));
+            }
+            let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+            String::from_utf16_lossy(&buf[..len])
+        };
+        let Some(updated) = change(&current) else {
+            RegCloseKey(key);
+            return Ok(false);
+        };
+        let data = paths::to_wide(&updated);
+        let status = RegSetValueExW(
+            key,
+            name.as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            data.as_ptr().cast(),
+            (data.len() * 2) as DWORD,
+        );
+        RegCloseKey(key);
+        if status != 0 {
+            return Err(format!("cannot write the user Path (error {})", status));
+        }
+        // New consoles and Explorer pick the change up without a sign-out
+        let mut result = 0;
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            0,
+            paths::to_wide("Environment").as_ptr() as isize,
+            SMTO_ABORTIFHUNG,
+            5000,
+            &mut result,
+        );
+        Ok(true)
+    }
+}
+
+fn abort(target: &Path, message: String) -> String {
+    Record::new(Level::Error, "INSTALL", &target.to_string_lossy(), "abort")
+        .message(message.as_str())
+        .emit();
+    message
+}
+
+/// `install` command.
+pub fn install() -> Result<(), String> {
+    let dir = install_dir().map_err(|e| abort(Path::new(""), e))?;
+    let target = dir.to_string_lossy().into_owned();
+    fs::create_dir_all(&dir).map_err(|e| abort(&dir, format!("cannot create: {}", e)))?;
+
+    let exe = std::env::current_exe()
+        .map_err(|e| abort(&dir, format!("cannot locate the executable: {}", e)))?;
+    let installed = dir.join("zDirComp.exe");
+    if exe != installed {
+        fs::copy(&exe, &installed)
+            .map_err(|e| abort(&dir, format!("cannot copy to {:?}: {}", installed, e)))?;
+    }
+    let config = dir.join(config::FILE_NAME);
+    if !config.exists() {
+        fs::write(&config, CONFIG_SKELETON)
+            .map_err(|e| abort(&dir, format!("cannot write {:?}: {}", config, e)))?;
+    }
+    let added = update_user_path(|path| with_entry(path, &target)).map_err(|e| abort(&dir, e))?;
+
+    Record::new(Level::Info, "INSTALL", &target, "summary")
+        .path(&installed)
+        .message(format!(
+            "installed {:?}{}",
+            installed,
+            if added {
+                ", added to the user Path (open a new console)"
+            } else {
+                ""
+            }
+        ))
+        .emit();
+    Ok(())
+}
+
+/// Delete what `install` wrote and nobody changed since: the executable
+/// and an unedited config skeleton. Returns whether the folder is gone.
+fn remove_installed(dir: &Path) -> Result<bool, String> {
+    let exe = dir.join("zDirComp.exe");
+    if exe.exists() {
+        fs::remove_file(&exe).map_err(|e| format!("cannot delete {:?}: {}", exe, e))?;
+    }
+    let config = dir.join(config::FILE_NAME);
+    if fs::read_to_string(&config).is_ok_and(|text| text == CONFIG_SKELETON) {
+        fs::remove_file(&config).map_err(|e| format!("cannot delete {:?}: {}", config, e))?;
+    }
+    Ok(fs::remove_dir(dir).is_ok())
+}
+
+/// `uninstall [--purge]` command.
+pub fn uninstall(purge: bool) -> Result<(), String> {
+    let dir = install_dir().map_err(|e| abort(Path::new(""), e))?;
+    let target = dir.to_string_lossy().into_owned();
+    update_user_path(|path| without_entry(path, &target)).map_err(|e| abort(&dir, e))?;
+
+    let running_inside = std::env::current_exe().is_ok_and(|exe| exe.starts_with(&dir));
+    let message = if !dir.exists() {
+        "removed from the user Path; nothing installed".to_string()
+    } else if running_inside {
+        format!(
+            "removed from the user Path; delete {:?} once this window is closed",
+            dir
+        )
+    } else if purge {
+        fs::remove_dir_all(&dir).map_err(|e| abort(&dir, format!("cannot delete: {}", e)))?;
+        "removed from the user Path and deleted with logs, state and settings".to_string()
+    } else if remove_installed(&dir).map_err(|e| abort(&dir, e))? {
+        "removed from the user Path and deleted".to_string()
+    } else {
+        format!(
+            "removed from the user Path and deleted the executable; {:?} keeps settings, \
+             logs and state (--purge deletes them)",
+            dir
+        )
+    };
+    Record::new(Level::Info, "INSTALL", &target, "summary")
+        .message(message)
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_path_entries() {
+        let dir = "C:\\Users\\a\\AppData\\Local\\Programs\\zDirComp";
+        assert_eq!(with_entry("", dir), Some(dir.to_string()));
+        assert_eq!(
+            with_entry("C:\\bin;", dir),
+            Some(format!("C:\\bin;{}", dir))
+        );
+        let listed = format!("C:\\bin;{}\\", dir.to_uppercase());
+        assert_eq!(with_entry(&listed, dir), None);
+        assert_eq!(without_entry(&listed, dir), Some("C:\\bin".to_string()));
+        assert_eq!(without_entry("C:\\bin", dir), None);
+    }
+
+    #[test]
+    fn test_remove_installed() {
+        let dir = TempDir::new("install");
+        fs::write(dir.join("zDirComp.exe"), b"MZ").unwrap();
+        fs::write(dir.join(config::FILE_NAME), CONFIG_SKELETON).unwrap();
+        assert!(remove_installed(&dir).unwrap());
+        assert!(!dir.exists());
+
+        // Edited settings and logs stay
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("zDirComp.exe"), b"MZ").unwrap();
+        fs::write(dir.join(config::FILE_NAME), "retention = \"7d\"\n").unwrap();
+        fs::write(dir.join("zDirComp.log"), b"x").unwrap();
+        assert!(!remove_installed(&dir).unwrap());
+        assert!(!dir.join("zDirComp.exe").exists());
+        assert!(dir.join(config::FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_config_skeleton_parses() {
+        // The commented examples must work once uncommented
+        let sample: String = CONFIG_SKELETON
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .filter(|line| line.contains(" = ") || line.starts_with('['))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let parsed = config::Config::parse(&sample).unwrap();
+        assert!(parsed.profile("tv").is_some());
+    }
+}
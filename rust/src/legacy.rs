@@ -0,0 +1,187 @@
+//! Invocation of the original Java tool: `file.torrent [directory [+] [-] [=]]`.
+//!
+//! Without a directory, prints the torrent's file paths. With one, prints
+//! `=path` for files in both, `-path` for files only in the directory and
+//! `+path` for files only in the torrent, each group sorted, limited to the
+//! given markers (all three if none). Output goes to stdout exactly as the
+//! Java version wrote it, so "run on completion" entries that redirect it
+//! into `zDirComp.cmd` keep working. Nothing is deleted.
+
+use crate::bencode;
+use crate::safety;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+/// Which groups to print.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Show {
+    plus: bool,
+    minus: bool,
+    equal: bool,
+}
+
+/// Whether `args` look like a Java-style command line.
+pub fn matches(args: &[String]) -> bool {
+    args.first()
+        .is_some_and(|a| a.to_lowercase().ends_with(".torrent"))
+}
+
+/// Parse the markers after the directory; they must come in `+ - =` order.
+fn parse_markers(markers: &[String]) -> Option<Show> {
+    if markers.is_empty() {
+        return Some(Show {
+            plus: true,
+            minus: true,
+            equal: true,
+        });
+    }
+    let mut show = Show {
+        plus: false,
+        minus: false,
+        equal: false,
+    };
+    let mut rest = markers.iter().map(String::as_str).peekable();
+    show.plus = rest.next_if_eq(&"+").is_some();
+    show.minus = rest.next_if_eq(&"-").is_some();
+    show.equal = rest.next_if_eq(&"=").is_some();
+    (rest.next().is_none() && (show.plus || show.minus || show.equal)).then_some(show)
+}
+
+/// Files under `dir` as paths relative to it, within the walk limits.
+fn dir_files(
+    dir: &Path,
+    relative: &Path,
+    depth: usize,
+    files: &mut Vec<String>,
+) -> Result<(), String> {
+    safety::check_walk(files.len(), depth)?;
+    let Ok(entries) = fs::read_dir(dir.join(relative)) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let path = relative.join(entry.file_name());
+        if entry.path().is_dir() {
+            dir_files(dir, &path, depth + 1, files)?;
+        } else {
+            files.push(path.display().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Sorted merge of the two lists into (equal, minus, plus).
+fn compare(
+    mut torrent: Vec<String>,
+    mut dir: Vec<String>,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    torrent.sort();
+    dir.sort();
+    let (mut equal, mut minus, mut plus) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut t, mut d) = (torrent.into_iter().peekable(), dir.into_iter().peekable());
+    while let (Some(tor), Some(file)) = (t.peek(), d.peek()) {
+        match tor.cmp(file) {
+            std::cmp::Ordering::Equal => {
+                equal.push(file.clone());
+                t.next();
+                d.next();
+            }
+            std::cmp::Ordering::Less => plus.extend(t.next()),
+            std::cmp::Ordering::Greater => minus.extend(d.next()),
+        }
+    }
+    // The Java version stopped at the end of the shorter list and lost the
+    // rest of the other one
+    plus.extend(t);
+    minus.extend(d);
+    (equal, minus, plus)
+}
+
+fn help() -> ! {
+    println!("Program parameters:\nfile.torrent [directory [+] [-] [=]]");
+    println!("file.torrent\tThe torrent file");
+    println!("directory\tDirectory to compare contents with torrent file");
+    println!("+\t\tDisplay files in torrent file, not exist in directory");
+    println!("-\t\tDisplay files in directory, not exist in torrent");
+    println!("=\t\tDisplay files existed in both directory and torrent");
+    println!("Absent of + - =, means all of them");
+    process::exit(1);
+}
+
+/// Run the Java-style command line in `args` (positionals only).
+pub fn run(args: &[String]) {
+    let show = parse_markers(args.get(2..).unwrap_or(&[])).unwrap_or_else(|| help());
+    let meta = bencode::parse_torrent_file(Path::new(&args[0])).unwrap_or_else(|e| {
+        println!("Torrent file: {}: {}", args[0], e);
+        process::exit(1);
+    });
+    let torrent: Vec<String> = meta
+        .files
+        .iter()
+        .map(|f| f.path.display().to_string())
+        .collect();
+
+    let Some(dir) = args.get(1) else {
+        torrent.iter().for_each(|path| println!("{}", path));
+        return;
+    };
+    let mut files = Vec::new();
+    if let Err(e) = dir_files(Path::new(dir), Path::new(""), 0, &mut files) {
+        println!("Directory: {}: {}", dir, e);
+        process::exit(1);
+    }
+    let (equal, minus, plus) = compare(torrent, files);
+    for (enabled, prefix, group) in [
+        (show.equal, '=', equal),
+        (show.minus, '-', minus),
+        (show.plus, '+', plus),
+    ] {
+        if enabled {
+            group.iter().for_each(|path| println!("{}{}", prefix, path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_markers() {
+        let show = |plus, minus, equal| Some(Show { plus, minus, equal });
+        assert_eq!(parse_markers(&[]), show(true, true, true));
+        assert_eq!(parse_markers(&strings(&["-"])), show(false, true, false));
+        assert_eq!(
+            parse_markers(&strings(&["+", "="])),
+            show(true, false, true)
+        );
+        // Out of order or unknown markers are rejected, as in the Java tool
+        assert_eq!(parse_markers(&strings(&["-", "+"])), None);
+        assert_eq!(parse_markers(&strings(&["x"])), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        let (equal, minus, plus) = compare(
+            strings(&["b", "a", "d"]),
+            strings(&["c", "a", "Thumbs.db", "d", "z"]),
+        );
+        assert_eq!(equal, strings(&["a", "d"]));
+        assert_eq!(minus, strings(&["Thumbs.db", "c", "z"]));
+        assert_eq!(plus, strings(&["b"]));
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(&strings(&[
+            "C:\\Torrents\\Show.TORRENT",
+            "D:\\Show"
+        ])));
+        assert!(!matches(&strings(&["sync", "a.torrent", "D:\\Show"])));
+    }
+}
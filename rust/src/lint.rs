@@ -0,0 +1,419 @@
+//! `lint` command: check a torrent for problems before it is used.
+//!
+//! Reads the raw bencode rather than `TorrentMeta`, which has already made
+//! paths lossy, and reports each problem with a severity:
+//! - error: the torrent is unsafe or unusable (path traversal, missing or
+//!   zero piece length, `pieces` not a multiple of 20 bytes or not matching
+//!   the total size, exact duplicate files, absurd file counts)
+//! - warning: usable, but not everywhere (non-UTF-8 paths, names Windows
+//!   cannot create, files differing only in case, very many files)
+//! - info: unusual but harmless (piece length not a power of two, trailing
+//!   bytes)
+//!
+//! Exits with code 1 if there is any error, so watch and batch scripts can
+//! refuse a torrent before syncing against it. `--format json` prints the
+//! findings.
+
+use crate::bencode::{self, BValue};
+use crate::json::Json;
+use crate::logger::{Level, Record};
+
+use std::fs;
+
+/// More files than this is a warning, and more than `MAX_FILES` an error.
+const MANY_FILES: usize = 100_000;
+const MAX_FILES: usize = 1_000_000;
+
+/// Device names Windows reserves in every folder, with any extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    fn level(self) -> Level {
+        match self {
+            Severity::Info => Level::Info,
+            Severity::Warning => Level::Warn,
+            Severity::Error => Level::Error,
+        }
+    }
+}
+
+/// One problem found.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Stable short name of the check, for scripts.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// All findings for one torrent.
+#[derive(Debug, Clone, Default)]
+pub struct LintReport {
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    fn add(&mut self, severity: Severity, code: &'static str, message: String) {
+        self.findings.push(Finding {
+            severity,
+            code,
+            message,
+        });
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    /// Result object for `--format json`.
+    pub fn to_json(&self) -> Json {
+        let findings = self.findings.iter().map(|f| {
+            Json::object()
+                .with("severity", f.severity.as_str())
+                .with("code", f.code)
+                .with("message", f.message.as_str())
+        });
+        Json::object()
+            .with("errors", self.count(Severity::Error) as i64)
+            .with("warnings", self.count(Severity::Warning) as i64)
+            .with("findings", Json::Array(findings.collect()))
+    }
+}
+
+/// Problems with one path component, `at` naming the file for messages.
+fn check_component(report: &mut LintReport, at: &str, raw: &[u8]) {
+    let name = String::from_utf8_lossy(raw);
+    if std::str::from_utf8(raw).is_err() {
+        report.add(
+            Severity::Warning,
+            "non-utf8",
+            format!("{}: component {:?} is not UTF-8", at, name),
+        );
+    }
+    if matches!(&*name, "" | "." | "..") || name.contains(['/', '\\', ':']) {
+        report.add(
+            Severity::Error,
+            "path-traversal",
+            format!("{}: component {:?} escapes or breaks the path", at, name),
+        );
+        return;
+    }
+    if name
+        .chars()
+        .any(|c| matches!(c, '<' | '>' | '"' | '|' | '?' | '*') || c.is_control())
+    {
+        report.add(
+            Severity::Warning,
+            "invalid-name",
+            format!("{}: {:?} has characters Windows does not allow", at, name),
+        );
+    } else if name.ends_with(['.', ' ']) {
+        report.add(
+            Severity::Warning,
+            "invalid-name",
+            format!(
+                "{}: {:?} ends in a dot or space, which Windows drops",
+                at, name
+            ),
+        );
+    }
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        report.add(
+            Severity::Warning,
+            "reserved-name",
+            format!("{}: {:?} is a reserved device name on Windows", at, name),
+        );
+    }
+}
+
+/// Check raw torrent data.
+pub fn lint(data: &[u8]) -> LintReport {
+    let mut report = LintReport::default();
+    let (root, rest) = match bencode::parse(data) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            report.add(Severity::Error, "parse", e.to_string());
+            return report;
+        }
+    };
+    if !rest.is_empty() {
+        report.add(
+            Severity::Info,
+            "trailing-data",
+            format!("{} bytes after the torrent are ignored", rest.len()),
+        );
+    }
+    let Some(info @ BValue::Dict(_)) = root.field(b"info") else {
+        report.add(
+            Severity::Error,
+            "no-info",
+            "no 'info' dictionary".to_string(),
+        );
+        return report;
+    };
+
+    match info.field(b"name").and_then(BValue::as_bytes) {
+        Some(name) => check_component(&mut report, "name", name),
+        None => report.add(Severity::Warning, "no-name", "no 'name'".to_string()),
+    }
+
+    match info.field(b"files") {
+        Some(BValue::List(files)) => {
+            if files.is_empty() {
+                report.add(
+                    Severity::Error,
+                    "file-count",
+                    "'files' is empty".to_string(),
+                );
+            } else if files.len() > MAX_FILES {
+                report.add(
+                    Severity::Error,
+                    "file-count",
+                    format!("{} files, more than {}", files.len(), MAX_FILES),
+                );
+            } else if files.len() > MANY_FILES {
+                report.add(
+                    Severity::Warning,
+                    "file-count",
+                    format!("{} files, more than {}", files.len(), MANY_FILES),
+                );
+            }
+            for (i, file) in files.iter().enumerate() {
+                let at = format!("files[{}]", i);
+                if file
+                    .field(b"length")
+                    .and_then(BValue::as_int)
+                    .is_none_or(|n| n < 0)
+                {
+                    report.add(
+                        Severity::Error,
+                        "bad-length",
+                        format!("{}: no non-negative 'length'", at),
+                    );
+                }
+                let Some(path) = file.field(b"path").and_then(BValue::as_list) else {
+                    report.add(
+                        Severity::Error,
+                        "bad-path",
+                        format!("{}: no 'path' list", at),
+                    );
+                    continue;
+                };
+                for component in path {
+                    match component.as_bytes() {
+                        Some(raw) => check_component(&mut report, &at, raw),
+                        None => report.add(
+                            Severity::Error,
+                            "bad-path",
+                            format!("{}: path component is not a string", at),
+                        ),
+                    }
+                }
+            }
+        }
+        Some(_) => report.add(
+            Severity::Error,
+            "bad-files",
+            "'files' is not a list".to_string(),
+        ),
+        None => {
+            if info
+                .field(b"length")
+                .and_then(BValue::as_int)
+                .is_none_or(|n| n < 0)
+            {
+                report.add(
+                    Severity::Error,
+                    "bad-length",
+                    "single-file torrent without a non-negative 'length'".to_string(),
+                );
+            }
+        }
+    }
+
+    let piece_length = info.field(b"piece length").and_then(BValue::as_int);
+    match piece_length {
+        None | Some(..=0) => report.add(
+            Severity::Error,
+            "piece-length",
+            format!("piece length is {:?}", piece_length),
+        ),
+        Some(n) if !(n as u64).is_power_of_two() => report.add(
+            Severity::Info,
+            "piece-length",
+            format!("piece length {} is not a power of two", n),
+        ),
+        Some(_) => {}
+    }
+    let pieces = info.field(b"pieces").and_then(BValue::as_bytes);
+    match pieces {
+        None => report.add(Severity::Error, "pieces", "no 'pieces' string".to_string()),
+        Some(p) if !p.len().is_multiple_of(20) => report.add(
+            Severity::Error,
+            "pieces",
+            format!("'pieces' is {} bytes, not a multiple of 20", p.len()),
+        ),
+        Some(_) => {}
+    }
+
+    // What only the parsed metadata shows
+    match bencode::torrent_meta(data) {
+        Ok(meta) => {
+            // Indices into `meta.files`, which drops padding entries, so
+            // name the file by path
+            for (first, second) in &meta.duplicates {
+                let (a, b) = (&meta.files[*first].path, &meta.files[*second].path);
+                let (severity, what) = match a == b {
+                    true => (Severity::Error, "listed twice"),
+                    false => (
+                        Severity::Warning,
+                        "differs only in case from an earlier file",
+                    ),
+                };
+                report.add(severity, "duplicate-file", format!("{:?} {}", b, what));
+            }
+            if let (Some(length @ 1..), Some(p)) = (piece_length, pieces) {
+                let expected = meta.total_size.div_ceil(length as u64);
+                if p.len().is_multiple_of(20) && (p.len() / 20) as u64 != expected {
+                    report.add(
+                        Severity::Error,
+                        "piece-count",
+                        format!(
+                            "{} piece hashes, but {} bytes in pieces of {} need {}",
+                            p.len() / 20,
+                            meta.total_size,
+                            length,
+                            expected
+                        ),
+                    );
+                }
+            }
+        }
+        // Already reported above, unless none of the checks caught it
+        Err(e) if !report.has_errors() => report.add(Severity::Error, "metadata", e.0),
+        Err(_) => {}
+    }
+    report
+}
+
+/// `lint` command: check the torrent at `torrent_path` and log every
+/// finding. `Err` only if it cannot be read.
+pub fn run(torrent_path: &str) -> Result<LintReport, String> {
+    let data = fs::read(torrent_path).map_err(|e| {
+        let message = format!("cannot read {:?}: {}", torrent_path, e);
+        Record::new(Level::Error, "LINT", torrent_path, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    })?;
+    let report = lint(&data);
+    for finding in &report.findings {
+        Record::new(finding.severity.level(), "LINT", torrent_path, finding.code)
+            .message(finding.message.as_str())
+            .emit();
+    }
+    Record::new(Level::Info, "LINT", torrent_path, "summary")
+        .message(match report.findings.len() {
+            0 => "no problems found".to_string(),
+            _ => format!(
+                "{} errors, {} warnings, {} notes",
+                report.count(Severity::Error),
+                report.count(Severity::Warning),
+                report.count(Severity::Info)
+            ),
+        })
+        .emit();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(data: &[u8]) -> Vec<(Severity, &'static str)> {
+        let report = lint(data);
+        report
+            .findings
+            .iter()
+            .map(|f| (f.severity, f.code))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        let pieces = "p".repeat(20);
+        let data = format!(
+            "d4:infod6:lengthi10e4:name5:a.iso12:piece lengthi16384e6:pieces20:{}ee",
+            pieces
+        );
+        assert!(codes(data.as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_problems() {
+        // A reserved name, a case duplicate, an exact duplicate, a bad
+        // piece length and a short `pieces`
+        let data = b"d4:infod5:filesl\
+                     d6:lengthi1e4:pathl7:CON.txteed6:lengthi1e4:pathl7:con.txtee\
+                     d6:lengthi1e4:pathl1:yeed6:lengthi1e4:pathl1:yee\
+                     e4:name1:t12:piece lengthi0e6:pieces5:xxxxxee";
+        let found = codes(data);
+        for expected in [
+            (Severity::Warning, "reserved-name"),
+            (Severity::Warning, "duplicate-file"),
+            (Severity::Error, "duplicate-file"),
+            (Severity::Error, "piece-length"),
+            (Severity::Error, "pieces"),
+        ] {
+            assert!(
+                found.contains(&expected),
+                "{:?} not in {:?}",
+                expected,
+                found
+            );
+        }
+        assert!(lint(data).has_errors());
+
+        // The parser refuses a traversal; lint names it
+        let data = b"d4:infod5:filesld6:lengthi1e4:pathl2:..1:xeee4:name1:tee";
+        assert!(codes(data).contains(&(Severity::Error, "path-traversal")));
+
+        let raw = b"d4:infod6:lengthi1e4:name2:\xff\xfe12:piece lengthi3e6:pieces20:\
+                    aaaaaaaaaaaaaaaaaaaaee";
+        assert_eq!(
+            codes(raw),
+            vec![
+                (Severity::Warning, "non-utf8"),
+                (Severity::Info, "piece-length")
+            ]
+        );
+        assert_eq!(codes(b"i1e"), vec![(Severity::Error, "no-info")]);
+        assert_eq!(codes(b"d4:info"), vec![(Severity::Error, "parse")]);
+    }
+}
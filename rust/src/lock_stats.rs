@@ -0,0 +1,231 @@
+//! Repeat offenders: which executables unlock keeps finding (`stats locks`).
+//!
+//! Terminating the same antivirus scanner or search indexer on every run
+//! treats the symptom; an exclusion for the seed folders fixes the cause.
+//! Each unlock run counts the executables that held files in `state`, one
+//! `locks.<name>` entry each: runs it was found in, runs it was terminated
+//! in, first and last seen, and runs per day for the last `HISTORY_DAYS`
+//! days. `stats locks` lists them, most frequent over the last 30 days first.
+
+use crate::console;
+use crate::json::Json;
+use crate::logger::{self, Level, Record};
+use crate::state;
+use crate::unlock::UnlockReport;
+
+use std::cmp::Reverse;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// State key prefix; the executable name follows.
+const PREFIX: &str = "locks.";
+/// Days of per-day counts kept.
+const HISTORY_DAYS: u64 = 90;
+const DAY: u64 = 86_400;
+
+/// What is known about one executable.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Offender {
+    seen: u64,
+    killed: u64,
+    /// Unix seconds.
+    first: u64,
+    last: u64,
+    /// (days since the epoch, runs that day), oldest first.
+    days: Vec<(u64, u64)>,
+}
+
+impl Offender {
+    /// Parse a stored `name=value` list; unknown or broken fields are 0.
+    fn parse(value: &str) -> Offender {
+        let mut offender = Offender::default();
+        for (name, v) in value.split(' ').filter_map(|pair| pair.split_once('=')) {
+            match name {
+                "seen" => offender.seen = v.parse().unwrap_or(0),
+                "killed" => offender.killed = v.parse().unwrap_or(0),
+                "first" => offender.first = v.parse().unwrap_or(0),
+                "last" => offender.last = v.parse().unwrap_or(0),
+                "days" => {
+                    offender.days = v
+                        .split(',')
+                        .filter_map(|d| d.split_once(':'))
+                        .filter_map(|(day, n)| Some((day.parse().ok()?, n.parse().ok()?)))
+                        .collect()
+                }
+                _ => {}
+            }
+        }
+        offender
+    }
+
+    fn render(&self) -> String {
+        let days: Vec<String> = self
+            .days
+            .iter()
+            .map(|(day, n)| format!("{}:{}", day, n))
+            .collect();
+        format!(
+            "seen={} killed={} first={} last={} days={}",
+            self.seen,
+            self.killed,
+            self.first,
+            self.last,
+            days.join(",")
+        )
+    }
+
+    /// Count one run at `now` (Unix seconds).
+    fn add(&mut self, killed: bool, now: u64) {
+        self.seen += 1;
+        if killed {
+            self.killed += 1;
+        }
+        if self.first == 0 {
+            self.first = now;
+        }
+        self.last = now;
+        let today = now / DAY;
+        self.days.retain(|(day, _)| day + HISTORY_DAYS > today);
+        match self.days.last_mut() {
+            Some((day, n)) if *day == today => *n += 1,
+            _ => self.days.push((today, 1)),
+        }
+    }
+
+    /// Runs in the `days` days up to `now`, today included.
+    fn recent(&self, days: u64, now: u64) -> u64 {
+        let today = now / DAY;
+        self.days
+            .iter()
+            .filter(|(day, _)| day + days > today)
+            .map(|(_, n)| n)
+            .sum()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Count the executables of one unlock run; failures only go to the log.
+pub fn record(result: &Result<UnlockReport, String>) {
+    let Ok(report) = result else {
+        return;
+    };
+    let now = now();
+    let mut names: Vec<&str> = report.locking.iter().map(|(_, n)| n.as_str()).collect();
+    names.sort_unstable();
+    names.dedup();
+    for name in names {
+        let killed = report.terminated.iter().any(|(_, n)| n == name);
+        let key = format!("{}{}", PREFIX, name.replace(['\t', '\r', '\n'], " "));
+        let counted = state::modify(&key, |value| {
+            let mut offender = Offender::parse(value.unwrap_or(""));
+            offender.add(killed, now);
+            Some(offender.render())
+        });
+        if let Err(e) = counted {
+            Record::new(Level::Warn, "", "", "lock-stats")
+                .message(format!("lock statistics not updated: {}", e))
+                .write();
+            return;
+        }
+    }
+}
+
+/// `stats locks` command: executables by how often they held files.
+pub fn run() {
+    let now = now();
+    let mut offenders: Vec<(String, Offender)> = state::entries(PREFIX)
+        .into_iter()
+        .map(|(name, value)| (name, Offender::parse(&value)))
+        .collect();
+    offenders.sort_by_key(|(name, o)| (Reverse(o.recent(30, now)), Reverse(o.seen), name.clone()));
+
+    if console::is_json() {
+        let list = offenders
+            .iter()
+            .map(|(name, o)| {
+                Json::object()
+                    .with("name", name.as_str())
+                    .with("last_7_days", o.recent(7, now) as i64)
+                    .with("last_30_days", o.recent(30, now) as i64)
+                    .with("seen", o.seen as i64)
+                    .with("killed", o.killed as i64)
+                    .with("first_seen", logger::date(o.first))
+                    .with("last_seen", logger::date(o.last))
+            })
+            .collect();
+        console::result(
+            &Json::object()
+                .with("command", "stats")
+                .with("target", "locks")
+                .with("ok", true)
+                .with("locks", Json::Array(list)),
+        );
+        return;
+    }
+    if offenders.is_empty() {
+        println!("No locking processes recorded yet; unlock runs add them.");
+        return;
+    }
+    println!(
+        "{:<28} {:>6} {:>7} {:>6} {:>6}  {:<10}  last seen",
+        "executable", "7 days", "30 days", "total", "killed", "first seen"
+    );
+    for (name, o) in &offenders {
+        println!(
+            "{:<28} {:>6} {:>7} {:>6} {:>6}  {:<10}  {}",
+            name,
+            o.recent(7, now),
+            o.recent(30, now),
+            o.seen,
+            o.killed,
+            logger::date(o.first),
+            logger::date(o.last)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offender() {
+        let start = 20_000 * DAY + 3_600;
+        let mut offender = Offender::default();
+        offender.add(true, start);
+        offender.add(false, start + 60);
+        offender.add(true, start + 10 * DAY);
+        assert_eq!(offender.days, vec![(20_000, 2), (20_010, 1)]);
+        assert_eq!((offender.seen, offender.killed), (3, 2));
+        assert_eq!(offender.first, start);
+        assert_eq!(offender.recent(7, start + 10 * DAY), 1);
+        assert_eq!(offender.recent(30, start + 10 * DAY), 3);
+
+        let stored = offender.render();
+        assert_eq!(
+            stored,
+            format!(
+                "seen=3 killed=2 first={} last={} days=20000:2,20010:1",
+                start,
+                start + 10 * DAY
+            )
+        );
+        assert_eq!(Offender::parse(&stored), offender);
+
+        // Day counts older than the history are dropped
+        offender.add(false, start + 100 * DAY);
+        assert_eq!(offender.days, vec![(20_100, 1)]);
+        assert_eq!(offender.seen, 4);
+        assert_eq!(
+            Offender::parse("seen=x days=1:2,bad"),
+            Offender {
+                days: vec![(1, 2)],
+                ..Offender::default()
+            }
+        );
+    }
+}
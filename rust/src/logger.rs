@@ -1,21 +1,374 @@
 //! Simple log file writer.
 //!
-//! Prepends timestamped lines to `zDirComp.log` next to the executable.
-//! Newest entries are always at the top of the file.
-//! All errors are silently ignored (best-effort logging).
+//! Two formats, selected once at startup with `--log-format`:
+//! - `text` (default): prepends timestamped lines to `zDirComp.log` next to the
+//!   executable. Newest entries are always at the top of the file.
+//! - `jsonl`: appends one JSON object per record to `zDirComp.jsonl`, oldest
+//!   first, so log shippers (Promtail, Filebeat) can tail it.
+//!
+//! If the file next to the executable cannot be written (read-only install
+//! directory, file locked by a viewer), `%LOCALAPPDATA%\zDirComp\` is used
+//! instead; `--log <path>` replaces both. Other errors are silently ignored
+//! (best-effort logging); `check` tells at startup whether logging works.
+//!
+//! Writes are serialized across processes with a named mutex per log file
+//! (`WriteLock`): a client finishing many torrents starts several instances
+//! at once, and the text log's read-and-rewrite would otherwise lose lines.
+//!
+//! Use `Record::emit` for events the user should also see on the console;
+//! `Record::write` only touches the log file.
+//!
+//! Every record carries the run ID (`run_id`), a short ULID generated once
+//! per process, so interleaved lines of concurrent runs can be told apart.
+//! JSON records also carry the build (`build_info::build_id`).
+//! The last `RECENT_LIMIT` records are also kept in memory for crash reports.
 
+use crate::build_info;
+use crate::console;
+use crate::json::Json;
+use crate::paths;
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
-/// Get the log file path (next to the executable).
-fn log_path() -> Option<PathBuf> {
-    std::env::current_exe().ok().and_then(|p| p.parent().map(|d| d.join("zDirComp.log")))
+/// Log output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Jsonl,
 }
 
-/// Format current local time as `[YYYY-MM-DD HH:MM:SS]`.
-fn timestamp() -> String {
+impl Format {
+    /// Parse a `--log-format` value.
+    pub fn parse(s: &str) -> Option<Format> {
+        match s.to_lowercase().as_str() {
+            "text" => Some(Format::Text),
+            "jsonl" | "json" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// Select the log format for the rest of the process.
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => Format::Jsonl,
+        _ => Format::Text,
+    }
+}
+
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Write the log to `path` instead of the default locations (`--log`).
+pub fn set_log_file(path: PathBuf) {
+    let _ = LOG_FILE.set(path);
+}
+
+/// Crockford base32, as used by ULIDs.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// ID of this run: a 16-character ULID variant (48-bit millisecond
+/// timestamp, 30 random bits), so IDs sort by start time.
+pub fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        // RandomState is seeded from the OS; no RNG crate needed
+        let random = RandomState::new().hash_one(std::process::id());
+        short_ulid(millis, random)
+    })
+}
+
+fn short_ulid(millis: u64, random: u64) -> String {
+    let timestamp = (0..10).rev().map(|i| (millis >> (i * 5)) & 31);
+    let randomness = (0..6).rev().map(|i| (random >> (i * 5)) & 31);
+    timestamp
+        .chain(randomness)
+        .map(|d| CROCKFORD[d as usize] as char)
+        .collect()
+}
+
+/// Severity of a log record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// One log event.
+///
+/// `command` is the mode in upper case (`SYNC`, `UNLOCK`), `target` the
+/// directory or file the run was invoked on, and `action` a short stable
+/// keyword (`delete`, `abort`, `summary`) for filtering in log pipelines.
+#[derive(Debug)]
+pub struct Record<'a> {
+    level: Level,
+    command: &'a str,
+    target: &'a str,
+    action: &'a str,
+    path: Option<&'a Path>,
+    code: Option<i64>,
+    message: String,
+}
+
+impl<'a> Record<'a> {
+    pub fn new(level: Level, command: &'a str, target: &'a str, action: &'a str) -> Record<'a> {
+        Record {
+            level,
+            command,
+            target,
+            action,
+            path: None,
+            code: None,
+            message: String::new(),
+        }
+    }
+
+    /// Human-readable message (the text after the dash in text logs).
+    pub fn message(mut self, message: impl Into<String>) -> Record<'a> {
+        self.message = message.into();
+        self
+    }
+
+    /// File the event is about, relative to the target.
+    pub fn path(mut self, path: &'a Path) -> Record<'a> {
+        self.path = Some(path);
+        self
+    }
+
+    /// OS or API error code.
+    pub fn code(mut self, code: Option<i64>) -> Record<'a> {
+        self.code = code;
+        self
+    }
+
+    /// Render in the classic text layout (without timestamp).
+    fn to_text(&self) -> String {
+        if self.target.is_empty() {
+            format!("{}: {}", self.level.as_str().to_uppercase(), self.message)
+        } else {
+            format!("{} {:?} — {}", self.command, self.target, self.message)
+        }
+    }
+
+    /// Render for the console: the text layout without the level prefix,
+    /// which `console` adds (colorized) itself.
+    fn to_console(&self) -> String {
+        if self.target.is_empty() {
+            self.message.clone()
+        } else {
+            format!("{} {:?} — {}", self.command, self.target, self.message)
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::object()
+            .with("ts", iso_timestamp())
+            .with("run", run_id())
+            .with("build", build_info::build_id())
+            .with("level", self.level.as_str())
+            .with("command", self.command)
+            .with("target", self.target)
+            .with("action", self.action)
+            .with("path", self.path.map(|p| p.to_string_lossy().into_owned()))
+            .with("code", self.code)
+            .with("message", self.message.as_str())
+    }
+
+    /// Write the record to the log file in the configured format.
+    pub fn write(self) {
+        remember(format!("{} {}", timestamp(), self.to_text()));
+        match format() {
+            Format::Text => prepend_line(&self.to_text()),
+            Format::Jsonl => append_line(&self.to_json().to_string()),
+        }
+    }
+
+    /// Print the record on the console (subject to `--quiet`/`--verbose`),
+    /// then write it to the log file.
+    pub fn emit(self) {
+        console::print(self.level, &self.to_console());
+        self.write();
+    }
+}
+
+/// Number of records kept in memory for `recent`.
+const RECENT_LIMIT: usize = 50;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+fn remember(line: String) {
+    if let Ok(mut recent) = RECENT.lock() {
+        if recent.len() == RECENT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+}
+
+/// The most recent records in text layout, oldest first.
+pub fn recent() -> Vec<String> {
+    RECENT
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Locations tried for a log file, in order: next to the executable, then
+/// `%LOCALAPPDATA%\zDirComp`.
+pub fn log_paths(file_name: &str) -> Vec<PathBuf> {
+    let exe_dir = std::env::current_exe().ok().and_then(|p| p.parent().map(Path::to_path_buf));
+    let local = std::env::var_os("LOCALAPPDATA").map(|d| PathBuf::from(d).join("zDirComp"));
+    exe_dir.into_iter().chain(local).map(|d| d.join(file_name)).collect()
+}
+
+/// Locations for the log of the configured format: the `--log` path alone,
+/// or the defaults.
+fn active_paths() -> Vec<PathBuf> {
+    match LOG_FILE.get() {
+        Some(path) => vec![path.clone()],
+        None => log_paths(match format() {
+            Format::Text => "zDirComp.log",
+            Format::Jsonl => "zDirComp.jsonl",
+        }),
+    }
+}
+
+/// Run `write` on each of `paths` (creating missing parent directories)
+/// until one succeeds; returns that path. Each attempt holds the path's
+/// `WriteLock`.
+pub fn write_first(
+    paths: &[PathBuf],
+    write: impl Fn(&Path) -> io::Result<()>,
+) -> Option<PathBuf> {
+    paths
+        .iter()
+        .find(|path| {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            let _lock = WriteLock::acquire(path);
+            write(path).is_ok()
+        })
+        .cloned()
+}
+
+/// How long a write waits for another process's lock before going ahead
+/// unlocked.
+const LOCK_TIMEOUT_MS: u32 = 5000;
+const WAIT_OBJECT_0: u32 = 0;
+const WAIT_ABANDONED: u32 = 0x80;
+
+extern "system" {
+    fn CreateMutexW(
+        lpMutexAttributes: *const std::ffi::c_void,
+        bInitialOwner: i32,
+        lpName: *const u16,
+    ) -> *mut std::ffi::c_void;
+    fn WaitForSingleObject(hHandle: *mut std::ffi::c_void, dwMilliseconds: u32) -> u32;
+    fn ReleaseMutex(hMutex: *mut std::ffi::c_void) -> i32;
+    fn CloseHandle(hObject: *mut std::ffi::c_void) -> i32;
+}
+
+/// Named mutex held while one process writes a log file.
+struct WriteLock(*mut std::ffi::c_void);
+
+impl WriteLock {
+    /// Take the lock for `path`; `None` (write unlocked) if the mutex cannot
+    /// be created or another process holds it past `LOCK_TIMEOUT_MS`.
+    fn acquire(path: &Path) -> Option<WriteLock> {
+        let name = paths::to_wide(mutex_name(path));
+        unsafe {
+            let handle = CreateMutexW(std::ptr::null(), 0, name.as_ptr());
+            if handle.is_null() {
+                return None;
+            }
+            match WaitForSingleObject(handle, LOCK_TIMEOUT_MS) {
+                // Abandoned: the previous owner died mid-write, the lock is ours now
+                WAIT_OBJECT_0 | WAIT_ABANDONED => Some(WriteLock(handle)),
+                _ => {
+                    CloseHandle(handle);
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        unsafe {
+            ReleaseMutex(self.0);
+            CloseHandle(self.0);
+        }
+    }
+}
+
+/// Mutex name for a log file: the same in every process (FNV-1a of the
+/// lowercased path), and free of backslashes, which names may not contain.
+fn mutex_name(path: &Path) -> String {
+    let hash = path
+        .to_string_lossy()
+        .to_lowercase()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("zDirComp.log.{:016x}", hash)
+}
+
+/// Append `text` to the first writable of `paths`.
+pub fn append_first(paths: &[PathBuf], text: &str) -> Option<PathBuf> {
+    write_first(paths, |path| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(text.as_bytes())
+    })
+}
+
+/// Find where the log will be written; the error lists the locations that
+/// all failed.
+pub fn check() -> Result<PathBuf, String> {
+    let paths = active_paths();
+    append_first(&paths, "").ok_or_else(|| {
+        let tried: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        format!("logging unavailable, cannot write {}", tried.join(" or "))
+    })
+}
+
+/// Local wall-clock time: (year, month, day, hour, minute, second, utc offset secs).
+fn local_time() -> (i64, i64, i64, i64, i64, i64, i64) {
     let now = SystemTime::now();
     let since_epoch = now
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -35,12 +388,36 @@ fn timestamp() -> String {
     // Convert days since epoch to Y-M-D (civil calendar)
     let (year, month, day) = days_to_ymd(days);
 
+    (year, month, day, hours, minutes, seconds, offset_secs)
+}
+
+/// Format current local time as `[YYYY-MM-DD HH:MM:SS]`.
+fn timestamp() -> String {
+    let (year, month, day, hours, minutes, seconds, _) = local_time();
     format!(
         "[{:04}-{:02}-{:02} {:02}:{:02}:{:02}]",
         year, month, day, hours, minutes, seconds
     )
 }
 
+/// Format current local time as RFC 3339, e.g. `2026-02-07T21:30:00+07:00`.
+pub fn iso_timestamp() -> String {
+    let (year, month, day, hours, minutes, seconds, offset) = local_time();
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.abs() / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        year, month, day, hours, minutes, seconds, sign, offset / 60, offset % 60
+    )
+}
+
+/// Local date of Unix time `secs`, as `YYYY-MM-DD`.
+pub fn date(secs: u64) -> String {
+    let days = (secs as i64 + local_utc_offset_secs()).div_euclid(86400);
+    let (year, month, day) = days_to_ymd(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
 /// Convert days since Unix epoch to (year, month, day).
 fn days_to_ymd(days: i64) -> (i64, i64, i64) {
     // Algorithm from Howard Hinnant's chrono-compatible date algorithms
@@ -57,6 +434,50 @@ fn days_to_ymd(days: i64) -> (i64, i64, i64) {
     (y, m, d)
 }
 
+/// Convert (year, month, day) to days since the Unix epoch.
+fn ymd_to_days(year: i64, month: i64, day: i64) -> i64 {
+    // Inverse of `days_to_ymd`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parse a point in time given on the command line: Unix seconds, or
+/// `YYYY-MM-DD[THH:MM[:SS]]` in local time (UTC with a trailing `Z`).
+pub fn parse_time(s: &str) -> Option<SystemTime> {
+    if let Ok(secs) = s.parse::<u64>() {
+        return SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs));
+    }
+    let (s, utc) = match s.strip_suffix(['Z', 'z']) {
+        Some(s) => (s, true),
+        None => (s, false),
+    };
+    let (date, time) = s.split_once(['T', ' ']).unwrap_or((s, "00:00"));
+    let number = |part: &str, range: std::ops::RangeInclusive<i64>| {
+        part.parse::<i64>().ok().filter(|n| range.contains(n))
+    };
+    let mut date = date.split('-');
+    let year = number(date.next()?, 1970..=9999)?;
+    let month = number(date.next()?, 1..=12)?;
+    let day = number(date.next()?, 1..=31)?;
+    let mut time = time.split(':');
+    let hours = number(time.next()?, 0..=23)?;
+    let minutes = number(time.next()?, 0..=59)?;
+    let seconds = time.next().map_or(Some(0), |s| number(s, 0..=59))?;
+    if date.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    let offset = if utc { 0 } else { local_utc_offset_secs() };
+    let secs = ymd_to_days(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds
+        - offset;
+    let secs = u64::try_from(secs).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
 /// Get local UTC offset in seconds using Win32 API.
 fn local_utc_offset_secs() -> i64 {
     #[repr(C)]
@@ -83,17 +504,107 @@ fn local_utc_offset_secs() -> i64 {
 }
 
 /// Prepend a log line to the top of the log file (newest first).
-pub fn log(message: &str) {
-    if let Some(path) = log_path() {
-        let new_line = format!("{} {}\n", timestamp(), message);
-
+fn prepend_line(message: &str) {
+    let new_line = format!("{} [{}] {}\n", timestamp(), run_id(), message);
+    write_first(&active_paths(), |path| {
         // Read existing content (empty if file doesn't exist yet)
-        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let existing = fs::read_to_string(path).unwrap_or_default();
 
         // Write new line + existing content
-        if let Ok(mut file) = fs::File::create(&path) {
-            let _ = file.write_all(new_line.as_bytes());
-            let _ = file.write_all(existing.as_bytes());
+        let mut file = fs::File::create(path)?;
+        file.write_all(new_line.as_bytes())?;
+        file.write_all(existing.as_bytes())
+    });
+}
+
+/// Append a line to the JSON lines log (oldest first).
+fn append_line(line: &str) {
+    append_first(&active_paths(), &format!("{}\n", line));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_text_layout() {
+        let rec = Record::new(Level::Info, "SYNC", "E:\\Online\\A", "summary")
+            .message("clean, nothing to remove");
+        assert_eq!(rec.to_text(), "SYNC \"E:\\\\Online\\\\A\" — clean, nothing to remove");
+
+        let rec = Record::new(Level::Error, "", "", "usage").message("bad args");
+        assert_eq!(rec.to_text(), "ERROR: bad args");
+        assert_eq!(rec.to_console(), "bad args");
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let rec = Record::new(Level::Warn, "SYNC", "D:\\x", "delete")
+            .path(Path::new("a.txt"))
+            .code(Some(5))
+            .message("failed");
+        let json = rec.to_json().to_string();
+        assert!(json.starts_with("{\"ts\":"));
+        assert!(json.contains(&format!("\"run\":\"{}\"", run_id())));
+        assert!(json.contains("\"level\":\"warn\",\"command\":\"SYNC\",\"target\":\"D:\\\\x\""));
+        assert!(json.contains("\"action\":\"delete\",\"path\":\"a.txt\",\"code\":5"));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let secs = |s| {
+            parse_time(s).map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs())
+        };
+        assert_eq!(secs("1700000000"), Some(1_700_000_000));
+        assert_eq!(secs("2023-11-14T22:13:20Z"), Some(1_700_000_000));
+        assert_eq!(secs("2024-02-29Z"), Some(1_709_164_800));
+        assert_eq!(secs("2024-02-29 00:00Z"), Some(1_709_164_800));
+        assert_eq!(secs("2024-13-01Z"), None);
+        assert_eq!(secs("2024-01-01T10Z"), None);
+        assert_eq!(secs("yesterday"), None);
+        for days in [0, 11_016, 19_782, 2_932_896] {
+            let (y, m, d) = days_to_ymd(days);
+            assert_eq!(ymd_to_days(y, m, d), days);
         }
     }
+
+    #[test]
+    fn test_short_ulid() {
+        assert_eq!(short_ulid(0, 0), "0000000000000000");
+        // Timestamp part of the ULID spec example 01ARZ3NDEKTSV4RRFFQ69G5FAV
+        assert_eq!(&short_ulid(1_469_922_850_259, u64::MAX)[..10], "01ARZ3NDEK");
+        assert_eq!(&short_ulid(0, 31)[10..], "00000Z");
+        assert_eq!(run_id().len(), 16);
+    }
+
+    #[test]
+    fn test_recent_ring() {
+        for i in 0..RECENT_LIMIT + 10 {
+            remember(format!("line {}", i));
+        }
+        let recent = recent();
+        assert_eq!(recent.len(), RECENT_LIMIT);
+        assert_eq!(recent[0], "line 10");
+    }
+
+    #[test]
+    fn test_mutex_name() {
+        let name = mutex_name(Path::new("C:\\Tools\\zDirComp.log"));
+        assert_eq!(name, mutex_name(Path::new("c:\\tools\\ZDIRCOMP.LOG")));
+        assert_ne!(name, mutex_name(Path::new("C:\\Tools\\zDirComp.jsonl")));
+        assert!(name.starts_with("zDirComp.log.") && !name.contains('\\'));
+    }
+
+    #[test]
+    fn test_append_first_falls_back() {
+        let dir = TempDir::new("logfall");
+        fs::create_dir_all(dir.join("taken.log")).unwrap();
+
+        // A directory in the way of the first location
+        let paths = [dir.join("taken.log"), dir.join("fallback").join("z.log")];
+        assert_eq!(append_first(&paths, "x\n"), Some(paths[1].clone()));
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "x\n");
+        assert_eq!(append_first(&paths[..1], "x\n"), None);
+    }
 }
@@ -2,52 +2,1291 @@
 //!
 //! Two modes:
 //!   sync   <torrent_file> <directory>  — delete extra files not in torrent
-//!   unlock <directory>                 — kill all processes locking files (RmForceShutdown)
+//!                                        (torrent_file may be `-` for stdin, or an http(s) URL)
+//!   unlock <directory>...              — kill all processes locking files (RmForceShutdown)
+//!          [--pid N | --name EXE]      — only that process, if it locks files there
+//!          [--kill-tree]               — also kill descendants of killed processes
+//!          [--who-details]             — also list which files each process holds
+//!          [--session current|all|ID]  — whose processes to kill (default: this session)
+//!          [--kill-default auto|ask|never] — for executables on no auto-/ask-/never-kill list
+//!          [--approve-kill-hook CMD]   — approves `ask` kills when there is no console
+//!   verify <torrent_file> <directory>  — check piece hashes (read-only)
+//!   sync-client <infohash> <directory> — sync using the file list from --client
+//!   sync-all --bt-backup DIR           — sync every torrent of a qBittorrent BT_backup folder
+//!   bench <directory>                  — measure walk/read/SHA-1 speed, suggest --threads
+//!   purge <directory>                  — delete kept files older than --retention (default 14d)
+//!   orphans <root> --torrents DIR|client [--quarantine] — entries no torrent owns
+//!     [--older-than 30d] [--min-size 1G] [--protect GLOB]... — hold back from quarantine
+//!   report <root> --torrents DIR --out FILE [--sample N] — HTML report on the whole library
+//!   repair-torrent <in> <out>          — rewrite a malformed torrent as a clean one
+//!   selftest                           — run sync and hashing against temp trees, check results
+//!   lint <torrent_file>                — check a torrent for unsafe paths and broken piece data
+//!   align-check <torrentA> <torrentB>  — can one copy of the data seed both (cross-seeding)
+//!   self-update [--channel stable]     — install a newer release from --update-url
+//!   version [--verbose]                — version; with --verbose commit, platform, features
+//!   metrics                            — cumulative run counters, Prometheus text format
+//!   exclusions add|remove <directory> — Defender exclusion and no indexing, against lockers
+//!   stats locks                        — executables unlock keeps finding, most frequent first
+//!   install / uninstall [--purge]      — per-user copy in %LOCALAPPDATA%\Programs, on Path
+//!   install-task --every 6h --args A   — run `zDirComp A` periodically (Task Scheduler)
+//!   uninstall-task                     — remove that task (both take --task-name)
+//!   audit-verify <directory>           — check the hash chain of --audit manifests
+//!   audit-complete --bt-backup DIR     — hash sampled pieces qBittorrent claims (--sample N)
+//!   completion powershell              — tab-completion script for commands and options
+//!   serve --api-token T [--listen A]   — localhost HTTP API queuing sync jobs (service feature)
+//!         [--job-timeout 30m]          — fail a job running longer and start the next
+//!   fuzz-bencode <file|dir>...         — run inputs through the bencode parsers (fuzz feature)
+//!
+//! `s`, `u` and `v` are short for sync, unlock and verify. A first argument
+//! ending in `.torrent` is the Java tool's `file.torrent [directory [+] [-]
+//! [=]]` form, handled read-only by `legacy`.
+//!
+//! Double-clicked from Explorer with no arguments, it asks for the torrent
+//! and directory with file/folder pickers and runs sync after confirmation.
+//!
+//! Global options:
+//!   --profile NAME                     — defaults from [profile.NAME] in zDirComp.toml
+//!   --rules FILE                       — keep/exclude/companion rules (repeatable)
+//!   --label TEXT                       — client label/category, `%L` in config values
+//!
+//! `%VAR%` / `${VAR}` are expanded in option values, not in positional
+//! arguments (a folder may be named `100%% done`); config values may also
+//! use the client tokens `%D %F %N %L %I` (see `expand`). WSL drive paths
+//! (`/mnt/e/Online/...`) are read as Windows paths (`E:\Online\...`).
+//!   --log-format text|jsonl            — classic text log or JSON lines
+//!   --audit DIR                        — hash-chained JSONL manifest of every action taken
+//!   --log PATH                         — log file (default: beside the exe or %LOCALAPPDATA%)
+//!   --quiet / --verbose                — console output: errors only / everything
+//!   --format text|json                 — json: one result object on stdout, messages on stderr
+//!   --tui                              — review the sync deletion plan before deleting
+//!   --clear-motw                       — strip Zone.Identifier from kept files after sync
+//!   --dir DIR (repeatable)             — more roots of a payload split across drives
+//!   --map FILE                         — torrent path<TAB>disk path lines for relocated files
+//!   --subpath REL                      — sync only this torrent-relative subtree
+//!   --priorities FILE                  — torrent path<TAB>priority lines; 0 = not downloaded
+//!   --delete-skipped                   — delete leftovers of files set to "don't download"
+//!   --skip-unchanged                   — skip dirs unchanged since the last clean sync/verify
+//!   --include-system                   — also delete hidden+system extras (desktop.ini, ...)
+//!   --protect GLOB (repeatable)        — sync: never delete matching files
+//!   --older-than 30d                   — sync: only delete extras unchanged this long
+//!   --delete-rate N                    — sync: file operations/s (network drives: 200; 0 = off)
+//!   --fix-case                         — rename case-only mismatches to the torrent's spelling
+//!   --rename-root [--root-tag GLOB]... — give the folder info.name if it differs only trivially
+//!   --preserve-dir-times               — put back folder modification times the run changed
+//!   --touch-expected[=TIME]            — set expected files' modification time to now or TIME
+//!   --allow-running                    — also kill/delete under programs run from the directory
+//!   --allow-copy                       — let moves across volumes (junctions) copy+delete
+//!   --import-safe                      — defer deleting files linked into --library or open
+//!   --library DIR (repeatable)         — media library roots (Sonarr/Radarr) for --import-safe
+//!   --retention 14d                    — keep deleted files in .zdc_kept this long (m/h/d/w)
+//!   --max-delete-percent 60            — abort plans deleting more of the files or bytes
+//!   --force-large-delete               — skip that check
+//!   --snapshot-over N                  — VSS snapshot before deleting more than N files
+//!   --pre-delete-hook CMD              — run with each plan as JSON on stdin; exit != 0 vetoes
+//!   --post-run-hook CMD                — run with the sync summary on stdin; exit != 0 fails
+//!   --hook-timeout SECS                — kill a hook running longer; counts as a veto (300)
+//!   --max-files N / --max-depth N      — abort walks of larger/deeper trees (junction loops)
+//!   --max-memory MB                    — cap the working set (pages out past it)
+//!   --cpu-affinity 0-3,6               — run only on these CPUs
+//!   --priority idle|below-normal|normal|above-normal|high|background — process priority
+//!   --threads N                        — hasher threads for verify (default: CPU count)
+//!   --export sfv|md5|sha1              — verify: write checksums of the files that passed
+//!   --export-per-dir                   — one checksum file per directory, not per torrent
+//!   --trust-sfv                        — verify: skip files an unchanged .sfv/.md5/.sha1 lists
+//!   --incremental                      — verify: hash only files changed since last clean run
+//!   --post-action recheck|pause|none   — tell the client after sync changed files
+//!   --client qbittorrent|transmission|deluge|utorrent, --client-url, --client-user, --client-pass
+//!   --http-timeout SECS                — connect/send/receive timeout for WebUI calls
+//!   --update-url URL                   — where self-update finds <channel>.txt manifests
+//!   --media-server plex|jellyfin, --media-url, --media-token — wait while a file is streamed
 
+mod align;
+#[cfg(feature = "service")]
+mod api;
+mod audit;
+#[cfg(feature = "verify")]
+mod bench;
 mod bencode;
+mod bt_backup;
+mod build_info;
+mod cache;
+mod casing;
+#[cfg(feature = "verify")]
+mod checksum;
+mod cli;
+#[cfg(feature = "client-apis")]
+mod client;
+mod completion;
+mod config;
+mod console;
+mod crash;
+mod dir_index;
+mod dirtimes;
+mod doctor;
+mod exclusions;
+mod expand;
+mod fastresume;
+#[cfg(feature = "gui")]
+mod gui;
+mod handles;
+#[cfg(feature = "verify")]
+mod hashing;
+mod hooks;
+#[cfg(feature = "client-apis")]
+mod http;
+mod ignore;
+mod imports;
+mod install;
+mod json;
+mod legacy;
+mod lint;
+mod lock_stats;
 mod logger;
+#[cfg(feature = "client-apis")]
+mod media;
+mod metrics;
+mod orphans;
+mod pathmap;
+mod paths;
+mod piecemap;
+mod policy;
+mod priorities;
+mod process_tree;
+mod repair;
+mod report;
+mod resources;
+mod rules;
+mod restart_manager;
+mod retry;
+mod root_name;
 mod safety;
+mod schedule;
+mod selftest;
+mod sha;
+#[cfg(feature = "verify")]
+mod spot_check;
+#[cfg(feature = "verify")]
+mod sparse;
+mod state;
+mod streams;
 mod sync;
+#[cfg(test)]
+mod temp_dir;
+mod throttle;
+mod trash;
+#[cfg(feature = "tui")]
+mod tui;
 mod unlock;
+#[cfg(feature = "client-apis")]
+mod update;
+#[cfg(feature = "verify")]
+mod usn;
+#[cfg(feature = "verify")]
+mod verdict;
+#[cfg(feature = "verify")]
+mod verify;
+mod volume;
+mod vss;
+mod walk;
+
+use logger::{Level, Record};
 
 use std::env;
 use std::process;
 
+/// Report a usage error on stderr and in the log, then exit with code 1.
+fn usage_error(message: &str) -> ! {
+    Record::new(Level::Error, "", "", "usage").message(message).emit();
+    process::exit(1);
+}
+
+/// Print `result` for `--format json`: `command`, `target`, `ok` and either
+/// the report's fields or `error`.
+fn print_result(command: &str, target: &str, result: Result<json::Json, &String>) {
+    let json = json::Json::object()
+        .with("command", command)
+        .with("target", target)
+        .with("ok", result.is_ok());
+    console::result(&match (json, result) {
+        (json::Json::Object(mut fields), Ok(json::Json::Object(report))) => {
+            fields.extend(report);
+            json::Json::Object(fields)
+        }
+        (json, Ok(_)) => json,
+        (json, Err(e)) => json.with("error", e.as_str()),
+    });
+}
+
+/// A torrent argument of `-` needs the torrent piped in.
+fn check_stdin_torrent(torrent: &str) {
+    use std::io::IsTerminal;
+    if torrent == bencode::STDIN && std::io::stdin().is_terminal() {
+        usage_error("'-' reads the torrent from stdin, but nothing is piped in");
+    }
+}
+
+/// Settings from the config file that are not plain options.
+#[derive(Debug, Default)]
+struct Profile {
+    /// Sync directories must lie inside this path (`root`).
+    root: Option<String>,
+    /// Extra keep patterns, as in `.zdirignore` (`keep`).
+    keep: Vec<String>,
+    /// Rule files, relative to the config file (`rules`).
+    rules: Vec<std::path::PathBuf>,
+    /// More roots of a split payload, used when `--dir` is not given (`dirs`).
+    dirs: Vec<String>,
+    /// Media library roots, used when `--library` is not given (`library`).
+    library: Vec<String>,
+    /// Unlock kill policy lists (`auto-kill`, `ask-kill`, `never-kill`).
+    kill: unlock::KillPolicy,
+}
+
+/// Client token values for this invocation, taken from its arguments.
+fn tokens(args: &cli::Args) -> expand::Tokens {
+    let mut tokens = expand::Tokens::default();
+    let pos = &args.positional;
+    let command = pos.first().map(|c| c.to_lowercase());
+    let (first, dir) = match command.as_deref() {
+        Some("unlock") => (None, pos.get(1)),
+        _ => (pos.get(1), pos.get(2)),
+    };
+    if let Some(dir) = dir {
+        tokens.set('D', dir);
+        if let Some(name) = std::path::Path::new(dir.trim_end_matches(['\\', '/'])).file_name() {
+            tokens.set('N', &name.to_string_lossy());
+        }
+    }
+    match (command.as_deref(), first) {
+        (Some("sync"), Some(torrent)) => tokens.set('F', torrent),
+        (Some("sync-client"), Some(hash)) => tokens.set('I', hash),
+        _ => {}
+    }
+    if let Some(label) = args.value("label") {
+        tokens.set('L', label);
+    }
+    tokens
+}
+
+/// Merge `zDirComp.toml` into `args`: the `--profile` section first, then
+/// top-level settings. The command line always wins. String values are
+/// expanded with `tokens`.
+fn apply_config(args: &mut cli::Args, tokens: &expand::Tokens) -> Profile {
+    let config = config::load().unwrap_or_else(|e| usage_error(&e));
+    let mut sections = Vec::new();
+    if let Some(name) = args.value("profile") {
+        match config.profile(name) {
+            Some(entries) => sections.push((format!("[profile.{}]", name), entries)),
+            None => usage_error(&format!(
+                "Unknown profile '{}': no [profile.{}] in {}",
+                name,
+                name,
+                config::FILE_NAME
+            )),
+        }
+    }
+    if let Some(entries) = config.section("") {
+        sections.push(("top level".to_string(), entries));
+    }
+
+    let mut profile = Profile::default();
+    for (section, entries) in sections {
+        for (key, value) in entries {
+            let result = match (key.as_str(), value) {
+                ("root", config::Value::Str(s)) => {
+                    profile
+                        .root
+                        .get_or_insert_with(|| expand::expand(s, tokens));
+                    Ok(())
+                }
+                ("dirs", config::Value::List(dirs)) => {
+                    if profile.dirs.is_empty() {
+                        profile.dirs = dirs.iter().map(|d| expand::expand(d, tokens)).collect();
+                    }
+                    Ok(())
+                }
+                ("library", config::Value::List(dirs)) => {
+                    if profile.library.is_empty() {
+                        profile.library = dirs.iter().map(|d| expand::expand(d, tokens)).collect();
+                    }
+                    Ok(())
+                }
+                ("rules", config::Value::List(files)) => {
+                    // An empty base (no config path) leaves the files as given
+                    let base = config::path()
+                        .and_then(|p| p.parent().map(std::path::Path::to_path_buf))
+                        .unwrap_or_default();
+                    let files = files.iter().map(|f| base.join(expand::expand(f, tokens)));
+                    profile.rules.extend(files);
+                    Ok(())
+                }
+                ("auto-kill", config::Value::List(names)) => {
+                    profile.kill.auto.extend(names.iter().cloned());
+                    Ok(())
+                }
+                ("ask-kill", config::Value::List(names)) => {
+                    profile.kill.ask.extend(names.iter().cloned());
+                    Ok(())
+                }
+                ("never-kill", config::Value::List(names)) => {
+                    profile.kill.never.extend(names.iter().cloned());
+                    Ok(())
+                }
+                ("keep", config::Value::List(patterns)) => {
+                    profile
+                        .keep
+                        .extend(patterns.iter().map(|p| expand::expand(p, tokens)));
+                    Ok(())
+                }
+                (_, config::Value::Str(s)) => {
+                    args.set_default(key, Some(expand::expand(s, tokens)))
+                }
+                (_, config::Value::Int(n)) => args.set_default(key, Some(n.to_string())),
+                (_, config::Value::Bool(true)) => args.set_default(key, None),
+                (_, config::Value::Bool(false)) => Ok(()),
+                (_, config::Value::List(_)) => Err(format!("'{}' does not take a list", key)),
+            };
+            if let Err(e) = result {
+                usage_error(&format!("{} {}: {}", config::FILE_NAME, section, e));
+            }
+        }
+    }
+    profile
+}
+
+/// Apply `--max-memory`, `--cpu-affinity` and `--priority`. A limit the
+/// system refuses is logged and the run goes on without it.
+fn resource_limits(args: &cli::Args) {
+    let mut applied = Vec::new();
+    if let Some(v) = args.value("max-memory") {
+        let mb = match v.parse::<u64>() {
+            Ok(mb) if mb >= resources::MIN_MEMORY_MB => mb,
+            _ => usage_error(&format!(
+                "Invalid --max-memory value '{}' (MiB, at least {})",
+                v,
+                resources::MIN_MEMORY_MB
+            )),
+        };
+        applied.push(("--max-memory", resources::set_max_memory(mb)));
+    }
+    if let Some(v) = args.value("cpu-affinity") {
+        let mask = resources::parse_affinity(v)
+            .unwrap_or_else(|e| usage_error(&format!("Invalid --cpu-affinity '{}': {}", v, e)));
+        applied.push(("--cpu-affinity", resources::set_affinity(mask)));
+    }
+    if let Some(v) = args.value("priority") {
+        let priority = resources::Priority::parse(v).unwrap_or_else(|| {
+            usage_error(&format!(
+                "Unknown priority '{}'. Use idle, below-normal, normal, above-normal, high \
+                 or background.",
+                v
+            ))
+        });
+        applied.push(("--priority", resources::set_priority(priority)));
+    }
+    for (option, result) in applied {
+        if let Err(e) = result {
+            Record::new(Level::Warn, "", option, "limits")
+                .message(e)
+                .emit();
+        }
+    }
+}
+
+/// Whether `dir` lies inside the profile's `root` (always, without one).
+fn inside_root(profile: &Profile, dir: &str) -> bool {
+    profile.root.as_deref().is_none_or(|root| paths::within(root, dir))
+}
+
+/// Refuse to sync a directory outside the profile's `root`.
+fn check_root(profile: &Profile, dir: &str) {
+    if !inside_root(profile, dir) {
+        let root = profile.root.as_deref().unwrap_or_default();
+        usage_error(&format!("'{}' is outside the profile root '{}'", dir, root));
+    }
+}
+
+/// `sync-all`: sync every torrent of the `--bt-backup` folder, going on
+/// past failures. `Err` if the folder cannot be read or any sync failed.
+fn sync_all(source: &str, args: &cli::Args, profile: &Profile) -> Result<(), String> {
+    for name in ["dir", "subpath", "map", "priorities"] {
+        if args.value(name).is_some() {
+            usage_error(&format!("--{} describes one torrent; sync-all cannot use it", name));
+        }
+    }
+    let options = sync_options(args, profile);
+    if !options.extra_dirs.is_empty() {
+        usage_error("the profile's dirs describe one torrent; sync-all cannot use them");
+    }
+    let scan = bt_backup::scan(std::path::Path::new(source)).inspect_err(|e| {
+        Record::new(Level::Error, "SYNC", source, "abort")
+            .message(e.as_str())
+            .emit();
+    })?;
+    for (torrent, reason) in &scan.skipped {
+        Record::new(Level::Warn, "SYNC", source, "skip")
+            .path(torrent)
+            .message(format!("{:?} skipped: {}", torrent, reason))
+            .emit();
+    }
+    let (mut synced, mut failed, mut skipped) = (0, 0, scan.skipped.len());
+    for entry in &scan.entries {
+        let (torrent, dir) = (entry.torrent.to_string_lossy(), entry.dir.to_string_lossy());
+        if !inside_root(profile, &dir) {
+            Record::new(Level::Warn, "SYNC", &dir, "skip")
+                .message(format!("{:?} is outside the profile root, skipped", dir))
+                .emit();
+            skipped += 1;
+            continue;
+        }
+        let options = sync::Options {
+            priorities: entry.priorities.clone(),
+            path_map: entry.path_map.clone(),
+            ..options.clone()
+        };
+        let result = sync::run(&torrent, &dir, &options);
+        metrics::record_sync(&result);
+        print_result("sync", &dir, result.as_ref().map(sync::SyncReport::to_json));
+        match result {
+            Ok(_) => synced += 1,
+            Err(_) => failed += 1,
+        }
+    }
+    Record::new(Level::Info, "SYNC", source, "summary")
+        .message(format!(
+            "sync-all: {} synced, {} failed, {} skipped",
+            synced, failed, skipped
+        ))
+        .emit();
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{} of {} syncs failed", n, synced + n)),
+    }
+}
+
+/// Collect sync settings from the options.
+fn sync_options(args: &cli::Args, profile: &Profile) -> sync::Options {
+    let extra_dirs = match args.values("dir") {
+        dirs if dirs.is_empty() => profile.dirs.clone(),
+        dirs => dirs.into_iter().map(String::from).collect(),
+    };
+    for dir in &extra_dirs {
+        check_root(profile, dir);
+    }
+    sync::Options {
+        #[cfg(feature = "client-apis")]
+        post: post_action(args),
+        #[cfg(feature = "tui")]
+        review: review_flag(args),
+        clear_motw: args.flag("clear-motw"),
+        include_system: args.flag("include-system"),
+        fix_case: args.flag("fix-case"),
+        rename_root: args.flag("rename-root"),
+        root_tags: match args.values("root-tag") {
+            tags if tags.is_empty() => {
+                root_name::DEFAULT_TAGS.iter().map(|t| t.to_string()).collect()
+            }
+            tags => tags.into_iter().map(String::from).collect(),
+        },
+        preserve_dir_times: args.flag("preserve-dir-times"),
+        touch_expected: match args.value("touch-expected") {
+            Some(v) => Some(logger::parse_time(v).unwrap_or_else(|| {
+                usage_error(&format!(
+                    "Invalid --touch-expected time '{}'. Use e.g. '2024-05-01T12:00'.",
+                    v
+                ))
+            })),
+            None => args.flag("touch-expected").then(std::time::SystemTime::now),
+        },
+        extra_dirs,
+        path_map: path_map(args),
+        subpath: match args.value("subpath") {
+            Some(rel) => pathmap::relative_path(rel)
+                .unwrap_or_else(|e| usage_error(&format!("--subpath: {}", e))),
+            None => std::path::PathBuf::new(),
+        },
+        priorities: priorities(args),
+        delete_skipped: args.flag("delete-skipped"),
+        skip_unchanged: args.flag("skip-unchanged"),
+        retention: retention(args),
+        max_delete_percent: args.value("max-delete-percent").map(|v| match v.parse::<u32>() {
+            Ok(n) if (1..=100).contains(&n) => n,
+            _ => usage_error(&format!("Invalid --max-delete-percent value '{}'", v)),
+        }),
+        force_large_delete: args.flag("force-large-delete"),
+        snapshot_over: args.value("snapshot-over").map(|v| {
+            v.parse::<usize>()
+                .unwrap_or_else(|_| usage_error(&format!("Invalid --snapshot-over value '{}'", v)))
+        }),
+        pre_delete_hook: args.value("pre-delete-hook").map(String::from),
+        protect: args.values("protect").into_iter().map(String::from).collect(),
+        older_than: args.value("older-than").map(|v| {
+            trash::parse_retention(v).unwrap_or_else(|| {
+                usage_error(&format!("Invalid --older-than '{}'. Use e.g. '30d'.", v))
+            })
+        }),
+        post_run_hook: args.value("post-run-hook").map(String::from),
+        delete_rate: args.value("delete-rate").map(|v| {
+            v.parse::<u32>()
+                .unwrap_or_else(|_| usage_error(&format!("Invalid --delete-rate value '{}'", v)))
+        }),
+        import_safe: args.flag("import-safe"),
+        allow_running: args.flag("allow-running"),
+        library: match args.values("library") {
+            dirs if dirs.is_empty() => profile.library.iter().map(Into::into).collect(),
+            dirs => dirs.into_iter().map(Into::into).collect(),
+        },
+        #[cfg(feature = "client-apis")]
+        media: media_server(args),
+        rules: rules(args, profile),
+        order: args.value("order").map(|v| {
+            sync::Order::parse(v).unwrap_or_else(|| {
+                usage_error(&format!("Unknown order '{}'. Use 'size', 'path' or 'mtime'.", v))
+            })
+        }),
+    }
+}
+
+/// Full name of a command given by its short alias.
+fn command_alias(command: &str) -> String {
+    match command {
+        "s" => "sync",
+        "u" => "unlock",
+        "v" => "verify",
+        other => other,
+    }
+    .to_string()
+}
+
+/// The profile's keep patterns layered with the `--rules` files and the
+/// config's `rules` files.
+fn rules(args: &cli::Args, profile: &Profile) -> rules::Rules {
+    let mut rules = rules::Rules {
+        keep: profile.keep.clone(),
+        ..rules::Rules::default()
+    };
+    let files = args.values("rules").into_iter().map(std::path::Path::new);
+    for file in files.chain(profile.rules.iter().map(|p| p.as_path())) {
+        rules.extend(rules::Rules::load(file).unwrap_or_else(|e| usage_error(&e)));
+    }
+    rules
+}
+
+/// Parse `--retention`, if given.
+fn retention(args: &cli::Args) -> Option<std::time::Duration> {
+    args.value("retention").map(|v| {
+        trash::parse_retention(v).unwrap_or_else(|| {
+            usage_error(&format!("Invalid retention '{}'. Use e.g. '14d', '12h' or '2w'.", v))
+        })
+    })
+}
+
+/// Load the `--map` file, if given.
+fn path_map(args: &cli::Args) -> pathmap::PathMap {
+    match args.value("map") {
+        Some(file) => pathmap::PathMap::load(std::path::Path::new(file))
+            .unwrap_or_else(|e| usage_error(&e)),
+        None => pathmap::PathMap::default(),
+    }
+}
+
+/// Load the `--priorities` file, if given.
+fn priorities(args: &cli::Args) -> priorities::Priorities {
+    match args.value("priorities") {
+        Some(file) => priorities::Priorities::load(std::path::Path::new(file))
+            .unwrap_or_else(|e| usage_error(&e)),
+        None => priorities::Priorities::default(),
+    }
+}
+
+/// Collect unlock settings from the options.
+fn unlock_options(args: &cli::Args, profile: &Profile) -> unlock::Options {
+    let target = match (args.value("pid"), args.value("name")) {
+        (Some(_), Some(_)) => usage_error("--pid and --name cannot be used together"),
+        (Some(v), None) => match v.parse::<u32>() {
+            Ok(pid) if pid > 0 => unlock::Target::Pid(pid),
+            _ => usage_error(&format!("Invalid process ID '{}'", v)),
+        },
+        (None, Some(v)) if !v.is_empty() => unlock::Target::Name(v.to_string()),
+        (None, Some(_)) => usage_error("--name requires an executable name"),
+        (None, None) => unlock::Target::All,
+    };
+    unlock::Options {
+        target,
+        kill_tree: args.flag("kill-tree"),
+        who_details: args.flag("who-details"),
+        allow_running: args.flag("allow-running"),
+        policy: unlock::KillPolicy {
+            default: match args.value("kill-default") {
+                None => unlock::Decision::default(),
+                Some(v) => unlock::Decision::parse(v).unwrap_or_else(|| {
+                    usage_error(&format!(
+                        "Unknown --kill-default '{}'. Use auto, ask or never.",
+                        v
+                    ))
+                }),
+            },
+            approve_hook: args.value("approve-kill-hook").map(String::from),
+            ..profile.kill.clone()
+        },
+        session: match args.value("session") {
+            None => unlock::Session::default(),
+            Some(v) => unlock::Session::parse(v).unwrap_or_else(|| {
+                usage_error(&format!("Unknown session '{}'. Use 'current', 'all' or an ID.", v))
+            }),
+        },
+        #[cfg(feature = "client-apis")]
+        media: media_server(args),
+    }
+}
+
+/// `--tui` needs a console to draw on and read keys from.
+#[cfg(feature = "tui")]
+fn review_flag(args: &cli::Args) -> bool {
+    use std::io::IsTerminal;
+    let review = args.flag("tui");
+    if review && !(std::io::stdin().is_terminal() && std::io::stdout().is_terminal()) {
+        usage_error("--tui requires an interactive console");
+    }
+    review
+}
+
+/// Build the `--post-action` configuration from the client options.
+#[cfg(feature = "client-apis")]
+fn post_action(args: &cli::Args) -> client::PostAction {
+    let action = match args.value("post-action") {
+        None => return client::PostAction::default(),
+        Some(v) => client::Action::parse(v).unwrap_or_else(|| {
+            usage_error(&format!(
+                "Unknown post action '{}'. Use 'recheck', 'pause' or 'none'.",
+                v
+            ))
+        }),
+    };
+    if action == client::Action::None {
+        return client::PostAction::default();
+    }
+    client::PostAction {
+        action,
+        config: Some(client_config(args, "--post-action")),
+    }
+}
+
+/// Build the `--media-server` settings, if given.
+#[cfg(feature = "client-apis")]
+fn media_server(args: &cli::Args) -> Option<media::Server> {
+    let kind = args.value("media-server").map(|v| {
+        media::Kind::parse(v).unwrap_or_else(|| {
+            usage_error(&format!("Unknown media server '{}'. Use 'plex' or 'jellyfin'.", v))
+        })
+    })?;
+    let token = args
+        .value("media-token")
+        .unwrap_or_else(|| usage_error("--media-server requires --media-token"));
+    let server = media::Server::new(kind, args.value("media-url"), token);
+    Some(server.unwrap_or_else(|e| usage_error(&e)))
+}
+
+/// Build the `--client` connection settings; `needed_by` names the option
+/// or command that requires them, for the error message.
+#[cfg(feature = "client-apis")]
+fn client_config(args: &cli::Args, needed_by: &str) -> client::Config {
+    let kind = match args.value("client") {
+        None => usage_error(&format!("{} requires --client", needed_by)),
+        Some(v) => client::Kind::parse(v).unwrap_or_else(|| {
+            usage_error(&format!(
+                "Unknown client '{}'. Use 'qbittorrent', 'transmission', 'deluge' or 'utorrent'.",
+                v
+            ))
+        }),
+    };
+    client::Config::new(
+        kind,
+        args.value("client-url"),
+        args.value("client-user").unwrap_or(""),
+        args.value("client-pass").unwrap_or(""),
+    )
+    .unwrap_or_else(|e| usage_error(&e))
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    crash::install();
+    #[cfg(feature = "client-apis")]
+    update::remove_old();
+    let (raw, repairs) = cli::repair_quoting(&env::args().skip(1).collect::<Vec<_>>());
+    let mut args = match cli::Args::parse(&raw) {
+        Ok(a) => a,
+        Err(e) => usage_error(&e),
+    };
+    let no_tokens = expand::Tokens::default();
+    args.map_option_values(|v| expand::expand(v, &no_tokens));
+    args.map_values(paths::from_wsl);
+    let tokens = tokens(&args);
+    let profile = apply_config(&mut args, &tokens);
+
+    if let Some(value) = args.value("log-format") {
+        match logger::Format::parse(value) {
+            Some(format) => logger::set_format(format),
+            None => usage_error(&format!(
+                "Unknown log format '{}'. Use 'text' or 'jsonl'.",
+                value
+            )),
+        }
+    }
+    if let Some(value) = args.value("log") {
+        logger::set_log_file(std::path::PathBuf::from(value));
+    }
+    if let Some(value) = args.value("audit") {
+        audit::set_dir(std::path::PathBuf::from(value));
+    }
+
+    #[cfg(feature = "client-apis")]
+    if let Some(value) = args.value("http-timeout") {
+        match value.parse::<u64>() {
+            Ok(secs) if secs > 0 => http::set_timeout(std::time::Duration::from_secs(secs)),
+            _ => usage_error(&format!("Invalid HTTP timeout '{}'", value)),
+        }
+    }
+
+    if let Some(value) = args.value("hook-timeout") {
+        match value.parse::<u64>() {
+            Ok(secs) if secs > 0 => hooks::set_timeout(std::time::Duration::from_secs(secs)),
+            _ => usage_error(&format!("Invalid hook timeout '{}'", value)),
+        }
+    }
 
-    if args.len() < 2 {
+    let limit = |name: &str| {
+        args.value(name).map(|v| match v.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => usage_error(&format!("Invalid --{} value '{}'", name, v)),
+        })
+    };
+    safety::set_walk_limits(limit("max-files"), limit("max-depth"));
+    safety::set_allow_copy(args.flag("allow-copy"));
+
+    if args.flag("quiet") && args.flag("verbose") {
+        usage_error("--quiet and --verbose cannot be used together");
+    } else if args.flag("quiet") {
+        console::set_verbosity(console::Verbosity::Quiet);
+    } else if args.flag("verbose") {
+        console::set_verbosity(console::Verbosity::Verbose);
+    }
+    match args.value("format") {
+        None | Some("text") => {}
+        Some("json") => console::set_json(true),
+        Some(v) => usage_error(&format!("Unknown format '{}'. Use 'text' or 'json'.", v)),
+    }
+    if let Err(e) = logger::check() {
+        console::print(Level::Warn, &e);
+    }
+    resource_limits(&args);
+
+    // Report argument repairs and reject paths that are still mangled
+    // before any command touches the filesystem
+    for (original, split) in &repairs {
+        Record::new(Level::Warn, "", original, "repair-args")
+            .message(format!(
+                "argument {:?} had a stray quote (trailing \\\" from the caller), read as {:?}",
+                original, split
+            ))
+            .emit();
+    }
+    for arg in args.positional.iter().skip(1) {
+        if let Some(c) = cli::invalid_path_char(arg) {
+            usage_error(&format!("Argument {:?} contains invalid path character {:?}", arg, c));
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    if raw.is_empty() && gui::launched_from_explorer() {
+        gui::run();
+        return;
+    }
+
+    // Command lines written for the Java version keep their output format
+    if legacy::matches(&args.positional) {
+        legacy::run(&args.positional);
+        return;
+    }
+
+    let pos = &args.positional;
+    if pos.is_empty() {
         eprintln!("zDirComp — Torrent Directory Comparison & Cleanup Tool");
         eprintln!();
         eprintln!("Usage:");
         eprintln!("  zDirComp.exe sync   <torrent_file> <directory>  — delete extra files");
-        eprintln!("  zDirComp.exe unlock <directory>                 — kill locking processes");
+        eprintln!("  zDirComp.exe unlock <directory>...              — kill locking processes");
+        eprintln!("         [--pid N | --name EXE]                   — only that one, if it locks files");
+        eprintln!("         [--kill-tree]                            — also kill their child processes");
+        eprintln!("         [--who-details]                          — show which files each one holds");
+        eprintln!("         [--session current|all|ID]               — sessions to touch (default: this one)");
+        eprintln!("         [--kill-default auto|ask|never]          — for programs on no *-kill config list");
+        eprintln!("         [--approve-kill-hook CMD]                — approves 'ask' kills without a console");
+        #[cfg(feature = "verify")]
+        eprintln!("  zDirComp.exe verify <torrent_file> <directory>  — check piece hashes");
+        #[cfg(feature = "client-apis")]
+        eprintln!("  zDirComp.exe sync-client <infohash> <directory> — sync via client WebUI");
+        eprintln!("  zDirComp.exe sync-all --bt-backup DIR           — sync every torrent qBittorrent saved there");
+        eprintln!("  zDirComp.exe doctor <directory>                 — show detected environment");
+        eprintln!("  zDirComp.exe purge <directory>                  — delete kept files past --retention");
+        eprintln!("  zDirComp.exe orphans <root> --torrents DIR|client [--quarantine] — data no torrent owns");
+        eprintln!("      [--older-than 30d] [--min-size 1G] [--protect GLOB]... — never quarantine these");
+        eprintln!("  zDirComp.exe report <root> --torrents DIR --out FILE [--sample N] — HTML library report");
+        eprintln!("  zDirComp.exe repair-torrent <in> <out>          — fix a malformed torrent file");
+        eprintln!("  zDirComp.exe selftest                           — check this build on temp trees");
+        eprintln!("  zDirComp.exe lint <torrent_file>                — check a torrent for problems");
+        eprintln!("  zDirComp.exe align-check <torrentA> <torrentB>  — can one copy of the data seed both");
+        #[cfg(feature = "client-apis")]
+        eprintln!("  zDirComp.exe self-update [--channel stable]     — install a newer release");
+        #[cfg(feature = "fuzz")]
+        eprintln!("  zDirComp.exe fuzz-bencode <file|dir>...         — parse inputs for a fuzzer (fuzz feature)");
+        #[cfg(feature = "service")]
+        eprintln!("  zDirComp.exe serve --api-token T [--listen 127.0.0.1:8765] — HTTP job API");
+        #[cfg(feature = "service")]
+        eprintln!("         [--job-timeout 30m]                      — give up on jobs running longer");
+        eprintln!("  zDirComp.exe metrics                            — print run counters (Prometheus)");
+        eprintln!("  zDirComp.exe exclusions add|remove <directory> — Defender exclusion, no indexing");
+        eprintln!("  zDirComp.exe stats locks                        — programs that keep locking files");
+        eprintln!("  zDirComp.exe install                            — copy to %LOCALAPPDATA%\\Programs, add to Path");
+        eprintln!("  zDirComp.exe uninstall [--purge]                — undo install (--purge: also logs, state, settings)");
+        eprintln!("  zDirComp.exe install-task --every 6h --args \"...\" — schedule periodic runs");
+        eprintln!("  zDirComp.exe uninstall-task                     — remove the scheduled task (--task-name)");
+        eprintln!("  zDirComp.exe audit-verify <directory>           — check the --audit manifests' hash chain");
+        #[cfg(feature = "verify")]
+        eprintln!("  zDirComp.exe audit-complete --bt-backup DIR     — spot-check pieces the client claims (--sample N)");
+        eprintln!("  zDirComp.exe completion powershell              — print a tab-completion script");
+        eprintln!("  zDirComp.exe version [--verbose]                — show version and build details");
+        eprintln!("  zDirComp.exe <file.torrent> [directory [+] [-] [=]] — Java version: list/compare only");
+        eprintln!("  (s, u and v are short for sync, unlock and verify; torrent_file may be '-' (stdin) or a URL)");
+        #[cfg(feature = "verify")]
+        eprintln!("  zDirComp.exe bench <directory>                  — measure disk and hashing speed");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --profile NAME                                  — use [profile.NAME] settings");
+        eprintln!("  --rules FILE                                    — keep/exclude/companion rule file (repeatable)");
+        eprintln!("  --label TEXT                                    — label for %L in the config");
+        eprintln!("  --log-format text|jsonl                         — log file format");
+        eprintln!("  --log PATH                                      — write the log to PATH");
+        eprintln!("  --audit DIR                                     — hash-chained manifest of deletions and kills");
+        eprintln!("  --quiet                                         — console: errors only");
+        eprintln!("  --verbose                                       — console: include debug");
+        eprintln!("  --format text|json                              — json: result object on stdout (ConvertFrom-Json)");
+        #[cfg(feature = "tui")]
+        eprintln!("  --tui                                           — review deletions first");
+        eprintln!("  --clear-motw                                    — strip Mark-of-the-Web after sync");
+        eprintln!("  --order size|path|mtime                         — deletion order (largest/A-Z/oldest first)");
+        eprintln!("  --dir DIR                                       — another root of a split payload (repeatable)");
+        eprintln!("  --map FILE                                      — relocated files: torrent path<TAB>disk path");
+        eprintln!("  --subpath REL                                   — sync only this subtree (e.g. 'Season 01')");
+        eprintln!("  --priorities FILE                               — files not downloaded: path<TAB>0");
+        eprintln!("  --delete-skipped                                — delete leftovers of skipped files");
+        eprintln!("  --skip-unchanged                                — skip dirs unchanged since the last clean run");
+        eprintln!("  --include-system                                — also delete hidden+system extras");
+        eprintln!("  --protect GLOB                                  — never delete matching files (repeatable)");
+        eprintln!("  --older-than 30d                                — only delete extras unchanged this long");
+        eprintln!("  --delete-rate N                                 — file operations per second (network: 200, 0 = no limit)");
+        eprintln!("  --fix-case                                      — rename case-only mismatches to the torrent's spelling");
+        eprintln!("  --rename-root                                   — give the folder the torrent's name if it differs trivially");
+        eprintln!("  --root-tag GLOB                                 — name prefixes ignored by that (default '[*]', repeatable)");
+        eprintln!("  --preserve-dir-times                            — keep folder modification times (media \"recently added\")");
+        eprintln!("  --touch-expected[=TIME]                         — set expected files' modification time (default now)");
+        eprintln!("  --allow-copy                                    — allow cross-volume moves as copy+delete");
+        eprintln!("  --allow-running                                 — touch programs started from the directory");
+        eprintln!("  --import-safe                                   — keep files linked into the library or open (*arr)");
+        eprintln!("  --library DIR                                   — media library root for --import-safe (repeatable)");
+        eprintln!("  --retention 14d                                 — keep deleted files in .zdc_kept (m/h/d/w)");
+        eprintln!("  --max-delete-percent 60                         — abort plans deleting more of the directory");
+        eprintln!("  --force-large-delete                            — skip the --max-delete-percent check");
+        eprintln!("  --snapshot-over N                               — VSS snapshot before deleting more than N files");
+        eprintln!("  --pre-delete-hook CMD                           — plan as JSON on stdin; nonzero exit vetoes it");
+        eprintln!("  --post-run-hook CMD                             — summary as JSON on stdin; nonzero exit fails the run");
+        eprintln!("  --hook-timeout SECS                             — kill hooks running longer, as a veto (default 300)");
+        eprintln!("  --max-files N                                   — abort if the tree has more entries (default 1000000)");
+        eprintln!("  --max-depth N                                   — abort if the tree is nested deeper (default 64)");
+        eprintln!("  --max-memory MB                                 — cap the working set of the tool");
+        eprintln!("  --cpu-affinity 0-3,6                            — run only on these CPUs");
+        eprintln!("  --priority idle|below-normal|normal|above-normal|high|background");
+        #[cfg(feature = "verify")]
+        eprintln!("  --threads N                                     — hasher threads for verify");
+        #[cfg(feature = "verify")]
+        eprintln!("  --export sfv|md5|sha1                           — verify: write checksum files");
+        #[cfg(feature = "verify")]
+        eprintln!("  --export-per-dir                                — one checksum file per directory");
+        #[cfg(feature = "verify")]
+        eprintln!("  --trust-sfv                                     — verify: trust unchanged files in .sfv/.md5/.sha1");
+        #[cfg(feature = "verify")]
+        eprintln!("  --incremental                                   — verify: only files the NTFS journal shows changed");
+        #[cfg(feature = "client-apis")]
+        {
+            eprintln!("  --post-action recheck|pause|none                — client action after sync");
+            eprintln!("  --client qbittorrent|transmission|deluge|utorrent — client WebUI");
+            eprintln!("  --client-url URL --client-user U --client-pass P");
+            eprintln!("  --http-timeout SECS                             — WebUI timeout (default 10)");
+            eprintln!("  --update-url URL                                — self-update manifest location");
+            eprintln!("  --media-server plex|jellyfin                    — defer while a file is streamed");
+            eprintln!("  --media-url URL --media-token T");
+        }
         process::exit(1);
     }
 
-    let command = args[1].to_lowercase();
+    let command = command_alias(&pos[0].to_lowercase());
+    if !matches!(command.as_str(), "version" | "metrics" | "stats" | "completion") {
+        Record::new(Level::Info, "", "", "start")
+            .message(format!("{} — {}", build_info::summary(), command))
+            .write();
+    }
 
     match command.as_str() {
         "sync" => {
-            if args.len() < 4 {
-                eprintln!("Error: sync requires 2 arguments: <torrent_file> <directory>");
-                logger::log("ERROR: sync requires 2 arguments: <torrent_file> <directory>");
+            if pos.len() < 3 {
+                usage_error("sync requires 2 arguments: <torrent_file> <directory>");
+            }
+            check_root(&profile, &pos[2]);
+            check_stdin_torrent(&pos[1]);
+            let result = sync::run(&pos[1], &pos[2], &sync_options(&args, &profile));
+            metrics::record_sync(&result);
+            print_result("sync", &pos[2], result.as_ref().map(sync::SyncReport::to_json));
+            if result.is_err() {
+                process::exit(1);
+            }
+        }
+        "sync-all" => {
+            let Some(source) = args.value("bt-backup") else {
+                usage_error("sync-all requires --bt-backup <qBittorrent BT_backup folder>");
+            };
+            if sync_all(source, &args, &profile).is_err() {
                 process::exit(1);
             }
-            sync::run(&args[2], &args[3]);
         }
         "unlock" => {
-            if args.len() < 3 {
-                eprintln!("Error: unlock requires 1 argument: <directory>");
-                logger::log("ERROR: unlock requires 1 argument: <directory>");
+            if pos.len() < 2 {
+                usage_error("unlock requires 1 argument: <directory>");
+            }
+            let options = unlock_options(&args, &profile);
+            let results = match &pos[1..] {
+                [dir] => vec![unlock::run(dir, &options)],
+                dirs => unlock::run_batch(dirs, &options),
+            };
+            for (dir, result) in pos[1..].iter().zip(&results) {
+                metrics::record_unlock(result);
+                lock_stats::record(result);
+                print_result("unlock", dir, result.as_ref().map(unlock::UnlockReport::to_json));
+            }
+            if results.iter().any(Result::is_err) {
+                process::exit(1);
+            }
+        }
+        "version" => {
+            if args.flag("verbose") {
+                println!("zDirComp {}", build_info::VERSION);
+                println!("commit:   {}", build_info::GIT_HASH);
+                println!("platform: {}", build_info::platform());
+                println!("features: {}", build_info::features().join(", "));
+            } else {
+                println!("zDirComp {}", build_info::VERSION);
+            }
+        }
+        "metrics" => metrics::run(),
+        "exclusions" => {
+            let (Some(action), Some(dir)) = (pos.get(1), pos.get(2)) else {
+                usage_error("exclusions requires 2 arguments: add|remove <directory>");
+            };
+            let result = match action.to_lowercase().as_str() {
+                "add" => exclusions::add(dir),
+                "remove" => exclusions::remove(dir),
+                _ => usage_error("exclusions requires 2 arguments: add|remove <directory>"),
+            };
+            if result.is_err() {
+                process::exit(1);
+            }
+        }
+        "stats" => match pos.get(1).map(|s| s.to_lowercase()).as_deref() {
+            Some("locks") => lock_stats::run(),
+            _ => usage_error("stats requires a report name: locks"),
+        },
+        "install" => {
+            if install::install().is_err() {
+                process::exit(1);
+            }
+        }
+        "uninstall" => {
+            if install::uninstall(args.flag("purge")).is_err() {
+                process::exit(1);
+            }
+        }
+        "install-task" => {
+            let every = args.value("every").map(|v| {
+                trash::parse_retention(v).unwrap_or_else(|| {
+                    usage_error(&format!("Invalid interval '{}'. Use e.g. '30m' or '6h'.", v))
+                })
+            });
+            let (Some(every), Some(task_args)) = (every, args.value("args")) else {
+                usage_error("install-task requires --every and --args");
+            };
+            let name = args.value("task-name").unwrap_or(schedule::DEFAULT_NAME);
+            if schedule::install(name, every, task_args).is_err() {
+                process::exit(1);
+            }
+        }
+        "uninstall-task" => {
+            let name = args.value("task-name").unwrap_or(schedule::DEFAULT_NAME);
+            if schedule::uninstall(name).is_err() {
+                process::exit(1);
+            }
+        }
+        "completion" => {
+            let Some(shell) = pos.get(1) else {
+                usage_error("completion requires 1 argument: powershell");
+            };
+            completion::run(shell).unwrap_or_else(|e| usage_error(&e));
+        }
+        "audit-verify" => {
+            if pos.len() < 2 {
+                usage_error("audit-verify requires 1 argument: <directory>");
+            }
+            if audit::verify(std::path::Path::new(&pos[1])).is_err() {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "verify")]
+        "audit-complete" => {
+            let Some(source) = args.value("bt-backup") else {
+                usage_error("audit-complete requires --bt-backup <qBittorrent BT_backup folder>");
+            };
+            let sample = match args.value("sample") {
+                None => spot_check::DEFAULT_SAMPLE,
+                Some(v) => v
+                    .parse::<usize>()
+                    .unwrap_or_else(|_| usage_error(&format!("Invalid --sample value '{}'", v))),
+            };
+            if spot_check::run(source, sample).is_err() {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "fuzz")]
+        "fuzz-bencode" => {
+            if pos.len() < 2 {
+                usage_error("fuzz-bencode requires at least 1 argument: <file|dir>...");
+            }
+            for arg in &pos[1..] {
+                let path = std::path::Path::new(arg);
+                let files: Vec<std::path::PathBuf> = match std::fs::read_dir(path) {
+                    Ok(entries) => entries.flatten().map(|e| e.path()).collect(),
+                    Err(_) => vec![path.to_path_buf()],
+                };
+                for file in files.iter().filter(|f| f.is_file()) {
+                    let data = std::fs::read(file).unwrap_or_else(|e| {
+                        usage_error(&format!("Cannot read {:?}: {}", file, e))
+                    });
+                    bencode::parse_fuzz(&data);
+                }
+            }
+        }
+        #[cfg(feature = "service")]
+        "serve" => {
+            let Some(token) = args.value("api-token") else {
+                usage_error("serve requires --api-token (or api-token in the config)");
+            };
+            let listen = args.value("listen").unwrap_or(api::DEFAULT_LISTEN);
+            let job_timeout = args.value("job-timeout").map(|v| {
+                trash::parse_retention(v).unwrap_or_else(|| {
+                    usage_error(&format!("Invalid --job-timeout '{}'. Use e.g. '30m' or '2h'.", v))
+                })
+            });
+            let root = profile.root.as_deref();
+            if api::run(listen, token, root, sync_options(&args, &profile), job_timeout).is_err() {
+                process::exit(1);
+            }
+        }
+        "doctor" => {
+            if pos.len() < 2 {
+                usage_error("doctor requires 1 argument: <directory>");
+            }
+            doctor::run(&pos[1]);
+        }
+        "purge" => {
+            if pos.len() < 2 {
+                usage_error("purge requires 1 argument: <directory>");
+            }
+            let retention = retention(&args).unwrap_or(trash::DEFAULT_RETENTION);
+            let result = sync::purge(&pos[1], retention);
+            metrics::record_purge(&result);
+            let freed = |bytes: &u64| json::Json::object().with("freed_bytes", *bytes as i64);
+            print_result("purge", &pos[1], result.as_ref().map(freed));
+            if result.is_err() {
+                process::exit(1);
+            }
+        }
+        "orphans" => {
+            if pos.len() < 2 {
+                usage_error("orphans requires 1 argument: <downloads_root>");
+            }
+            let known = match args.value("torrents") {
+                None => usage_error("orphans requires --torrents <torrent folder|client>"),
+                #[cfg(feature = "client-apis")]
+                Some("client") => {
+                    let paths = client_config(&args, "--torrents client")
+                        .connect()
+                        .content_paths()
+                        .unwrap_or_else(|e| usage_error(&format!("--torrents client: {}", e)));
+                    orphans::Known::from_paths(&paths)
+                }
+                Some(dir) => orphans::Known::from_dir(std::path::Path::new(dir))
+                    .unwrap_or_else(|e| usage_error(&format!("--torrents: {}", e))),
+            };
+            let policy = orphans::Policy {
+                older_than: args.value("older-than").map(|v| {
+                    trash::parse_retention(v).unwrap_or_else(|| {
+                        usage_error(&format!("Invalid --older-than '{}'. Use e.g. '30d'.", v))
+                    })
+                }),
+                min_size: args.value("min-size").map_or(0, |v| {
+                    orphans::parse_size(v).unwrap_or_else(|| {
+                        usage_error(&format!("Invalid --min-size '{}'. Use e.g. '1G'.", v))
+                    })
+                }),
+                protect: args.values("protect").into_iter().map(String::from).collect(),
+            };
+            let quarantine = args.flag("quarantine");
+            if orphans::run(&pos[1], &known, &policy, quarantine).is_err() {
+                process::exit(1);
+            }
+        }
+        "repair-torrent" => {
+            if pos.len() < 3 {
+                usage_error("repair-torrent requires 2 arguments: <in.torrent> <out.torrent>");
+            }
+            if repair::run(&pos[1], &pos[2]).is_err() {
+                process::exit(1);
+            }
+        }
+        "selftest" => {
+            if selftest::run().is_err() {
+                process::exit(1);
+            }
+        }
+        "lint" => {
+            if pos.len() < 2 {
+                usage_error("lint requires 1 argument: <torrent_file>");
+            }
+            let result = lint::run(&pos[1]);
+            print_result("lint", &pos[1], result.as_ref().map(lint::LintReport::to_json));
+            if !result.is_ok_and(|report| !report.has_errors()) {
+                process::exit(1);
+            }
+        }
+        "align-check" => {
+            if pos.len() < 3 {
+                usage_error("align-check requires 2 arguments: <torrentA> <torrentB>");
+            }
+            let result = align::run(&pos[1], &pos[2]);
+            print_result("align-check", &pos[2], result.as_ref().map(align::AlignReport::to_json));
+            if !result.is_ok_and(|report| report.verdict.serves_both()) {
+                process::exit(1);
+            }
+        }
+        "report" => {
+            if pos.len() < 2 {
+                usage_error("report requires 1 argument: <downloads_root>");
+            }
+            let (Some(source), Some(out)) = (args.value("torrents"), args.value("out")) else {
+                usage_error("report requires --torrents <torrent folder> and --out <file.html>");
+            };
+            let sample = args.value("sample").map(|v| {
+                v.parse::<usize>()
+                    .unwrap_or_else(|_| usage_error(&format!("Invalid --sample value '{}'", v)))
+            });
+            if report::run(&pos[1], source, std::path::Path::new(out), sample).is_err() {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "client-apis")]
+        "sync-client" => {
+            if pos.len() < 3 {
+                usage_error("sync-client requires 2 arguments: <infohash> <directory>");
+            }
+            if client::parse_info_hash(&pos[1]).is_none() {
+                usage_error(&format!("'{}' is not a 40-character hex infohash", pos[1]));
+            }
+            let config = client_config(&args, "sync-client");
+            check_root(&profile, &pos[2]);
+            let options = sync_options(&args, &profile);
+            let result = sync::run_client(&pos[1], &pos[2], &config, &options);
+            metrics::record_sync(&result);
+            print_result("sync-client", &pos[2], result.as_ref().map(sync::SyncReport::to_json));
+            if result.is_err() {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "verify")]
+        "verify" => {
+            if pos.len() < 3 {
+                usage_error("verify requires 2 arguments: <torrent_file> <directory>");
+            }
+            check_stdin_torrent(&pos[1]);
+            let threads = match args.value("threads") {
+                None => {
+                    // One hasher per core on SSDs, a single sequential reader on HDDs
+                    let kind = volume::detect(std::path::Path::new(&pos[2]));
+                    let threads = kind.hash_threads(hashing::default_threads());
+                    Record::new(Level::Debug, "VERIFY", &pos[2], "volume")
+                        .message(format!("{} volume, {} hasher thread(s)", kind, threads))
+                        .emit();
+                    threads
+                }
+                Some(v) => match v.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => usage_error(&format!("Invalid thread count '{}'", v)),
+                },
+            };
+            let options = verify::Options {
+                threads,
+                path_map: path_map(&args),
+                priorities: priorities(&args),
+                export: args.value("export").map(|v| {
+                    checksum::Format::parse(v).unwrap_or_else(|| {
+                        usage_error(&format!(
+                            "Unknown checksum format '{}'. Use 'sfv', 'md5' or 'sha1'.",
+                            v
+                        ))
+                    })
+                }),
+                export_per_dir: args.flag("export-per-dir"),
+                trust_sfv: args.flag("trust-sfv"),
+                incremental: args.flag("incremental"),
+                skip_unchanged: args.flag("skip-unchanged"),
+            };
+            let result = verify::run(&pos[1], &pos[2], &options);
+            print_result("verify", &pos[2], result.as_ref().map(verify::VerifyReport::to_json));
+            if !result.is_ok_and(|report| report.passed()) {
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "client-apis")]
+        "self-update" => {
+            let Some(url) = args.value("update-url") else {
+                usage_error("self-update requires --update-url (or update-url in the config)");
+            };
+            if update::run(url, args.value("channel").unwrap_or("stable")).is_err() {
                 process::exit(1);
             }
-            unlock::run(&args[2]);
+        }
+        #[cfg(feature = "verify")]
+        "bench" => {
+            if pos.len() < 2 {
+                usage_error("bench requires 1 argument: <directory>");
+            }
+            bench::run(&pos[1]);
         }
         _ => {
-            eprintln!("Error: Unknown command '{}'. Use 'sync' or 'unlock'.", command);
-            logger::log(&format!("ERROR: Unknown command '{}'. Use 'sync' or 'unlock'.", command));
-            process::exit(1);
+            usage_error(&format!(
+                "Unknown command '{}'. Use 'sync', 'sync-client', 'sync-all', 'unlock', 'verify', \
+                 'doctor', 'bench', 'purge', 'orphans', 'report', 'repair-torrent', 'selftest', \
+                 'lint', 'align-check', 'self-update', 'version', 'metrics', 'stats', \
+                 'exclusions', 'install', 'uninstall', 'install-task', 'uninstall-task', \
+                 'audit-verify', 'audit-complete' or 'completion'.",
+                command
+            ));
         }
     }
 }
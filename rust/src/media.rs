@@ -0,0 +1,179 @@
+//! Currently-playing guard (`--media-server`) for Plex and Jellyfin.
+//!
+//! Before unlock terminates processes or sync deletes files, the configured
+//! server's session list is fetched. If any file it is streaming lies under
+//! the target directory, the run is deferred: nothing is touched, and the
+//! next scheduled run tries again. An unreachable server only warns, so an
+//! outage never blocks cleanup.
+
+use crate::http::{self, Url};
+use crate::imports;
+use crate::json::Json;
+use crate::logger::{Level, Record};
+
+use std::path::{Path, PathBuf};
+
+/// Supported media servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Plex,
+    Jellyfin,
+}
+
+impl Kind {
+    pub fn parse(value: &str) -> Option<Kind> {
+        match value.to_lowercase().as_str() {
+            "plex" => Some(Kind::Plex),
+            "jellyfin" => Some(Kind::Jellyfin),
+            _ => None,
+        }
+    }
+
+    fn default_url(self) -> &'static str {
+        match self {
+            Kind::Plex => "http://127.0.0.1:32400",
+            Kind::Jellyfin => "http://127.0.0.1:8096",
+        }
+    }
+}
+
+/// Connection settings from `--media-server`, `--media-url` and `--media-token`.
+#[derive(Debug, Clone)]
+pub struct Server {
+    pub kind: Kind,
+    pub url: Url,
+    pub token: String,
+}
+
+impl Server {
+    /// Build from CLI values; `url` falls back to the server's default port.
+    pub fn new(kind: Kind, url: Option<&str>, token: &str) -> Result<Server, String> {
+        Ok(Server {
+            kind,
+            url: Url::parse(url.unwrap_or(kind.default_url()))?,
+            token: token.to_string(),
+        })
+    }
+
+    /// Paths of the files being streamed right now.
+    pub fn playing(&self) -> Result<Vec<PathBuf>, String> {
+        let (path, header) = match self.kind {
+            Kind::Plex => ("/status/sessions", "X-Plex-Token"),
+            Kind::Jellyfin => ("/Sessions?activeWithinSeconds=60", "X-Emby-Token"),
+        };
+        let headers = [
+            (header, self.token.as_str()),
+            ("Accept", "application/json"),
+        ];
+        // Keep a base path such as Jellyfin's `/jellyfin`
+        let url = self
+            .url
+            .with_path(&format!("{}{}", self.url.path.trim_end_matches('/'), path));
+        let response = http::get(&url, &headers)?;
+        if response.status != 200 {
+            return Err(format!("{:?} returned HTTP {}", self.kind, response.status));
+        }
+        let json = Json::parse(&response.text())?;
+        let files = match self.kind {
+            Kind::Plex => plex_files(&json),
+            Kind::Jellyfin => jellyfin_files(&json),
+        };
+        Ok(files.into_iter().map(PathBuf::from).collect())
+    }
+}
+
+/// Items of the array `key` in `json`; empty if absent.
+fn list<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+/// `MediaContainer.Metadata[].Media[].Part[].file` of a Plex session list.
+fn plex_files(json: &Json) -> Vec<String> {
+    let Some(container) = json.get("MediaContainer") else {
+        return Vec::new();
+    };
+    list(container, "Metadata")
+        .iter()
+        .flat_map(|item| list(item, "Media"))
+        .flat_map(|media| list(media, "Part"))
+        .filter_map(|part| part.get("file").and_then(Json::as_str).map(String::from))
+        .collect()
+}
+
+/// `[].NowPlayingItem.Path` of a Jellyfin session list.
+fn jellyfin_files(json: &Json) -> Vec<String> {
+    json.as_array()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|session| session.get("NowPlayingItem")?.get("Path")?.as_str())
+        .map(String::from)
+        .collect()
+}
+
+/// The first of `playing` that lies under `dir`.
+fn streamed_under(playing: &[PathBuf], dir: &Path) -> Option<PathBuf> {
+    playing
+        .iter()
+        .find(|path| imports::inside(path, dir))
+        .cloned()
+}
+
+/// Whether `command` on `dir_path` must wait for a stream to end; logs the
+/// deferral, or a warning when the server cannot be asked.
+pub fn defer(server: Option<&Server>, command: &str, dir_path: &str) -> bool {
+    let Some(server) = server else {
+        return false;
+    };
+    match server.playing() {
+        Ok(playing) => match streamed_under(&playing, Path::new(dir_path)) {
+            Some(file) => {
+                Record::new(Level::Info, command, dir_path, "defer")
+                    .path(&file)
+                    .message(format!("{:?} is being streamed, run deferred", file))
+                    .emit();
+                true
+            }
+            None => false,
+        },
+        Err(e) => {
+            Record::new(Level::Warn, command, dir_path, "media-server")
+                .message(format!("cannot query {:?} sessions: {}", server.kind, e))
+                .emit();
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_files() {
+        let plex = Json::parse(
+            r#"{"MediaContainer": {"size": 1, "Metadata": [{"Media": [{"Part": [
+                {"file": "E:\\Online\\Show\\ep1.mkv"}]}]}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(plex_files(&plex), vec!["E:\\Online\\Show\\ep1.mkv"]);
+        assert!(plex_files(&Json::parse(r#"{"MediaContainer": {"size": 0}}"#).unwrap()).is_empty());
+
+        let jellyfin = Json::parse(
+            r#"[{"UserName": "a"}, {"NowPlayingItem": {"Path": "E:\\Online\\Film\\f.mkv"}}]"#,
+        )
+        .unwrap();
+        assert_eq!(jellyfin_files(&jellyfin), vec!["E:\\Online\\Film\\f.mkv"]);
+    }
+
+    #[test]
+    fn test_streamed_under() {
+        let playing = vec![PathBuf::from("E:\\Online\\Show\\ep1.mkv")];
+        assert_eq!(
+            streamed_under(&playing, Path::new("e:\\online\\show")),
+            Some(playing[0].clone())
+        );
+        assert_eq!(streamed_under(&playing, Path::new("E:\\Online\\Sh")), None);
+        assert_eq!(Kind::parse("Plex"), Some(Kind::Plex));
+        assert_eq!(Kind::parse("emby"), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Cumulative counters across runs, in the Prometheus text format.
+//!
+//! Each sync, sync-client, unlock and purge run adds its totals to one
+//! `metrics` entry in `state`. The `metrics` command prints them, e.g. for
+//! the node_exporter textfile collector or a scheduled task that publishes
+//! the file. Nothing leaves the machine.
+
+use crate::logger::{Level, Record};
+use crate::state;
+use crate::sync::SyncReport;
+use crate::unlock::UnlockReport;
+
+/// State key holding `name=value` pairs separated by spaces.
+const KEY: &str = "metrics";
+/// Metric name prefix.
+const PREFIX: &str = "zdircomp_";
+
+/// Counters with their help text, in output order.
+const COUNTERS: &[(&str, &str)] = &[
+    ("runs_total", "Sync, sync-client, unlock and purge runs."),
+    (
+        "files_deleted_total",
+        "Extra files deleted (or moved to .zdc_kept) by sync.",
+    ),
+    (
+        "bytes_freed_total",
+        "Bytes released by deleting or purging files.",
+    ),
+    (
+        "errors_total",
+        "Aborted runs plus files and processes that failed.",
+    ),
+    ("processes_killed_total", "Processes terminated by unlock."),
+];
+
+fn parse(value: &str) -> Vec<(String, u64)> {
+    value
+        .split(' ')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(name, n)| Some((name.to_string(), n.parse().ok()?)))
+        .collect()
+}
+
+/// Add `increments` to the counters in a stored `value`.
+fn apply(value: Option<&str>, increments: &[(&str, u64)]) -> String {
+    let mut counters = parse(value.unwrap_or(""));
+    for &(name, n) in increments {
+        match counters.iter_mut().find(|(k, _)| k == name) {
+            Some((_, total)) => *total += n,
+            None => counters.push((name.to_string(), n)),
+        }
+    }
+    counters
+        .iter()
+        .map(|(name, n)| format!("{}={}", name, n))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prometheus text exposition of a stored `value`; missing counters are 0.
+fn render(value: Option<&str>) -> String {
+    let counters = parse(value.unwrap_or(""));
+    let mut out = String::new();
+    for (name, help) in COUNTERS {
+        let n = counters
+            .iter()
+            .find(|(k, _)| k == name)
+            .map_or(0, |(_, n)| *n);
+        out.push_str(&format!("# HELP {}{} {}\n", PREFIX, name, help));
+        out.push_str(&format!("# TYPE {}{} counter\n", PREFIX, name));
+        out.push_str(&format!("{}{} {}\n", PREFIX, name, n));
+    }
+    out
+}
+
+/// Add one run's totals; failures only go to the log.
+fn add(increments: &[(&str, u64)]) {
+    if let Err(e) = state::modify(KEY, |value| Some(apply(value, increments))) {
+        Record::new(Level::Warn, "", "", "metrics")
+            .message(format!("metrics not updated: {}", e))
+            .write();
+    }
+}
+
+/// Count a sync or sync-client run.
+pub fn record_sync(result: &Result<SyncReport, String>) {
+    match result {
+        Ok(report) => add(&[
+            ("runs_total", 1),
+            ("files_deleted_total", report.deleted.len() as u64),
+            ("bytes_freed_total", report.freed_bytes),
+            ("errors_total", report.errors.len() as u64),
+        ]),
+        Err(_) => add(&[("runs_total", 1), ("errors_total", 1)]),
+    }
+}
+
+/// Count an unlock run.
+pub fn record_unlock(result: &Result<UnlockReport, String>) {
+    match result {
+        Ok(report) => add(&[
+            ("runs_total", 1),
+            ("processes_killed_total", report.terminated.len() as u64),
+            ("errors_total", report.errors.len() as u64),
+        ]),
+        Err(_) => add(&[("runs_total", 1), ("errors_total", 1)]),
+    }
+}
+
+/// Count a purge run that released `freed` bytes.
+pub fn record_purge(result: &Result<u64, String>) {
+    match result {
+        Ok(freed) => add(&[("runs_total", 1), ("bytes_freed_total", *freed)]),
+        Err(_) => add(&[("runs_total", 1), ("errors_total", 1)]),
+    }
+}
+
+/// `metrics` command: print the counters.
+pub fn run() {
+    print!("{}", render(state::get(KEY).as_deref()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_render() {
+        let value = apply(None, &[("runs_total", 1), ("files_deleted_total", 3)]);
+        let value = apply(Some(&value), &[("runs_total", 1), ("errors_total", 2)]);
+        assert_eq!(value, "runs_total=2 files_deleted_total=3 errors_total=2");
+
+        let text = render(Some(&value));
+        assert!(text.contains("# TYPE zdircomp_runs_total counter\nzdircomp_runs_total 2\n"));
+        assert!(text.contains("zdircomp_bytes_freed_total 0\n"));
+        assert_eq!(text.lines().count(), COUNTERS.len() * 3);
+    }
+}
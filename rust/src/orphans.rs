@@ -0,0 +1,422 @@
+//! `orphans`: folders under a downloads root that no torrent owns.
+//!
+//! The complement of sync, which cleans inside one torrent's directory:
+//! this lists whole entries of the root (data of torrents removed from the
+//! client) with their size and age. Torrents are known from a folder of
+//! `.torrent` files, where a qBittorrent `BT_backup` fastresume gives the
+//! exact location and a plain `.torrent` only its name, or from the
+//! client's WebUI (`--torrents client`, qBittorrent).
+//!
+//! A folder holding a known torrent up to two levels down is a category
+//! (`root\tv\Show`); its entries are checked in turn. `.zdc_*` folders and
+//! hidden+system entries are never orphans. `--quarantine` moves the
+//! orphans into a `.zdc_kept` batch of the root, so `purge` deletes them
+//! once the retention window has passed. It refuses when torrent locations
+//! are known but none lies under the root: the client reports paths in
+//! another form, and every folder would look orphaned.
+//!
+//! A grace period keeps fresh leftovers (a torrent removed by mistake can
+//! still be re-added): orphans modified within `--older-than`, smaller than
+//! `--min-size`, or named like a `--protect` glob are listed as held and
+//! never quarantined. So is one too large to walk within `--max-files` /
+//! `--max-depth`, since its age and size are then unknown.
+
+use crate::audit;
+use crate::bencode;
+use crate::bt_backup;
+use crate::ignore;
+use crate::logger::{Level, Record};
+use crate::paths;
+use crate::safety;
+use crate::trash::{self, Trash};
+use crate::walk::Walker;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How far below the root a category folder may hold torrents.
+const CATEGORY_DEPTH: usize = 2;
+
+/// Torrents known to exist, by location or, when that is unknown, by name.
+#[derive(Debug, Default)]
+pub struct Known {
+    /// Content paths, lowercased with `\` separators.
+    paths: Vec<String>,
+    /// Torrent names, lowercased.
+    names: HashSet<String>,
+    /// `.torrent` files that could not be read; their data looks orphaned.
+    pub unreadable: Vec<PathBuf>,
+}
+
+fn normalize(path: &Path) -> String {
+    let text = path.to_string_lossy().replace('/', "\\");
+    text.trim_end_matches('\\').to_lowercase()
+}
+
+impl Known {
+    /// From content paths reported by a client.
+    #[cfg(any(feature = "client-apis", test))]
+    pub fn from_paths(paths: &[PathBuf]) -> Known {
+        Known {
+            paths: paths.iter().map(|p| normalize(p)).collect(),
+            ..Known::default()
+        }
+    }
+
+    /// From the `.torrent` files in `dir`, with their fastresume files
+    /// where present.
+    pub fn from_dir(dir: &Path) -> Result<Known, String> {
+        let mut known = Known::default();
+        for pair in bt_backup::pairs(dir)? {
+            match pair {
+                Ok(pair) => known.paths.push(normalize(&pair.content_dir())),
+                Err((torrent, _)) => match bencode::parse_torrent_file(&torrent) {
+                    Ok(meta) => {
+                        known.names.insert(meta.name.to_lowercase());
+                    }
+                    Err(_) => known.unreadable.push(torrent),
+                },
+            }
+        }
+        Ok(known)
+    }
+
+    /// Whether no torrent is known at all.
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.names.is_empty()
+    }
+
+    /// Whether `path` is a torrent's data.
+    fn owns(&self, path: &Path) -> bool {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase());
+        self.paths.contains(&normalize(path)) || name.is_some_and(|n| self.names.contains(&n))
+    }
+
+    /// Whether the folder `dir` holds a torrent's data up to `depth` levels
+    /// below it.
+    fn holds(&self, dir: &Path, depth: usize) -> bool {
+        let prefix = format!("{}\\", normalize(dir));
+        if self.paths.iter().any(|p| p.starts_with(&prefix)) {
+            return true;
+        }
+        if depth == 0 || self.names.is_empty() {
+            return false;
+        }
+        children(dir)
+            .iter()
+            .any(|child| self.owns(child) || (child.is_dir() && self.holds(child, depth - 1)))
+    }
+}
+
+/// Which orphans may be quarantined.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// `--older-than`: minimum time since anything inside was modified.
+    pub older_than: Option<Duration>,
+    /// `--min-size`, in bytes.
+    pub min_size: u64,
+    /// `--protect` globs, matched against the entry name.
+    pub protect: Vec<String>,
+}
+
+impl Policy {
+    /// Whether `name` matches a `--protect` glob (case-insensitive).
+    fn protects(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.protect
+            .iter()
+            .any(|pattern| ignore::glob_match(&pattern.to_lowercase(), &name))
+    }
+
+    /// Why an orphan of `bytes`, untouched for `age`, is held back, if it is.
+    fn holds(&self, bytes: u64, age: Option<Duration>) -> Option<String> {
+        if let Some(min) = self.older_than {
+            if age.is_none_or(|age| age < min) {
+                return Some("modified within --older-than".to_string());
+            }
+        }
+        if bytes < self.min_size {
+            return Some("smaller than --min-size".to_string());
+        }
+        None
+    }
+}
+
+/// Parse a size: a number with an optional `K`, `M`, `G` or `T` suffix
+/// (binary units, an optional trailing `B` allowed); a bare number is bytes.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim().to_ascii_uppercase();
+    let s = s.strip_suffix('B').unwrap_or(&s);
+    let (number, shift) = match s.chars().last()? {
+        'K' => (&s[..s.len() - 1], 10),
+        'M' => (&s[..s.len() - 1], 20),
+        'G' => (&s[..s.len() - 1], 30),
+        'T' => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// Entries of `dir` that can be orphans, sorted.
+fn children(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    entries.retain(|path| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        !name.starts_with(".zdc_") && !paths::is_hidden_system(path)
+    });
+    entries.sort();
+    entries
+}
+
+/// Entries under `dir` that no known torrent owns, descending into
+/// categories up to `depth` levels.
+fn find(dir: &Path, known: &Known, depth: usize, orphans: &mut Vec<PathBuf>) {
+    for child in children(dir) {
+        if known.owns(&child) {
+            continue;
+        }
+        if child.is_dir() && depth > 0 && known.holds(&child, depth - 1) {
+            find(&child, known, depth - 1, orphans);
+        } else {
+            orphans.push(child);
+        }
+    }
+}
+
+/// What is under an orphan (junctions and symlinks are not followed).
+#[derive(Debug, Default)]
+struct Usage {
+    /// Total size of the files.
+    bytes: u64,
+    /// Newest modification time.
+    newest: Option<SystemTime>,
+    /// The walk stopped at `--max-files` / `--max-depth`; a newer or
+    /// larger file may lie beyond what was counted.
+    partial: bool,
+}
+
+fn usage(path: &Path) -> Usage {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Usage::default();
+    };
+    let mut usage = Usage {
+        bytes: if meta.is_file() { meta.len() } else { 0 },
+        newest: meta.modified().ok(),
+        partial: false,
+    };
+    if meta.is_dir() {
+        let walked = Walker::new(path).skip_reparse_points().on_entry(|entry| {
+            usage.bytes += entry.size;
+            usage.newest = usage.newest.max(entry.modified);
+            Ok(())
+        });
+        usage.partial = walked.is_err();
+    }
+    usage
+}
+
+/// Orphans under `dir` with their size, for reports.
+pub fn list(dir: &Path, known: &Known) -> Vec<(PathBuf, u64)> {
+    let mut orphans = Vec::new();
+    find(dir, known, CATEGORY_DEPTH, &mut orphans);
+    orphans
+        .into_iter()
+        .map(|path| {
+            let bytes = usage(&path).bytes;
+            (path, bytes)
+        })
+        .collect()
+}
+
+/// `orphans` command: list (and with `quarantine` move away, as `policy`
+/// allows) the orphans under `root`.
+pub fn run(root: &str, known: &Known, policy: &Policy, quarantine: bool) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "ORPHANS", root, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let dir = Path::new(root);
+    if !dir.is_dir() {
+        return Err(abort("directory does not exist, aborted".to_string()));
+    }
+    // Known locations are absolute; a relative root would match none
+    if !dir.is_absolute() {
+        return Err(abort("the root must be an absolute path".to_string()));
+    }
+    for torrent in &known.unreadable {
+        Record::new(Level::Warn, "ORPHANS", root, "unreadable")
+            .path(torrent)
+            .message(format!(
+                "{:?} cannot be read; its data may be listed",
+                torrent
+            ))
+            .emit();
+    }
+    if quarantine && known.is_empty() {
+        return Err(abort("no torrents known, nothing quarantined".to_string()));
+    }
+    if quarantine && !known.unreadable.is_empty() {
+        return Err(abort(
+            "unreadable torrents, nothing quarantined; fix or remove them".to_string(),
+        ));
+    }
+    // Client paths in another form (a container mount, UNC, another drive
+    // letter) match nothing, which would make every folder an orphan
+    if quarantine && !known.paths.is_empty() && !known.holds(dir, 0) {
+        return Err(abort(
+            "no known torrent location lies under the root (paths in another form?), \
+             nothing quarantined"
+                .to_string(),
+        ));
+    }
+    if quarantine && !safety::check_depth(dir, 2) {
+        return Err(abort("path too shallow, aborted".to_string()));
+    }
+
+    let mut orphans = Vec::new();
+    find(dir, known, CATEGORY_DEPTH, &mut orphans);
+    let (trash, batch) = (Trash::new(dir), trash::now_secs());
+    let (mut total, mut held, mut moved, mut errors) = (0u64, 0, 0, 0);
+    for path in &orphans {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if policy.protects(&name) {
+            Record::new(Level::Debug, "ORPHANS", root, "protected")
+                .path(path)
+                .message(format!("{:?} protected by --protect", path))
+                .emit();
+            held += 1;
+            continue;
+        }
+        let Usage {
+            bytes,
+            newest,
+            partial,
+        } = usage(path);
+        total += bytes;
+        let age = newest.and_then(|m| SystemTime::now().duration_since(m).ok());
+        let days = age.map_or(0, |age| age.as_secs() / 86_400);
+        let hold = match partial {
+            true => Some("too large to inspect (--max-files / --max-depth)".to_string()),
+            false => policy.holds(bytes, age),
+        };
+        Record::new(Level::Info, "ORPHANS", root, "orphan")
+            .path(path)
+            .message(format!(
+                "{:?}: {} bytes, untouched for {} days{}",
+                path,
+                bytes,
+                days,
+                hold.as_ref()
+                    .map_or(String::new(), |why| format!(", held: {}", why))
+            ))
+            .emit();
+        if hold.is_some() {
+            held += 1;
+            continue;
+        }
+        if !quarantine {
+            continue;
+        }
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        match trash.quarantine(relative, batch) {
+            Ok(()) => {
+                audit::record(
+                    "ORPHANS",
+                    root,
+                    "quarantine",
+                    Some(path),
+                    &bytes.to_string(),
+                );
+                moved += 1;
+            }
+            Err(e) => {
+                Record::new(Level::Error, "ORPHANS", root, "error")
+                    .path(path)
+                    .message(format!("cannot quarantine {:?}: {}", path, e))
+                    .emit();
+                errors += 1;
+            }
+        }
+    }
+    Record::new(Level::Info, "ORPHANS", root, "summary")
+        .message(format!(
+            "{} orphans, {} bytes, {} held{}",
+            orphans.len(),
+            total,
+            held,
+            if quarantine {
+                format!(", {} moved to {}", moved, trash::KEPT_DIR)
+            } else {
+                String::new()
+            }
+        ))
+        .emit();
+    match errors {
+        0 => Ok(()),
+        n => Err(format!("{} orphans could not be quarantined", n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_find() {
+        let root = TempDir::new("orphans");
+        let (show, gone) = (root.join("tv").join("Show"), root.join("tv").join("Gone"));
+        for dir in [
+            &show,
+            &gone,
+            &root.join("Film"),
+            &root.join("Old"),
+            &root.join(".zdc_kept"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(root.join("single.iso"), b"x").unwrap();
+        fs::write(root.join("stray.iso"), b"x").unwrap();
+        let mut known = Known::from_paths(&[show, root.join("single.iso")]);
+        known.names.insert("film".to_string());
+
+        let mut orphans = Vec::new();
+        find(&root, &known, CATEGORY_DEPTH, &mut orphans);
+        // What run checks before quarantining
+        assert!(known.holds(&root, 0));
+        let mounted = Known::from_paths(&[PathBuf::from("/downloads/tv/Show")]);
+        assert!(!mounted.holds(&root, 0));
+        assert_eq!(
+            orphans,
+            vec![root.join("Old"), root.join("stray.iso"), gone]
+        );
+    }
+
+    #[test]
+    fn test_policy() {
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("512kb"), Some(512 << 10));
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("1.5G"), None);
+        assert_eq!(parse_size("G"), None);
+
+        let day = Duration::from_secs(86_400);
+        let policy = Policy {
+            older_than: Some(30 * day),
+            min_size: 1 << 20,
+            protect: vec!["keep*".to_string()],
+        };
+        assert!(policy.protects("Keep.This"));
+        assert!(!policy.protects("Other"));
+        assert!(policy.holds(1 << 30, Some(40 * day)).is_none());
+        assert!(policy.holds(1 << 30, Some(3 * day)).is_some());
+        assert!(policy.holds(1 << 30, None).is_some());
+        assert!(policy.holds(1 << 10, Some(40 * day)).is_some());
+    }
+}
@@ -0,0 +1,145 @@
+//! `--map` file: torrent paths relocated on disk.
+//!
+//! Clients can rename files and move subfolders of a torrent without
+//! touching the `.torrent`, so its paths no longer match the disk. A map
+//! file lists `torrent path<TAB>disk path` per line (both relative to the
+//! download directory, `/` or `\`, `#` comments). An entry naming a folder
+//! also relocates everything below it; the longest matching entry wins.
+//! Matching is case-insensitive, like Windows paths.
+
+use crate::bencode::TorrentFile;
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Parsed map file.
+#[derive(Debug, Clone, Default)]
+pub struct PathMap {
+    entries: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Relative path from map file text; `..` and absolute paths are refused
+/// so a map cannot point outside the download directory.
+pub fn relative_path(text: &str) -> Result<PathBuf, String> {
+    let mut path = PathBuf::new();
+    for part in text.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return Err(format!("'{}' leaves the download directory", text)),
+            _ if part.contains(':') => return Err(format!("'{}' is not a relative path", text)),
+            _ => path.push(part),
+        }
+    }
+    if path.as_os_str().is_empty() || text.starts_with(['/', '\\']) {
+        return Err(format!("'{}' is not a relative path", text));
+    }
+    Ok(path)
+}
+
+fn lower_components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy().to_lowercase()),
+            _ => None,
+        })
+        .collect()
+}
+
+impl PathMap {
+    /// From (torrent path, disk path) pairs, both relative.
+    pub fn new(entries: Vec<(PathBuf, PathBuf)>) -> PathMap {
+        PathMap { entries }
+    }
+
+    /// Parse map text; errors name the offending line.
+    pub fn parse(text: &str) -> Result<PathMap, String> {
+        let mut entries = Vec::new();
+        for (number, raw) in text.lines().enumerate() {
+            let line = raw.trim_end_matches('\r');
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let error = |what: &str| format!("map line {}: {}", number + 1, what);
+            let (from, to) = line
+                .split_once('\t')
+                .ok_or_else(|| error("expected 'torrent path<TAB>disk path'"))?;
+            let from = relative_path(from.trim()).map_err(|e| error(&e))?;
+            let to = relative_path(to.trim()).map_err(|e| error(&e))?;
+            entries.push((from, to));
+        }
+        Ok(PathMap { entries })
+    }
+
+    /// Load a map file.
+    pub fn load(path: &Path) -> Result<PathMap, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read map {}: {}", path.display(), e))?;
+        PathMap::parse(&text)
+    }
+
+    /// On-disk location of torrent path `path`, if an entry covers it.
+    pub fn map(&self, path: &Path) -> Option<PathBuf> {
+        let parts = lower_components(path);
+        let (from, to) = self
+            .entries
+            .iter()
+            .filter(|(from, _)| {
+                let prefix = lower_components(from);
+                parts.len() >= prefix.len() && parts[..prefix.len()] == prefix[..]
+            })
+            .max_by_key(|(from, _)| from.components().count())?;
+        let rest: PathBuf = path.components().skip(from.components().count()).collect();
+        Some(to.join(rest))
+    }
+
+    /// Rewrite file paths in place; returns how many were relocated.
+    pub fn apply(&self, files: &mut [TorrentFile]) -> usize {
+        let mut count = 0;
+        for file in files {
+            if let Some(mapped) = self.map(&file.path) {
+                file.path = mapped;
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let map = PathMap::parse(
+            "# renamed in the client\n\
+             Show/S01/ep1.mkv\tShow/S01/Episode 1.mkv\n\
+             Show/Extras\tBonus\n\
+             Show\\Extras\\trailer.mkv\tTrailer.mkv\n",
+        )
+        .unwrap();
+        let show = Path::new("Show");
+        assert_eq!(
+            map.map(&show.join("S01").join("EP1.mkv")),
+            Some(show.join("S01").join("Episode 1.mkv"))
+        );
+        assert_eq!(
+            map.map(&show.join("Extras").join("a").join("b.jpg")),
+            Some(Path::new("Bonus").join("a").join("b.jpg"))
+        );
+        assert_eq!(
+            map.map(&show.join("Extras").join("trailer.mkv")),
+            Some(PathBuf::from("Trailer.mkv"))
+        );
+        assert_eq!(map.map(&show.join("S01").join("ep2.mkv")), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PathMap::parse("a.mkv b.mkv").is_err());
+        assert!(PathMap::parse("a.mkv\t../b.mkv").is_err());
+        assert!(PathMap::parse("a.mkv\tC:\\b.mkv").is_err());
+        let e = PathMap::parse("\n\n\\a\tb").unwrap_err();
+        assert!(e.contains("line 3"), "{}", e);
+    }
+}
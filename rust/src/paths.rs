@@ -0,0 +1,190 @@
+//! Path normalization for 8.3 short names (raw FFI, no external crates).
+//!
+//! Clients sometimes pass `D:\DOWNLO~1\X`. Directory listings return long
+//! names, so a short-name target would make every relative path differ
+//! from the torrent's. `long_path` expands such names with
+//! `GetLongPathNameW`; paths without short-looking components are returned
+//! unchanged without a system call.
+//!
+//! Also answers whether a path is marked hidden+system (`desktop.ini`,
+//! `$RECYCLE.BIN`, `System Volume Information`), which sync keeps by default.
+//!
+//! Scripts run under WSL pass drive paths as `/mnt/e/Online/...`;
+//! `from_wsl` turns those into `E:\Online\...` so the same wrapper works
+//! from both shells.
+//!
+//! `within` decides whether a directory lies inside a profile `root` once
+//! `.`/`..` are resolved, so `root\..\Windows` does not count as inside.
+
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
+const INVALID_FILE_ATTRIBUTES: u32 = 0xFFFF_FFFF;
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+
+extern "system" {
+    fn GetLongPathNameW(lpszShortPath: *const u16, lpszLongPath: *mut u16, cchBuffer: u32) -> u32;
+    fn GetFileAttributesW(lpFileName: *const u16) -> u32;
+}
+
+/// NUL-terminated UTF-16 for Win32 `W` functions.
+pub fn to_wide(s: impl AsRef<OsStr>) -> Vec<u16> {
+    s.as_ref()
+        .to_string_lossy()
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// Whether `path` has both the hidden and the system attribute.
+pub fn is_hidden_system(path: &Path) -> bool {
+    let attributes = unsafe { GetFileAttributesW(to_wide(path).as_ptr()) };
+    attributes != INVALID_FILE_ATTRIBUTES && hidden_system(attributes)
+}
+
+/// Whether Win32 file attributes have both hidden and system set.
+pub fn hidden_system(attributes: u32) -> bool {
+    let both = FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM;
+    attributes & both == both
+}
+
+/// Whether a file name has the 8.3 alias shape `NAME~N[.EXT]`.
+fn is_short_name(name: &str) -> bool {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) => (stem, Some(ext)),
+        None => (name, None),
+    };
+    let Some((base, number)) = stem.rsplit_once('~') else {
+        return false;
+    };
+    !base.is_empty()
+        && stem.len() <= 8
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+        && ext.is_none_or(|e| e.len() <= 3)
+}
+
+/// Whether any component of `path` may be a short name.
+fn has_short_name(path: &Path) -> bool {
+    path.components().any(|c| match c {
+        Component::Normal(s) => is_short_name(&s.to_string_lossy()),
+        _ => false,
+    })
+}
+
+/// Expand 8.3 short names in an existing path; anything else (or any
+/// failure) returns the path as given.
+pub fn long_path(path: &Path) -> PathBuf {
+    if !has_short_name(path) {
+        return path.to_path_buf();
+    }
+    let wide = to_wide(path);
+    let mut buf = vec![0u16; 1024];
+    loop {
+        let len = unsafe { GetLongPathNameW(wide.as_ptr(), buf.as_mut_ptr(), buf.len() as u32) };
+        if len == 0 {
+            return path.to_path_buf();
+        }
+        if (len as usize) < buf.len() {
+            return PathBuf::from(String::from_utf16_lossy(&buf[..len as usize]));
+        }
+        // Too small: `len` is the required size including the NUL
+        buf = vec![0u16; len as usize];
+    }
+}
+
+/// A WSL drive path (`/mnt/<letter>[/...]`) as the Windows path it names;
+/// anything else is returned unchanged.
+pub fn from_wsl(arg: &str) -> String {
+    let Some(rest) = arg.strip_prefix("/mnt/") else {
+        return arg.to_string();
+    };
+    let mut chars = rest.chars();
+    match (chars.next(), chars.as_str()) {
+        (Some(letter), tail)
+            if letter.is_ascii_alphabetic() && (tail.is_empty() || tail.starts_with('/')) =>
+        {
+            format!(
+                "{}:\\{}",
+                letter.to_ascii_uppercase(),
+                tail.trim_start_matches('/').replace('/', "\\")
+            )
+        }
+        _ => arg.to_string(),
+    }
+}
+
+/// Whether `dir` lies inside `root` (or is it). Both are made absolute
+/// and `.`/`..` resolved, through links too when both exist; components
+/// compare case-insensitively, as on NTFS.
+pub fn within(root: &str, dir: &str) -> bool {
+    let resolve = |path: &str| -> Option<Vec<String>> {
+        let path = std::path::absolute(path).ok()?;
+        let mut parts = Vec::new();
+        for part in path.to_string_lossy().split(['\\', '/']) {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop()?;
+                }
+                _ => parts.push(part.to_lowercase()),
+            }
+        }
+        Some(parts)
+    };
+    let (root, dir) = match (std::fs::canonicalize(root), std::fs::canonicalize(dir)) {
+        (Ok(root), Ok(dir)) => (
+            root.to_string_lossy().into_owned(),
+            dir.to_string_lossy().into_owned(),
+        ),
+        _ => (root.to_string(), dir.to_string()),
+    };
+    match (resolve(&root), resolve(&dir)) {
+        (Some(root), Some(dir)) => dir.starts_with(&root),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_short_name() {
+        assert!(is_short_name("DOWNLO~1"));
+        assert!(is_short_name("PROGRA~2.EXE"));
+        assert!(!is_short_name("~1"));
+        assert!(!is_short_name("Downloads"));
+        assert!(!is_short_name("backup~old.txt"));
+        assert!(!is_short_name("LONGNAME~1.html"));
+        assert!(has_short_name(&Path::new("D:").join("DOWNLO~1").join("X")));
+        assert!(!has_short_name(&Path::new("D:").join("Downloads").join("X")));
+    }
+
+    #[test]
+    fn test_long_path_passthrough() {
+        let p = Path::new("E:\\Online\\Show");
+        assert_eq!(long_path(p), p);
+    }
+
+    #[test]
+    fn test_from_wsl() {
+        assert_eq!(from_wsl("/mnt/e/Online/Show 1/"), "E:\\Online\\Show 1\\");
+        assert_eq!(from_wsl("/mnt/d"), "D:\\");
+        assert_eq!(from_wsl("E:\\Online"), "E:\\Online");
+        assert_eq!(from_wsl("/mnt/wsl/share"), "/mnt/wsl/share");
+        assert_eq!(from_wsl("/home/user"), "/home/user");
+    }
+
+    #[test]
+    fn test_within() {
+        assert!(within("E:\\Online", "E:\\Online"));
+        assert!(within("E:\\Online\\", "e:/online/TV/x"));
+        assert!(within("E:\\Online", "E:\\Online\\TV\\..\\Film"));
+        assert!(!within("E:\\Online", "E:\\Online 2"));
+        // `..` cannot escape the root
+        assert!(!within("E:\\Online", "E:\\Online\\..\\..\\Windows"));
+        assert!(!within("E:\\Online", "E:\\Online\\TV\\..\\..\\other"));
+    }
+}
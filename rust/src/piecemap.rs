@@ -0,0 +1,194 @@
+//! Piece ↔ file mapping.
+//!
+//! A torrent's files are concatenated in order into one byte stream that is
+//! cut into `piece_length` pieces. A piece can span several files and a file
+//! can span several pieces; zero-length files occupy no bytes and belong to
+//! no piece.
+
+use crate::bencode::TorrentMeta;
+
+use std::ops::Range;
+
+/// Part of a piece that lives in one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Index into `TorrentMeta::files`.
+    pub file: usize,
+    /// Byte offset inside that file.
+    pub offset: u64,
+    /// Number of bytes.
+    pub length: u64,
+}
+
+/// Offsets of every file in the torrent's byte stream.
+#[derive(Debug, Clone)]
+pub struct PieceMap {
+    piece_length: u64,
+    total_size: u64,
+    /// Start offset of each file in the stream.
+    starts: Vec<u64>,
+    lengths: Vec<u64>,
+}
+
+impl PieceMap {
+    /// Build the map. Returns `None` if the torrent has no piece length.
+    pub fn new(meta: &TorrentMeta) -> Option<PieceMap> {
+        if meta.piece_length == 0 {
+            return None;
+        }
+        let mut starts = Vec::with_capacity(meta.files.len());
+        let mut offset = 0u64;
+        for file in &meta.files {
+            starts.push(offset);
+            offset += file.length;
+        }
+        Some(PieceMap {
+            piece_length: meta.piece_length,
+            total_size: offset,
+            starts,
+            lengths: meta.files.iter().map(|f| f.length).collect(),
+        })
+    }
+
+    /// Number of pieces needed to cover all file data.
+    pub fn piece_count(&self) -> usize {
+        self.total_size.div_ceil(self.piece_length) as usize
+    }
+
+    /// Size of piece `piece` in bytes (0 if out of range).
+    pub fn piece_size(&self, piece: usize) -> u64 {
+        let start = piece as u64 * self.piece_length;
+        if start >= self.total_size {
+            return 0;
+        }
+        (self.total_size - start).min(self.piece_length)
+    }
+
+    /// File spans making up piece `piece`, in stream order.
+    pub fn spans(&self, piece: usize) -> Vec<Span> {
+        let start = piece as u64 * self.piece_length;
+        let end = start + self.piece_size(piece);
+        let mut spans = Vec::new();
+        if start >= end {
+            return spans;
+        }
+
+        // The last file starting at or before `start` holds it: files are
+        // contiguous, and empty ones before it share its start
+        let first = self.starts.partition_point(|&s| s <= start).saturating_sub(1);
+
+        for file in first..self.starts.len() {
+            let file_start = self.starts[file];
+            if file_start >= end {
+                break;
+            }
+            let file_end = file_start + self.lengths[file];
+            let from = start.max(file_start);
+            let to = end.min(file_end);
+            if from < to {
+                spans.push(Span {
+                    file,
+                    offset: from - file_start,
+                    length: to - from,
+                });
+            }
+        }
+        spans
+    }
+
+    /// Pieces that contain bytes of file `file` (empty for zero-length files).
+    pub fn file_pieces(&self, file: usize) -> Range<usize> {
+        let length = self.lengths[file];
+        if length == 0 {
+            return 0..0;
+        }
+        let start = self.starts[file];
+        let first = start / self.piece_length;
+        let last = (start + length - 1) / self.piece_length;
+        first as usize..last as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::TorrentFile;
+    use std::path::PathBuf;
+
+    fn meta(lengths: &[u64], piece_length: u64) -> TorrentMeta {
+        let files: Vec<TorrentFile> = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, &length)| TorrentFile {
+                path: PathBuf::from(format!("f{}", i)),
+                length,
+                skip: false,
+            })
+            .collect();
+        TorrentMeta {
+            name: "t".to_string(),
+            total_size: lengths.iter().sum(),
+            files,
+            piece_length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_spans_across_files() {
+        // f0: 0..10, f1: empty, f2: 10..25 — pieces of 8 bytes
+        let map = PieceMap::new(&meta(&[10, 0, 15], 8)).unwrap();
+        assert_eq!(map.piece_count(), 4);
+        assert_eq!(map.piece_size(3), 1);
+        assert_eq!(
+            map.spans(1),
+            vec![
+                Span {
+                    file: 0,
+                    offset: 8,
+                    length: 2
+                },
+                Span {
+                    file: 2,
+                    offset: 0,
+                    length: 6
+                },
+            ]
+        );
+        assert_eq!(
+            map.spans(3),
+            vec![Span {
+                file: 2,
+                offset: 14,
+                length: 1
+            }]
+        );
+        assert!(map.spans(4).is_empty());
+
+        // Empty files on a piece boundary
+        let map = PieceMap::new(&meta(&[0, 8, 0, 0, 8], 8)).unwrap();
+        let whole = |file| Span {
+            file,
+            offset: 0,
+            length: 8,
+        };
+        assert_eq!(map.spans(0), vec![whole(1)]);
+        assert_eq!(map.spans(1), vec![whole(4)]);
+    }
+
+    #[test]
+    fn test_file_pieces() {
+        let map = PieceMap::new(&meta(&[10, 0, 15], 8)).unwrap();
+        assert_eq!(map.file_pieces(0), 0..2);
+        assert_eq!(map.file_pieces(1), 0..0);
+        assert_eq!(map.file_pieces(2), 1..4);
+    }
+
+    #[test]
+    fn test_empty_and_missing_piece_length() {
+        assert!(PieceMap::new(&meta(&[10], 0)).is_none());
+        let map = PieceMap::new(&meta(&[0, 0], 16)).unwrap();
+        assert_eq!(map.piece_count(), 0);
+        assert!(map.spans(0).is_empty());
+    }
+}
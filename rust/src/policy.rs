@@ -0,0 +1,280 @@
+//! Deletion policies: which files of a root sync may delete.
+//!
+//! Every indexed file is put to a `Chain` of policies in order. The first
+//! that keeps or defers the file decides; a file no policy objects to is
+//! deleted. Built in:
+//! - `DefaultPolicy`: torrent files, `.zdirignore`/rules matches, companions and
+//!   the trash and kept folders
+//! - `SystemFiles`: hidden+system files and folders (off with
+//!   `--include-system`)
+//! - `KeepGlobs`: `--protect` globs
+//! - `MinAge`: files modified within `--older-than`
+//! - `HardlinkGuard`: files hard-linked into the library (`--import-safe`),
+//!   deferred rather than kept
+//!
+//! A new rule is one more `DeletionPolicy` on the chain sync builds.
+
+use crate::dir_index::{DirIndex, Meta};
+use crate::ignore::{self, Ignore};
+use crate::imports;
+use crate::trash::{KEPT_DIR, TRASH_DIR};
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// A file that may be deleted.
+#[derive(Debug, Clone, Copy)]
+pub struct FileInfo<'a> {
+    /// Path relative to the root.
+    pub relative: &'a Path,
+    pub meta: &'a Meta,
+}
+
+/// What policies may consult besides the file itself.
+#[derive(Debug, Clone, Copy)]
+pub struct SyncContext<'a> {
+    /// The root the paths are relative to.
+    pub dir: &'a Path,
+    pub index: &'a DirIndex,
+    /// Files of the torrent, relative to the root.
+    pub expected: &'a HashSet<PathBuf>,
+    pub now: SystemTime,
+}
+
+/// A policy's verdict on one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// No objection; a later policy may still keep the file.
+    Delete,
+    /// Keep the file. A reason is logged; `None` is for the files every
+    /// sync keeps (the torrent's own), which would only be noise.
+    Keep(Option<String>),
+    /// Keep the file for now and report it as deferred, with the reason.
+    Defer(String),
+}
+
+/// One rule about which files may be deleted.
+pub trait DeletionPolicy {
+    /// Short name, used in log actions (`keep-<name>`).
+    fn name(&self) -> &'static str;
+
+    fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> Decision;
+}
+
+/// Policies asked in order.
+#[derive(Default)]
+pub struct Chain {
+    policies: Vec<Box<dyn DeletionPolicy>>,
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    /// Add `policy`, asked after the ones already on the chain.
+    pub fn with(mut self, policy: impl DeletionPolicy + 'static) -> Chain {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    /// The first decision other than `Delete`, with the name of the policy
+    /// that made it; `Delete` if there is none.
+    pub fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> (&'static str, Decision) {
+        for policy in &self.policies {
+            match policy.decide(candidate, ctx) {
+                Decision::Delete => {}
+                decision => return (policy.name(), decision),
+            }
+        }
+        ("", Decision::Delete)
+    }
+}
+
+/// Whether `relative` lies in the trash or the kept folder.
+pub fn is_internal(relative: &Path) -> bool {
+    relative.starts_with(TRASH_DIR) || relative.starts_with(KEPT_DIR)
+}
+
+/// Keep what the torrent lists and what ignore files and rules protect.
+pub struct DefaultPolicy {
+    pub ignore: Ignore,
+}
+
+impl DeletionPolicy for DefaultPolicy {
+    fn name(&self) -> &'static str {
+        "default"
+    }
+
+    fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> Decision {
+        let relative = candidate.relative;
+        let kept = is_internal(relative)
+            || ctx.expected.contains(relative)
+            || self.ignore.is_ignored(relative)
+            || self.ignore.is_companion(relative, ctx.expected);
+        match kept {
+            true => Decision::Keep(None),
+            false => Decision::Delete,
+        }
+    }
+}
+
+/// Keep files that are, or lie in a folder that is, hidden+system (folder
+/// customizations, recycle bin, volume information).
+pub struct SystemFiles;
+
+impl DeletionPolicy for SystemFiles {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> Decision {
+        let system = candidate
+            .relative
+            .ancestors()
+            .filter(|a| !a.as_os_str().is_empty())
+            .any(|a| ctx.index.is_hidden_system(a));
+        match system {
+            true => Decision::Keep(Some("hidden system file".to_string())),
+            false => Decision::Delete,
+        }
+    }
+}
+
+/// Keep files matching a glob (case-insensitive). A pattern with a `/` is
+/// matched against the whole relative path, others against each name in it.
+pub struct KeepGlobs {
+    pub patterns: Vec<String>,
+}
+
+impl DeletionPolicy for KeepGlobs {
+    fn name(&self) -> &'static str {
+        "glob"
+    }
+
+    fn decide(&self, candidate: &FileInfo, _: &SyncContext) -> Decision {
+        let names: Vec<String> = candidate
+            .relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(s) => Some(s.to_string_lossy().to_lowercase()),
+                _ => None,
+            })
+            .collect();
+        let path = names.join("/");
+        let matching = self.patterns.iter().find(|pattern| {
+            let pattern = pattern.to_lowercase().replace('\\', "/");
+            match pattern.contains('/') {
+                true => ignore::glob_match(&pattern, &path),
+                false => names.iter().any(|name| ignore::glob_match(&pattern, name)),
+            }
+        });
+        match matching {
+            Some(pattern) => Decision::Keep(Some(format!("matches --protect {}", pattern))),
+            None => Decision::Delete,
+        }
+    }
+}
+
+/// Keep files modified less than `min` ago, or at an unknown time.
+pub struct MinAge {
+    pub min: Duration,
+}
+
+impl DeletionPolicy for MinAge {
+    fn name(&self) -> &'static str {
+        "age"
+    }
+
+    fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> Decision {
+        let age = candidate
+            .meta
+            .modified
+            .and_then(|modified| ctx.now.duration_since(modified).ok());
+        match age.is_none_or(|age| age < self.min) {
+            true => Decision::Keep(Some("modified within --older-than".to_string())),
+            false => Decision::Delete,
+        }
+    }
+}
+
+/// Defer files with another hard link inside `library` (any other link
+/// when it is empty): an *arr import still uses the data.
+pub struct HardlinkGuard {
+    pub library: Vec<PathBuf>,
+}
+
+impl DeletionPolicy for HardlinkGuard {
+    fn name(&self) -> &'static str {
+        "hardlink"
+    }
+
+    fn decide(&self, candidate: &FileInfo, ctx: &SyncContext) -> Decision {
+        match imports::linked(&ctx.dir.join(candidate.relative), &self.library) {
+            Some(link) => Decision::Defer(format!("hard-linked as {:?}", link)),
+            None => Decision::Delete,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_chain() {
+        let dir = TempDir::new("policy");
+        fs::create_dir_all(dir.join("Extras")).unwrap();
+        for name in ["movie.mkv", "a.nfo", "b.txt"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+        fs::write(dir.join("Extras").join("keep.srt"), b"x").unwrap();
+
+        let index = DirIndex::build(&dir, Path::new("")).unwrap();
+        let expected: HashSet<PathBuf> = [PathBuf::from("movie.mkv")].into();
+        let ctx = SyncContext {
+            dir: &dir,
+            index: &index,
+            expected: &expected,
+            now: SystemTime::now(),
+        };
+        let chain = Chain::new()
+            .with(DefaultPolicy {
+                ignore: Ignore::default(),
+            })
+            .with(KeepGlobs {
+                patterns: vec!["*.NFO".to_string(), "extras/*.srt".to_string()],
+            });
+        let decide = |relative: &Path| {
+            let meta = index.get(relative).unwrap();
+            chain.decide(
+                &FileInfo {
+                    relative,
+                    meta: &meta,
+                },
+                &ctx,
+            )
+        };
+        assert_eq!(
+            decide(Path::new("movie.mkv")),
+            ("default", Decision::Keep(None))
+        );
+        assert_eq!(decide(Path::new("a.nfo")).0, "glob");
+        assert_eq!(decide(&Path::new("Extras").join("keep.srt")).0, "glob");
+        assert_eq!(decide(Path::new("b.txt")), ("", Decision::Delete));
+
+        // Just written: too young for a day's minimum age
+        let chain = chain.with(MinAge {
+            min: Duration::from_secs(86_400),
+        });
+        let meta = index.get(Path::new("b.txt")).unwrap();
+        let candidate = FileInfo {
+            relative: Path::new("b.txt"),
+            meta: &meta,
+        };
+        assert_eq!(chain.decide(&candidate, &ctx).0, "age");
+    }
+}
@@ -0,0 +1,99 @@
+//! `--priorities` file: which torrent files were not downloaded.
+//!
+//! Without a client API the `.torrent` alone cannot say which files the user
+//! deselected. A priorities file lists `torrent path<TAB>priority` per line
+//! (`#` comments); priority 0 means "don't download", as in qBittorrent and
+//! uTorrent. Files not listed keep their current setting.
+
+use crate::bencode::TorrentFile;
+use crate::pathmap;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Parsed priorities file.
+#[derive(Debug, Clone, Default)]
+pub struct Priorities {
+    entries: Vec<(PathBuf, i64)>,
+}
+
+impl Priorities {
+    /// From (torrent path, priority) pairs.
+    pub fn new(entries: Vec<(PathBuf, i64)>) -> Priorities {
+        Priorities { entries }
+    }
+
+    /// Parse priorities text; errors name the offending line.
+    pub fn parse(text: &str) -> Result<Priorities, String> {
+        let mut entries = Vec::new();
+        for (number, raw) in text.lines().enumerate() {
+            let line = raw.trim_end_matches('\r');
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let error = |what: &str| format!("priorities line {}: {}", number + 1, what);
+            let (path, priority) = line
+                .rsplit_once('\t')
+                .ok_or_else(|| error("expected 'torrent path<TAB>priority'"))?;
+            let path = pathmap::relative_path(path.trim()).map_err(|e| error(&e))?;
+            let priority = priority
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| error(&format!("invalid priority '{}'", priority.trim())))?;
+            entries.push((path, priority));
+        }
+        Ok(Priorities { entries })
+    }
+
+    /// Load a priorities file.
+    pub fn load(path: &Path) -> Result<Priorities, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read priorities {}: {}", path.display(), e))?;
+        Priorities::parse(&text)
+    }
+
+    /// Mark listed files; returns how many are now skipped.
+    pub fn apply(&self, files: &mut [TorrentFile]) -> usize {
+        for (path, priority) in &self.entries {
+            let key = path.to_string_lossy().to_lowercase();
+            for file in files
+                .iter_mut()
+                .filter(|f| f.path.to_string_lossy().to_lowercase() == key)
+            {
+                file.skip = *priority == 0;
+            }
+        }
+        files.iter().filter(|f| f.skip).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &Path) -> TorrentFile {
+        TorrentFile {
+            path: path.to_path_buf(),
+            length: 1,
+            skip: false,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let priorities =
+            Priorities::parse("# deselected\nS01/sample.mkv\t0\nS01/ep1.mkv\t1\n").unwrap();
+        let s01 = Path::new("S01");
+        let mut files = vec![file(&s01.join("ep1.mkv")), file(&s01.join("Sample.mkv"))];
+        assert_eq!(priorities.apply(&mut files), 1);
+        assert!(!files[0].skip);
+        assert!(files[1].skip);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Priorities::parse("a.mkv 0").is_err());
+        assert!(Priorities::parse("a.mkv\thigh").is_err());
+        assert!(Priorities::parse("../a.mkv\t0").is_err());
+    }
+}
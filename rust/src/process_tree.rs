@@ -0,0 +1,254 @@
+//! Process tree lookup and termination (Toolhelp snapshot, raw FFI).
+//!
+//! Used by `unlock --kill-tree`: some lockers are respawned by a watchdog
+//! child or spawn helpers that inherit the handle, so their descendants are
+//! terminated too. Windows keeps a stale parent PID after the parent exits,
+//! so a "child" created before its parent is not counted. For the same
+//! reason `terminate` checks the creation time from the snapshot again
+//! before killing, so a PID reused since then is not hit.
+
+use crate::imports;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const TH32CS_SNAPPROCESS: DWORD = 0x0000_0002;
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const PROCESS_TERMINATE: DWORD = 0x0001;
+const PROCESS_QUERY_LIMITED_INFORMATION: DWORD = 0x1000;
+const MAX_PATH: usize = 260;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct PROCESSENTRY32W {
+    dwSize: DWORD,
+    cntUsage: DWORD,
+    th32ProcessID: DWORD,
+    th32DefaultHeapID: usize,
+    th32ModuleID: DWORD,
+    cntThreads: DWORD,
+    th32ParentProcessID: DWORD,
+    pcPriClassBase: i32,
+    dwFlags: DWORD,
+    szExeFile: [u16; MAX_PATH],
+}
+
+extern "system" {
+    fn CreateToolhelp32Snapshot(dwFlags: DWORD, th32ProcessID: DWORD) -> HANDLE;
+    fn Process32FirstW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> i32;
+    fn Process32NextW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> i32;
+    fn OpenProcess(dwDesiredAccess: DWORD, bInheritHandle: i32, dwProcessId: DWORD) -> HANDLE;
+    fn GetProcessTimes(
+        hProcess: HANDLE,
+        lpCreationTime: *mut u64,
+        lpExitTime: *mut u64,
+        lpKernelTime: *mut u64,
+        lpUserTime: *mut u64,
+    ) -> i32;
+    fn TerminateProcess(hProcess: HANDLE, uExitCode: u32) -> i32;
+    fn QueryFullProcessImageNameW(
+        hProcess: HANDLE,
+        dwFlags: DWORD,
+        lpExeName: *mut u16,
+        lpdwSize: *mut DWORD,
+    ) -> i32;
+    fn ProcessIdToSessionId(dwProcessId: DWORD, pSessionId: *mut DWORD) -> i32;
+    fn GetLastError() -> DWORD;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// One process from the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessEntry {
+    pub pid: u32,
+    pub parent: u32,
+    pub name: String,
+    /// FILETIME of process creation, 0 if it could not be read.
+    created: u64,
+}
+
+/// Creation time of an open process (0 if unavailable).
+unsafe fn handle_created(handle: HANDLE) -> u64 {
+    let (mut created, mut exited, mut kernel, mut user) = (0u64, 0u64, 0u64, 0u64);
+    match GetProcessTimes(handle, &mut created, &mut exited, &mut kernel, &mut user) {
+        0 => 0,
+        _ => created,
+    }
+}
+
+/// Creation time of a process (0 if unavailable).
+fn creation_time(pid: u32) -> u64 {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return 0;
+        }
+        let created = handle_created(handle);
+        CloseHandle(handle);
+        created
+    }
+}
+
+/// All running processes.
+fn snapshot() -> Result<Vec<ProcessEntry>, String> {
+    unsafe {
+        let snap = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snap == INVALID_HANDLE_VALUE {
+            return Err(format!(
+                "CreateToolhelp32Snapshot failed (error {})",
+                GetLastError()
+            ));
+        }
+        let mut entries = Vec::new();
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as DWORD;
+        let mut more = Process32FirstW(snap, &mut entry) != 0;
+        while more {
+            let len = entry
+                .szExeFile
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(MAX_PATH);
+            entries.push(ProcessEntry {
+                pid: entry.th32ProcessID,
+                parent: entry.th32ParentProcessID,
+                name: String::from_utf16_lossy(&entry.szExeFile[..len]),
+                created: creation_time(entry.th32ProcessID),
+            });
+            more = Process32NextW(snap, &mut entry) != 0;
+        }
+        CloseHandle(snap);
+        Ok(entries)
+    }
+}
+
+/// Descendants of `root` in `entries`, parents before children.
+fn descendants_of(entries: &[ProcessEntry], root: u32) -> Vec<ProcessEntry> {
+    let mut children: HashMap<u32, Vec<&ProcessEntry>> = HashMap::new();
+    for e in entries {
+        // PID 0 (System Idle) lists itself as its own parent
+        if e.pid != e.parent {
+            children.entry(e.parent).or_default().push(e);
+        }
+    }
+    let created = |pid: u32| entries.iter().find(|e| e.pid == pid).map(|e| e.created);
+
+    let mut out: Vec<ProcessEntry> = Vec::new();
+    let mut queue = vec![root];
+    let mut i = 0;
+    while i < queue.len() {
+        let parent = queue[i];
+        i += 1;
+        let parent_created = created(parent).unwrap_or(0);
+        for child in children.get(&parent).into_iter().flatten() {
+            // A child older than its parent has a reused parent PID
+            let stale = child.created != 0 && parent_created != 0 && child.created < parent_created;
+            if stale || child.pid == root || queue.contains(&child.pid) {
+                continue;
+            }
+            queue.push(child.pid);
+            out.push((*child).clone());
+        }
+    }
+    out
+}
+
+/// Current descendants of process `pid`, parents before children.
+pub fn descendants(pid: u32) -> Result<Vec<ProcessEntry>, String> {
+    Ok(descendants_of(&snapshot()?, pid))
+}
+
+/// Full path of the executable of process `pid`.
+pub fn image_path(pid: u32) -> Option<PathBuf> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let mut buf = [0u16; MAX_PATH * 4];
+        let mut size = buf.len() as DWORD;
+        let ok = QueryFullProcessImageNameW(handle, 0, buf.as_mut_ptr(), &mut size) != 0;
+        CloseHandle(handle);
+        ok.then(|| PathBuf::from(String::from_utf16_lossy(&buf[..size as usize])))
+    }
+}
+
+/// Processes started from an executable under `dir`, with that path.
+pub fn running_under(dir: &Path) -> Vec<(ProcessEntry, PathBuf)> {
+    snapshot()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|entry| {
+            let image = image_path(entry.pid)?;
+            imports::inside(&image, dir).then_some((entry, image))
+        })
+        .collect()
+}
+
+/// Terminal Services session of process `pid`.
+pub fn session_id(pid: u32) -> Option<u32> {
+    let mut session = 0;
+    (unsafe { ProcessIdToSessionId(pid, &mut session) } != 0).then_some(session)
+}
+
+/// Terminate the snapshot's process immediately (exit code 1), unless its
+/// PID now belongs to a process created at another time.
+pub fn terminate(entry: &ProcessEntry) -> Result<(), String> {
+    unsafe {
+        let access = PROCESS_TERMINATE | PROCESS_QUERY_LIMITED_INFORMATION;
+        let handle = OpenProcess(access, 0, entry.pid);
+        if handle.is_null() {
+            return Err(format!("OpenProcess failed (error {})", GetLastError()));
+        }
+        let created = handle_created(handle);
+        if entry.created == 0 || created != entry.created {
+            CloseHandle(handle);
+            return Err("exited and its PID was reused, or its start time is unknown".to_string());
+        }
+        let ok = TerminateProcess(handle, 1) != 0;
+        let error = GetLastError();
+        CloseHandle(handle);
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("TerminateProcess failed (error {})", error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pid: u32, parent: u32, created: u64) -> ProcessEntry {
+        ProcessEntry {
+            pid,
+            parent,
+            name: format!("p{}.exe", pid),
+            created,
+        }
+    }
+
+    #[test]
+    fn test_descendants_of() {
+        let entries = vec![
+            entry(0, 0, 0),
+            entry(10, 1, 100),
+            entry(11, 10, 110),
+            entry(12, 11, 120),
+            entry(13, 10, 130),
+            // Stale: parent PID 10 was reused after this one started
+            entry(14, 10, 50),
+            entry(20, 1, 100),
+        ];
+        let pids: Vec<u32> = descendants_of(&entries, 10).iter().map(|e| e.pid).collect();
+        assert_eq!(pids, vec![11, 13, 12]);
+        assert!(descendants_of(&entries, 20).is_empty());
+        assert!(descendants_of(&entries, 0).is_empty());
+    }
+}
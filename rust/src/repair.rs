@@ -0,0 +1,321 @@
+//! `repair-torrent`: rewrite a slightly broken torrent as a clean one.
+//!
+//! The strict parser (`bencode::parse`) refuses malformed data. This one
+//! reads on where the damage is recoverable and records each fix:
+//! - dictionary keys out of order, or repeated (the last value is kept)
+//! - integers with leading zeros, a `+` sign or `-0`
+//! - a byte string whose declared length does not end on the start of the
+//!   next value: the nearest length that does is taken (for `pieces`, only
+//!   multiples of 20)
+//! - lists and dictionaries left open at the end of the data
+//! - stray bytes after the torrent
+//!
+//! The output is canonical bencode and must read as a torrent. Fixes inside
+//! `info` change the infohash, so clients see a different torrent.
+
+use crate::bencode::{self, BValue};
+use crate::logger::{Level, Record};
+use crate::sha;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// How far a string length is searched around the declared one.
+const MAX_SLACK: usize = 256;
+
+/// A fix made: where in the torrent, and what.
+type Fix = (String, String);
+
+/// Lenient parser state.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Lists and dictionaries open around `pos`.
+    depth: usize,
+    fixes: Vec<Fix>,
+}
+
+/// Location of `key` inside the value at `at`.
+fn child(at: &str, key: &str) -> String {
+    match at {
+        "" => key.to_string(),
+        _ => format!("{}.{}", at, key),
+    }
+}
+
+impl Parser<'_> {
+    fn fix(&mut self, at: &str, what: String) {
+        let at = if at.is_empty() { "top level" } else { at };
+        self.fixes.push((at.to_string(), what));
+    }
+
+    /// Whether a value (or the end of a list or dictionary) starts at `pos`.
+    fn value_starts(&self, pos: usize) -> bool {
+        match self.data.get(pos) {
+            None => true,
+            Some(b'i' | b'l' | b'd' | b'e') => true,
+            Some(b'0'..=b'9') => {
+                let digits = self.data[pos..].iter().take_while(|b| b.is_ascii_digit());
+                self.data.get(pos + digits.count()) == Some(&b':')
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn value(&mut self, at: &str, pieces: bool) -> Result<BValue, String> {
+        match self.data.get(self.pos) {
+            None => Err(format!("{}: data ends early", at)),
+            Some(b'i') => self.integer(at),
+            Some(b'l' | b'd') if self.depth >= bencode::MAX_DEPTH => Err(format!(
+                "{}: nested deeper than {} levels",
+                at,
+                bencode::MAX_DEPTH
+            )),
+            Some(b'l') => self.nested(|p| p.list(at)),
+            Some(b'd') => self.nested(|p| p.dict(at)),
+            Some(b'0'..=b'9') => self.string(at, pieces).map(BValue::Bytes),
+            Some(&other) => Err(format!(
+                "{}: unexpected byte 0x{:02x} at offset {}",
+                at, other, self.pos
+            )),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<BValue, String>,
+    ) -> Result<BValue, String> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn integer(&mut self, at: &str) -> Result<BValue, String> {
+        let start = self.pos + 1;
+        let end = self.data[start..]
+            .iter()
+            .position(|&b| b == b'e')
+            .map(|n| start + n)
+            .ok_or_else(|| format!("{}: integer has no end", at))?;
+        let text = String::from_utf8_lossy(&self.data[start..end]).into_owned();
+        let n = text
+            .parse::<i64>()
+            .map_err(|_| format!("{}: bad integer '{}'", at, text))?;
+        if text != n.to_string() {
+            self.fix(at, format!("integer '{}' written as {}", text, n));
+        }
+        self.pos = end + 1;
+        Ok(BValue::Integer(n))
+    }
+
+    fn string(&mut self, at: &str, pieces: bool) -> Result<Vec<u8>, String> {
+        let colon = self.data[self.pos..]
+            .iter()
+            .position(|&b| b == b':')
+            .map(|n| self.pos + n)
+            .ok_or_else(|| format!("{}: string has no ':'", at))?;
+        let text = String::from_utf8_lossy(&self.data[self.pos..colon]).into_owned();
+        let declared = text
+            .parse::<usize>()
+            .map_err(|_| format!("{}: bad string length '{}'", at, text))?;
+        let start = colon + 1;
+        let fits = |len: usize| {
+            start + len <= self.data.len()
+                && (!pieces || len.is_multiple_of(20))
+                && self.value_starts(start + len)
+        };
+        let len = if fits(declared) {
+            declared
+        } else {
+            let nearest = (1..=MAX_SLACK)
+                .flat_map(|delta| [declared.checked_sub(delta), declared.checked_add(delta)])
+                .flatten()
+                .find(|&len| fits(len))
+                .ok_or_else(|| format!("{}: string length {} cannot be recovered", at, declared))?;
+            self.fix(
+                at,
+                format!("string length {} corrected to {}", declared, nearest),
+            );
+            nearest
+        };
+        self.pos = start + len;
+        Ok(self.data[start..start + len].to_vec())
+    }
+
+    fn list(&mut self, at: &str) -> Result<BValue, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            match self.data.get(self.pos) {
+                None => {
+                    self.fix(at, "list not closed".to_string());
+                    break;
+                }
+                Some(b'e') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => {
+                    let item = self.value(&format!("{}[{}]", at, items.len()), false)?;
+                    items.push(item);
+                }
+            }
+        }
+        Ok(BValue::List(items))
+    }
+
+    fn dict(&mut self, at: &str) -> Result<BValue, String> {
+        self.pos += 1;
+        let mut map = BTreeMap::new();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut unsorted = false;
+        loop {
+            match self.data.get(self.pos) {
+                None => {
+                    self.fix(at, "dictionary not closed".to_string());
+                    break;
+                }
+                Some(b'e') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'0'..=b'9') => {}
+                Some(&other) => {
+                    return Err(format!(
+                        "{}: dictionary key expected, found byte 0x{:02x} at offset {}",
+                        at, other, self.pos
+                    ))
+                }
+            }
+            let key = self.string(&child(at, "<key>"), false)?;
+            let name = String::from_utf8_lossy(&key).into_owned();
+            let location = child(at, &name);
+            if matches!(self.data.get(self.pos), None | Some(b'e')) {
+                self.fix(&location, "value missing, key dropped".to_string());
+                continue;
+            }
+            let value = self.value(&location, key == b"pieces")?;
+            unsorted |= previous.as_ref().is_some_and(|p| *p > key);
+            if map.insert(key.clone(), value).is_some() {
+                self.fix(&location, "key repeated, last value kept".to_string());
+            }
+            previous = Some(key);
+        }
+        if unsorted {
+            self.fix(at, "dictionary keys sorted".to_string());
+        }
+        Ok(BValue::Dict(map))
+    }
+}
+
+/// Leniently parse `data`; returns the clean encoding and the fixes made.
+pub fn repair(data: &[u8]) -> Result<(Vec<u8>, Vec<Fix>), String> {
+    let mut parser = Parser {
+        data,
+        pos: 0,
+        depth: 0,
+        fixes: Vec::new(),
+    };
+    let root = parser.value("", false)?;
+    if !matches!(root, BValue::Dict(_)) {
+        return Err("not a torrent: the top level is not a dictionary".to_string());
+    }
+    let trailing = data.len() - parser.pos;
+    if trailing > 0 {
+        parser.fix(
+            "",
+            format!("{} stray bytes after the torrent dropped", trailing),
+        );
+    }
+    let clean = bencode::encode(&root);
+    bencode::torrent_meta(&clean).map_err(|e| format!("not a torrent after repair: {}", e))?;
+    Ok((clean, parser.fixes))
+}
+
+/// `repair-torrent` command: write a clean version of `input` to `output`.
+pub fn run(input: &str, output: &str) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "REPAIR", input, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    if Path::new(input) == Path::new(output) {
+        return Err(abort(
+            "write the repaired torrent to another file, keeping the original".to_string(),
+        ));
+    }
+    let data = fs::read(input).map_err(|e| abort(format!("cannot read {:?}: {}", input, e)))?;
+    let (clean, fixes) = repair(&data).map_err(abort)?;
+    fs::write(output, &clean).map_err(|e| abort(format!("cannot write {:?}: {}", output, e)))?;
+    for (at, what) in &fixes {
+        Record::new(Level::Info, "REPAIR", input, "fix")
+            .message(format!("{}: {}", at, what))
+            .emit();
+    }
+    let info_changed = fixes
+        .iter()
+        .any(|(at, _)| at == "info" || at.starts_with("info."));
+    if info_changed {
+        let meta = bencode::torrent_meta(&clean).map_err(|e| abort(e.to_string()))?;
+        Record::new(Level::Warn, "REPAIR", input, "infohash")
+            .message(format!(
+                "info dictionary changed, new infohash {}; clients know the original under \
+                 another hash",
+                sha::hex(&meta.info_hash)
+            ))
+            .emit();
+    }
+    Record::new(Level::Info, "REPAIR", input, "summary")
+        .path(Path::new(output))
+        .message(match fixes.len() {
+            0 => format!("already well-formed, written to {:?}", output),
+            n => format!("{} fixes, written to {:?}", n, output),
+        })
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair() {
+        // Keys out of order, "name" declared 4 bytes long for "a.iso", a
+        // leading zero, the outer dictionary left open and stray bytes
+        let broken = b"d4:infod4:name4:a.iso6:lengthi05e12:piece lengthi16384e\
+                       6:pieces20:aaaaaaaaaaaaaaaaaaaae8:announce3:url";
+        let (clean, fixes) = repair(broken).unwrap();
+        let fixes: Vec<String> = fixes
+            .iter()
+            .map(|(at, what)| format!("{}: {}", at, what))
+            .collect();
+        assert_eq!(
+            fixes,
+            vec![
+                "info.name: string length 4 corrected to 5",
+                "info.length: integer '05' written as 5",
+                "info: dictionary keys sorted",
+                "top level: dictionary not closed",
+                "top level: dictionary keys sorted",
+            ]
+        );
+        let meta = bencode::torrent_meta(&clean).unwrap();
+        assert_eq!((meta.name.as_str(), meta.total_size), ("a.iso", 5));
+        let (_, none) = repair(&clean).unwrap();
+        assert!(none.is_empty());
+
+        let mut trailing = clean.clone();
+        trailing.extend_from_slice(b"\r\n");
+        let (again, fixes) = repair(&trailing).unwrap();
+        assert_eq!(again, clean);
+        assert_eq!(fixes[0].1, "2 stray bytes after the torrent dropped");
+
+        assert!(repair(b"i5e").is_err());
+        assert!(repair(b"d4:infoi1ee").is_err());
+        assert!(repair(&[b'l'; 100_000]).is_err());
+    }
+}
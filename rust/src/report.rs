@@ -0,0 +1,426 @@
+//! `report`: one self-contained HTML page on the state of a whole library.
+//!
+//! For every torrent of a `--torrents` folder (a qBittorrent `BT_backup`
+//! folder, or plain `.torrent` files whose data lies in `root\<name>`) the
+//! report lists missing files and the extras sync would delete, what the
+//! client's fastresume claims, and with `--sample N` a spot check of that
+//! claim (see `spot_check`). Orphans of the root (see `orphans`) and the
+//! extras add up to the space reclaimable. Read-only; tables sort by
+//! clicking a column header.
+
+use crate::bencode::{self, TorrentMeta};
+use crate::bt_backup::{self, Pair};
+use crate::logger::{self, Level, Record};
+use crate::orphans::{self, Known};
+#[cfg(feature = "verify")]
+use crate::spot_check;
+use crate::sync;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entries shown per list in the details section.
+const MAX_LISTED: usize = 200;
+
+/// What was found for one torrent.
+#[derive(Debug, Default)]
+struct Row {
+    name: String,
+    /// The torrent's directory, or where it is assumed to be.
+    dir: PathBuf,
+    files: usize,
+    bytes: u64,
+    /// Wanted files absent or of the wrong size.
+    missing: Vec<PathBuf>,
+    /// Files sync would delete, with their size.
+    extras: Vec<(PathBuf, u64)>,
+    /// What the fastresume file says about the download.
+    claim: String,
+    verify: String,
+    /// The spot check found bad pieces.
+    corrupt: bool,
+    /// Why the data could not be checked.
+    problem: Option<String>,
+}
+
+impl Row {
+    fn status(&self) -> &'static str {
+        match (
+            &self.problem,
+            self.missing.is_empty(),
+            self.extras.is_empty(),
+        ) {
+            (Some(_), _, _) => "not checked",
+            _ if self.corrupt => "corrupt pieces",
+            (None, true, true) => "ok",
+            (None, false, true) => "missing files",
+            (None, true, false) => "extras",
+            (None, false, false) => "missing files, extras",
+        }
+    }
+
+    fn extra_bytes(&self) -> u64 {
+        self.extras.iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    /// Compare the files of `meta` with the disk; single-file torrents
+    /// share their directory, so only missing files are looked for.
+    fn inspect(&mut self, meta: &TorrentMeta, single_file: bool) {
+        self.files = meta.files.len();
+        self.bytes = meta.total_size;
+        if !self.dir.is_dir() {
+            self.problem = Some("directory not found".to_string());
+            return;
+        }
+        for file in meta.files.iter().filter(|f| !f.skip) {
+            let size = fs::metadata(self.dir.join(&file.path)).map(|m| m.len());
+            if size.map_or(true, |size| size != file.length) {
+                self.missing.push(file.path.clone());
+            }
+        }
+        if single_file {
+            return;
+        }
+        match sync::extras(&self.dir, &meta.files) {
+            Ok(extras) => {
+                self.extras = extras
+                    .into_iter()
+                    .map(|relative| {
+                        let bytes = fs::metadata(self.dir.join(&relative)).map_or(0, |m| m.len());
+                        (relative, bytes)
+                    })
+                    .collect()
+            }
+            Err(e) => self.problem = Some(e),
+        }
+    }
+}
+
+/// Row of a torrent with fastresume data.
+fn pair_row(pair: &Pair, sample: Option<usize>) -> Row {
+    let mut row = Row {
+        name: pair.meta.name.clone(),
+        dir: pair.content_dir(),
+        claim: if pair.resume.complete(&pair.meta) {
+            "complete".to_string()
+        } else {
+            "partly downloaded".to_string()
+        },
+        verify: "not checked".to_string(),
+        ..Row::default()
+    };
+    let mut meta = pair.meta.clone();
+    let settings = pair
+        .resume
+        .file_priorities(&meta)
+        .and_then(|priorities| Ok((priorities, pair.resume.path_map(&meta)?)));
+    match settings {
+        Ok((priorities, path_map)) => {
+            priorities.apply(&mut meta.files);
+            path_map.apply(&mut meta.files);
+            row.inspect(&meta, pair.single_file());
+        }
+        Err(e) => row.problem = Some(e),
+    }
+    if row.problem.is_none() {
+        verify(&mut row, pair, sample);
+    }
+    row
+}
+
+#[cfg(feature = "verify")]
+fn verify(row: &mut Row, pair: &Pair, sample: Option<usize>) {
+    let Some(count) = sample else {
+        return;
+    };
+    row.verify = match spot_check::check(pair, count) {
+        Ok((sampled, bad)) if bad.is_empty() => format!("{} sampled pieces OK", sampled),
+        Ok((sampled, bad)) => {
+            row.corrupt = true;
+            format!("{} of {} sampled pieces bad", bad.len(), sampled)
+        }
+        Err(e) => e,
+    };
+}
+
+#[cfg(not(feature = "verify"))]
+fn verify(_: &mut Row, _: &Pair, _: Option<usize>) {}
+
+/// A row per torrent of `source`, sorted by file name.
+fn rows(root: &Path, source: &Path, sample: Option<usize>) -> Result<Vec<Row>, String> {
+    let mut rows = Vec::new();
+    for pair in bt_backup::pairs(source)? {
+        let (torrent, reason) = match pair {
+            Ok(pair) => {
+                rows.push(pair_row(&pair, sample));
+                continue;
+            }
+            Err(skipped) => skipped,
+        };
+        let row = match bencode::parse_torrent_file(&torrent) {
+            Ok(meta) => {
+                let single = meta.files.len() == 1 && meta.files[0].path == Path::new(&meta.name);
+                let mut row = Row {
+                    name: meta.name.clone(),
+                    dir: if single {
+                        root.to_path_buf()
+                    } else {
+                        root.join(&meta.name)
+                    },
+                    claim: reason,
+                    verify: "not checked".to_string(),
+                    ..Row::default()
+                };
+                row.inspect(&meta, single);
+                row
+            }
+            Err(e) => Row {
+                name: torrent.to_string_lossy().into_owned(),
+                problem: Some(e),
+                ..Row::default()
+            },
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Escape text for HTML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Bytes in binary units, e.g. `1.5 GiB`.
+fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let (mut value, mut unit) = (bytes as f64, 0);
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// A table cell sorted by `bytes` and showing them readably.
+fn size_cell(bytes: u64) -> String {
+    format!("<td data-v=\"{}\">{}</td>", bytes, human(bytes))
+}
+
+/// A count cell, sorted numerically.
+fn count_cell(count: usize) -> String {
+    format!("<td data-v=\"{}\">{}</td>", count, count)
+}
+
+const STYLE: &str = "body{font-family:Segoe UI,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:2em}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left}\
+th{background:#eee;cursor:pointer}\
+tr.ok td:nth-child(2){color:#2a7a2a}tr.bad td:nth-child(2){color:#b22}\
+details{margin:.3em 0}";
+
+const SCRIPT: &str = "document.querySelectorAll('th').forEach(function (th) {\
+th.addEventListener('click', function () {\
+var body = th.closest('table').tBodies[0], i = th.cellIndex;\
+var asc = th.dataset.dir !== 'asc'; th.dataset.dir = asc ? 'asc' : 'desc';\
+var key = function (row) { var c = row.cells[i];\
+return c.dataset.v !== undefined ? Number(c.dataset.v) : c.textContent.toLowerCase(); };\
+Array.from(body.rows).sort(function (a, b) { var x = key(a), y = key(b);\
+return (x < y ? -1 : x > y ? 1 : 0) * (asc ? 1 : -1); })\
+.forEach(function (r) { body.appendChild(r); }); }); });";
+
+/// A `<details>` list of `paths`, at most `MAX_LISTED` of them.
+fn list(html: &mut String, title: &str, paths: &[String]) {
+    if paths.is_empty() {
+        return;
+    }
+    let _ = write!(html, "<p>{}:</p><ul>", title);
+    for path in paths.iter().take(MAX_LISTED) {
+        let _ = write!(html, "<li>{}</li>", escape(path));
+    }
+    if paths.len() > MAX_LISTED {
+        let _ = write!(html, "<li>and {} more</li>", paths.len() - MAX_LISTED);
+    }
+    html.push_str("</ul>");
+}
+
+/// The report page.
+fn render(root: &str, rows: &[Row], orphans: &[(PathBuf, u64)], unreadable: usize) -> String {
+    let extra_bytes: u64 = rows.iter().map(Row::extra_bytes).sum();
+    let orphan_bytes: u64 = orphans.iter().map(|(_, bytes)| bytes).sum();
+    let healthy = rows.iter().filter(|row| row.status() == "ok").count();
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <title>zDirComp report: {root}</title><style>{STYLE}</style></head><body>\
+         <h1>Library report: {root}</h1><p>Generated {time} by zDirComp {version}.</p>\
+         <table><tbody>\
+         <tr><th>Torrents</th><td>{total}</td></tr>\
+         <tr><th>OK</th><td>{healthy}</td></tr>\
+         <tr><th>With problems</th><td>{problems}</td></tr>\
+         <tr><th>Extras</th><td>{extras}</td></tr>\
+         <tr><th>Orphans</th><td>{orphans}</td></tr>\
+         <tr><th>Reclaimable</th><td>{reclaimable}</td></tr>\
+         </tbody></table>",
+        root = escape(root),
+        time = logger::iso_timestamp(),
+        version = env!("CARGO_PKG_VERSION"),
+        total = rows.len(),
+        problems = rows.len() - healthy,
+        extras = human(extra_bytes),
+        orphans = human(orphan_bytes),
+        reclaimable = human(extra_bytes + orphan_bytes),
+    );
+
+    html.push_str(
+        "<h2>Torrents</h2><table><thead><tr><th>Name</th><th>Status</th><th>Files</th>\
+         <th>Size</th><th>Missing</th><th>Extras</th><th>Extra size</th><th>Client</th>\
+         <th>Spot check</th><th>Directory</th></tr></thead><tbody>",
+    );
+    for row in rows {
+        let status = match &row.problem {
+            Some(problem) => format!("{}: {}", row.status(), problem),
+            None => row.status().to_string(),
+        };
+        let _ = write!(
+            html,
+            "<tr class=\"{}\"><td>{}</td><td>{}</td>{}{}{}{}{}\
+             <td>{}</td><td>{}</td><td>{}</td></tr>",
+            if row.status() == "ok" { "ok" } else { "bad" },
+            escape(&row.name),
+            escape(&status),
+            count_cell(row.files),
+            size_cell(row.bytes),
+            count_cell(row.missing.len()),
+            count_cell(row.extras.len()),
+            size_cell(row.extra_bytes()),
+            escape(&row.claim),
+            escape(&row.verify),
+            escape(&row.dir.to_string_lossy()),
+        );
+    }
+    html.push_str("</tbody></table>");
+
+    let _ = write!(html, "<h2>Orphans</h2>");
+    if unreadable > 0 {
+        let _ = write!(
+            html,
+            "<p>{} torrents could not be read; their data may be listed here.</p>",
+            unreadable
+        );
+    }
+    html.push_str("<table><thead><tr><th>Path</th><th>Size</th></tr></thead><tbody>");
+    for (path, bytes) in orphans {
+        let _ = write!(
+            html,
+            "<tr><td>{}</td>{}</tr>",
+            escape(&path.to_string_lossy()),
+            size_cell(*bytes)
+        );
+    }
+    html.push_str("</tbody></table><h2>Details</h2>");
+    for row in rows
+        .iter()
+        .filter(|row| !row.missing.is_empty() || !row.extras.is_empty())
+    {
+        let _ = write!(
+            html,
+            "<details><summary>{} ({} missing, {} extras)</summary>",
+            escape(&row.name),
+            row.missing.len(),
+            row.extras.len()
+        );
+        let lossy = |path: &Path| path.to_string_lossy().into_owned();
+        let missing: Vec<String> = row.missing.iter().map(|p| lossy(p)).collect();
+        let extras: Vec<String> = row
+            .extras
+            .iter()
+            .map(|(p, bytes)| format!("{} ({})", lossy(p), human(*bytes)))
+            .collect();
+        list(&mut html, "Missing", &missing);
+        list(&mut html, "Extras", &extras);
+        html.push_str("</details>");
+    }
+    let _ = write!(html, "<script>{}</script></body></html>", SCRIPT);
+    html
+}
+
+/// `report` command: write the report on `root` and the torrents of
+/// `source` to `out`.
+pub fn run(root: &str, source: &str, out: &Path, sample: Option<usize>) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "REPORT", root, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let dir = Path::new(root);
+    if !dir.is_dir() {
+        return Err(abort("directory does not exist, aborted".to_string()));
+    }
+    // Known locations are absolute; a relative root would match none
+    if !dir.is_absolute() {
+        return Err(abort("the root must be an absolute path".to_string()));
+    }
+    let rows = rows(dir, Path::new(source), sample).map_err(abort)?;
+    let known = Known::from_dir(Path::new(source)).map_err(abort)?;
+    let orphans = orphans::list(dir, &known);
+    let html = render(root, &rows, &orphans, known.unreadable.len());
+    fs::write(out, html).map_err(|e| abort(format!("cannot write {:?}: {}", out, e)))?;
+    Record::new(Level::Info, "REPORT", root, "summary")
+        .path(out)
+        .message(format!(
+            "{} torrents, {} with problems, {} orphans; written to {:?}",
+            rows.len(),
+            rows.iter().filter(|row| row.status() != "ok").count(),
+            orphans.len(),
+            out
+        ))
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(human(512), "512 B");
+        assert_eq!(human(3 << 29), "1.5 GiB");
+        let rows = [
+            Row {
+                name: "<Show>".to_string(),
+                extras: vec![(PathBuf::from("sample.mkv"), 2048)],
+                ..Row::default()
+            },
+            Row {
+                name: "Film".to_string(),
+                problem: Some("directory not found".to_string()),
+                ..Row::default()
+            },
+        ];
+        assert_eq!(rows[0].status(), "extras");
+        assert_eq!(rows[1].status(), "not checked");
+        let html = render("E:\\TV", &rows, &[(PathBuf::from("Old"), 1024)], 0);
+        assert!(html.contains("<td>&lt;Show&gt;</td>"));
+        assert!(html.contains("<td data-v=\"2048\">2.0 KiB</td>"));
+        assert!(html.contains("not checked: directory not found"));
+        assert!(html.contains("<td>3.0 KiB</td>"));
+    }
+}
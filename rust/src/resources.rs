@@ -0,0 +1,162 @@
+//! `--max-memory`, `--cpu-affinity`, `--priority`: keep the tool small on
+//! busy hosts (raw FFI, no external crates).
+//!
+//! On a seedbox the client, the web UI and media servers share the machine;
+//! a verify of a large library should not take it over. The caps apply to
+//! the whole process from startup, hooks it starts included (they inherit
+//! affinity and priority class, not the memory cap).
+//!
+//! `--max-memory` is a hard working-set limit: past it Windows pages the
+//! tool's memory out rather than failing allocations, so a capped run gets
+//! slower, not killed. `--cpu-affinity` also lowers the default number of
+//! hasher threads to the CPUs allowed.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+/// Pseudo handle returned by `GetCurrentProcess`.
+const CURRENT_PROCESS: HANDLE = -1isize as HANDLE;
+const QUOTA_LIMITS_HARDWS_MIN_DISABLE: u32 = 0x2;
+const QUOTA_LIMITS_HARDWS_MAX_ENABLE: u32 = 0x4;
+const PROCESS_MODE_BACKGROUND_BEGIN: u32 = 0x0010_0000;
+
+/// Smallest `--max-memory`, in MiB; below it the tool mostly pages.
+pub const MIN_MEMORY_MB: u64 = 16;
+
+extern "system" {
+    fn SetProcessWorkingSetSizeEx(process: HANDLE, min: usize, max: usize, flags: u32) -> i32;
+    fn SetProcessAffinityMask(process: HANDLE, mask: usize) -> i32;
+    fn SetPriorityClass(process: HANDLE, class: u32) -> i32;
+}
+
+/// CPUs allowed by `--cpu-affinity`; 0 when not set.
+static CPUS: AtomicUsize = AtomicUsize::new(0);
+
+/// CPUs the process may run on, if `--cpu-affinity` restricted them.
+#[cfg(feature = "verify")]
+pub fn cpus() -> Option<usize> {
+    match CPUS.load(Ordering::Relaxed) {
+        0 => None,
+        n => Some(n),
+    }
+}
+
+/// Scheduling priority (`--priority`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Idle,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+    /// Lowest CPU priority, plus low I/O and memory priority.
+    Background,
+}
+
+impl Priority {
+    pub fn parse(s: &str) -> Option<Priority> {
+        match s.to_ascii_lowercase().as_str() {
+            "idle" => Some(Priority::Idle),
+            "below-normal" => Some(Priority::BelowNormal),
+            "normal" => Some(Priority::Normal),
+            "above-normal" => Some(Priority::AboveNormal),
+            "high" => Some(Priority::High),
+            "background" => Some(Priority::Background),
+            _ => None,
+        }
+    }
+
+    /// Value for `SetPriorityClass`.
+    fn class(self) -> u32 {
+        match self {
+            Priority::Idle => 0x40,
+            Priority::BelowNormal => 0x4000,
+            Priority::Normal => 0x20,
+            Priority::AboveNormal => 0x8000,
+            Priority::High => 0x80,
+            Priority::Background => PROCESS_MODE_BACKGROUND_BEGIN,
+        }
+    }
+}
+
+/// Parse a CPU list such as `0-3,6` into an affinity mask.
+pub fn parse_affinity(s: &str) -> Result<u64, String> {
+    let cpu = |s: &str| match s.trim().parse::<u32>() {
+        Ok(n) if n < 64 => Ok(n),
+        _ => Err(format!("'{}' is not a CPU number (0-63)", s.trim())),
+    };
+    let mut mask = 0u64;
+    for part in s.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (cpu(first)?, cpu(last)?),
+            None => (cpu(part)?, cpu(part)?),
+        };
+        if first > last {
+            return Err(format!("empty CPU range '{}'", part.trim()));
+        }
+        for n in first..=last {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+/// Cap the working set at `mb` MiB.
+pub fn set_max_memory(mb: u64) -> Result<(), String> {
+    let max = usize::try_from(mb << 20).map_err(|_| format!("{} MiB is too large", mb))?;
+    let flags = QUOTA_LIMITS_HARDWS_MIN_DISABLE | QUOTA_LIMITS_HARDWS_MAX_ENABLE;
+    // The minimum is not enforced (MIN_DISABLE) but must lie below the maximum
+    let ok = unsafe { SetProcessWorkingSetSizeEx(CURRENT_PROCESS, 1 << 20, max, flags) };
+    if ok == 0 {
+        return Err(format!(
+            "cannot cap memory at {} MiB: {}",
+            mb,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Run only on the CPUs in `mask`.
+pub fn set_affinity(mask: u64) -> Result<(), String> {
+    let ok = unsafe { SetProcessAffinityMask(CURRENT_PROCESS, mask as usize) };
+    if ok == 0 {
+        return Err(format!(
+            "cannot set CPU affinity {:#x}: {}",
+            mask,
+            std::io::Error::last_os_error()
+        ));
+    }
+    CPUS.store(mask.count_ones() as usize, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn set_priority(priority: Priority) -> Result<(), String> {
+    let ok = unsafe { SetPriorityClass(CURRENT_PROCESS, priority.class()) };
+    if ok == 0 {
+        return Err(format!(
+            "cannot set priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_affinity() {
+        assert_eq!(parse_affinity("0-3,6"), Ok(0b100_1111));
+        assert_eq!(parse_affinity(" 2 "), Ok(0b100));
+        assert_eq!(parse_affinity("63"), Ok(1 << 63));
+        assert!(parse_affinity("64").is_err());
+        assert!(parse_affinity("3-1").is_err());
+        assert!(parse_affinity("0,,1").is_err());
+        assert_eq!(Priority::parse("Below-Normal"), Some(Priority::BelowNormal));
+        assert_eq!(Priority::parse("realtime"), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! Safe wrapper around the Win32 Restart Manager (raw FFI, no external crates).
+//!
+//! `LockQuery` registers files in an RM session and lists the processes
+//! holding them; each `LockingProcess` can then be terminated on its own.
+//! RM identifies a process by PID plus start time, so a PID reused after
+//! the query is never hit. Its callers are unlock and sync's
+//! `--import-safe` check (`imports`); there is no graceful close, since
+//! unlock always forces.
+//!
+//! A session cannot drop files once registered, so it is never reused for
+//! another set. Starting one is the main cost of a query; `locked` checks
+//! many groups (directories, files) with one session when none is held and
+//! narrows down by halves when some are. Batches span one command: an
+//! `unlock` of several directories, or the planned files of one sync with
+//! `--import-safe`. Jobs queued with `serve` are syncs run one after the
+//! other, so each still starts its own session.
+
+use crate::paths;
+
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+// ============================================================
+// Win32 type definitions and FFI declarations
+// ============================================================
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type WCHAR = u16;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type LPCWSTR = *const u16;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type UINT = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const ERROR_MORE_DATA: DWORD = 234;
+const RM_FORCE_SHUTDOWN: DWORD = 1;
+const CCH_RM_SESSION_KEY: usize = 32;
+const CCH_RM_MAX_APP_NAME: usize = 255;
+const CCH_RM_MAX_SVC_NAME: usize = 63;
+const PROCESS_QUERY_LIMITED_INFORMATION: DWORD = 0x1000;
+const MAX_PATH: usize = 260;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct RM_UNIQUE_PROCESS {
+    dwProcessId: DWORD,
+    /// FILETIME; guards against a reused PID.
+    ProcessStartTime: [DWORD; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct RM_PROCESS_INFO {
+    Process: RM_UNIQUE_PROCESS,
+    strAppName: [WCHAR; CCH_RM_MAX_APP_NAME + 1],
+    strServiceShortName: [WCHAR; CCH_RM_MAX_SVC_NAME + 1],
+    ApplicationType: i32,
+    AppStatus: u32,
+    TSSessionId: DWORD,
+    bRestartable: i32,
+}
+
+#[link(name = "rstrtmgr")]
+extern "system" {
+    fn RmStartSession(
+        pSessionHandle: *mut DWORD,
+        dwSessionFlags: DWORD,
+        strSessionKey: *mut WCHAR,
+    ) -> DWORD;
+
+    fn RmEndSession(dwSessionHandle: DWORD) -> DWORD;
+
+    fn RmRegisterResources(
+        dwSessionHandle: DWORD,
+        nFiles: UINT,
+        rgsFileNames: *const LPCWSTR,
+        nApplications: UINT,
+        rgApplications: *const RM_UNIQUE_PROCESS,
+        nServices: UINT,
+        rgsServiceNames: *const LPCWSTR,
+    ) -> DWORD;
+
+    fn RmGetList(
+        dwSessionHandle: DWORD,
+        pnProcInfoNeeded: *mut UINT,
+        pnProcInfo: *mut UINT,
+        rgAffectedApps: *mut RM_PROCESS_INFO,
+        lpdwRebootReasons: *mut DWORD,
+    ) -> DWORD;
+
+    fn RmShutdown(
+        dwSessionHandle: DWORD,
+        lActionFlags: DWORD,
+        fnStatus: *const std::ffi::c_void,
+    ) -> DWORD;
+}
+
+extern "system" {
+    fn OpenProcess(dwDesiredAccess: DWORD, bInheritHandle: i32, dwProcessId: DWORD) -> HANDLE;
+    fn QueryFullProcessImageNameW(
+        hProcess: HANDLE,
+        dwFlags: DWORD,
+        lpExeName: *mut WCHAR,
+        lpdwSize: *mut DWORD,
+    ) -> i32;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+// ============================================================
+// Errors and helpers
+// ============================================================
+
+/// A failed Restart Manager call and its Win32 error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RmError {
+    pub function: &'static str,
+    pub code: u32,
+}
+
+impl fmt::Display for RmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed (error {})", self.function, self.code)
+    }
+}
+
+fn check(function: &'static str, code: DWORD) -> Result<(), RmError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(RmError { function, code })
+    }
+}
+
+/// UTF-16 buffer up to the first NUL.
+fn from_wide(buf: &[WCHAR]) -> String {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
+}
+
+/// An open RM session, ended on drop (even on error/panic).
+struct Session(DWORD);
+
+impl Session {
+    fn start() -> Result<Session, RmError> {
+        let mut handle: DWORD = 0;
+        let mut key = [0u16; CCH_RM_SESSION_KEY + 1];
+        check("RmStartSession", unsafe {
+            RmStartSession(&mut handle, 0, key.as_mut_ptr())
+        })?;
+        Ok(Session(handle))
+    }
+
+    fn shutdown(&self, flags: DWORD) -> Result<(), RmError> {
+        check("RmShutdown", unsafe {
+            RmShutdown(self.0, flags, std::ptr::null())
+        })
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe {
+            RmEndSession(self.0);
+        }
+    }
+}
+
+/// Shut down exactly `processes` through a session that registers only
+/// those applications, so nothing else locking the files is touched.
+fn shutdown_processes(processes: &[RM_UNIQUE_PROCESS], flags: DWORD) -> Result<(), RmError> {
+    let session = Session::start()?;
+    check("RmRegisterResources", unsafe {
+        RmRegisterResources(
+            session.0,
+            0,
+            std::ptr::null(),
+            processes.len() as UINT,
+            processes.as_ptr(),
+            0,
+            std::ptr::null(),
+        )
+    })?;
+    session.shutdown(flags)
+}
+
+// ============================================================
+// Public API
+// ============================================================
+
+/// A process that holds one of the queried files open.
+#[derive(Debug, Clone)]
+pub struct LockingProcess {
+    process: RM_UNIQUE_PROCESS,
+    /// Process ID.
+    pub pid: u32,
+    /// Executable file name (e.g. `uTorrent.exe`), or RM's display name if
+    /// the process cannot be opened.
+    pub name: String,
+    /// Terminal Services session the process runs in.
+    pub session: u32,
+}
+
+impl LockingProcess {
+    fn from_info(info: &RM_PROCESS_INFO) -> LockingProcess {
+        LockingProcess {
+            process: info.Process,
+            pid: info.Process.dwProcessId,
+            name: image_name(info.Process.dwProcessId)
+                .unwrap_or_else(|| from_wide(&info.strAppName)),
+            session: info.TSSessionId,
+        }
+    }
+
+    /// Force the process to exit (RmForceShutdown).
+    pub fn terminate(&self) -> Result<(), RmError> {
+        shutdown_processes(&[self.process], RM_FORCE_SHUTDOWN)
+    }
+}
+
+/// Executable file name of a running process.
+fn image_name(pid: u32) -> Option<String> {
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let mut buf = [0u16; MAX_PATH * 4];
+        let mut size = buf.len() as DWORD;
+        let ok = QueryFullProcessImageNameW(handle, 0, buf.as_mut_ptr(), &mut size) != 0;
+        CloseHandle(handle);
+        if !ok {
+            return None;
+        }
+        let path = String::from_utf16_lossy(&buf[..size as usize]);
+        Path::new(&path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+    }
+}
+
+/// Files registered in an RM session, ready to be queried for lockers.
+pub struct LockQuery {
+    session: Session,
+}
+
+impl LockQuery {
+    /// Start a session and register `paths` with it.
+    pub fn new(paths: &[String]) -> Result<LockQuery, RmError> {
+        let wide_paths: Vec<Vec<u16>> = paths.iter().map(paths::to_wide).collect();
+        let wide_ptrs: Vec<LPCWSTR> = wide_paths.iter().map(|w| w.as_ptr()).collect();
+
+        let session = Session::start()?;
+        check("RmRegisterResources", unsafe {
+            RmRegisterResources(
+                session.0,
+                wide_ptrs.len() as UINT,
+                wide_ptrs.as_ptr(),
+                0,
+                std::ptr::null(),
+                0,
+                std::ptr::null(),
+            )
+        })?;
+        Ok(LockQuery { session })
+    }
+
+    /// Processes currently locking any registered file.
+    pub fn processes(&self) -> Result<Vec<LockingProcess>, RmError> {
+        let mut reason: DWORD = 0;
+        let mut needed: UINT = 0;
+        let mut list: Vec<RM_PROCESS_INFO> = Vec::new();
+        // The list can grow between the sizing call and the fetch; retry then
+        loop {
+            let mut count = list.len() as UINT;
+            let result = unsafe {
+                RmGetList(
+                    self.session.0,
+                    &mut needed,
+                    &mut count,
+                    if list.is_empty() {
+                        std::ptr::null_mut()
+                    } else {
+                        list.as_mut_ptr()
+                    },
+                    &mut reason,
+                )
+            };
+            match result {
+                0 => {
+                    list.truncate(count as usize);
+                    return Ok(list.iter().map(LockingProcess::from_info).collect());
+                }
+                ERROR_MORE_DATA => list = vec![unsafe { std::mem::zeroed() }; needed as usize],
+                code => {
+                    return Err(RmError {
+                        function: "RmGetList",
+                        code,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Force every process locking a registered file to exit — the same
+    /// RmShutdown(RmForceShutdown) call rqbit uses.
+    pub fn terminate_all(&self) -> Result<(), RmError> {
+        self.session.shutdown(RM_FORCE_SHUTDOWN)
+    }
+}
+
+/// Bisect `0..count`: `probe` a range, split it in halves while it finds
+/// something, and return the single indices it found something for.
+fn bisect<T, E>(
+    count: usize,
+    mut probe: impl FnMut(Range<usize>) -> Result<Vec<T>, E>,
+) -> Result<Vec<(usize, Vec<T>)>, E> {
+    let mut found = Vec::new();
+    let mut pending = Vec::new();
+    pending.push(0..count);
+    while let Some(range) = pending.pop() {
+        if range.is_empty() {
+            continue;
+        }
+        let hits = probe(range.clone())?;
+        if hits.is_empty() {
+            continue;
+        }
+        if range.len() == 1 {
+            found.push((range.start, hits));
+            continue;
+        }
+        let mid = range.start + range.len() / 2;
+        pending.push(mid..range.end);
+        pending.push(range.start..mid);
+    }
+    Ok(found)
+}
+
+/// Which of `groups` (each a list of files) are held open, with the
+/// processes holding them. One session covers all groups when none is
+/// held; otherwise halves are queried until the held groups are found,
+/// about 2·k·log2(n) sessions for k held out of n.
+pub fn locked(groups: &[Vec<String>]) -> Result<Vec<(usize, Vec<LockingProcess>)>, RmError> {
+    bisect(groups.len(), |range| {
+        let files: Vec<String> = groups[range].iter().flatten().cloned().collect();
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+        LockQuery::new(&files)?.processes()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bisect() {
+        let held = [3, 4, 60];
+        let mut probes = 0;
+        let found = bisect(64, |range| {
+            probes += 1;
+            Ok::<_, ()>(held.iter().filter(|h| range.contains(h)).copied().collect())
+        })
+        .unwrap();
+        let indices: Vec<usize> = found.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indices, held);
+        assert_eq!(found[2].1, vec![60]);
+        // Instead of one query per index
+        assert!(probes < 30, "{} probes", probes);
+
+        let mut probes = 0;
+        let found = bisect(64, |_| {
+            probes += 1;
+            Ok::<Vec<()>, ()>(Vec::new())
+        });
+        assert_eq!((found.unwrap().len(), probes), (0, 1));
+        assert!(bisect(0, |_| Err::<Vec<()>, _>(())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wide_roundtrip() {
+        let w = paths::to_wide("E:\\Online\\x");
+        assert_eq!(w.last(), Some(&0));
+        assert_eq!(from_wide(&w), "E:\\Online\\x");
+    }
+
+    #[test]
+    fn test_error_display() {
+        let e = check("RmGetList", 5).unwrap_err();
+        assert_eq!(e.to_string(), "RmGetList failed (error 5)");
+        assert!(check("RmGetList", 0).is_ok());
+    }
+}
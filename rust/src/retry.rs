@@ -0,0 +1,241 @@
+//! Retries for file operations on network shares.
+//!
+//! An SMB session can drop in the middle of a run: Wi-Fi roaming, a NAS
+//! spinning up or waking from sleep, a server closing idle sessions.
+//! Windows then fails the operation with `ERROR_NETNAME_DELETED` or a
+//! semaphore timeout, and the same call succeeds a moment later once the
+//! redirector has reconnected. The wrappers here retry such transient
+//! errors on UNC paths and mapped network drives, up to `ATTEMPTS` times
+//! with growing, jittered delays. Other errors, and errors on local disks,
+//! are returned at once.
+//!
+//! A retried operation may have succeeded on the server before the reply
+//! was lost: a rename whose source is gone but whose target exists, or a
+//! delete of a file no longer there, counts as done.
+
+use crate::logger::{Level, Record};
+use crate::volume::{self, Kind};
+
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Tries per operation, the first included.
+pub const ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry; doubled for each further one.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Win32 errors of a dropped or stalled network connection.
+const TRANSIENT: [i32; 10] = [
+    51,   // ERROR_REM_NOT_LIST
+    53,   // ERROR_BAD_NETPATH
+    54,   // ERROR_NETWORK_BUSY
+    58,   // ERROR_BAD_NET_RESP
+    59,   // ERROR_UNEXP_NET_ERR
+    64,   // ERROR_NETNAME_DELETED
+    121,  // ERROR_SEM_TIMEOUT
+    240,  // ERROR_VC_DISCONNECTED
+    1231, // ERROR_NETWORK_UNREACHABLE
+    1236, // ERROR_CONNECTION_ABORTED
+];
+
+/// Whether an error may go away by trying again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The connection to the share dropped or stalled.
+    Transient,
+    /// Anything else: access denied, file in use, not found, ...
+    Permanent,
+}
+
+impl ErrorClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::Transient => "transient",
+            ErrorClass::Permanent => "permanent",
+        }
+    }
+}
+
+/// Classify `e` by its Win32 error code.
+pub fn classify(e: &io::Error) -> ErrorClass {
+    match e.raw_os_error() {
+        Some(code) if TRANSIENT.contains(&code) => ErrorClass::Transient,
+        _ if e.kind() == io::ErrorKind::TimedOut => ErrorClass::Transient,
+        _ => ErrorClass::Permanent,
+    }
+}
+
+/// Whether `path` is a UNC path (`\\server\share`, `\\?\UNC\server\share`).
+pub fn is_unc(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    match path.strip_prefix(r"\\?\") {
+        Some(rest) => rest
+            .get(..4)
+            .is_some_and(|p| p.eq_ignore_ascii_case(r"UNC\")),
+        None => path.starts_with(r"\\") && !path.starts_with(r"\\.\"),
+    }
+}
+
+/// Whether `path` lies on a share: UNC, or a mapped network drive.
+fn on_network(path: &Path) -> bool {
+    is_unc(path) || volume::detect(path) == Kind::Network
+}
+
+/// Delay before retry `retry` (1-based), scaled by `jitter` in 0.5..1.5.
+fn delay(retry: u32, jitter: f64) -> Duration {
+    BASE_DELAY
+        .saturating_mul(1 << (retry - 1).min(16))
+        .mul_f64(jitter)
+}
+
+/// A random factor in 0.5..1.5, so that runs dropped by the same outage
+/// do not all come back at once.
+fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    0.5 + (random % 1000) as f64 / 1000.0
+}
+
+/// Run `op` on `path`, retrying transient errors on network paths. After
+/// a transient failure, `done` tells whether the operation took effect
+/// anyway, from the error of the next try.
+fn run<T>(
+    path: &Path,
+    what: &str,
+    done: impl Fn(&io::Error) -> Option<T>,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut retry = 0;
+    loop {
+        let e = match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if retry > 0 {
+            if let Some(value) = done(&e) {
+                return Ok(value);
+            }
+        }
+        retry += 1;
+        if retry >= ATTEMPTS || classify(&e) != ErrorClass::Transient || !on_network(path) {
+            return Err(e);
+        }
+        let wait = delay(retry, jitter());
+        let target = path.to_string_lossy();
+        Record::new(Level::Warn, "NET", &target, "retry")
+            .code(e.raw_os_error().map(i64::from))
+            .message(format!(
+                "{} failed ({}), try {} of {} in {:.1} s",
+                what,
+                e,
+                retry + 1,
+                ATTEMPTS,
+                wait.as_secs_f64()
+            ))
+            .emit();
+        thread::sleep(wait);
+    }
+}
+
+/// `fs::rename`, retried; done if `from` is gone and `to` exists.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let done = |e: &io::Error| {
+        (e.kind() == io::ErrorKind::NotFound && fs::symlink_metadata(to).is_ok()).then_some(())
+    };
+    run(from, "rename", done, || fs::rename(from, to))
+}
+
+/// `fs::remove_file`, retried; done if the file is gone.
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    let done = |e: &io::Error| (e.kind() == io::ErrorKind::NotFound).then_some(());
+    run(path, "delete", done, || fs::remove_file(path))
+}
+
+/// `fs::remove_dir`, retried; done if the directory is gone.
+pub fn remove_dir(path: &Path) -> io::Result<()> {
+    let done = |e: &io::Error| (e.kind() == io::ErrorKind::NotFound).then_some(());
+    run(path, "remove directory", done, || fs::remove_dir(path))
+}
+
+/// `fs::remove_dir_all`, retried; done if the directory is gone.
+pub fn remove_dir_all(path: &Path) -> io::Result<()> {
+    let done = |e: &io::Error| (e.kind() == io::ErrorKind::NotFound).then_some(());
+    run(path, "remove directory", done, || fs::remove_dir_all(path))
+}
+
+/// `fs::create_dir_all`, retried.
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    run(
+        path,
+        "create directory",
+        |_| None,
+        || fs::create_dir_all(path),
+    )
+}
+
+/// `fs::copy`, retried.
+pub fn copy(from: &Path, to: &Path) -> io::Result<u64> {
+    run(from, "copy", |_| None, || fs::copy(from, to))
+}
+
+/// Create an empty file, retried.
+pub fn create_file(path: &Path) -> io::Result<()> {
+    run(
+        path,
+        "create",
+        |_| None,
+        || fs::File::create(path).map(drop),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_classify() {
+        let transient = io::Error::from_raw_os_error(64);
+        assert_eq!(classify(&transient), ErrorClass::Transient);
+        let denied = io::Error::from_raw_os_error(5);
+        assert_eq!(classify(&denied), ErrorClass::Permanent);
+        assert_eq!(
+            classify(&io::Error::from(io::ErrorKind::TimedOut)),
+            ErrorClass::Transient
+        );
+
+        assert!(is_unc(Path::new(r"\\nas\tv\a.mkv")));
+        assert!(is_unc(Path::new(r"\\?\unc\nas\tv")));
+        assert!(!is_unc(Path::new(r"\\?\E:\Online")));
+        assert!(!is_unc(Path::new(r"\\.\E:")));
+        assert!(!is_unc(Path::new(r"E:\Online")));
+
+        assert_eq!(delay(1, 1.0), Duration::from_secs(1));
+        assert_eq!(delay(3, 0.5), Duration::from_secs(2));
+        let jitter = jitter();
+        assert!((0.5..1.5).contains(&jitter));
+    }
+
+    #[test]
+    fn test_local_errors_not_retried() {
+        let dir = TempDir::new("retry");
+        let mut tries = 0;
+        let result = run(
+            &dir,
+            "test",
+            |_| None,
+            || -> io::Result<()> {
+                tries += 1;
+                Err(io::Error::from_raw_os_error(64))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(tries, 1);
+        assert!(remove_file(&dir.join("missing")).is_err());
+    }
+}
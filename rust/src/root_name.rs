@@ -0,0 +1,91 @@
+//! `--rename-root`: give the payload folder the torrent's name.
+//!
+//! Clients, importers and cross-seed tools find a payload by its folder
+//! name. A folder whose name differs from `info.name` only trivially
+//! (letter case, trailing dots or spaces, tag prefixes such as `[Group] `)
+//! is reported by every sync; with `--rename-root` sync renames it before
+//! anything else. Tags are the `--root-tag` globs (default `[*]`), removed
+//! from the front of either name for the comparison. Windows drops trailing
+//! dots and spaces from names, so the folder gets the torrent's name
+//! without them. The client has to find the folder under the new name, so
+//! rename folders it does not seed from under the old one.
+
+use crate::ignore::glob_match;
+
+/// Default `--root-tag`.
+pub const DEFAULT_TAGS: &[&str] = &["[*]"];
+
+/// Separators left after a tag.
+const SEPARATORS: [char; 4] = [' ', '-', '_', '.'];
+
+/// `name` as compared: leading `tags` removed, trailing dots and spaces
+/// trimmed, lowercased. A tag that is the whole name stays.
+fn key(name: &str, tags: &[String]) -> String {
+    let name = name.trim().to_lowercase();
+    let tags: Vec<String> = tags.iter().map(|t| t.to_lowercase()).collect();
+    let mut rest = name.as_str();
+    while let Some(end) = tags.iter().find_map(|tag| {
+        (1..=rest.len())
+            .filter(|&i| rest.is_char_boundary(i))
+            .find(|&i| glob_match(tag, &rest[..i]))
+    }) {
+        let next = rest[end..].trim_start_matches(SEPARATORS);
+        if next.is_empty() {
+            break;
+        }
+        rest = next;
+    }
+    rest.trim_end_matches(['.', ' ']).to_string()
+}
+
+/// The name folder `folder` should have for torrent name `name`, if the
+/// two differ only trivially. A name that could point elsewhere (a
+/// separator, a drive, `..`) never qualifies.
+pub fn wanted(folder: &str, name: &str, tags: &[String]) -> Option<String> {
+    if name.contains(['\\', '/', ':']) || name.contains("..") {
+        return None;
+    }
+    let target = name.trim_end_matches(['.', ' ']);
+    if target.is_empty() || folder == target || key(folder, tags) != key(name, tags) {
+        return None;
+    }
+    Some(target.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wanted() {
+        let tags: Vec<String> = DEFAULT_TAGS.iter().map(|t| t.to_string()).collect();
+        let wanted = |folder: &str, name: &str| super::wanted(folder, name, &tags);
+        assert_eq!(wanted("show.s01", "Show.S01"), Some("Show.S01".into()));
+        assert_eq!(wanted("Show.S01", "Show.S01..."), None);
+        assert_eq!(wanted("show.s01", "Show.S01."), Some("Show.S01".into()));
+        assert_eq!(wanted("Show S01", "Show S01 ."), None);
+        assert_eq!(
+            wanted("[GRP] Show.S01", "Show.S01"),
+            Some("Show.S01".into())
+        );
+        assert_eq!(
+            wanted("Show.S01", "[a][b] - Show.S01"),
+            Some("[a][b] - Show.S01".into())
+        );
+        assert_eq!(wanted("Show.S01", "Show.S01"), None);
+        assert_eq!(wanted("Show.S02", "Show.S01"), None);
+        // Names that could leave the parent directory
+        assert_eq!(wanted("show", "..\\Show"), None);
+        assert_eq!(wanted("show", "x/Show"), None);
+        assert_eq!(wanted("show", "C:Show"), None);
+        // A name that is all tag is compared whole
+        assert_eq!(wanted("[x]", "[y]"), None);
+        // Custom tags replace the default
+        let tags = vec!["(*)".to_string()];
+        assert_eq!(
+            super::wanted("(2019) Film", "Film", &tags),
+            Some("Film".into())
+        );
+        assert_eq!(super::wanted("[GRP] Film", "Film", &tags), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! `--rules` files: keep, exclude and companion rules that can be shared.
+//!
+//! One rule per line, `<kind> <pattern>`; blank lines and `#` comments are
+//! skipped. Patterns are as in `.zdirignore`, relative to the sync root:
+//! - `keep GLOB`: never delete matching extras
+//! - `exclude GLOB`: delete matching extras after all, overriding keep
+//!   rules, the profile's `keep` list and `.zdirignore` files (files of the
+//!   torrent are never extras)
+//! - `companion GLOB`: keep a matching extra only while a torrent file in
+//!   the same folder shares its name up to a dot (`Movie.en.srt` with
+//!   `Movie.mkv`, `Album.cue` with `Album.flac`)
+//!
+//! Rule sets are layered: every `--rules` file (the option repeats) and
+//! every file of the config's `rules` list adds to the others.
+
+use std::fs;
+use std::path::Path;
+
+/// Rules from one or more rule files.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    pub keep: Vec<String>,
+    pub exclude: Vec<String>,
+    pub companion: Vec<String>,
+}
+
+impl Rules {
+    /// Parse rule file text; errors name the offending line.
+    pub fn parse(text: &str) -> Result<Rules, String> {
+        let mut rules = Rules::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, pattern) = line
+                .split_once(char::is_whitespace)
+                .map(|(kind, pattern)| (kind, pattern.trim()))
+                .ok_or_else(|| format!("line {}: expected '<kind> <pattern>'", number + 1))?;
+            let list = match kind.to_lowercase().as_str() {
+                "keep" => &mut rules.keep,
+                "exclude" => &mut rules.exclude,
+                "companion" => &mut rules.companion,
+                _ => {
+                    return Err(format!(
+                        "line {}: unknown rule '{}', use keep, exclude or companion",
+                        number + 1,
+                        kind
+                    ))
+                }
+            };
+            list.push(pattern.to_string());
+        }
+        Ok(rules)
+    }
+
+    /// Load a rule file.
+    pub fn load(path: &Path) -> Result<Rules, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+        Rules::parse(&text).map_err(|e| format!("{:?} {}", path, e))
+    }
+
+    /// Layer `other` on top of these rules.
+    pub fn extend(&mut self, other: Rules) {
+        self.keep.extend(other.keep);
+        self.exclude.extend(other.exclude);
+        self.companion.extend(other.companion);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rules = Rules::parse(
+            "# scene extras\nkeep *.nfo\n\nKEEP   Sample/\nexclude *.exe\ncompanion *.srt\n",
+        )
+        .unwrap();
+        assert_eq!(rules.keep, vec!["*.nfo", "Sample/"]);
+        assert_eq!(rules.exclude, vec!["*.exe"]);
+        assert_eq!(rules.companion, vec!["*.srt"]);
+
+        assert!(Rules::parse("keep").unwrap_err().contains("line 1"));
+        assert!(Rules::parse("\ndelete *.txt")
+            .unwrap_err()
+            .contains("line 2"));
+    }
+}
@@ -1,9 +1,146 @@
-//! Path depth safety guard.
+//! Path depth safety guard and directory walk limits.
 //!
 //! Prevents operations on directories that are too shallow (e.g., drive root or
 //! first-level directories) to avoid accidentally deleting files from other torrents.
+//! Walks abort once they exceed `--max-files` entries or `--max-depth` levels, so
+//! a mistakenly targeted huge tree or a junction loop fails early.
+//! Moves must stay on one volume: a rename across volumes would have to
+//! become copy+delete, which can double disk usage mid-run, so it is
+//! refused unless `--allow-copy`, and then only if the copies fit in the
+//! free space of the destination volume. Before deleting, sync checks that
+//! the target is still on the volume it was planned on.
+//! A thread given a cancel flag (API jobs past `--job-timeout`) stops its
+//! walks and sync deletions once the flag is set.
 
+use crate::volume;
+
+use std::cell::RefCell;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static MAX_FILES: AtomicUsize = AtomicUsize::new(1_000_000);
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(64);
+static ALLOW_COPY: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CANCEL: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Stop walks and sync deletions on the calling thread once `flag` is set.
+#[cfg(any(feature = "service", test))]
+pub fn set_cancel(flag: Arc<AtomicBool>) {
+    CANCEL.with(|cancel| *cancel.borrow_mut() = Some(flag));
+}
+
+/// `Err` once the work on the calling thread was cancelled.
+pub fn check_cancelled() -> Result<(), String> {
+    let cancelled = CANCEL.with(|cancel| {
+        cancel
+            .borrow()
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    });
+    if cancelled {
+        return Err("cancelled, stopped".to_string());
+    }
+    Ok(())
+}
+
+/// Set the walk limits (`--max-files`, `--max-depth`).
+pub fn set_walk_limits(max_files: Option<usize>, max_depth: Option<usize>) {
+    if let Some(n) = max_files {
+        MAX_FILES.store(n, Ordering::Relaxed);
+    }
+    if let Some(n) = max_depth {
+        MAX_DEPTH.store(n, Ordering::Relaxed);
+    }
+}
+
+/// Check a walk that has collected `entries` paths and is about to enter a
+/// directory `depth` levels below its root.
+pub fn check_walk(entries: usize, depth: usize) -> Result<(), String> {
+    check_cancelled()?;
+    let max_files = MAX_FILES.load(Ordering::Relaxed);
+    let max_depth = MAX_DEPTH.load(Ordering::Relaxed);
+    if entries > max_files {
+        return Err(format!(
+            "more than {} files and directories (--max-files), aborted",
+            max_files
+        ));
+    }
+    if depth > max_depth {
+        return Err(format!(
+            "nested deeper than {} levels (--max-depth; junction loop?), aborted",
+            max_depth
+        ));
+    }
+    Ok(())
+}
+
+/// Allow moves across volumes as copy+delete (`--allow-copy`).
+pub fn set_allow_copy(allow: bool) {
+    ALLOW_COPY.store(allow, Ordering::Relaxed);
+}
+
+pub fn allow_copy() -> bool {
+    ALLOW_COPY.load(Ordering::Relaxed)
+}
+
+/// Whether two volume mount points are the same. Unknown ones are assumed
+/// to match; the move itself then reports any problem.
+fn roots_match(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => true,
+    }
+}
+
+/// Whether `path` lies on the volume mounted at `root` (from
+/// `volume::volume_root`). Mount points and junctions inside a tree can
+/// put parts of it on other volumes.
+pub fn on_volume(path: &Path, root: Option<&str>) -> bool {
+    roots_match(volume::volume_root(path).as_deref(), root)
+}
+
+/// Check that `required` bytes fit on the volume holding `dir`. Unknown
+/// free space passes.
+pub fn check_free_space(dir: &Path, required: u64) -> Result<(), String> {
+    match volume::free_bytes(dir) {
+        Some(free) if free < required => Err(format!(
+            "needs {} MiB but only {} MiB are free on the volume, aborted",
+            required.div_ceil(1 << 20),
+            free >> 20
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Check that `dir` is still there, on the volume `planned` identified
+/// when the plan was made, and that the volume can be written. A drive
+/// letter remapped in between would otherwise have the plan applied to
+/// another disk. Without a planned identity only presence is checked.
+pub fn check_same_volume(dir: &Path, planned: Option<&volume::Identity>) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err("directory is gone (volume unmounted?)".to_string());
+    }
+    let Some(planned) = planned else {
+        return Ok(());
+    };
+    let moved = |now: &volume::Identity| {
+        now.serial != planned.serial || !roots_match(Some(&now.root), Some(&planned.root))
+    };
+    match volume::identity(dir) {
+        None => Err(format!("volume {} is no longer mounted", planned.root)),
+        Some(now) if moved(&now) => Err(format!(
+            "volume changed since planning ({} serial {:08X}, now {} serial {:08X}); \
+             drive letter remapped?",
+            planned.root, planned.serial, now.root, now.serial
+        )),
+        Some(now) if now.read_only => Err(format!("volume {} is read-only", now.root)),
+        Some(_) => Ok(()),
+    }
+}
 
 /// Check that the given path has at least `min_depth` components.
 ///
@@ -62,4 +199,44 @@ mod tests {
         assert!(check_depth(Path::new("E:\\Online\\MyTorrent"), 3));
         assert!(check_depth(Path::new("E:\\Online\\Category\\MyTorrent"), 3));
     }
+
+    #[test]
+    fn test_roots_match() {
+        assert!(roots_match(Some("E:\\"), Some("e:\\")));
+        assert!(!roots_match(Some("E:\\"), Some("E:\\Mounts\\Disk2\\")));
+        assert!(roots_match(None, Some("E:\\")));
+    }
+
+    #[test]
+    fn test_check_same_volume() {
+        let missing = std::env::temp_dir().join("zdircomp-no-such-dir");
+        assert!(check_same_volume(&missing, None).unwrap_err().contains("gone"));
+        assert!(check_same_volume(&std::env::temp_dir(), None).is_ok());
+    }
+
+    #[test]
+    fn test_check_walk() {
+        assert!(check_walk(10, 3).is_ok());
+        assert!(check_walk(1_000_001, 0).unwrap_err().contains("--max-files"));
+        assert!(check_walk(0, 65).unwrap_err().contains("--max-depth"));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let flag = Arc::new(AtomicBool::new(false));
+        std::thread::spawn({
+            let flag = Arc::clone(&flag);
+            move || {
+                set_cancel(Arc::clone(&flag));
+                assert!(check_walk(10, 3).is_ok());
+                flag.store(true, Ordering::Relaxed);
+                assert!(check_walk(10, 3).unwrap_err().contains("cancelled"));
+            }
+        })
+        .join()
+        .unwrap();
+        // Other threads are not affected
+        assert!(flag.load(Ordering::Relaxed));
+        assert!(check_cancelled().is_ok());
+    }
 }
@@ -0,0 +1,131 @@
+//! `install-task` / `uninstall-task`: periodic runs via Task Scheduler.
+//!
+//! Creates (or replaces, `/F`) a scheduled task that starts this executable
+//! with `--args` every `--every` interval, using `schtasks.exe`. The task
+//! runs as the installing user while they are logged on; use the Task
+//! Scheduler UI to change that. `--task-name` defaults to `zDirComp`.
+
+use crate::logger::{Level, Record};
+
+use std::process::Command;
+use std::time::Duration;
+
+/// Default `--task-name`.
+pub const DEFAULT_NAME: &str = "zDirComp";
+/// Longest `/TR` command line `schtasks` accepts.
+const MAX_COMMAND: usize = 261;
+
+/// `schtasks /SC` schedule type and `/MO` modifier for an interval, in the
+/// coarsest unit that divides it.
+fn schedule(every: Duration) -> Option<(&'static str, u64)> {
+    let secs = every.as_secs();
+    if secs == 0 || !secs.is_multiple_of(60) {
+        return None;
+    }
+    let (minutes, hours, days) = (secs / 60, secs / 3600, secs / 86_400);
+    if secs.is_multiple_of(86_400) && days <= 365 {
+        Some(("DAILY", days))
+    } else if secs.is_multiple_of(3600) && hours <= 23 {
+        Some(("HOURLY", hours))
+    } else if minutes <= 1439 {
+        Some(("MINUTE", minutes))
+    } else {
+        None
+    }
+}
+
+/// The `/TR` value: quoted executable path and arguments.
+fn task_command(exe: &str, args: &str) -> Result<String, String> {
+    let command = format!("\"{}\" {}", exe, args).trim_end().to_string();
+    if command.len() > MAX_COMMAND {
+        return Err(format!(
+            "task command line is {} characters, schtasks allows {}; move options \
+             into zDirComp.toml (--profile)",
+            command.len(),
+            MAX_COMMAND
+        ));
+    }
+    Ok(command)
+}
+
+fn schtasks(args: &[&str]) -> Result<(), String> {
+    let output = Command::new("schtasks.exe")
+        .args(args)
+        .output()
+        .map_err(|e| format!("cannot run schtasks.exe: {}", e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(format!("schtasks failed: {}", stderr.trim()))
+}
+
+/// Create or update task `name` running the exe with `args` every `every`.
+pub fn install(name: &str, every: Duration, args: &str) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "TASK", name, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let (kind, modifier) = schedule(every).ok_or_else(|| {
+        abort("--every must be whole minutes, up to 1439m, 23h or 365d".to_string())
+    })?;
+    let exe = std::env::current_exe()
+        .map_err(|e| abort(format!("cannot locate the executable: {}", e)))?;
+    let command = task_command(&exe.to_string_lossy(), args).map_err(abort)?;
+    let modifier = modifier.to_string();
+    let unit = match kind {
+        "DAILY" => "days",
+        "HOURLY" => "hours",
+        _ => "minutes",
+    };
+    schtasks(&[
+        "/Create", "/F", "/TN", name, "/SC", kind, "/MO", &modifier, "/TR", &command,
+    ])
+    .map_err(abort)?;
+    Record::new(Level::Info, "TASK", name, "install")
+        .message(format!(
+            "scheduled every {} {}: {}",
+            modifier, unit, command
+        ))
+        .emit();
+    Ok(())
+}
+
+/// Delete task `name`.
+pub fn uninstall(name: &str) -> Result<(), String> {
+    schtasks(&["/Delete", "/F", "/TN", name]).inspect_err(|e| {
+        Record::new(Level::Error, "TASK", name, "abort")
+            .message(e.as_str())
+            .emit();
+    })?;
+    Record::new(Level::Info, "TASK", name, "uninstall")
+        .message("scheduled task removed")
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let h = |n: u64| Duration::from_secs(n * 3600);
+        assert_eq!(schedule(h(6)), Some(("HOURLY", 6)));
+        assert_eq!(schedule(h(48)), Some(("DAILY", 2)));
+        assert_eq!(schedule(Duration::from_secs(90 * 60)), Some(("MINUTE", 90)));
+        assert_eq!(schedule(h(30)), None);
+        assert_eq!(schedule(Duration::from_secs(30)), None);
+    }
+
+    #[test]
+    fn test_task_command() {
+        assert_eq!(
+            task_command("C:\\Tools\\zDirComp.exe", "sync --profile tv").unwrap(),
+            "\"C:\\Tools\\zDirComp.exe\" sync --profile tv"
+        );
+        assert!(task_command("C:\\zDirComp.exe", &"x".repeat(300)).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! `selftest`: run the destructive code paths against throwaway trees.
+//!
+//! Each case builds a directory and a matching synthetic torrent in a
+//! fresh folder under `%TEMP%`, runs the real command code on it (sync with
+//! its safety checks, the read-only comparison `report` uses, piece
+//! hashing) and checks what is left on disk. Every case is logged as
+//! passed or failed; the command exits with code 1 if any failed. Nothing
+//! outside the temp folder is touched. Each sync waits its usual 3 seconds
+//! first, so a run takes some seconds.
+
+use crate::bencode::{self, BValue};
+#[cfg(feature = "verify")]
+use crate::hashing::{self, Algorithm};
+use crate::logger::{Level, Record};
+#[cfg(feature = "verify")]
+use crate::piecemap::PieceMap;
+use crate::sha;
+use crate::sync;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PIECE_LENGTH: usize = 16 * 1024;
+
+/// One check: name and body, given an empty folder of its own.
+type Case = (&'static str, fn(&Path) -> Result<(), String>);
+
+const CASES: &[Case] = &[
+    ("compare", compare_lists_extras),
+    ("sync", sync_deletes_extras),
+    ("sync-wrong-torrent", sync_refuses_wrong_torrent),
+    ("sync-empty-files", sync_creates_empty_files),
+    ("sync-fix-case", sync_fixes_case),
+    #[cfg(feature = "verify")]
+    ("hash", hash_finds_corruption),
+];
+
+fn expect(ok: bool, what: &str) -> Result<(), String> {
+    if ok {
+        Ok(())
+    } else {
+        Err(what.to_string())
+    }
+}
+
+/// Test data: `len` bytes that differ from file to file.
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed))
+        .collect()
+}
+
+/// Write `contents` to `dir/relative` (`/`-separated), creating folders.
+fn write(dir: &Path, relative: &str, contents: &[u8]) -> Result<PathBuf, String> {
+    let path = relative
+        .split('/')
+        .fold(dir.to_path_buf(), |p, c| p.join(c));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("cannot create {:?}: {}", parent, e))?;
+    }
+    fs::write(&path, contents).map_err(|e| format!("cannot write {:?}: {}", path, e))?;
+    Ok(path)
+}
+
+/// Write a multi-file torrent for `files` (`/`-separated path, contents)
+/// to `dir/test.torrent` and return its path.
+fn torrent(dir: &Path, files: &[(&str, &[u8])]) -> Result<String, String> {
+    let bytes = |b: &[u8]| BValue::Bytes(b.to_vec());
+    let dict = |entries: Vec<(&str, BValue)>| {
+        BValue::Dict(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.as_bytes().to_vec(), v))
+                .collect(),
+        )
+    };
+    let payload: Vec<u8> = files.iter().flat_map(|(_, d)| d.iter().copied()).collect();
+    let pieces: Vec<u8> = payload.chunks(PIECE_LENGTH).flat_map(sha::sha1).collect();
+    let list = files
+        .iter()
+        .map(|(path, contents)| {
+            let path = path.split('/').map(|c| bytes(c.as_bytes())).collect();
+            dict(vec![
+                ("length", BValue::Integer(contents.len() as i64)),
+                ("path", BValue::List(path)),
+            ])
+        })
+        .collect();
+    let info = dict(vec![
+        ("files", BValue::List(list)),
+        ("name", bytes(b"selftest")),
+        ("piece length", BValue::Integer(PIECE_LENGTH as i64)),
+        ("pieces", bytes(&pieces)),
+    ]);
+    let path = dir.join("test.torrent");
+    fs::write(&path, bencode::encode(&dict(vec![("info", info)])))
+        .map_err(|e| format!("cannot write {:?}: {}", path, e))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// The download folder of a case, beside its torrent.
+fn payload_dir(case: &Path) -> Result<PathBuf, String> {
+    let dir = case.join("Payload");
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create {:?}: {}", dir, e))?;
+    Ok(dir)
+}
+
+fn dir_str(dir: &Path) -> String {
+    dir.to_string_lossy().into_owned()
+}
+
+fn compare_lists_extras(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let movie = data(5000, 1);
+    write(&dir, "movie.mkv", &movie)?;
+    write(&dir, "Extras/junk.txt", b"x")?;
+    let meta = bencode::parse_torrent_file(Path::new(&torrent(case, &[("movie.mkv", &movie)])?))?;
+    let extras = sync::extras(&dir, &meta.files)?;
+    expect(
+        extras == vec![Path::new("Extras").join("junk.txt")],
+        "extras are not exactly Extras/junk.txt",
+    )?;
+    expect(
+        dir.join("Extras").join("junk.txt").is_file(),
+        "comparing deleted a file",
+    )
+}
+
+fn sync_deletes_extras(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let (a, b) = (data(4000, 1), data(3000, 2));
+    let kept = [write(&dir, "a.bin", &a)?, write(&dir, "Sub/b.bin", &b)?];
+    write(&dir, "extra.nfo", b"nfo")?;
+    write(&dir, "Junk/x.tmp", b"tmp")?;
+    let torrent = torrent(case, &[("a.bin", &a), ("Sub/b.bin", &b)])?;
+
+    let report = sync::run(&torrent, &dir_str(&dir), &sync::Options::default())?;
+    expect(
+        report.deleted.len() == 2,
+        "not exactly the 2 extras deleted",
+    )?;
+    expect(
+        report.deleted_dirs == 1,
+        "the emptied folder was not removed",
+    )?;
+    expect(!dir.join("extra.nfo").exists(), "extra.nfo still there")?;
+    expect(!dir.join("Junk").exists(), "Junk still there")?;
+    expect(
+        fs::read(&kept[0]).ok() == Some(a) && fs::read(&kept[1]).ok() == Some(b),
+        "a torrent file was changed or deleted",
+    )
+}
+
+fn sync_refuses_wrong_torrent(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let files = ["one.mkv", "two.mkv", "three.mkv"];
+    for (seed, name) in files.iter().enumerate() {
+        write(&dir, name, &data(1000, seed as u8))?;
+    }
+    let torrent = torrent(case, &[("other.mkv", &data(1000, 9))])?;
+    let result = sync::run(&torrent, &dir_str(&dir), &sync::Options::default());
+    expect(
+        result.is_err(),
+        "a plan deleting every file was not refused",
+    )?;
+    expect(
+        files.iter().all(|name| dir.join(name).is_file()),
+        "a file was deleted despite the refusal",
+    )
+}
+
+fn sync_creates_empty_files(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let contents = data(2000, 3);
+    write(&dir, "data.bin", &contents)?;
+    let torrent = torrent(case, &[("data.bin", &contents), ("Sub/empty.txt", b"")])?;
+    let report = sync::run(&torrent, &dir_str(&dir), &sync::Options::default())?;
+    let empty = dir.join("Sub").join("empty.txt");
+    expect(
+        report.created == vec![empty.clone()],
+        "empty.txt not reported as created",
+    )?;
+    expect(
+        fs::metadata(&empty).is_ok_and(|m| m.len() == 0),
+        "empty.txt missing or not empty",
+    )
+}
+
+fn sync_fixes_case(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let contents = data(2000, 4);
+    write(&dir, "season 1/EP1.mkv", &contents)?;
+    let torrent = torrent(case, &[("Season 1/ep1.mkv", &contents)])?;
+    let options = sync::Options {
+        fix_case: true,
+        ..sync::Options::default()
+    };
+    let report = sync::run(&torrent, &dir_str(&dir), &options)?;
+    expect(
+        report.deleted.is_empty(),
+        "a file differing in case was deleted",
+    )?;
+    let names = |dir: &Path| -> Vec<String> {
+        fs::read_dir(dir)
+            .map(|entries| {
+                let names = entries
+                    .flatten()
+                    .map(|e| e.file_name().to_string_lossy().into_owned());
+                names.collect()
+            })
+            .unwrap_or_default()
+    };
+    expect(
+        names(&dir) == ["Season 1"],
+        "folder not renamed to 'Season 1'",
+    )?;
+    expect(
+        names(&dir.join("Season 1")) == ["ep1.mkv"],
+        "file not renamed to 'ep1.mkv'",
+    )
+}
+
+#[cfg(feature = "verify")]
+fn hash_finds_corruption(case: &Path) -> Result<(), String> {
+    let dir = payload_dir(case)?;
+    let (a, b) = (data(3 * PIECE_LENGTH - 100, 5), data(1000, 6));
+    let big = write(&dir, "a.bin", &a)?;
+    write(&dir, "b.bin", &b)?;
+    let meta =
+        bencode::parse_torrent_file(Path::new(&torrent(case, &[("a.bin", &a), ("b.bin", &b)])?))?;
+    let map = PieceMap::new(&meta).ok_or("no piece map for the torrent")?;
+    let failed = || {
+        let all = vec![true; map.piece_count()];
+        let digests = hashing::hash_pieces(&dir, &meta, &map, Algorithm::Sha1, 2, &all);
+        let pieces = digests.iter().zip(&meta.piece_hashes);
+        let failed = pieces
+            .enumerate()
+            .filter(|(_, (d, e))| d.as_deref() != Some(&e[..]));
+        failed.map(|(piece, _)| piece).collect::<Vec<usize>>()
+    };
+    expect(failed().is_empty(), "intact data failed its piece hashes")?;
+    let mut corrupt = a;
+    corrupt[PIECE_LENGTH + 10] ^= 0xFF;
+    fs::write(&big, &corrupt).map_err(|e| format!("cannot write {:?}: {}", big, e))?;
+    expect(
+        failed() == vec![1],
+        "the corrupted piece was not the only one failing",
+    )
+}
+
+/// `selftest` command: run every case; `Err` if any failed.
+pub fn run() -> Result<(), String> {
+    let base = std::env::temp_dir().join(format!("zdircomp-selftest-{}", std::process::id()));
+    let target = dir_str(&base);
+    let mut failed = 0;
+    for (name, check) in CASES {
+        let case = base.join(name);
+        let _ = fs::remove_dir_all(&case);
+        let result = fs::create_dir_all(&case)
+            .map_err(|e| format!("cannot create {:?}: {}", case, e))
+            .and_then(|()| check(&case));
+        match result {
+            Ok(()) => Record::new(Level::Info, "SELFTEST", &target, "pass")
+                .message(format!("{}: passed", name))
+                .emit(),
+            Err(e) => {
+                failed += 1;
+                Record::new(Level::Error, "SELFTEST", &target, "fail")
+                    .path(&case)
+                    .message(format!("{}: {}", name, e))
+                    .emit();
+            }
+        }
+    }
+    // Failed cases are left for a look
+    if failed == 0 {
+        let _ = fs::remove_dir_all(&base);
+    }
+    Record::new(Level::Info, "SELFTEST", &target, "summary")
+        .message(format!(
+            "{} of {} cases passed",
+            CASES.len() - failed,
+            CASES.len()
+        ))
+        .emit();
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{} cases failed", n)),
+    }
+}
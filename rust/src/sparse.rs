@@ -0,0 +1,118 @@
+//! Allocated size of sparse files (raw FFI, no external crates).
+//!
+//! Clients preallocate downloads as sparse files: the logical size is right
+//! from the start, but unwritten regions are holes. The allocated ranges
+//! (`FSCTL_QUERY_ALLOCATED_RANGES`) tell how much data was really written.
+
+use crate::paths;
+
+use std::path::Path;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const INVALID_FILE_ATTRIBUTES: DWORD = 0xFFFF_FFFF;
+const FILE_ATTRIBUTE_SPARSE_FILE: DWORD = 0x0200;
+const GENERIC_READ: DWORD = 0x8000_0000;
+const FILE_SHARE_ALL: DWORD = 0x7;
+const OPEN_EXISTING: DWORD = 3;
+const FSCTL_QUERY_ALLOCATED_RANGES: DWORD = 0x0009_40CF;
+const ERROR_MORE_DATA: i32 = 234;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct FILE_ALLOCATED_RANGE_BUFFER {
+    FileOffset: i64,
+    Length: i64,
+}
+
+extern "system" {
+    fn GetFileAttributesW(lpFileName: *const u16) -> DWORD;
+    fn CreateFileW(
+        lpFileName: *const u16,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: *const std::ffi::c_void,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn DeviceIoControl(
+        hDevice: HANDLE,
+        dwIoControlCode: DWORD,
+        lpInBuffer: *const std::ffi::c_void,
+        nInBufferSize: DWORD,
+        lpOutBuffer: *mut std::ffi::c_void,
+        nOutBufferSize: DWORD,
+        lpBytesReturned: *mut DWORD,
+        lpOverlapped: *mut std::ffi::c_void,
+    ) -> i32;
+    fn GetLastError() -> DWORD;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// Bytes actually allocated in the first `length` bytes of a sparse file.
+/// `None` if the file is not sparse (fully allocated) or cannot be queried.
+pub fn allocated_bytes(path: &Path, length: u64) -> Option<u64> {
+    let wide = paths::to_wide(path);
+    unsafe {
+        let attributes = GetFileAttributesW(wide.as_ptr());
+        if attributes == INVALID_FILE_ATTRIBUTES || attributes & FILE_ATTRIBUTE_SPARSE_FILE == 0 {
+            return None;
+        }
+        let handle = CreateFileW(
+            wide.as_ptr(),
+            GENERIC_READ,
+            FILE_SHARE_ALL,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            0,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+
+        let mut total = 0u64;
+        let mut query = FILE_ALLOCATED_RANGE_BUFFER {
+            FileOffset: 0,
+            Length: length as i64,
+        };
+        let mut ranges = [FILE_ALLOCATED_RANGE_BUFFER::default(); 64];
+        let result = loop {
+            let mut returned: DWORD = 0;
+            let ok = DeviceIoControl(
+                handle,
+                FSCTL_QUERY_ALLOCATED_RANGES,
+                &query as *const _ as *const std::ffi::c_void,
+                std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as DWORD,
+                ranges.as_mut_ptr() as *mut std::ffi::c_void,
+                std::mem::size_of_val(&ranges) as DWORD,
+                &mut returned,
+                std::ptr::null_mut(),
+            ) != 0;
+            let more = !ok && GetLastError() as i32 == ERROR_MORE_DATA;
+            if !ok && !more {
+                break None;
+            }
+            let count = returned as usize / std::mem::size_of::<FILE_ALLOCATED_RANGE_BUFFER>();
+            total += ranges[..count].iter().map(|r| r.Length as u64).sum::<u64>();
+            if !more || count == 0 {
+                break Some(total);
+            }
+            // Continue after the last range returned
+            let last = ranges[count - 1];
+            let next = last.FileOffset + last.Length;
+            query = FILE_ALLOCATED_RANGE_BUFFER {
+                FileOffset: next,
+                Length: length as i64 - next,
+            };
+        };
+        CloseHandle(handle);
+        result
+    }
+}
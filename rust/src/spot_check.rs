@@ -0,0 +1,146 @@
+//! `audit-complete`: does the data on disk back the client's claims?
+//!
+//! For every torrent of a `--bt-backup` folder, a sample of the pieces the
+//! fastresume bitfield marks as downloaded is hashed from disk (read-only).
+//! A piece that is missing or does not match flags the torrent: the client
+//! would seed, or report complete, data that is not there, after bit rot or
+//! a move that lost files. `--sample N` pieces (default 16) are taken
+//! evenly across the claimed ones; 0 hashes them all.
+
+use crate::bt_backup::{self, Pair};
+use crate::hashing::{self, Algorithm};
+use crate::logger::{Level, Record};
+use crate::piecemap::PieceMap;
+
+use std::path::Path;
+
+/// Default `--sample`.
+pub const DEFAULT_SAMPLE: usize = 16;
+
+/// Up to `count` of `claimed` (all for 0), spread evenly.
+fn sample(claimed: &[usize], count: usize) -> Vec<usize> {
+    if count == 0 || count >= claimed.len() {
+        return claimed.to_vec();
+    }
+    (0..count)
+        .map(|k| claimed[k * claimed.len() / count])
+        .collect()
+}
+
+/// Hash a sample of the pieces `pair`'s client claims to have; returns the
+/// number sampled and the pieces that failed.
+pub fn check(pair: &Pair, count: usize) -> Result<(usize, Vec<usize>), String> {
+    if pair.resume.have.is_empty() {
+        return Err("fastresume has no piece bitfield".to_string());
+    }
+    let mut meta = pair.meta.clone();
+    pair.resume.path_map(&meta)?.apply(&mut meta.files);
+    let map = PieceMap::new(&meta)
+        .filter(|map| map.piece_count() == meta.piece_hashes.len())
+        .ok_or("torrent piece data is missing or inconsistent")?;
+    let claimed: Vec<usize> = (0..map.piece_count())
+        .filter(|&piece| pair.resume.have.get(piece).copied().unwrap_or(false))
+        .collect();
+    let sampled = sample(&claimed, count);
+    let mut selected = vec![false; map.piece_count()];
+    sampled.iter().for_each(|&piece| selected[piece] = true);
+    let dir = pair.content_dir();
+    let threads = hashing::default_threads();
+    let digests = hashing::hash_pieces(&dir, &meta, &map, Algorithm::Sha1, threads, &selected);
+    let bad = sampled
+        .iter()
+        .copied()
+        .filter(|&piece| digests[piece].as_deref() != Some(&meta.piece_hashes[piece][..]))
+        .collect();
+    Ok((sampled.len(), bad))
+}
+
+/// `audit-complete` command. `Err` if the folder cannot be read or any
+/// torrent is flagged.
+pub fn run(source: &str, count: usize) -> Result<(), String> {
+    let pairs = bt_backup::pairs(Path::new(source)).inspect_err(|e| {
+        Record::new(Level::Error, "AUDIT", source, "abort")
+            .message(e.as_str())
+            .emit();
+    })?;
+    let (mut checked, mut flagged, mut skipped) = (0, 0, 0);
+    for pair in pairs {
+        let pair = match pair {
+            Ok(pair) => pair,
+            Err((torrent, reason)) => {
+                Record::new(Level::Warn, "AUDIT", source, "skip")
+                    .path(&torrent)
+                    .message(format!("{:?} skipped: {}", torrent, reason))
+                    .emit();
+                skipped += 1;
+                continue;
+            }
+        };
+        let dir = pair.content_dir();
+        let target = dir.to_string_lossy();
+        match check(&pair, count) {
+            Err(reason) => {
+                Record::new(Level::Warn, "AUDIT", &target, "skip")
+                    .path(&pair.torrent)
+                    .message(format!("{:?} skipped: {}", pair.meta.name, reason))
+                    .emit();
+                skipped += 1;
+            }
+            Ok((sampled, bad)) if bad.is_empty() => {
+                Record::new(Level::Debug, "AUDIT", &target, "complete")
+                    .message(format!(
+                        "{:?}: {} sampled pieces OK",
+                        pair.meta.name, sampled
+                    ))
+                    .emit();
+                checked += 1;
+            }
+            Ok((sampled, bad)) => {
+                let claim = if pair.resume.complete(&pair.meta) {
+                    "complete"
+                } else {
+                    "partly downloaded"
+                };
+                Record::new(Level::Error, "AUDIT", &target, "incomplete")
+                    .path(&pair.torrent)
+                    .message(format!(
+                        "{:?}: client says {}, but {} of {} sampled pieces are missing or \
+                         corrupt (first: piece {}); recheck it in the client",
+                        pair.meta.name,
+                        claim,
+                        bad.len(),
+                        sampled,
+                        bad[0]
+                    ))
+                    .emit();
+                checked += 1;
+                flagged += 1;
+            }
+        }
+    }
+    Record::new(Level::Info, "AUDIT", source, "summary")
+        .message(format!(
+            "audit-complete: {} checked, {} flagged, {} skipped",
+            checked, flagged, skipped
+        ))
+        .emit();
+    match flagged {
+        0 => Ok(()),
+        n => Err(format!("{} torrents flagged", n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let claimed: Vec<usize> = (10..20).collect();
+        assert_eq!(sample(&claimed, 0), claimed);
+        assert_eq!(sample(&claimed, 50), claimed);
+        assert_eq!(sample(&claimed, 2), vec![10, 15]);
+        assert_eq!(sample(&claimed, 5), vec![10, 12, 14, 16, 18]);
+        assert!(sample(&[], 3).is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+//! Small persistent key/value store, `zDirComp.state`.
+//!
+//! Kept where the log goes (next to the executable, else
+//! `%LOCALAPPDATA%\zDirComp`), one `key<TAB>value` per line. Updates are
+//! read-modify-write under the same cross-process lock as log writes.
+
+use crate::logger;
+
+use std::fs;
+
+/// State file name.
+pub const FILE_NAME: &str = "zDirComp.state";
+
+fn parse(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn render(entries: &[(String, String)]) -> String {
+    entries
+        .iter()
+        .map(|(k, v)| format!("{}\t{}\n", k, v))
+        .collect()
+}
+
+/// Set `key` to `value` in `entries`, or remove it for `None`.
+fn update(entries: &mut Vec<(String, String)>, key: &str, value: Option<&str>) {
+    entries.retain(|(k, _)| k != key);
+    if let Some(value) = value {
+        entries.push((key.to_string(), value.to_string()));
+    }
+}
+
+/// Stored value of `key`.
+pub fn get(key: &str) -> Option<String> {
+    logger::log_paths(FILE_NAME)
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .and_then(|text| parse(&text).into_iter().find(|(k, _)| k == key))
+        .map(|(_, v)| v)
+}
+
+/// Stored entries whose key starts with `prefix`, the prefix removed.
+pub fn entries(prefix: &str) -> Vec<(String, String)> {
+    logger::log_paths(FILE_NAME)
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|text| parse(&text))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix(prefix)?.to_string(), v)))
+        .collect()
+}
+
+/// Store `value` under `key` (`None` removes it). Keys and values must not
+/// contain tabs or line breaks.
+pub fn set(key: &str, value: Option<&str>) -> Result<(), String> {
+    modify(key, |_| value.map(str::to_string))
+}
+
+/// Replace the value of `key` with `f` of the current one, in one locked
+/// read-modify-write, so concurrent runs can update counters safely.
+pub fn modify(key: &str, f: impl Fn(Option<&str>) -> Option<String>) -> Result<(), String> {
+    let paths = logger::log_paths(FILE_NAME);
+    logger::write_first(&paths, |path| {
+        let mut entries = parse(&fs::read_to_string(path).unwrap_or_default());
+        let current = entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        let value = f(current);
+        update(&mut entries, key, value.as_deref());
+        fs::write(path, render(&entries))
+    })
+    .map(drop)
+    .ok_or_else(|| format!("cannot write {}", FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_round_trip() {
+        let mut entries = parse("a\t1\nbroken line\nb\t2 3\n");
+        assert_eq!(entries.len(), 2);
+        update(&mut entries, "a", Some("9"));
+        update(&mut entries, "b", None);
+        assert_eq!(render(&entries), "a\t9\n");
+    }
+}
@@ -0,0 +1,168 @@
+//! NTFS alternate data streams (raw FFI, no external crates).
+//!
+//! Browsers and clients tag downloads with a `Zone.Identifier` stream (the
+//! Mark-of-the-Web); other junk can live only in streams of expected files.
+//! A handle open on a stream also blocks a plain delete of its file, so
+//! deletion can fall back to POSIX semantics, which unlinks the name while
+//! such handles are still open.
+
+use crate::paths;
+
+use std::io;
+use std::path::Path;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const FIND_STREAM_INFO_STANDARD: i32 = 0;
+const MAX_PATH: usize = 260;
+const DELETE: DWORD = 0x0001_0000;
+const FILE_SHARE_ALL: DWORD = 0x7;
+const OPEN_EXISTING: DWORD = 3;
+const FILE_FLAG_OPEN_REPARSE_POINT: DWORD = 0x0020_0000;
+const FILE_DISPOSITION_INFO_EX_CLASS: i32 = 21;
+const FILE_DISPOSITION_FLAG_DELETE: DWORD = 0x1;
+const FILE_DISPOSITION_FLAG_POSIX_SEMANTICS: DWORD = 0x2;
+const FILE_DISPOSITION_FLAG_IGNORE_READONLY_ATTRIBUTE: DWORD = 0x10;
+
+/// Mark-of-the-Web stream name.
+pub const ZONE_IDENTIFIER: &str = "Zone.Identifier";
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct WIN32_FIND_STREAM_DATA {
+    StreamSize: i64,
+    cStreamName: [u16; MAX_PATH + 36],
+}
+
+extern "system" {
+    fn FindFirstStreamW(
+        lpFileName: *const u16,
+        InfoLevel: i32,
+        lpFindStreamData: *mut WIN32_FIND_STREAM_DATA,
+        dwFlags: DWORD,
+    ) -> HANDLE;
+    fn FindNextStreamW(hFindStream: HANDLE, lpFindStreamData: *mut WIN32_FIND_STREAM_DATA) -> i32;
+    fn FindClose(hFindFile: HANDLE) -> i32;
+    fn CreateFileW(
+        lpFileName: *const u16,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: *const std::ffi::c_void,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn SetFileInformationByHandle(
+        hFile: HANDLE,
+        FileInformationClass: i32,
+        lpFileInformation: *const std::ffi::c_void,
+        dwBufferSize: DWORD,
+    ) -> i32;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// Name of an alternate stream from a `:name:$DATA` entry; `None` for the
+/// unnamed main stream (`::$DATA`).
+fn stream_name(raw: &str) -> Option<&str> {
+    let name = raw.strip_prefix(':')?;
+    let name = name.strip_suffix(":$DATA").unwrap_or(name);
+    (!name.is_empty()).then_some(name)
+}
+
+/// Alternate data streams of a file (empty if none or unsupported).
+pub fn list(path: &Path) -> Vec<String> {
+    let wide = paths::to_wide(path);
+    let mut names = Vec::new();
+    unsafe {
+        let mut data: WIN32_FIND_STREAM_DATA = std::mem::zeroed();
+        let find = FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0);
+        if find == INVALID_HANDLE_VALUE {
+            return names;
+        }
+        loop {
+            let len = data
+                .cStreamName
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(data.cStreamName.len());
+            let raw = String::from_utf16_lossy(&data.cStreamName[..len]);
+            if let Some(name) = stream_name(&raw) {
+                names.push(name.to_string());
+            }
+            if FindNextStreamW(find, &mut data) == 0 {
+                break;
+            }
+        }
+        FindClose(find);
+    }
+    names
+}
+
+/// Remove the Mark-of-the-Web from a file. Returns whether it had one.
+pub fn clear_zone_identifier(path: &Path) -> io::Result<bool> {
+    let mut stream = path.as_os_str().to_owned();
+    stream.push(":");
+    stream.push(ZONE_IDENTIFIER);
+    match std::fs::remove_file(&stream) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete a file with POSIX semantics: the name goes away immediately even
+/// if other handles (e.g. on one of its streams) are still open, as long as
+/// they allow sharing delete. Read-only files are deleted as well.
+pub fn delete_posix(path: &Path) -> io::Result<()> {
+    let wide = paths::to_wide(path);
+    let flags = FILE_DISPOSITION_FLAG_DELETE
+        | FILE_DISPOSITION_FLAG_POSIX_SEMANTICS
+        | FILE_DISPOSITION_FLAG_IGNORE_READONLY_ATTRIBUTE;
+    unsafe {
+        let handle = CreateFileW(
+            wide.as_ptr(),
+            DELETE,
+            FILE_SHARE_ALL,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            FILE_FLAG_OPEN_REPARSE_POINT,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let ok = SetFileInformationByHandle(
+            handle,
+            FILE_DISPOSITION_INFO_EX_CLASS,
+            &flags as *const DWORD as *const std::ffi::c_void,
+            std::mem::size_of::<DWORD>() as DWORD,
+        ) != 0;
+        let error = io::Error::last_os_error();
+        CloseHandle(handle);
+        if ok {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_name() {
+        assert_eq!(stream_name("::$DATA"), None);
+        assert_eq!(
+            stream_name(":Zone.Identifier:$DATA"),
+            Some("Zone.Identifier")
+        );
+        assert_eq!(stream_name(":extra"), Some("extra"));
+        assert_eq!(stream_name("bogus"), None);
+    }
+}
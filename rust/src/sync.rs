@@ -3,146 +3,1479 @@
 //! Steps:
 //! 1. Sleep 3 seconds (wait for uTorrent to release file handles)
 //! 2. Validate path depth (safety guard)
-//! 3. Parse .torrent → extract expected file list
-//! 4. Walk directory depth-first (children before parents)
-//! 5. Delete files not in the expected set
+//! 3. Parse .torrent → extract expected file list (`TorrentMeta`); with
+//!    `sync-client` the list comes from the client's WebUI instead
+//! 4. Walk directory depth-first (children before parents) and plan the
+//!    deletion of files not in the expected set, except those matched by a
+//!    `.zdirignore` or marked hidden+system (unless `--include-system`),
+//!    after renaming entries that differ from the torrent only in letter
+//!    case to its spelling (`--fix-case`, see `casing`; `--rename-root`
+//!    renames the folder itself first, see `root_name`),
+//!    sorted by `--order` (`--tui` reviews the plan). Steps 4-6 repeat for
+//!    every extra root given with `--dir`; `--subpath` limits them and the
+//!    expected set to one subtree
+//! 5. Check that every planned path resolves below the directory (the whole
+//!    run aborts otherwise), then delete the planned files: rename them all
+//!    into `.zdc_trash`, then delete the staged files (files staged by an
+//!    interrupted run are moved back before step 4). With `--retention` the
+//!    staged files are moved to `.zdc_kept` instead, and kept batches past
+//!    the window are deleted before step 4. More than `--snapshot-over` files
+//!    are only deleted after a shadow copy of the volume was taken (see
+//!    `vss`)
 //! 6. Delete empty directories
+//! 7. Create missing zero-length files listed in the torrent
+//! 8. Optionally ask the torrent client to recheck/pause (`--post-action`)
+//! 9. Optionally clear the Mark-of-the-Web from expected files (`--clear-motw`)
+//!
+//! With `--import-safe`, extras hard-linked into the media library or open
+//! in another process are left for a later run (see `imports`). With
+//! `--media-server`, the whole run waits while Plex/Jellyfin streams a file
+//! from the directory (see `media`). A program started from a root aborts
+//! the run unless `--allow-running`.
+//!
+//! With `--skip-unchanged`, roots that look as they did after the last
+//! clean sync are skipped before step 4 (see `cache`).
 
+use crate::audit;
 use crate::bencode;
-use crate::logger;
+use crate::bencode::TorrentMeta;
+use crate::cache;
+use crate::casing;
+#[cfg(feature = "client-apis")]
+use crate::client::{self, Action, PostAction};
+use crate::dir_index::DirIndex;
+use crate::dirtimes::{self, DirTimes};
+use crate::hooks;
+use crate::ignore::Ignore;
+use crate::imports;
+use crate::json::Json;
+use crate::logger::{Level, Record};
+#[cfg(feature = "client-apis")]
+use crate::media;
+use crate::pathmap::PathMap;
+use crate::paths;
+use crate::piecemap::PieceMap;
+use crate::policy::{
+    self, Chain, Decision, DefaultPolicy, FileInfo, HardlinkGuard, KeepGlobs, MinAge, SyncContext,
+    SystemFiles,
+};
+use crate::priorities::Priorities;
+use crate::process_tree;
+use crate::retry::{self, ErrorClass};
+use crate::root_name;
+use crate::rules::Rules;
 use crate::safety;
+use crate::sha;
+use crate::streams;
+use crate::throttle::Throttle;
+use crate::trash::{self, Trash, KEPT_DIR, TRASH_DIR};
+use crate::volume;
+use crate::vss;
+use crate::walk::Walker;
+#[cfg(feature = "tui")]
+use crate::tui;
 
+use std::cmp::Reverse;
 use std::collections::HashSet;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
+
+/// Outcome of a sync run, for callers that combine several runs.
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Deleted files, as full paths.
+    pub deleted: Vec<PathBuf>,
+    /// Bytes of deleted files, and of kept files purged (`--retention`).
+    pub freed_bytes: u64,
+    /// Number of empty directories removed.
+    pub deleted_dirs: u32,
+    /// Zero-length files created, as full paths.
+    pub created: Vec<PathBuf>,
+    /// Entries renamed to the torrent's names (`--fix-case`, `--rename-root`), as new
+    /// full paths.
+    pub renamed: Vec<PathBuf>,
+    /// Extras kept for now by `--import-safe`, as full paths.
+    pub deferred: Vec<PathBuf>,
+    /// Directories whose modification time was put back
+    /// (`--preserve-dir-times`).
+    pub restored_dir_times: usize,
+    /// Expected files given a new modification time (`--touch-expected`).
+    pub touched: usize,
+    /// Files that could not be deleted, created or renamed.
+    pub errors: Vec<FileError>,
+    /// The `--tui` review was cancelled; nothing more was deleted.
+    pub cancelled: bool,
+}
+
+/// A file sync failed on, after any retries.
+#[derive(Debug, Clone)]
+pub struct FileError {
+    /// Full path.
+    pub path: PathBuf,
+    pub error: String,
+    /// `Transient` if the network share was still unreachable after the
+    /// last retry.
+    pub class: ErrorClass,
+}
+
+impl FileError {
+    fn new(path: PathBuf, e: &io::Error) -> FileError {
+        FileError {
+            path,
+            error: e.to_string(),
+            class: retry::classify(e),
+        }
+    }
+}
+
+impl SyncReport {
+    /// Result object for `--format json`.
+    pub fn to_json(&self) -> Json {
+        let paths = |list: &[PathBuf]| {
+            Json::Array(
+                list.iter()
+                    .map(|p| Json::from(p.to_string_lossy().into_owned()))
+                    .collect(),
+            )
+        };
+        let errors = self.errors.iter().map(|e| {
+            Json::object()
+                .with("path", e.path.to_string_lossy().into_owned())
+                .with("error", e.error.as_str())
+                .with("class", e.class.as_str())
+        });
+        Json::object()
+            .with("deleted", paths(&self.deleted))
+            .with("freed_bytes", self.freed_bytes as i64)
+            .with("deleted_dirs", i64::from(self.deleted_dirs))
+            .with("created", paths(&self.created))
+            .with("renamed", paths(&self.renamed))
+            .with("deferred", paths(&self.deferred))
+            .with("restored_dir_times", self.restored_dir_times as i64)
+            .with("touched", self.touched as i64)
+            .with("errors", Json::Array(errors.collect()))
+            .with("cancelled", self.cancelled)
+    }
+}
+
+/// Log an `abort` record; its message becomes the run's error.
+fn abort(target: &str, message: impl Into<String>) -> String {
+    let message = message.into();
+    Record::new(Level::Error, "SYNC", target, "abort")
+        .message(message.as_str())
+        .emit();
+    message
+}
 
-/// Run the sync operation.
-pub fn run(torrent_path: &str, dir_path: &str) {
+/// Steps 1-2: wait for the client to let go, then check the path depth.
+fn prepare(dir_path: &str) -> Result<(), String> {
     // Step 1: Delay 3 seconds
     thread::sleep(Duration::from_secs(3));
 
-    let dir = Path::new(dir_path);
-
     // Step 2: Safety guard
-    if !safety::check_depth(dir, 3) {
-        logger::log(&format!(
-            "SYNC {:?} — path too shallow, aborted",
-            dir_path
-        ));
-        std::process::exit(1);
+    if !safety::check_depth(Path::new(dir_path), 3) {
+        return Err(abort(dir_path, "path too shallow, aborted"));
     }
+    Ok(())
+}
+
+/// Deletion order for the planned files (`--order`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Largest first, so space is reclaimed fastest.
+    Size,
+    /// Alphabetical by relative path.
+    Path,
+    /// Oldest modification time first.
+    Mtime,
+}
+
+impl Order {
+    /// Parse an `--order` value.
+    pub fn parse(s: &str) -> Option<Order> {
+        match s.to_lowercase().as_str() {
+            "size" => Some(Order::Size),
+            "path" => Some(Order::Path),
+            "mtime" => Some(Order::Mtime),
+            _ => None,
+        }
+    }
+}
+
+/// Default `--max-delete-percent`.
+pub const DEFAULT_MAX_DELETE_PERCENT: u32 = 60;
+
+/// Settings beyond the two positional arguments.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Client action after the directory changed (`--post-action`).
+    #[cfg(feature = "client-apis")]
+    pub post: PostAction,
+    /// Review the deletion plan interactively before deleting (`--tui`).
+    #[cfg(feature = "tui")]
+    pub review: bool,
+    /// Remove `Zone.Identifier` streams from expected files (`--clear-motw`).
+    pub clear_motw: bool,
+    /// `--rules` files and the profile's keep patterns, applied like a
+    /// root `.zdirignore`.
+    pub rules: Rules,
+    /// Deletion order; `None` keeps walk order (`--order`).
+    pub order: Option<Order>,
+    /// Also delete hidden+system extras (`--include-system`).
+    pub include_system: bool,
+    /// Rename entries that differ from the torrent only in letter case
+    /// (`--fix-case`).
+    pub fix_case: bool,
+    /// Rename the directory to the torrent name if it differs only
+    /// trivially (`--rename-root`); otherwise only report it.
+    pub rename_root: bool,
+    /// Name prefixes `--rename-root` ignores, as globs (`--root-tag`).
+    pub root_tags: Vec<String>,
+    /// Put back directory modification times changed by the run
+    /// (`--preserve-dir-times`).
+    pub preserve_dir_times: bool,
+    /// Set the modification time of the expected files present to this
+    /// (`--touch-expected`).
+    pub touch_expected: Option<SystemTime>,
+    /// More roots holding parts of the payload (`--dir`, config `dirs`).
+    pub extra_dirs: Vec<String>,
+    /// Torrent paths relocated on disk (`--map`).
+    pub path_map: PathMap,
+    /// Only sync this torrent-relative subtree (`--subpath`); empty for all.
+    pub subpath: PathBuf,
+    /// Files marked "don't download" (`--priorities`).
+    pub priorities: Priorities,
+    /// Delete leftovers of "don't download" files (`--delete-skipped`).
+    pub delete_skipped: bool,
+    /// Skip roots unchanged since the last clean sync (`--skip-unchanged`).
+    pub skip_unchanged: bool,
+    /// Keep deleted files in `.zdc_kept` this long (`--retention`).
+    pub retention: Option<Duration>,
+    /// Defer deleting files linked into the library or open (`--import-safe`).
+    pub import_safe: bool,
+    /// Media library roots for `--import-safe` (`--library`, config `library`).
+    pub library: Vec<PathBuf>,
+    /// Delete even while a program started from the directory runs
+    /// (`--allow-running`).
+    pub allow_running: bool,
+    /// Largest share of the files or bytes one run may delete, in percent
+    /// (`--max-delete-percent`; `DEFAULT_MAX_DELETE_PERCENT` if unset).
+    pub max_delete_percent: Option<u32>,
+    /// Skip that check (`--force-large-delete`).
+    pub force_large_delete: bool,
+    /// Snapshot the volume before deleting more files than this (`--snapshot-over`).
+    pub snapshot_over: Option<usize>,
+    /// File operations per second while deleting; `Some(0)` for no limit,
+    /// `None` to decide by drive type (`--delete-rate`).
+    pub delete_rate: Option<u32>,
+    /// Never delete files matching these globs (`--protect`).
+    pub protect: Vec<String>,
+    /// Only delete files unchanged this long (`--older-than`).
+    pub older_than: Option<Duration>,
+    /// Command shown each root's plan, which may veto it
+    /// (`--pre-delete-hook`).
+    pub pre_delete_hook: Option<String>,
+    /// Command shown the summary of a completed run, which may fail it
+    /// (`--post-run-hook`).
+    pub post_run_hook: Option<String>,
+    /// Plex/Jellyfin server whose streams defer the run (`--media-server`).
+    #[cfg(feature = "client-apis")]
+    pub media: Option<media::Server>,
+}
+
+/// Run the sync operation. Errors are aborts, already logged.
+pub fn run(torrent_path: &str, dir_path: &str, options: &Options) -> Result<SyncReport, String> {
+    prepare(dir_path)?;
 
     // Step 3: Parse torrent file
-    let expected_files = match bencode::parse_torrent_file(Path::new(torrent_path)) {
-        Ok(files) => files,
-        Err(e) => {
-            logger::log(&format!(
-                "SYNC {:?} — {}",
-                torrent_path, e
-            ));
-            std::process::exit(1);
+    let meta = bencode::parse_torrent_file(Path::new(torrent_path))
+        .map_err(|e| abort(torrent_path, e.to_string()))?;
+
+    Record::new(Level::Debug, "SYNC", torrent_path, "parse")
+        .message(format!(
+            "torrent {:?}: {} files, {} bytes, {} pieces of {} bytes{}",
+            meta.name,
+            meta.files.len(),
+            meta.total_size,
+            meta.pieces,
+            meta.piece_length,
+            if meta.private { ", private" } else { "" }
+        ))
+        .emit();
+
+    if let Some(map) = PieceMap::new(&meta) {
+        if map.piece_count() != meta.pieces {
+            Record::new(Level::Warn, "SYNC", torrent_path, "parse")
+                .message(format!(
+                    "torrent has {} piece hashes but its files need {}",
+                    meta.pieces,
+                    map.piece_count()
+                ))
+                .emit();
         }
+    }
+
+    sync_meta(meta, torrent_path, dir_path, options)
+}
+
+/// Run sync with the file list of torrent `hash` as reported by the client.
+#[cfg(feature = "client-apis")]
+pub fn run_client(
+    hash: &str,
+    dir_path: &str,
+    config: &client::Config,
+    options: &Options,
+) -> Result<SyncReport, String> {
+    prepare(dir_path)?;
+
+    // Step 3: Ask the client for the file list
+    let meta = config
+        .connect()
+        .files(hash)
+        .map_err(|e| abort(hash, e))?;
+
+    Record::new(Level::Debug, "SYNC", hash, "parse")
+        .message(format!(
+            "client torrent {:?}: {} files, {} bytes",
+            meta.name,
+            meta.files.len(),
+            meta.total_size
+        ))
+        .emit();
+
+    sync_meta(meta, hash, dir_path, options)
+}
+
+/// Steps 4-9 for an already loaded file list. `source` names where the list
+/// came from (torrent path or infohash) for log records.
+fn sync_meta(
+    mut meta: TorrentMeta,
+    source: &str,
+    dir_path: &str,
+    options: &Options,
+) -> Result<SyncReport, String> {
+    // A single-file torrent names the file, not the directory
+    let single_file = meta.files.len() == 1 && meta.files[0].path == Path::new(&meta.name);
+    let relocated = options.path_map.apply(&mut meta.files);
+    if relocated > 0 {
+        Record::new(Level::Debug, "SYNC", source, "map")
+            .message(format!("{} torrent paths relocated by --map", relocated))
+            .emit();
+    }
+    // Everything outside `--subpath` is neither expected nor walked
+    meta.files.retain(|f| f.path.starts_with(&options.subpath));
+    let skipped = options.priorities.apply(&mut meta.files);
+    if skipped > 0 {
+        Record::new(Level::Debug, "SYNC", source, "skip")
+            .message(format!(
+                "{} files set to don't download{}",
+                skipped,
+                if options.delete_skipped { ", leftovers will be deleted" } else { "" }
+            ))
+            .emit();
+    }
+
+    for &(first, later) in &meta.duplicates {
+        Record::new(Level::Warn, "SYNC", source, "duplicate")
+            .path(&meta.files[later].path)
+            .message(format!(
+                "torrent lists {:?} more than once (entries {} and {}, paths may differ in case)",
+                meta.files[later].path, first, later
+            ))
+            .emit();
+    }
+
+    // Build HashSet of expected relative paths; "don't download" files are
+    // never created, and only kept if present unless `--delete-skipped`
+    let empty_files: Vec<PathBuf> = meta
+        .files
+        .iter()
+        .filter(|f| f.length == 0 && !f.skip)
+        .map(|f| f.path.clone())
+        .collect();
+    let expected: HashSet<PathBuf> = meta
+        .files
+        .iter()
+        .filter(|f| !(f.skip && options.delete_skipped))
+        .map(|f| f.path.clone())
+        .collect();
+
+    // The payload may be split across roots (`--dir`); any of them may hold
+    // an expected file, so each root is cleaned against the full list
+    let mut roots = vec![dir_path];
+    roots.extend(options.extra_dirs.iter().map(String::as_str));
+    for root in &roots[1..] {
+        if !safety::check_depth(Path::new(root), 3) {
+            return Err(abort(root, "path too shallow, aborted"));
+        }
+    }
+
+    // What hooks are told about the torrent
+    let torrent = Json::object()
+        .with("source", source)
+        .with("info_hash", sha::hex(&meta.info_hash))
+        .with("name", meta.name.as_str());
+
+    let mut report = SyncReport::default();
+    let mut dirs: Vec<PathBuf> = roots.iter().map(|root| normalize_root(root)).collect();
+    if options.skip_unchanged {
+        let unchanged = cache::fingerprint(&dirs)
+            .is_ok_and(|fp| cache::is_clean("sync", &meta.info_hash, &dirs[0], fp));
+        if unchanged {
+            Record::new(Level::Info, "SYNC", dir_path, "summary")
+                .message("unchanged since the last clean sync, skipped")
+                .emit();
+            return Ok(report);
+        }
+    }
+    #[cfg(feature = "client-apis")]
+    if media::defer(options.media.as_ref(), "SYNC", dir_path) {
+        return Ok(report);
+    }
+    // Deleting under a program started from the payload breaks it mid-run
+    if !options.allow_running {
+        for (dir, root) in dirs.iter().zip(&roots) {
+            if let Some((process, image)) = process_tree::running_under(dir).first() {
+                return Err(abort(
+                    root,
+                    format!(
+                        "{} (pid {}) runs from {:?}, aborted (see --allow-running)",
+                        process.name, process.pid, image
+                    ),
+                ));
+            }
+        }
+    }
+    // Only a run that goes ahead renames the folder
+    let renamed_root = match single_file {
+        true => None,
+        false => rename_root(&meta.name, dir_path, options),
     };
+    let dir_path = renamed_root
+        .as_ref()
+        .map_or(dir_path, |(_, path)| path.as_str());
+    if let Some((dir, _)) = &renamed_root {
+        roots[0] = dir_path;
+        dirs[0] = normalize_root(dir_path);
+        report.renamed.push(dir.clone());
+    }
+    let times: Vec<DirTimes> = match options.preserve_dir_times {
+        true => dirs.iter().map(|dir| DirTimes::capture(dir)).collect(),
+        false => Vec::new(),
+    };
+    let mut synced = Ok(());
+    for (dir, root) in dirs.iter().zip(&roots) {
+        synced = sync_root(dir, root, &expected, &torrent, options, &mut report);
+        if synced.is_err() || report.cancelled {
+            break;
+        }
+    }
 
-    // Build HashSet of expected relative paths
-    let expected: HashSet<PathBuf> = expected_files.into_iter().collect();
+    // Zero-length files have no pieces, so the client may never create them
+    if synced.is_ok() && !report.cancelled {
+        create_empty_files(&dirs, dir_path, &empty_files, &mut report);
+    }
+    // Also after an abort: recovering the trash may have touched folders
+    for (times, root) in times.iter().zip(&roots) {
+        restore_dir_times(times, root, &mut report);
+    }
+    synced?;
+    if report.cancelled {
+        return Ok(report);
+    }
+
+    // Step 6: Log summary
+    let (deleted_files, deleted_dirs) = (report.deleted.len(), report.deleted_dirs);
+    let created_files = report.created.len();
+    let deferred_files = report.deferred.len();
+    let renamed = report.renamed.len();
+    if deleted_files == 0
+        && deleted_dirs == 0
+        && created_files == 0
+        && deferred_files == 0
+        && renamed == 0
+    {
+        Record::new(Level::Info, "SYNC", dir_path, "summary")
+            .message("clean, nothing to remove")
+            .emit();
+    } else {
+        let mut message = format!(
+            "deleted {} files, {} empty dirs",
+            deleted_files, deleted_dirs
+        );
+        if created_files > 0 {
+            message.push_str(&format!(", created {} empty files", created_files));
+        }
+        if deferred_files > 0 {
+            message.push_str(&format!(", deferred {} until imported", deferred_files));
+        }
+        if renamed > 0 {
+            message.push_str(&format!(", fixed the case of {} names", renamed));
+        }
+        if roots.len() > 1 {
+            message.push_str(&format!(" across {} roots", roots.len()));
+        }
+        Record::new(Level::Info, "SYNC", dir_path, "summary")
+            .message(message)
+            .emit();
+
+        // Step 8: Client no longer has the deleted data — recheck or pause
+        #[cfg(feature = "client-apis")]
+        if options.post.action != Action::None {
+            match options.post.apply(&meta.info_hash) {
+                Ok(()) => Record::new(Level::Info, "SYNC", dir_path, "post-action")
+                    .message(format!("client {:?} requested", options.post.action).to_lowercase())
+                    .emit(),
+                Err(e) => Record::new(Level::Warn, "SYNC", dir_path, "post-action")
+                    .message(e)
+                    .emit(),
+            }
+        }
+    }
 
+    // Step 9: Mark-of-the-Web and stream-only extras on expected files
+    if options.clear_motw {
+        for (dir, root) in dirs.iter().zip(&roots) {
+            clear_motw(dir, root, &meta.files);
+        }
+    }
+
+    if let Some(hook) = &options.post_run_hook {
+        let input = torrent
+            .with("hook", "post-run")
+            .with("directory", dir_path)
+            .with("report", report.to_json());
+        hooks::run("post-run", hook, dir_path, &input).map_err(|e| abort(dir_path, e))?;
+    }
+
+    // Fingerprint the result for the next `--skip-unchanged` run
+    if options.skip_unchanged {
+        let settled = report.errors.is_empty() && report.deferred.is_empty();
+        let clean = settled.then(|| cache::fingerprint(&dirs).ok());
+        if let Err(e) = cache::record("sync", &meta.info_hash, &dirs[0], clean.flatten()) {
+            Record::new(Level::Warn, "SYNC", dir_path, "cache")
+                .message(e)
+                .emit();
+        }
+    }
+    Ok(report)
+}
+
+/// Put back the directory times of `root` recorded before the run.
+fn restore_dir_times(times: &DirTimes, root: &str, report: &mut SyncReport) {
+    let restored = times.restore();
+    for (path, e) in &restored.errors {
+        Record::new(Level::Warn, "SYNC", root, "dir-times")
+            .path(path)
+            .code(e.raw_os_error().map(i64::from))
+            .message(format!("cannot restore the modification time of {:?}: {}", path, e))
+            .emit();
+    }
+    if restored.restored > 0 {
+        Record::new(Level::Info, "SYNC", root, "dir-times")
+            .message(format!(
+                "restored the modification time of {} directories",
+                restored.restored
+            ))
+            .emit();
+    }
+    report.restored_dir_times += restored.restored;
+}
+
+/// Directory listings use long names; expand an 8.3 root to match.
+fn normalize_root(dir_path: &str) -> PathBuf {
+    let long_dir = paths::long_path(Path::new(dir_path));
+    if long_dir != Path::new(dir_path) {
+        Record::new(Level::Debug, "SYNC", dir_path, "normalize")
+            .message(format!("short path expanded to {:?}", long_dir))
+            .emit();
+    }
+    long_dir
+}
+
+/// Steps 4-6 for one root: plan, review and delete its extras, adding the
+/// results to `report`. `torrent` describes the torrent to hooks.
+fn sync_root(
+    dir: &Path,
+    dir_path: &str,
+    expected: &HashSet<PathBuf>,
+    torrent: &Json,
+    options: &Options,
+    report: &mut SyncReport,
+) -> Result<(), String> {
     if !dir.exists() {
-        logger::log(&format!(
-            "SYNC {:?} — directory does not exist, aborted",
-            dir_path
-        ));
-        std::process::exit(1);
+        return Err(abort(dir_path, "directory does not exist, aborted"));
     }
 
-    // Step 4-5: Walk and delete
-    let mut deleted_files = 0u32;
-    let mut deleted_dirs = 0u32;
+    recover_trash(dir, dir_path);
+    if let Some(retention) = options.retention {
+        report.freed_bytes += expire_kept(dir, "SYNC", dir_path, retention);
+    }
 
-    // Collect all entries depth-first (children before parents)
-    let entries = walk_depth_first(dir);
+    // Step 4: Walk and plan
+    let mut ignore = Ignore::load(dir).map_err(|e| abort(dir_path, e))?;
+    ignore.add_rules(&options.rules);
+    for file in &ignore.files {
+        Record::new(Level::Debug, "SYNC", dir_path, "ignore")
+            .path(file)
+            .message(format!("using ignore patterns from {:?}", file))
+            .emit();
+    }
+    let volume_at_plan = volume::identity(dir);
+    let mut index = DirIndex::build(dir, &options.subpath).map_err(|e| abort(dir_path, e))?;
+    if options.fix_case && fix_case(dir, dir_path, &index, expected, report) > 0 {
+        index = DirIndex::build(dir, &options.subpath).map_err(|e| abort(dir_path, e))?;
+    }
+    let ctx = SyncContext {
+        dir,
+        index: &index,
+        expected,
+        now: SystemTime::now(),
+    };
+    let (mut planned, mut deferred) = plan(&policies(ignore, options), &ctx, dir_path);
+    if let Some(order) = options.order {
+        sort_planned(&index, &mut planned, order);
+    }
 
-    for entry_path in &entries {
-        let relative = match entry_path.strip_prefix(dir) {
-            Ok(r) => r.to_path_buf(),
-            Err(_) => continue,
-        };
+    // One lock query for the whole plan, so not a policy
+    if options.import_safe && !planned.is_empty() {
+        let held = imports::in_use(dir, &planned);
+        let held_paths: HashSet<&PathBuf> = held.iter().map(|(relative, _)| relative).collect();
+        planned.retain(|relative| !held_paths.contains(relative));
+        for (relative, names) in held {
+            deferred.push((relative, format!("open in {}", names)));
+        }
+    }
+    for (relative, why) in deferred {
+        Record::new(Level::Info, "SYNC", dir_path, "defer")
+            .path(&relative)
+            .message(format!("deletion of {:?} deferred: {}", relative, why))
+            .emit();
+        report.deferred.push(dir.join(relative));
+    }
+
+    if !options.force_large_delete && !planned.is_empty() {
+        let limit = options.max_delete_percent.unwrap_or(DEFAULT_MAX_DELETE_PERCENT);
+        check_share(&index, &planned, limit).map_err(|e| abort(dir_path, e))?;
+    }
 
-        if entry_path.is_dir() {
-            // Try to remove empty directory (non-recursive, safe)
-            if fs::remove_dir(entry_path).is_ok() {
-                deleted_dirs += 1;
+    #[cfg(feature = "tui")]
+    if options.review && !planned.is_empty() {
+        match tui::review(&planned) {
+            Some(confirmed) => planned = confirmed,
+            None => {
+                Record::new(Level::Info, "SYNC", dir_path, "abort")
+                    .message("review cancelled, nothing deleted")
+                    .emit();
+                report.cancelled = true;
+                return Ok(());
             }
-        } else if !expected.contains(&relative) {
-            // File not in torrent — delete it
-            match fs::remove_file(entry_path) {
-                Ok(()) => deleted_files += 1,
-                Err(e) => {
-                    logger::log(&format!(
-                        "SYNC {:?} — failed to delete {:?}: {}",
-                        dir_path, relative, e
-                    ));
-                }
+        }
+    }
+
+    if let Some(hook) = options.pre_delete_hook.as_ref().filter(|_| !planned.is_empty()) {
+        let files = planned.iter().map(|relative| {
+            Json::object()
+                .with("path", relative.to_string_lossy().into_owned())
+                .with("size", index.get(relative).map_or(0, |m| m.size as i64))
+        });
+        let input = torrent
+            .clone()
+            .with("hook", "pre-delete")
+            .with("directory", dir_path)
+            .with("files", Json::Array(files.collect()));
+        hooks::run("pre-delete", hook, dir_path, &input)
+            .map_err(|e| abort(dir_path, format!("{}, nothing deleted", e)))?;
+    }
+
+    // Files behind mount points are copied into the trash; they must fit
+    if safety::allow_copy() {
+        let required = Trash::new(dir).copy_bytes(&planned);
+        safety::check_free_space(dir, required).map_err(|e| abort(dir_path, e))?;
+    }
+
+    if options.snapshot_over.is_some_and(|limit| planned.len() > limit) {
+        snapshot(dir, dir_path, planned.len())?;
+    }
+
+    // Review, hooks and snapshots take time; the drive may have changed
+    if !planned.is_empty() {
+        safety::check_same_volume(dir, volume_at_plan.as_ref())
+            .map_err(|e| abort(dir_path, format!("{}, nothing deleted", e)))?;
+    }
+
+    // Step 5-6: Delete planned files and empty directories
+    let batch = options.retention.map(|_| trash::now_secs());
+    let rate = delete_rate(dir, dir_path, options.delete_rate, planned.len());
+    execute(dir, &options.subpath, dir_path, &planned, batch, rate, report)
+        .map_err(|e| abort(dir_path, e))?;
+
+    if let Some(time) = options.touch_expected {
+        touch_expected(dir, dir_path, &index, expected, time, report);
+    }
+    Ok(())
+}
+
+/// Set the modification time of the expected files of `index` to `time`,
+/// for tools that expire seeds by file age.
+fn touch_expected(
+    dir: &Path,
+    dir_path: &str,
+    index: &DirIndex,
+    expected: &HashSet<PathBuf>,
+    time: SystemTime,
+    report: &mut SyncReport,
+) {
+    let mut touched = 0;
+    for (relative, meta) in index.files() {
+        if !expected.contains(relative) || meta.modified == Some(time) {
+            continue;
+        }
+        let path = dir.join(relative);
+        match dirtimes::set_modified(&path, time) {
+            Ok(()) => touched += 1,
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "touch")
+                    .path(relative)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("failed to set the time of {:?}: {}", relative, e))
+                    .emit();
+                report.errors.push(FileError::new(path, &e));
             }
         }
     }
+    if touched > 0 {
+        Record::new(Level::Info, "SYNC", dir_path, "touch")
+            .message(format!("set the modification time of {} expected files", touched))
+            .emit();
+    }
+    report.touched += touched;
+}
 
-    // Step 6: Log summary
-    if deleted_files == 0 && deleted_dirs == 0 {
-        logger::log(&format!(
-            "SYNC {:?} — clean, nothing to remove",
-            dir_path
-        ));
-    } else {
-        logger::log(&format!(
-            "SYNC {:?} — deleted {} files, {} empty dirs",
-            dir_path, deleted_files, deleted_dirs
+/// The pace for deleting `count` files from `dir`: `--delete-rate`, else
+/// the default for its drive type. `None` for no limit.
+fn delete_rate(dir: &Path, dir_path: &str, rate: Option<u32>, count: usize) -> Option<u32> {
+    if count == 0 {
+        return None;
+    }
+    match rate {
+        Some(0) => None,
+        Some(rate) => Some(rate),
+        None => {
+            let kind = volume::detect(dir);
+            kind.delete_rate().inspect(|rate| {
+                Record::new(Level::Info, "SYNC", dir_path, "throttle")
+                    .message(format!(
+                        "{} drive: at most {} file operations per second (see --delete-rate)",
+                        kind, rate
+                    ))
+                    .emit();
+            })
+        }
+    }
+}
+
+/// `--rename-root`: report, or with the flag rename, a directory whose
+/// name differs from the torrent's only trivially. Returns the directory
+/// and its path string after a rename.
+fn rename_root(name: &str, dir_path: &str, options: &Options) -> Option<(PathBuf, String)> {
+    let dir = Path::new(dir_path.trim_end_matches(['\\', '/']));
+    let (parent, folder) = (dir.parent()?, dir.file_name()?.to_str()?);
+    let wanted = root_name::wanted(folder, name, &options.root_tags)?;
+    if !options.rename_root {
+        Record::new(Level::Info, "SYNC", dir_path, "root-name")
+            .message(format!(
+                "folder name {:?} differs from the torrent name {:?} (see --rename-root)",
+                folder, wanted
+            ))
+            .emit();
+        return None;
+    }
+    match casing::rename(parent, Path::new(folder), Path::new(&wanted)) {
+        Ok(()) => {
+            let renamed = parent.join(&wanted);
+            let detail = format!("to {:?}", wanted);
+            audit::record("SYNC", dir_path, "rename", Some(dir), &detail);
+            Record::new(Level::Info, "SYNC", dir_path, "root-name")
+                .message(format!("renamed {:?} to {:?}", folder, wanted))
+                .emit();
+            let path = renamed.to_string_lossy().into_owned();
+            Some((renamed, path))
+        }
+        Err(e) => {
+            Record::new(Level::Warn, "SYNC", dir_path, "root-name")
+                .code(e.raw_os_error().map(i64::from))
+                .message(format!(
+                    "failed to rename {:?} to {:?}, kept: {}",
+                    folder, wanted, e
+                ))
+                .emit();
+            None
+        }
+    }
+}
+
+/// Rename entries of `index` that differ from `expected` only in letter
+/// case; returns how many were renamed.
+fn fix_case(
+    dir: &Path,
+    dir_path: &str,
+    index: &DirIndex,
+    expected: &HashSet<PathBuf>,
+    report: &mut SyncReport,
+) -> usize {
+    let mut renamed = 0;
+    for (from, to) in casing::mismatches(index, expected) {
+        match casing::rename(dir, &from, &to) {
+            Ok(()) => {
+                let detail = format!("to {:?}", to);
+                audit::record("SYNC", dir_path, "rename", Some(&dir.join(&from)), &detail);
+                Record::new(Level::Info, "SYNC", dir_path, "case")
+                    .path(&to)
+                    .message(format!("renamed {:?} to {:?}", from, to))
+                    .emit();
+                report.renamed.push(dir.join(&to));
+                renamed += 1;
+            }
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "case")
+                    .path(&from)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("failed to rename {:?} to {:?}: {}", from, to, e))
+                    .emit();
+                report.errors.push(FileError::new(dir.join(&from), &e));
+            }
+        }
+    }
+    renamed
+}
+
+/// Shadow-copy the volume holding `dir` before `count` deletions; a sync
+/// that asked for a snapshot does not delete without one.
+fn snapshot(dir: &Path, dir_path: &str, count: usize) -> Result<(), String> {
+    let volume = volume::volume_root(dir)
+        .ok_or_else(|| abort(dir_path, "no volume for the snapshot, aborted"))?;
+    let id = vss::create(&volume)
+        .map_err(|e| abort(dir_path, format!("{}, nothing deleted", e)))?;
+    audit::record("SYNC", dir_path, "snapshot", None, &format!("{} of {}", id, volume));
+    Record::new(Level::Info, "SYNC", dir_path, "snapshot")
+        .message(format!(
+            "shadow copy {} of {} taken before deleting {} files",
+            id, volume, count
+        ))
+        .emit();
+    Ok(())
+}
+
+/// Remove `Zone.Identifier` from every expected file and report any other
+/// alternate streams, which are left alone. Files resolving outside `dir`
+/// are not touched.
+fn clear_motw(dir: &Path, dir_path: &str, files: &[bencode::TorrentFile]) {
+    let Ok(root) = fs::canonicalize(dir) else {
+        return;
+    };
+    let mut cleared = 0u32;
+    for file in files {
+        let path = dir.join(&file.path);
+        if !path.is_file() {
+            continue;
+        }
+        if !inside(&root, dir, &file.path) {
+            Record::new(Level::Warn, "SYNC", dir_path, "motw")
+                .path(&file.path)
+                .message("resolves outside the directory, Zone.Identifier kept")
+                .emit();
+            continue;
+        }
+        match streams::clear_zone_identifier(&path) {
+            Ok(true) => cleared += 1,
+            Ok(false) => {}
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "motw")
+                    .path(&file.path)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("failed to clear Zone.Identifier: {}", e))
+                    .emit();
+            }
+        }
+        let extra = streams::list(&path);
+        if !extra.is_empty() {
+            Record::new(Level::Info, "SYNC", dir_path, "streams")
+                .path(&file.path)
+                .message(format!("alternate data streams kept: {}", extra.join(", ")))
+                .emit();
+        }
+    }
+    Record::new(Level::Info, "SYNC", dir_path, "motw")
+        .message(format!("cleared Zone.Identifier from {} files", cleared))
+        .emit();
+}
+
+/// The deletion policies of a sync with `options`, in the order asked.
+fn policies(ignore: Ignore, options: &Options) -> Chain {
+    let mut chain = Chain::new().with(DefaultPolicy { ignore });
+    if !options.include_system {
+        chain = chain.with(SystemFiles);
+    }
+    if !options.protect.is_empty() {
+        chain = chain.with(KeepGlobs {
+            patterns: options.protect.clone(),
+        });
+    }
+    if let Some(min) = options.older_than {
+        chain = chain.with(MinAge { min });
+    }
+    if options.import_safe {
+        chain = chain.with(HardlinkGuard {
+            library: options.library.clone(),
+        });
+    }
+    chain
+}
+
+/// Put every indexed file to `chain`: the files to delete, relative to the
+/// index root in walk order (children before parents), and the deferred
+/// ones with the reason. Kept files with a reason are logged. Nothing is
+/// deleted.
+fn plan(
+    chain: &Chain,
+    ctx: &SyncContext,
+    dir_path: &str,
+) -> (Vec<PathBuf>, Vec<(PathBuf, String)>) {
+    let (mut planned, mut deferred) = (Vec::new(), Vec::new());
+    for (relative, meta) in ctx.index.files() {
+        match chain.decide(&FileInfo { relative, meta }, ctx) {
+            (_, Decision::Delete) => planned.push(relative.to_path_buf()),
+            (_, Decision::Keep(None)) => {}
+            (name, Decision::Keep(Some(why))) => {
+                Record::new(Level::Debug, "SYNC", dir_path, &format!("keep-{}", name))
+                    .path(relative)
+                    .message(format!("kept {:?}: {}", relative, why))
+                    .emit();
+            }
+            (_, Decision::Defer(why)) => deferred.push((relative.to_path_buf(), why)),
+        }
+    }
+    (planned, deferred)
+}
+
+/// Files a plain sync would delete from `dir` for a torrent listing
+/// `files`, relative to `dir`. Nothing is deleted (`report`).
+pub fn extras(dir: &Path, files: &[bencode::TorrentFile]) -> Result<Vec<PathBuf>, String> {
+    let expected = files.iter().map(|f| f.path.clone()).collect();
+    let index = DirIndex::build(dir, Path::new(""))?;
+    let chain = Chain::new()
+        .with(DefaultPolicy {
+            ignore: Ignore::load(dir)?,
+        })
+        .with(SystemFiles);
+    let ctx = SyncContext {
+        dir,
+        index: &index,
+        expected: &expected,
+        now: SystemTime::now(),
+    };
+    Ok(plan(&chain, &ctx, &dir.to_string_lossy()).0)
+}
+
+/// Reorder planned files; ties and unreadable metadata fall back to path
+/// order. Empty directories are still removed after all files.
+fn sort_planned(index: &DirIndex, planned: &mut [PathBuf], order: Order) {
+    match order {
+        Order::Path => planned.sort(),
+        Order::Size => planned.sort_by_cached_key(|relative| {
+            let size = index.get(relative).map_or(0, |m| m.size);
+            (Reverse(size), relative.clone())
+        }),
+        Order::Mtime => planned.sort_by_cached_key(|relative| {
+            let modified = index
+                .get(relative)
+                .and_then(|m| m.modified)
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, relative.clone())
+        }),
+    }
+}
+
+/// Refuse a plan that removes more than `limit` percent of the files or
+/// bytes in `index`: the signature of a wrong torrent/directory pair.
+fn check_share(index: &DirIndex, planned: &[PathBuf], limit: u32) -> Result<(), String> {
+    let (mut files, mut bytes) = (0u64, 0u64);
+    for (_, meta) in index.files().filter(|(relative, _)| !policy::is_internal(relative)) {
+        files += 1;
+        bytes += meta.size;
+    }
+    let planned_bytes: u64 = planned.iter().filter_map(|r| index.get(r)).map(|m| m.size).sum();
+    let over = |part: u64, whole: u64| whole > 0 && part * 100 > whole * u64::from(limit);
+    if over(planned.len() as u64, files) || over(planned_bytes, bytes) {
+        return Err(format!(
+            "plan deletes {} of {} files ({} of {} MiB), over {}%; wrong torrent for this \
+             directory? Nothing deleted (see --force-large-delete)",
+            planned.len(),
+            files,
+            planned_bytes >> 20,
+            bytes >> 20,
+            limit
         ));
     }
+    Ok(())
+}
+
+/// Whether `path` resolves to somewhere below `root` (canonical). Its
+/// parent is resolved, so junctions and symlinked directories on the way
+/// count but a symlink itself is judged by where it sits.
+fn contained(root: &Path, path: &Path) -> bool {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    fs::canonicalize(parent).is_ok_and(|parent| {
+        let resolved = parent.join(name);
+        resolved.starts_with(root) && resolved != root
+    })
 }
 
-/// Walk a directory tree depth-first, returning paths with children before parents.
-/// This ensures we can delete files first, then their parent directories if empty.
-fn walk_depth_first(root: &Path) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    walk_recursive(root, &mut result);
-    result
+/// Whether `dir/relative` is a plain relative path that, if it exists,
+/// resolves below `root` (`dir` canonical); see `check_contained`.
+fn inside(root: &Path, dir: &Path, relative: &Path) -> bool {
+    let plain = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    let path = dir.join(relative);
+    let escapes =
+        !safety::allow_copy() && fs::symlink_metadata(&path).is_ok() && !contained(root, &path);
+    plain && !escapes
 }
 
-/// Recursive helper: collect files first, then directories (post-order).
-fn walk_recursive(dir: &Path, result: &mut Vec<PathBuf>) {
-    let entries = match fs::read_dir(dir) {
-        Ok(e) => e,
-        Err(_) => return,
+/// Last check before anything is removed: every planned path must be a
+/// plain relative path resolving below `dir`. With `--allow-copy`, which
+/// follows mount points and junctions on purpose, only the first part is
+/// checked.
+fn check_contained(dir: &Path, planned: &[PathBuf]) -> Result<PathBuf, String> {
+    let root = fs::canonicalize(dir).map_err(|e| format!("cannot resolve the root: {}", e))?;
+    for relative in planned {
+        if !inside(&root, dir, relative) {
+            return Err(format!(
+                "{:?} resolves outside the directory, nothing deleted, aborted",
+                relative
+            ));
+        }
+    }
+    Ok(root)
+}
+
+/// Delete the planned files, then any directories under `dir/scope` left
+/// empty, recording both in `report`. With a `batch`, files are moved into
+/// that batch of `.zdc_kept` rather than deleted. With a `rate`, at most
+/// that many renames, deletes and directory removals run per second. Fails,
+/// leaving the rest in place, if a path would resolve outside `dir` or the
+/// thread is cancelled (`safety::set_cancel`).
+fn execute(
+    dir: &Path,
+    scope: &Path,
+    dir_path: &str,
+    planned: &[PathBuf],
+    batch: Option<u64>,
+    rate: Option<u32>,
+    report: &mut SyncReport,
+) -> Result<(), String> {
+    let root = check_contained(dir, planned)?;
+    let trash = Trash::new(dir);
+    let mut throttle = rate.map(Throttle::new);
+    let mut pace = || throttle.as_mut().map_or((), Throttle::tick);
+
+    // Phase 1: stage every file; one that cannot be moved stays in place
+    let mut staged = Vec::with_capacity(planned.len());
+    for relative in planned {
+        if safety::check_cancelled().is_err() {
+            break;
+        }
+        let size = fs::symlink_metadata(dir.join(relative)).map_or(0, |m| m.len());
+        pace();
+        match trash.stage(relative) {
+            Ok(()) => staged.push((relative, size)),
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "delete")
+                    .path(relative)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("failed to delete {:?}: {}", relative, e))
+                    .emit();
+                report.errors.push(FileError::new(dir.join(relative), &e));
+            }
+        }
+    }
+
+    // A cancelled run puts back everything not yet deleted
+    let cancelled = |from: usize, staged: &[(&PathBuf, u64)]| {
+        let e = safety::check_cancelled().err()?;
+        for (relative, _) in &staged[from..] {
+            put_back(&trash, dir_path, relative);
+        }
+        trash.remove();
+        Some(e)
     };
+    if let Some(e) = cancelled(0, &staged) {
+        return Err(e);
+    }
 
-    // Collect entries and sort for deterministic behavior
-    let mut dirs = Vec::new();
-    let mut files = Vec::new();
+    // Phase 2: delete (or keep) the staged files; put back any that fail
+    for (i, &(relative, size)) in staged.iter().enumerate() {
+        if let Some(e) = cancelled(i, &staged) {
+            return Err(e);
+        }
+        pace();
+        let result = match batch {
+            Some(batch) => trash.keep(relative, batch),
+            None => trash.purge(relative).map(|()| report.freed_bytes += size),
+        };
+        let Err(e) = result else {
+            let action = if batch.is_some() { "keep" } else { "delete" };
+            let detail = format!("{} bytes", size);
+            audit::record("SYNC", dir_path, action, Some(&dir.join(relative)), &detail);
+            report.deleted.push(dir.join(relative));
+            continue;
+        };
+        Record::new(Level::Warn, "SYNC", dir_path, "delete")
+            .path(relative)
+            .code(e.raw_os_error().map(i64::from))
+            .message(format!("failed to delete {:?}: {}", relative, e))
+            .emit();
+        report.errors.push(FileError::new(dir.join(relative), &e));
+        put_back(&trash, dir_path, relative);
+    }
+    trash.remove();
 
-    for entry in entries.flatten() {
-        let path = entry.path();
-        if path.is_dir() {
-            dirs.push(path);
-        } else {
-            files.push(path);
+    // Directories come after their contents in walk order
+    let mut outside = None;
+    let walked = Walker::new(&dir.join(scope)).on_entry(|entry| {
+        if !entry.is_dir {
+            return Ok(());
+        }
+        if !safety::allow_copy() && !contained(&root, &entry.path) {
+            outside = Some(entry.path.clone());
+            return Err(format!(
+                "{:?} resolves outside the directory, aborted",
+                entry.path
+            ));
         }
+        // Try to remove empty directory (non-recursive, safe)
+        pace();
+        if retry::remove_dir(&entry.path).is_ok() {
+            audit::record("SYNC", dir_path, "rmdir", Some(&entry.path), "");
+            report.deleted_dirs += 1;
+        }
+        Ok(())
+    });
+    match walked {
+        Err(e) if outside.is_some() => Err(e),
+        Err(e) => {
+            Record::new(Level::Warn, "SYNC", dir_path, "rmdir")
+                .message(format!("removing empty directories stopped: {}", e))
+                .emit();
+            Ok(())
+        }
+        Ok(_) => Ok(()),
     }
+}
 
-    // Recurse into subdirectories first (depth-first)
-    for d in &dirs {
-        walk_recursive(d, result);
+/// Move a staged file back to where it was, logging a failure.
+fn put_back(trash: &Trash, dir_path: &str, relative: &Path) {
+    if let Err(e) = trash.restore(relative) {
+        Record::new(Level::Error, "SYNC", dir_path, "restore")
+            .path(relative)
+            .code(e.raw_os_error().map(i64::from))
+            .message(format!("{:?} left in {}: {}", relative, TRASH_DIR, e))
+            .emit();
     }
+}
 
-    // Add files
-    for f in files {
-        result.push(f);
+/// Move back files staged by an interrupted run, so planning sees the
+/// directory as it was before that run.
+fn recover_trash(dir: &Path, dir_path: &str) {
+    let trash = Trash::new(dir);
+    let staged = trash.staged();
+    if staged.is_empty() {
+        return;
+    }
+    let mut restored = 0u32;
+    for relative in &staged {
+        match trash.restore(relative) {
+            Ok(()) => restored += 1,
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "restore")
+                    .path(relative)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("{:?} left in {}: {}", relative, TRASH_DIR, e))
+                    .emit();
+            }
+        }
     }
+    trash.remove();
+    Record::new(Level::Warn, "SYNC", dir_path, "restore")
+        .message(format!(
+            "previous run was interrupted, restored {} of {} staged files",
+            restored,
+            staged.len()
+        ))
+        .emit();
+}
+
+/// Delete kept batches of `dir` older than `retention`, logging the totals.
+/// Returns the bytes freed.
+fn expire_kept(dir: &Path, command: &str, dir_path: &str, retention: Duration) -> u64 {
+    let expired = Trash::new(dir).expire(retention, trash::now_secs());
+    for (path, e) in &expired.errors {
+        Record::new(Level::Warn, command, dir_path, "purge")
+            .path(path)
+            .message(format!("cannot delete kept files in {:?}: {}", path, e))
+            .emit();
+    }
+    if expired.batches > 0 {
+        let message = format!(
+            "purged {} files ({} MiB) kept longer than {} days, from {} runs",
+            expired.files,
+            expired.bytes >> 20,
+            retention.as_secs_f64() / 86_400.0,
+            expired.batches
+        );
+        audit::record(command, dir_path, "purge", Some(&dir.join(KEPT_DIR)), &message);
+        Record::new(Level::Info, command, dir_path, "purge")
+            .message(message)
+            .emit();
+    }
+    expired.bytes
+}
+
+/// `purge` command: delete kept files of `dir_path` older than `retention`.
+/// Returns the bytes freed.
+pub fn purge(dir_path: &str, retention: Duration) -> Result<u64, String> {
+    let dir = normalize_root(dir_path);
+    if !dir.is_dir() {
+        let message = "directory does not exist, aborted";
+        Record::new(Level::Error, "PURGE", dir_path, "abort")
+            .message(message)
+            .emit();
+        return Err(message.to_string());
+    }
+    if !dir.join(KEPT_DIR).is_dir() {
+        Record::new(Level::Info, "PURGE", dir_path, "summary")
+            .message(format!("no {} directory, nothing to purge", KEPT_DIR))
+            .emit();
+        return Ok(0);
+    }
+    Ok(expire_kept(&dir, "PURGE", dir_path, retention))
+}
+
+/// Create missing zero-length torrent files (and their parent directories)
+/// in the first of `dirs`. Files that exist in any of `dirs` are left
+/// untouched. Nothing is created if a path would resolve outside it.
+fn create_empty_files(
+    dirs: &[PathBuf],
+    dir_path: &str,
+    empty_files: &[PathBuf],
+    report: &mut SyncReport,
+) {
+    let root = match check_contained(&dirs[0], empty_files) {
+        Ok(root) => root,
+        Err(e) => {
+            Record::new(Level::Error, "SYNC", dir_path, "create")
+                .message(e.replace("nothing deleted", "nothing created"))
+                .emit();
+            return;
+        }
+    };
+    for relative in empty_files {
+        if dirs.iter().any(|dir| dir.join(relative).exists()) {
+            continue;
+        }
+        let path = dirs[0].join(relative);
+        // A junction on the way must not lead the new directories elsewhere
+        let inside = safety::allow_copy()
+            || path
+                .ancestors()
+                .skip(1)
+                .find(|a| a.exists())
+                .and_then(|a| fs::canonicalize(a).ok())
+                .is_some_and(|a| a.starts_with(&root));
+        if !inside {
+            Record::new(Level::Error, "SYNC", dir_path, "create")
+                .path(relative)
+                .message(format!("{:?} resolves outside the directory, not created", relative))
+                .emit();
+            continue;
+        }
+        let result = match path.parent() {
+            Some(parent) => retry::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|()| retry::create_file(&path));
+        match result {
+            Ok(()) => {
+                audit::record("SYNC", dir_path, "create", Some(&path), "");
+                report.created.push(path);
+            }
+            Err(e) => {
+                Record::new(Level::Warn, "SYNC", dir_path, "create")
+                    .path(relative)
+                    .code(e.raw_os_error().map(i64::from))
+                    .message(format!("failed to create empty file {:?}: {}", relative, e))
+                    .emit();
+                report.errors.push(FileError::new(path, &e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    fn index(dir: &Path) -> DirIndex {
+        DirIndex::build(dir, Path::new("")).unwrap()
+    }
+
+    /// What the default policy alone plans to delete.
+    fn planned(
+        dir: &Path,
+        index: &DirIndex,
+        expected: &HashSet<PathBuf>,
+        ignore: Ignore,
+    ) -> Vec<PathBuf> {
+        let ctx = SyncContext {
+            dir,
+            index,
+            expected,
+            now: SystemTime::now(),
+        };
+        plan(&Chain::new().with(DefaultPolicy { ignore }), &ctx, "").0
+    }
+
+    #[test]
+    fn test_zero_length_expected_file_kept() {
+        let dir = TempDir::new("zero-kept");
+        fs::create_dir(dir.join("Sub")).unwrap();
+        fs::write(dir.join("Sub").join("empty.txt"), b"").unwrap();
+        fs::write(dir.join("extra.txt"), b"x").unwrap();
+
+        let expected: HashSet<PathBuf> = [Path::new("Sub").join("empty.txt")].into_iter().collect();
+        let planned = planned(&dir, &index(&dir), &expected, Ignore::default());
+        assert_eq!(planned, vec![PathBuf::from("extra.txt")]);
+        let mut report = SyncReport::default();
+        execute(&dir, Path::new(""), "", &planned, None, None, &mut report).unwrap();
+        assert_eq!(report.deleted, vec![dir.join("extra.txt")]);
+        assert_eq!(report.deleted_dirs, 0);
+        assert!(dir.join("Sub").join("empty.txt").exists());
+        assert!(!dir.join("extra.txt").exists());
+    }
+
+    #[test]
+    fn test_zdirignore_kept() {
+        let dir = TempDir::new("zdirignore");
+        fs::create_dir(dir.join("Art")).unwrap();
+        fs::write(dir.join("Art").join(".zdirignore"), b"*.png\n").unwrap();
+        fs::write(dir.join("Art").join("cover.png"), b"x").unwrap();
+        fs::write(dir.join("Art").join("junk.txt"), b"x").unwrap();
+        fs::write(dir.join("top.png"), b"x").unwrap();
+
+        let planned = planned(&dir, &index(&dir), &HashSet::new(), Ignore::load(&dir).unwrap());
+        assert_eq!(
+            planned,
+            vec![Path::new("Art").join("junk.txt"), PathBuf::from("top.png")]
+        );
+    }
+
+    #[test]
+    fn test_missing_zero_length_file_created() {
+        let dir = TempDir::new("zero-created");
+        fs::write(dir.join("present.txt"), b"").unwrap();
+
+        let empty = vec![PathBuf::from("present.txt"), Path::new("A").join("new.txt")];
+        let mut report = SyncReport::default();
+        create_empty_files(&[dir.to_path_buf()], "", &empty, &mut report);
+        assert_eq!(report.created, vec![dir.join("A").join("new.txt")]);
+        assert_eq!(fs::metadata(dir.join("A").join("new.txt")).unwrap().len(), 0);
+
+        // Present in a second root: not created in the first
+        let other = TempDir::new("zero-created-other");
+        fs::write(other.join("b.txt"), b"").unwrap();
+        let dirs = [dir.to_path_buf(), other.to_path_buf()];
+        let mut report = SyncReport::default();
+        create_empty_files(&dirs, "", &[PathBuf::from("b.txt")], &mut report);
+        assert!(report.created.is_empty());
+        assert!(!dir.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_check_contained() {
+        let dir = TempDir::new("contained");
+        fs::create_dir_all(dir.join("Sub")).unwrap();
+        fs::write(dir.join("Sub").join("a.nfo"), b"x").unwrap();
+        let inside = vec![PathBuf::from("Sub").join("a.nfo")];
+        assert!(check_contained(&dir, &inside).is_ok());
+        // A parent component never reaches the executor
+        let escape = vec![PathBuf::from("Sub").join("..").join("..").join("x.nfo")];
+        let mut report = SyncReport::default();
+        assert!(execute(&dir, Path::new(""), "", &escape, None, None, &mut report).is_err());
+        assert!(report.deleted.is_empty());
+        create_empty_files(&[dir.to_path_buf()], "", &escape, &mut report);
+        assert!(report.created.is_empty());
+    }
+
+    #[test]
+    fn test_check_share() {
+        let dir = TempDir::new("share");
+        fs::write(dir.join("movie.mkv"), vec![0u8; 1000]).unwrap();
+        fs::write(dir.join("a.nfo"), b"x").unwrap();
+        fs::write(dir.join("b.txt"), b"x").unwrap();
+        let nfo = vec![PathBuf::from("a.nfo")];
+        assert!(check_share(&index(&dir), &nfo, 60).is_ok());
+        // Two of three files is over 60% even though the bytes are not
+        let both = vec![PathBuf::from("a.nfo"), PathBuf::from("b.txt")];
+        assert!(check_share(&index(&dir), &both, 60).is_err());
+        assert!(check_share(&index(&dir), &both, 70).is_ok());
+    }
+
+    #[test]
+    fn test_sort_planned_by_size() {
+        let dir = TempDir::new("order");
+        fs::write(dir.join("a.txt"), b"x").unwrap();
+        fs::write(dir.join("b.txt"), b"xxx").unwrap();
+        fs::write(dir.join("c.txt"), b"x").unwrap();
+
+        let mut planned = vec![
+            PathBuf::from("c.txt"),
+            PathBuf::from("a.txt"),
+            PathBuf::from("b.txt"),
+        ];
+        sort_planned(&index(&dir), &mut planned, Order::Size);
+        assert_eq!(planned, ["b.txt", "a.txt", "c.txt"].map(PathBuf::from));
+        sort_planned(&index(&dir), &mut planned, Order::Path);
+        assert_eq!(planned, ["a.txt", "b.txt", "c.txt"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_subpath_scope() {
+        let dir = TempDir::new("subpath");
+        fs::create_dir_all(dir.join("S01").join("Empty")).unwrap();
+        fs::create_dir_all(dir.join("S02").join("Empty")).unwrap();
+        fs::write(dir.join("S01").join("junk.txt"), b"x").unwrap();
+        fs::write(dir.join("S02").join("junk.txt"), b"x").unwrap();
 
-    // Add directories after their contents (post-order)
-    for d in dirs {
-        result.push(d);
+        let scope = Path::new("S01");
+        let planned = planned(
+            &dir,
+            &DirIndex::build(&dir, scope).unwrap(),
+            &HashSet::new(),
+            Ignore::default(),
+        );
+        assert_eq!(planned, vec![scope.join("junk.txt")]);
+        let mut report = SyncReport::default();
+        execute(&dir, scope, "", &planned, None, None, &mut report).unwrap();
+        assert_eq!((report.deleted.len(), report.deleted_dirs), (1, 1));
+        assert!(dir.join("S02").join("junk.txt").exists());
+        assert!(dir.join("S02").join("Empty").exists());
     }
 }
@@ -0,0 +1,72 @@
+//! `--delete-rate`: pace file operations on slow targets.
+//!
+//! Deleting tens of thousands of files on an SMB share sends one request
+//! per file as fast as the server answers, which starves everything else on
+//! the link, the torrent client included. A `Throttle` lets operations
+//! through in batches of a tenth of a second's worth and sleeps between
+//! batches, so the average stays at the rate and the link gets regular
+//! gaps. Sync paces itself this way on network drives unless told
+//! otherwise (`volume::Kind::delete_rate`).
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Paces operations to at most `rate` per second.
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u32,
+    started: Instant,
+    done: u64,
+}
+
+impl Throttle {
+    /// A throttle for `rate` operations per second; `rate` must not be 0.
+    pub fn new(rate: u32) -> Throttle {
+        Throttle {
+            rate: rate.max(1),
+            started: Instant::now(),
+            done: 0,
+        }
+    }
+
+    /// Count one operation; after each batch, wait until the rate allows
+    /// the next.
+    pub fn tick(&mut self) {
+        self.done += 1;
+        let batch = u64::from(self.rate / 10).max(1);
+        if self.done.is_multiple_of(batch) {
+            if let Some(wait) = wait(self.done, self.rate, self.started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+}
+
+/// How long to wait after `done` operations took `elapsed`, to average no
+/// more than `rate` per second.
+fn wait(done: u64, rate: u32, elapsed: Duration) -> Option<Duration> {
+    let due = Duration::from_secs_f64(done as f64 / f64::from(rate));
+    due.checked_sub(elapsed).filter(|wait| !wait.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        // 50 operations at 100/s are due after half a second
+        assert_eq!(
+            wait(50, 100, Duration::from_millis(100)),
+            Some(Duration::from_millis(400))
+        );
+        assert_eq!(wait(50, 100, Duration::from_millis(600)), None);
+
+        // Batches of 2 at 20/s: 4 operations take at least 0.2 s
+        let mut throttle = Throttle::new(20);
+        for _ in 0..4 {
+            throttle.tick();
+        }
+        assert!(throttle.started.elapsed() >= Duration::from_millis(200));
+    }
+}
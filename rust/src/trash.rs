@@ -0,0 +1,306 @@
+//! Two-phase deletion through a `.zdc_trash` staging directory.
+//!
+//! Sync first renames every planned file into `.zdc_trash` under the
+//! target (same volume, so renames are cheap and atomic), then deletes the
+//! staged files. A run that is interrupted between the phases leaves the
+//! files in the trash, and the next run moves them back before planning,
+//! so a crash never leaves a half-applied plan behind.
+//!
+//! A file behind a mount point or junction lives on another volume, where
+//! the rename would fail; it is copied and deleted instead only with
+//! `--allow-copy` (see `safety`).
+//!
+//! With `--retention`, phase 2 moves the staged files into a
+//! `.zdc_kept/<unix seconds>` batch instead of deleting them, and batches
+//! older than the retention window are deleted at the start of later syncs
+//! (or by the `purge` command).
+
+use crate::retry;
+use crate::safety;
+use crate::streams;
+use crate::volume;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Staging directory name, directly under the sync target.
+pub const TRASH_DIR: &str = ".zdc_trash";
+/// Directory of files kept for `--retention`, directly under the target.
+pub const KEPT_DIR: &str = ".zdc_kept";
+/// Retention of the `purge` command without `--retention`.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(14 * 86_400);
+
+/// Parse a retention window: a number with an `m`, `h`, `d` or `w` suffix
+/// (minutes, hours, days, weeks); a bare number is days.
+pub fn parse_retention(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_lowercase()),
+        _ => (s, 'd'),
+    };
+    let secs = match unit {
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return None,
+    };
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => n.checked_mul(secs).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Current time in seconds since the Unix epoch, the name of a new batch.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// What `Trash::expire` removed.
+#[derive(Debug, Default)]
+pub struct Expired {
+    pub batches: u32,
+    pub files: u64,
+    pub bytes: u64,
+    /// Batches that could not be deleted, with the error.
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// The staging directory of one sync target.
+pub struct Trash {
+    dir: PathBuf,
+    root: PathBuf,
+    kept: PathBuf,
+    /// Mount point of the volume holding the trash.
+    volume: Option<String>,
+}
+
+/// Move `from` to `to`, which is on the volume mounted at `volume`.
+fn move_file(from: &Path, to: &Path, volume: Option<&str>) -> io::Result<()> {
+    if safety::on_volume(from, volume) {
+        return retry::rename(from, to);
+    }
+    if !safety::allow_copy() {
+        return Err(io::Error::other(
+            "on another volume (mount point or junction), not moved without --allow-copy",
+        ));
+    }
+    retry::copy(from, to)?;
+    retry::remove_file(from)
+}
+
+impl Trash {
+    pub fn new(dir: &Path) -> Trash {
+        Trash {
+            dir: dir.to_path_buf(),
+            root: dir.join(TRASH_DIR),
+            kept: dir.join(KEPT_DIR),
+            volume: volume::volume_root(dir),
+        }
+    }
+
+    /// Phase 1: move `relative` into the trash.
+    pub fn stage(&self, relative: &Path) -> io::Result<()> {
+        let target = self.root.join(relative);
+        if let Some(parent) = target.parent() {
+            retry::create_dir_all(parent)?;
+        }
+        move_file(&self.dir.join(relative), &target, self.volume.as_deref())
+    }
+
+    /// Bytes `stage` would copy rather than rename for `planned`: files on
+    /// another volume than the trash.
+    pub fn copy_bytes(&self, planned: &[PathBuf]) -> u64 {
+        planned
+            .iter()
+            .map(|relative| self.dir.join(relative))
+            .filter(|path| !safety::on_volume(path, self.volume.as_deref()))
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Phase 2: delete a staged file. A handle open on one of the file's
+    /// streams blocks a plain delete; POSIX semantics unlink it regardless.
+    pub fn purge(&self, relative: &Path) -> io::Result<()> {
+        let path = self.root.join(relative);
+        retry::remove_file(&path).or_else(|e| streams::delete_posix(&path).map_err(|_| e))
+    }
+
+    /// Phase 2 with `--retention`: move a staged file into batch `batch`
+    /// of the kept files. Both directories are on the target's volume.
+    pub fn keep(&self, relative: &Path, batch: u64) -> io::Result<()> {
+        let target = self.kept.join(batch.to_string()).join(relative);
+        if let Some(parent) = target.parent() {
+            retry::create_dir_all(parent)?;
+        }
+        retry::rename(&self.root.join(relative), &target)
+    }
+
+    /// Move `relative` (a file or a whole directory) of the target straight
+    /// into batch `batch` of the kept files, for `orphans --quarantine`.
+    pub fn quarantine(&self, relative: &Path, batch: u64) -> io::Result<()> {
+        let target = self.kept.join(batch.to_string()).join(relative);
+        if let Some(parent) = target.parent() {
+            retry::create_dir_all(parent)?;
+        }
+        retry::rename(&self.dir.join(relative), &target)
+    }
+
+    /// Delete kept batches older than `retention` at time `now` (seconds
+    /// since the epoch). Directories not named like a batch are left alone.
+    pub fn expire(&self, retention: Duration, now: u64) -> Expired {
+        let mut expired = Expired::default();
+        let Ok(entries) = fs::read_dir(&self.kept) else {
+            return expired;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(batch) = entry.file_name().to_str().and_then(|n| n.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if !path.is_dir() || now.saturating_sub(batch) < retention.as_secs() {
+                continue;
+            }
+            let mut files = Vec::new();
+            collect_files(&path, &path, &mut files);
+            let bytes: u64 = files
+                .iter()
+                .filter_map(|f| fs::metadata(path.join(f)).ok())
+                .map(|m| m.len())
+                .sum();
+            match retry::remove_dir_all(&path) {
+                Ok(()) => {
+                    expired.batches += 1;
+                    expired.files += files.len() as u64;
+                    expired.bytes += bytes;
+                }
+                Err(e) => expired.errors.push((path, e.to_string())),
+            }
+        }
+        let _ = fs::remove_dir(&self.kept);
+        expired
+    }
+
+    /// Move a staged file back; an existing file at the original path wins.
+    pub fn restore(&self, relative: &Path) -> io::Result<()> {
+        let original = self.dir.join(relative);
+        if original.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "original path is occupied",
+            ));
+        }
+        if let Some(parent) = original.parent() {
+            retry::create_dir_all(parent)?;
+        }
+        let volume = volume::volume_root(&original);
+        move_file(&self.root.join(relative), &original, volume.as_deref())
+    }
+
+    /// Files left in the trash (by an interrupted run), as relative paths.
+    pub fn staged(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        collect_files(&self.root, &self.root, &mut files);
+        files
+    }
+
+    /// Remove the trash directory if nothing is left in it.
+    pub fn remove(&self) {
+        remove_empty_dirs(&self.root);
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, files);
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.push(relative.to_path_buf());
+        }
+    }
+}
+
+/// Remove `dir` and its subdirectories bottom-up where empty.
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    let _ = fs::remove_dir(dir);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_stage_restore() {
+        let dir = TempDir::new("trash");
+        fs::create_dir_all(dir.join("Sub")).unwrap();
+        fs::write(dir.join("Sub").join("a.txt"), b"a").unwrap();
+        fs::write(dir.join("b.txt"), b"b").unwrap();
+
+        let trash = Trash::new(&dir);
+        let a = Path::new("Sub").join("a.txt");
+        trash.stage(&a).unwrap();
+        trash.stage(Path::new("b.txt")).unwrap();
+        assert!(!dir.join(&a).exists());
+        let mut staged = trash.staged();
+        staged.sort();
+        assert_eq!(staged, vec![a.clone(), PathBuf::from("b.txt")]);
+
+        // Interrupted run: the next one moves the files back
+        trash.restore(&a).unwrap();
+        trash.purge(Path::new("b.txt")).unwrap();
+        trash.remove();
+        assert_eq!(fs::read(dir.join(&a)).unwrap(), b"a");
+        assert!(!dir.join("b.txt").exists());
+        assert!(!dir.join(TRASH_DIR).exists());
+    }
+
+    #[test]
+    fn test_keep_expire() {
+        let dir = TempDir::new("kept");
+        fs::write(dir.join("old.txt"), b"old").unwrap();
+        fs::write(dir.join("new.txt"), b"new!").unwrap();
+
+        let trash = Trash::new(&dir);
+        for (name, batch) in [("old.txt", 1_000), ("new.txt", 90_000)] {
+            trash.stage(Path::new(name)).unwrap();
+            trash.keep(Path::new(name), batch).unwrap();
+        }
+        trash.remove();
+        assert!(trash.staged().is_empty());
+
+        // One day later only the first batch is past a 12 hour window
+        let expired = trash.expire(Duration::from_secs(43_200), 87_400);
+        assert_eq!((expired.batches, expired.files, expired.bytes), (1, 1, 3));
+        assert!(!dir.join(KEPT_DIR).join("1000").exists());
+        assert!(dir.join(KEPT_DIR).join("90000").join("new.txt").exists());
+    }
+
+    #[test]
+    fn test_parse_retention() {
+        assert_eq!(parse_retention("14d"), Some(Duration::from_secs(14 * 86_400)));
+        assert_eq!(parse_retention("2W"), Some(Duration::from_secs(2 * 604_800)));
+        assert_eq!(parse_retention("12h"), Some(Duration::from_secs(43_200)));
+        assert_eq!(parse_retention("7"), Some(Duration::from_secs(7 * 86_400)));
+        assert_eq!(parse_retention("0d"), None);
+        assert_eq!(parse_retention("3y"), None);
+        assert_eq!(parse_retention("d"), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! Interactive review of a sync deletion plan (`--tui`).
+//!
+//! Shows the planned deletions as a collapsible tree in the console. Files
+//! and whole folders can be toggled between delete and keep before
+//! confirming; nothing is deleted until Enter is pressed.
+//!
+//! Keys: ↑/↓ move, → expand, ← collapse / go to parent, Space toggle,
+//! `a` toggle everything, Enter confirm, Esc or `q` cancel.
+//!
+//! Rendering uses ANSI escapes (enabled by `console`), input comes from the
+//! CRT's `_getwch`, so no external crates are needed.
+
+use crate::console;
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// One file or folder in the plan tree.
+#[derive(Debug)]
+struct Node {
+    name: String,
+    depth: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Index into the plan for files, `None` for folders.
+    file: Option<usize>,
+    expanded: bool,
+}
+
+/// Display state of a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Delete,
+    Keep,
+    Mixed,
+}
+
+/// The plan as a tree plus the per-file keep flags.
+#[derive(Debug)]
+struct Tree {
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    keep: Vec<bool>,
+}
+
+impl Tree {
+    fn new(plan: &[PathBuf]) -> Tree {
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            keep: vec![false; plan.len()],
+        };
+        for (index, path) in plan.iter().enumerate() {
+            let components: Vec<String> = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let mut parent: Option<usize> = None;
+            for (depth, name) in components.iter().enumerate() {
+                let is_file = depth + 1 == components.len();
+                let siblings = match parent {
+                    Some(p) => &tree.nodes[p].children,
+                    None => &tree.roots,
+                };
+                let existing = siblings.iter().copied().find(|&n| {
+                    tree.nodes[n].file.is_none() && !is_file && tree.nodes[n].name == *name
+                });
+                let node = match existing {
+                    Some(n) => n,
+                    None => {
+                        let n = tree.nodes.len();
+                        tree.nodes.push(Node {
+                            name: name.clone(),
+                            depth,
+                            parent,
+                            children: Vec::new(),
+                            file: is_file.then_some(index),
+                            expanded: depth == 0,
+                        });
+                        match parent {
+                            Some(p) => tree.nodes[p].children.push(n),
+                            None => tree.roots.push(n),
+                        }
+                        n
+                    }
+                };
+                parent = Some(node);
+            }
+        }
+        tree
+    }
+
+    /// Plan indices of all files at or below `node`.
+    fn files_under(&self, node: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        let mut stack = vec![node];
+        while let Some(n) = stack.pop() {
+            if let Some(f) = self.nodes[n].file {
+                out.push(f);
+            }
+            stack.extend(&self.nodes[n].children);
+        }
+        out
+    }
+
+    fn mark(&self, node: usize) -> Mark {
+        let files = self.files_under(node);
+        let kept = files.iter().filter(|&&f| self.keep[f]).count();
+        if kept == 0 {
+            Mark::Delete
+        } else if kept == files.len() {
+            Mark::Keep
+        } else {
+            Mark::Mixed
+        }
+    }
+
+    /// Flip a file, or set a whole folder to keep (or back to delete if it
+    /// was already fully kept).
+    fn toggle(&mut self, node: usize) {
+        let keep = self.mark(node) != Mark::Keep;
+        for f in self.files_under(node) {
+            self.keep[f] = keep;
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        let keep = self.keep.iter().any(|k| !k);
+        self.keep.iter_mut().for_each(|k| *k = keep);
+    }
+
+    /// Nodes currently shown, in display order.
+    fn rows(&self) -> Vec<usize> {
+        let mut rows = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(n) = stack.pop() {
+            rows.push(n);
+            if self.nodes[n].expanded {
+                stack.extend(self.nodes[n].children.iter().rev());
+            }
+        }
+        rows
+    }
+
+    /// Files still marked for deletion, in plan order.
+    fn selected(&self, plan: &[PathBuf]) -> Vec<PathBuf> {
+        plan.iter()
+            .zip(&self.keep)
+            .filter(|(_, keep)| !**keep)
+            .map(|(p, _)| p.clone())
+            .collect()
+    }
+}
+
+/// A key press, decoded from `_getwch`.
+#[derive(Debug, PartialEq, Eq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Char(char),
+    Enter,
+    Escape,
+}
+
+fn read_key() -> Key {
+    extern "C" {
+        fn _getwch() -> u32;
+    }
+    loop {
+        let c = unsafe { _getwch() };
+        // Extended keys arrive as a 0x00/0xE0 prefix followed by a scan code
+        if c == 0 || c == 0xE0 {
+            match unsafe { _getwch() } {
+                72 => return Key::Up,
+                80 => return Key::Down,
+                75 => return Key::Left,
+                77 => return Key::Right,
+                73 => return Key::PageUp,
+                81 => return Key::PageDown,
+                _ => continue,
+            }
+        }
+        return match c {
+            13 => Key::Enter,
+            27 => Key::Escape,
+            c => Key::Char(char::from_u32(c).unwrap_or('\0')),
+        };
+    }
+}
+
+/// Visible console rows (fallback 25).
+fn console_height() -> usize {
+    #[repr(C)]
+    struct ScreenBufferInfo {
+        size: [i16; 2],
+        cursor: [i16; 2],
+        attributes: u16,
+        window: [i16; 4], // left, top, right, bottom
+        max_size: [i16; 2],
+    }
+    extern "system" {
+        fn GetStdHandle(nStdHandle: u32) -> *mut std::ffi::c_void;
+        fn GetConsoleScreenBufferInfo(
+            hConsoleOutput: *mut std::ffi::c_void,
+            lpInfo: *mut ScreenBufferInfo,
+        ) -> i32;
+    }
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    unsafe {
+        let mut info = std::mem::zeroed::<ScreenBufferInfo>();
+        if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) != 0 {
+            return (info.window[3] - info.window[1] + 1).max(8) as usize;
+        }
+    }
+    25
+}
+
+fn draw(tree: &Tree, rows: &[usize], cursor: usize, top: usize, height: usize) -> io::Result<()> {
+    let selected = tree.keep.iter().filter(|k| !**k).count();
+    let mut out = io::stdout().lock();
+    write!(out, "\x1b[H\x1b[2J")?;
+    writeln!(
+        out,
+        "Deletion plan: {} of {} extra files will be deleted",
+        selected,
+        tree.keep.len()
+    )?;
+    writeln!(
+        out,
+        "\x1b[2m↑↓ move  ←→ fold  Space toggle  a all  Enter confirm  Esc cancel\x1b[0m"
+    )?;
+    for (i, &n) in rows.iter().enumerate().skip(top).take(height) {
+        let node = &tree.nodes[n];
+        let mark = match tree.mark(n) {
+            Mark::Delete => "\x1b[31m[x]\x1b[0m",
+            Mark::Keep => "\x1b[32m[ ]\x1b[0m",
+            Mark::Mixed => "\x1b[33m[~]\x1b[0m",
+        };
+        let fold = match (node.file, node.expanded) {
+            (Some(_), _) => "  ",
+            (None, true) => "▾ ",
+            (None, false) => "▸ ",
+        };
+        let line = format!("{}{} {}{}", "  ".repeat(node.depth), mark, fold, node.name);
+        if i == cursor {
+            writeln!(out, "\x1b[7m{}\x1b[0m", line)?;
+        } else {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    out.flush()
+}
+
+/// Show the plan and let the user deselect entries.
+///
+/// Returns the files still selected for deletion, or `None` if cancelled.
+pub fn review(plan: &[PathBuf]) -> Option<Vec<PathBuf>> {
+    console::ansi();
+    let mut tree = Tree::new(plan);
+    let mut cursor = 0usize;
+    let mut top = 0usize;
+    let height = console_height().saturating_sub(3).max(5);
+
+    let result = loop {
+        let rows = tree.rows();
+        cursor = cursor.min(rows.len().saturating_sub(1));
+        if cursor < top {
+            top = cursor;
+        } else if cursor >= top + height {
+            top = cursor + 1 - height;
+        }
+        if draw(&tree, &rows, cursor, top, height).is_err() {
+            break None;
+        }
+
+        let node = rows[cursor];
+        match read_key() {
+            Key::Up | Key::Char('k') => cursor = cursor.saturating_sub(1),
+            Key::Down | Key::Char('j') => cursor += 1,
+            Key::PageUp => cursor = cursor.saturating_sub(height),
+            Key::PageDown => cursor += height,
+            Key::Right => tree.nodes[node].expanded = true,
+            Key::Left => {
+                if tree.nodes[node].file.is_none() && tree.nodes[node].expanded {
+                    tree.nodes[node].expanded = false;
+                } else if let Some(parent) = tree.nodes[node].parent {
+                    cursor = rows.iter().position(|&r| r == parent).unwrap_or(cursor);
+                }
+            }
+            Key::Char(' ') => tree.toggle(node),
+            Key::Char('a') => tree.toggle_all(),
+            Key::Enter => break Some(tree.selected(plan)),
+            Key::Escape | Key::Char('q') => break None,
+            Key::Char(_) => {}
+        }
+    };
+
+    let _ = write!(io::stdout(), "\x1b[H\x1b[2J");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn plan() -> Vec<PathBuf> {
+        vec![
+            Path::new("Extras").join("a.nfo"),
+            Path::new("Extras").join("Sub").join("b.txt"),
+            PathBuf::from("c.url"),
+        ]
+    }
+
+    #[test]
+    fn test_tree_rows() {
+        let tree = Tree::new(&plan());
+        let names: Vec<&str> = tree
+            .rows()
+            .iter()
+            .map(|&n| tree.nodes[n].name.as_str())
+            .collect();
+        // Top-level folders start expanded, deeper ones collapsed
+        assert_eq!(names, vec!["Extras", "a.nfo", "Sub", "c.url"]);
+    }
+
+    #[test]
+    fn test_toggle_folder() {
+        let plan = plan();
+        let mut tree = Tree::new(&plan);
+        let extras = tree.roots[0];
+        tree.toggle(tree.nodes[extras].children[0]);
+        assert_eq!(tree.mark(extras), Mark::Mixed);
+        tree.toggle(extras);
+        assert_eq!(tree.mark(extras), Mark::Keep);
+        let selected = tree.selected(&plan);
+        assert_eq!(selected, vec![PathBuf::from("c.url")]);
+        tree.toggle_all();
+        assert!(tree.selected(&plan).is_empty());
+    }
+}
@@ -1,242 +1,785 @@
 //! Mode 2: Unlock — kill processes locking files in a directory.
 //!
-//! Uses Win32 Restart Manager API via raw FFI (no external crates).
+//! Uses the Restart Manager wrapper in `restart_manager`.
 //! Uses RmShutdown(RmForceShutdown) — same approach as rqbit.
-//! Terminates ALL locking processes (no exclusions).
-
-use crate::logger;
+//! Terminates ALL locking processes in its own session (`--session` widens
+//! or moves that on multi-user servers), unless `--pid` or `--name` picks
+//! one: that process is only terminated if Restart Manager confirms it
+//! locks files under the directory. Programs whose executable lies under
+//! the directory are skipped unless `--allow-running`. `--kill-tree` also
+//! terminates the descendants of every terminated process, except that
+//! programs started from Explorer or a system host (`SHELLS`) are left
+//! running unless they are on `auto-kill`.
+//! `--who-details` reports the files under the directory each locking
+//! process holds open (see `handles`).
+//! The config's `auto-kill`, `ask-kill` and `never-kill` lists decide per
+//! executable (`--kill-default` for the rest): `never` is left running,
+//! `ask` needs a yes at the console or from `--approve-kill-hook`.
+//! Several directories are queried together first (`run_batch`), so ones
+//! nobody locks cost no session of their own.
+
+use crate::audit;
+use crate::handles;
+use crate::hooks;
+use crate::imports;
+use crate::json::Json;
+use crate::logger::{Level, Record};
+#[cfg(feature = "client-apis")]
+use crate::media;
+use crate::paths;
+use crate::process_tree::{self, ProcessEntry};
+use crate::restart_manager::{self, LockQuery, LockingProcess, RmError};
 use crate::safety;
+use crate::volume;
 
 use std::fs;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
 
-// ============================================================
-// Win32 type definitions and FFI declarations
-// ============================================================
-
-#[allow(non_camel_case_types)]
-type DWORD = u32;
-#[allow(non_camel_case_types)]
-type WCHAR = u16;
-#[allow(non_camel_case_types)]
-type LPCWSTR = *const u16;
-#[allow(non_camel_case_types)]
-type UINT = u32;
-
-const ERROR_MORE_DATA: DWORD = 234;
-const RM_FORCE_SHUTDOWN: DWORD = 1;
-const CCH_RM_SESSION_KEY: usize = 32;
-
-#[link(name = "rstrtmgr")]
-extern "system" {
-    fn RmStartSession(
-        pSessionHandle: *mut DWORD,
-        dwSessionFlags: DWORD,
-        strSessionKey: *mut WCHAR,
-    ) -> DWORD;
-
-    fn RmEndSession(dwSessionHandle: DWORD) -> DWORD;
-
-    fn RmRegisterResources(
-        dwSessionHandle: DWORD,
-        nFiles: UINT,
-        rgsFileNames: *const LPCWSTR,
-        nApplications: UINT,
-        rgApplications: *const std::ffi::c_void,
-        nServices: UINT,
-        rgsServiceNames: *const LPCWSTR,
-    ) -> DWORD;
-
-    fn RmGetList(
-        dwSessionHandle: DWORD,
-        pnProcInfoNeeded: *mut UINT,
-        pnProcInfo: *mut UINT,
-        rgAffectedApps: *mut std::ffi::c_void,
-        lpdwRebootReasons: *mut DWORD,
-    ) -> DWORD;
-
-    fn RmShutdown(
-        dwSessionHandle: DWORD,
-        lActionFlags: DWORD,
-        fnStatus: *const std::ffi::c_void,
-    ) -> DWORD;
-}
-
-// ============================================================
-// RAII guard for Restart Manager session
-// ============================================================
-
-struct RmSessionGuard(DWORD);
-
-impl Drop for RmSessionGuard {
-    fn drop(&mut self) {
-        unsafe {
-            RmEndSession(self.0);
-        }
-    }
-}
+/// Shells and system hosts whose descendants are whatever the user or
+/// Windows started; `--kill-tree` only takes `auto-kill` ones from them.
+const SHELLS: &[&str] = &[
+    "explorer.exe",
+    "services.exe",
+    "svchost.exe",
+    "winlogon.exe",
+    "wininit.exe",
+    "userinit.exe",
+    "csrss.exe",
+    "smss.exe",
+    "lsass.exe",
+    "sihost.exe",
+];
 
 // ============================================================
 // Helper functions
 // ============================================================
 
-/// Convert a Rust string to a null-terminated UTF-16 wide string.
-fn to_wide(s: &str) -> Vec<u16> {
-    s.encode_utf16().chain(std::iter::once(0)).collect()
-}
-
-/// Collect all file paths recursively from a directory.
-fn collect_files(dir: &Path) -> Vec<String> {
+/// Collect all file paths recursively from a directory, within the
+/// `safety` walk limits.
+fn collect_files(dir: &Path) -> Result<Vec<String>, String> {
     let mut files = Vec::new();
-    collect_files_recursive(dir, &mut files);
-    files
+    collect_files_recursive(dir, 0, &mut files)?;
+    Ok(files)
 }
 
-fn collect_files_recursive(dir: &Path, files: &mut Vec<String>) {
+fn collect_files_recursive(
+    dir: &Path,
+    depth: usize,
+    files: &mut Vec<String>,
+) -> Result<(), String> {
+    safety::check_walk(files.len(), depth)?;
     let entries = match fs::read_dir(dir) {
         Ok(e) => e,
-        Err(_) => return,
+        Err(_) => return Ok(()),
     };
 
     for entry in entries.flatten() {
         let path = entry.path();
         if path.is_dir() {
-            collect_files_recursive(&path, files);
+            collect_files_recursive(&path, depth + 1, files)?;
         } else if let Some(s) = path.to_str() {
             files.push(s.to_string());
         }
     }
+    safety::check_walk(files.len(), depth)
+}
+
+/// `--name` match: case-insensitive, with or without the `.exe` suffix.
+fn name_matches(exe: &str, wanted: &str) -> bool {
+    let strip = |s: &str| {
+        let lower = s.to_lowercase();
+        match lower.strip_suffix(".exe") {
+            Some(stem) => stem.to_string(),
+            None => lower,
+        }
+    };
+    strip(exe) == strip(wanted)
 }
 
 // ============================================================
 // Main unlock function
 // ============================================================
 
-/// Run the unlock operation.
-pub fn run(dir_path: &str) {
+/// Which locking processes to terminate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Every process locking a file under the directory.
+    All,
+    /// `--pid`: one process by ID.
+    Pid(u32),
+    /// `--name`: processes by executable name.
+    Name(String),
+}
+
+/// Whose processes unlock may terminate (`--session`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Session {
+    /// Only the session unlock runs in, so other RDP users are left alone.
+    #[default]
+    Current,
+    /// Every session.
+    All,
+    /// One session by ID.
+    Id(u32),
+}
+
+impl Session {
+    pub fn parse(value: &str) -> Option<Session> {
+        match value.to_lowercase().as_str() {
+            "current" => Some(Session::Current),
+            "all" => Some(Session::All),
+            id => id.parse().ok().map(Session::Id),
+        }
+    }
+
+    /// Whether a process in `session` may be terminated; an unknown
+    /// session only matches `All`.
+    fn allows(self, session: Option<u32>, current: Option<u32>) -> bool {
+        match self {
+            Session::All => true,
+            Session::Id(id) => session == Some(id),
+            Session::Current => session.is_some() && session == current,
+        }
+    }
+}
+
+/// What the kill policy says about one executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decision {
+    /// Terminate without asking.
+    #[default]
+    Auto,
+    /// Terminate only once approved.
+    Ask,
+    /// Never terminate.
+    Never,
+}
+
+impl Decision {
+    pub fn parse(value: &str) -> Option<Decision> {
+        match value.to_lowercase().as_str() {
+            "auto" => Some(Decision::Auto),
+            "ask" => Some(Decision::Ask),
+            "never" => Some(Decision::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Per-executable kill policy from the config.
+#[derive(Debug, Clone, Default)]
+pub struct KillPolicy {
+    /// Executables terminated without asking (`auto-kill`).
+    pub auto: Vec<String>,
+    /// Executables terminated once approved (`ask-kill`).
+    pub ask: Vec<String>,
+    /// Executables never terminated (`never-kill`).
+    pub never: Vec<String>,
+    /// Decision for executables on no list (`--kill-default`).
+    pub default: Decision,
+    /// Command approving `ask` processes when there is no console to ask
+    /// at (`--approve-kill-hook`).
+    pub approve_hook: Option<String>,
+}
+
+impl KillPolicy {
+    /// Decision for executable `name`; `never` wins over `ask` over `auto`.
+    pub fn decide(&self, name: &str) -> Decision {
+        let listed = |list: &[String]| list.iter().any(|n| name_matches(name, n));
+        if listed(&self.never) {
+            Decision::Never
+        } else if listed(&self.ask) {
+            Decision::Ask
+        } else if listed(&self.auto) {
+            Decision::Auto
+        } else {
+            self.default
+        }
+    }
+
+    /// Whether `name` is on the `auto-kill` list itself, not just allowed
+    /// by `--kill-default`.
+    fn listed_auto(&self, name: &str) -> bool {
+        self.auto.iter().any(|n| name_matches(name, n))
+    }
+}
+
+/// Settings beyond the directory argument.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Which lockers to terminate (`--pid` / `--name`).
+    pub target: Target,
+    /// Terminate descendants of terminated processes too (`--kill-tree`).
+    pub kill_tree: bool,
+    /// Report the files each locking process holds (`--who-details`).
+    pub who_details: bool,
+    /// Sessions whose processes may be terminated (`--session`).
+    pub session: Session,
+    /// Also terminate programs started from the directory (`--allow-running`).
+    pub allow_running: bool,
+    /// Which executables may be terminated, and with whose approval.
+    pub policy: KillPolicy,
+    /// Plex/Jellyfin server whose streams defer the run (`--media-server`).
+    #[cfg(feature = "client-apis")]
+    pub media: Option<media::Server>,
+}
+
+/// Outcome of an unlock run, for callers that combine several runs.
+#[derive(Debug, Clone, Default)]
+pub struct UnlockReport {
+    /// Processes Restart Manager reported as locking files, as (pid, name).
+    pub locking: Vec<(u32, String)>,
+    /// Processes terminated, descendants from `--kill-tree` included.
+    pub terminated: Vec<(u32, String)>,
+    /// Restart Manager and termination failures.
+    pub errors: Vec<String>,
+}
+
+impl UnlockReport {
+    /// Result object for `--format json`.
+    pub fn to_json(&self) -> Json {
+        let processes = |list: &[(u32, String)]| {
+            Json::Array(
+                list.iter()
+                    .map(|(pid, name)| {
+                        Json::object()
+                            .with("pid", i64::from(*pid))
+                            .with("name", name.as_str())
+                    })
+                    .collect(),
+            )
+        };
+        Json::object()
+            .with("locking", processes(&self.locking))
+            .with("terminated", processes(&self.terminated))
+            .with(
+                "errors",
+                Json::Array(self.errors.iter().map(|e| Json::from(e.as_str())).collect()),
+            )
+    }
+}
+
+fn rm_error(dir_path: &str, e: RmError, report: &mut UnlockReport) {
+    Record::new(Level::Error, "UNLOCK", dir_path, "error")
+        .code(Some(i64::from(e.code)))
+        .message(e.to_string())
+        .emit();
+    report.errors.push(e.to_string());
+}
+
+/// Log an `abort` record; its message becomes the run's error.
+fn abort(dir_path: &str, message: impl Into<String>) -> String {
+    let message = message.into();
+    Record::new(Level::Error, "UNLOCK", dir_path, "abort")
+        .message(message.as_str())
+        .emit();
+    message
+}
+
+/// `--who-details`: log the files under `dir` each locking process holds.
+fn report_handles(dir_path: &str, dir: &Path, processes: &[LockingProcess]) {
+    let pids: Vec<u32> = processes.iter().map(|p| p.pid).collect();
+    let files = match handles::open_files(&pids) {
+        Ok(files) => files,
+        Err(e) => {
+            Record::new(Level::Warn, "UNLOCK", dir_path, "handles")
+                .message(e)
+                .emit();
+            return;
+        }
+    };
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let dir = paths::long_path(&dir);
+    for process in processes {
+        let held: Vec<_> = files
+            .iter()
+            .filter(|f| f.pid == process.pid)
+            .filter_map(|f| handles::relative_to(&f.path, &dir))
+            .collect();
+        if held.is_empty() {
+            Record::new(Level::Info, "UNLOCK", dir_path, "handles")
+                .message(format!(
+                    "{} (pid {}): no open handles found (access denied or memory-mapped)",
+                    process.name, process.pid
+                ))
+                .emit();
+        }
+        for relative in &held {
+            Record::new(Level::Info, "UNLOCK", dir_path, "handles")
+                .path(relative)
+                .message(format!("{} (pid {}) holds {:?}", process.name, process.pid, relative))
+                .emit();
+        }
+    }
+}
+
+/// Ask whether a process may be terminated: at the console when there is
+/// one, else through the approval hook. `parent` is set for a descendant
+/// found by `--kill-tree`. No answer means no.
+fn approve(
+    dir_path: &str,
+    pid: u32,
+    name: &str,
+    session: Option<u32>,
+    parent: Option<u32>,
+    policy: &KillPolicy,
+) -> bool {
+    if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        match parent {
+            Some(parent) => eprint!(
+                "Terminate {} (pid {}), child of pid {} which locks files in {}? [y/N] ",
+                name, pid, parent, dir_path
+            ),
+            None => eprint!(
+                "Terminate {} (pid {}), which locks files in {}? [y/N] ",
+                name, pid, dir_path
+            ),
+        }
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        let _ = std::io::stdin().read_line(&mut answer);
+        return matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    }
+    let Some(command) = &policy.approve_hook else {
+        return false;
+    };
+    let input = Json::object()
+        .with("directory", dir_path)
+        .with("pid", i64::from(pid))
+        .with("name", name)
+        .with("session", session.map(i64::from))
+        .with("parent", parent.map(i64::from));
+    match hooks::run("approve-kill", command, dir_path, &input) {
+        Ok(()) => true,
+        Err(e) => {
+            Record::new(Level::Info, "UNLOCK", dir_path, "skip-policy")
+                .message(e)
+                .emit();
+            false
+        }
+    }
+}
+
+/// Descendants of the processes about to be terminated, looked up before
+/// their parents are gone. Processes in `victims` themselves, those
+/// `allowed` rejects by PID and those the kill policy withholds (`never`,
+/// or `ask` and not approved) are skipped, as are descendants of a shell
+/// victim (`SHELLS`) that are not on `auto-kill`.
+fn collect_tree(
+    dir_path: &str,
+    victims: &[LockingProcess],
+    allowed: &dyn Fn(u32) -> bool,
+    policy: &KillPolicy,
+) -> Vec<ProcessEntry> {
+    let mut tree: Vec<ProcessEntry> = Vec::new();
+    for victim in victims {
+        let shell = SHELLS.iter().any(|s| name_matches(&victim.name, s));
+        match process_tree::descendants(victim.pid) {
+            Ok(found) => {
+                for entry in found {
+                    let known = victims.iter().any(|v| v.pid == entry.pid)
+                        || tree.iter().any(|t| t.pid == entry.pid);
+                    if known || !allowed(entry.pid) {
+                        continue;
+                    }
+                    let reason = match policy.decide(&entry.name) {
+                        Decision::Never => Some("never terminated by the kill policy"),
+                        _ if shell && !policy.listed_auto(&entry.name) => {
+                            Some("started from a shell or system host and not on auto-kill")
+                        }
+                        Decision::Auto => None,
+                        Decision::Ask => {
+                            let session = process_tree::session_id(entry.pid);
+                            let parent = Some(entry.parent);
+                            (!approve(dir_path, entry.pid, &entry.name, session, parent, policy))
+                                .then_some("termination not approved")
+                        }
+                    };
+                    match reason {
+                        None => tree.push(entry),
+                        Some(reason) => Record::new(Level::Info, "UNLOCK", dir_path, "skip-policy")
+                            .message(format!(
+                                "{} (pid {}, child of {}): {}",
+                                entry.name, entry.pid, entry.parent, reason
+                            ))
+                            .emit(),
+                    }
+                }
+            }
+            Err(e) => Record::new(Level::Warn, "UNLOCK", dir_path, "kill-tree")
+                .message(format!("{} (pid {}): {}", victim.name, victim.pid, e))
+                .emit(),
+        }
+    }
+    tree
+}
+
+/// Step 5 for `--kill-tree`: terminate the collected descendants.
+fn kill_tree(dir_path: &str, tree: &[ProcessEntry], report: &mut UnlockReport) {
+    for entry in tree {
+        let name = format!("{} (pid {}, child of {})", entry.name, entry.pid, entry.parent);
+        match process_tree::terminate(entry) {
+            Ok(()) => {
+                audit::record("UNLOCK", dir_path, "kill", None, &name);
+                Record::new(Level::Info, "UNLOCK", dir_path, "kill-tree")
+                    .message(format!("terminated {}", name))
+                    .emit();
+                report.terminated.push((entry.pid, entry.name.clone()));
+            }
+            Err(e) => {
+                Record::new(Level::Warn, "UNLOCK", dir_path, "kill-tree")
+                    .message(format!("{}: {}", name, e))
+                    .emit();
+                report.errors.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+}
+
+/// Run the unlock operation. Errors are aborts, already logged.
+pub fn run(dir_path: &str, options: &Options) -> Result<UnlockReport, String> {
+    match prepare(dir_path, options)? {
+        Some(file_paths) => Ok(unlock_files(dir_path, &file_paths, options)),
+        None => Ok(UnlockReport::default()),
+    }
+}
+
+/// Unlock several directories. Their files are queried together first, one
+/// Restart Manager session per volume, so only directories found locked
+/// need sessions of their own. Results are in the order of `dirs`.
+pub fn run_batch(dirs: &[String], options: &Options) -> Vec<Result<UnlockReport, String>> {
+    let mut results = Vec::with_capacity(dirs.len());
+    let mut files = Vec::with_capacity(dirs.len());
+    let mut volumes: Vec<(Option<String>, Vec<usize>)> = Vec::new();
+    for (i, dir_path) in dirs.iter().enumerate() {
+        match prepare(dir_path, options) {
+            Ok(Some(file_paths)) => {
+                let root = volume::volume_root(Path::new(dir_path));
+                match volumes.iter_mut().find(|(r, _)| *r == root) {
+                    Some((_, members)) => members.push(i),
+                    None => volumes.push((root, vec![i])),
+                }
+                files.push(file_paths);
+                results.push(Ok(UnlockReport::default()));
+            }
+            Ok(None) => {
+                files.push(Vec::new());
+                results.push(Ok(UnlockReport::default()));
+            }
+            Err(e) => {
+                files.push(Vec::new());
+                results.push(Err(e));
+            }
+        }
+    }
+    for (root, members) in volumes {
+        let root = root.unwrap_or_default();
+        let groups: Vec<Vec<String>> = members.iter().map(|&i| files[i].clone()).collect();
+        let held: Vec<usize> = match restart_manager::locked(&groups) {
+            Ok(found) => found.into_iter().map(|(g, _)| members[g]).collect(),
+            // Each directory gets its own query, which reports the error
+            Err(e) => {
+                Record::new(Level::Warn, "UNLOCK", &root, "batch")
+                    .code(Some(i64::from(e.code)))
+                    .message(format!("{}, querying directories one by one", e))
+                    .emit();
+                members.clone()
+            }
+        };
+        Record::new(Level::Debug, "UNLOCK", &root, "batch")
+            .message(format!(
+                "{} of {} directories locked",
+                held.len(),
+                members.len()
+            ))
+            .emit();
+        for i in members {
+            if held.contains(&i) {
+                results[i] = Ok(unlock_files(&dirs[i], &files[i], options));
+            } else {
+                Record::new(Level::Info, "UNLOCK", &dirs[i], "summary")
+                    .message("no locking processes found")
+                    .emit();
+            }
+        }
+    }
+    results
+}
+
+/// Checks before the query. Returns the directory's files, or `None` when
+/// there is nothing to unlock (already logged).
+#[cfg_attr(not(feature = "client-apis"), allow(unused_variables))]
+fn prepare(dir_path: &str, options: &Options) -> Result<Option<Vec<String>>, String> {
     let dir = Path::new(dir_path);
 
     // Safety guard
     if !safety::check_depth(dir, 3) {
-        logger::log(&format!(
-            "UNLOCK {:?} — path too shallow, aborted",
-            dir_path
-        ));
-        std::process::exit(1);
+        return Err(abort(dir_path, "path too shallow, aborted"));
     }
 
     if !dir.exists() {
-        logger::log(&format!(
-            "UNLOCK {:?} — directory does not exist, skipped",
-            dir_path
-        ));
-        return;
+        Record::new(Level::Info, "UNLOCK", dir_path, "skip")
+            .message("directory does not exist, skipped")
+            .emit();
+        return Ok(None);
+    }
+    // Killing the media server would cut the stream
+    #[cfg(feature = "client-apis")]
+    if media::defer(options.media.as_ref(), "UNLOCK", dir_path) {
+        return Ok(None);
     }
 
     // Collect all file paths
-    let file_paths = collect_files(dir);
+    let file_paths = collect_files(dir).map_err(|e| abort(dir_path, e))?;
     if file_paths.is_empty() {
-        logger::log(&format!(
-            "UNLOCK {:?} — no files found, skipped",
-            dir_path
-        ));
-        return;
-    }
-
-    // Convert to wide strings
-    let wide_paths: Vec<Vec<u16>> = file_paths.iter().map(|p| to_wide(p)).collect();
-    let wide_ptrs: Vec<LPCWSTR> = wide_paths.iter().map(|w| w.as_ptr()).collect();
-
-    unsafe {
-        // Step 1: Start Restart Manager session
-        let mut session_handle: DWORD = 0;
-        let mut session_key = [0u16; CCH_RM_SESSION_KEY + 1];
-
-        let result = RmStartSession(
-            &mut session_handle,
-            0,
-            session_key.as_mut_ptr(),
-        );
-        if result != 0 {
-            logger::log(&format!(
-                "UNLOCK {:?} — RmStartSession failed (error {})",
-                dir_path, result
-            ));
-            return;
-        }
+        Record::new(Level::Info, "UNLOCK", dir_path, "skip")
+            .message("no files found, skipped")
+            .emit();
+        return Ok(None);
+    }
+    Ok(Some(file_paths))
+}
 
-        // RAII guard ensures RmEndSession is called even on error/panic
-        let _guard = RmSessionGuard(session_handle);
-
-        // Step 2: Register all files with Restart Manager
-        let result = RmRegisterResources(
-            session_handle,
-            wide_ptrs.len() as UINT,
-            wide_ptrs.as_ptr(),
-            0,
-            std::ptr::null(),
-            0,
-            std::ptr::null(),
-        );
-        if result != 0 {
-            logger::log(&format!(
-                "UNLOCK {:?} — RmRegisterResources failed (error {})",
-                dir_path, result
-            ));
-            return;
+/// Query and terminate the processes locking `file_paths`, the files of
+/// `dir_path`.
+fn unlock_files(dir_path: &str, file_paths: &[String], options: &Options) -> UnlockReport {
+    let target = &options.target;
+    let dir = Path::new(dir_path);
+    let mut report = UnlockReport::default();
+
+    // Step 1-2: Start a Restart Manager session with all files registered
+    let query = match LockQuery::new(file_paths) {
+        Ok(q) => q,
+        Err(e) => {
+            rm_error(dir_path, e, &mut report);
+            return report;
         }
+    };
 
-        // Step 3: Query for locking processes (just to get count for logging)
-        let mut reason: DWORD = 0;
-        let mut n_proc_info_needed: UINT = 0;
-        let mut n_proc_info: UINT = 0;
-
-        let result = RmGetList(
-            session_handle,
-            &mut n_proc_info_needed,
-            &mut n_proc_info,
-            std::ptr::null_mut(),
-            &mut reason,
-        );
-
-        if result == 0 && n_proc_info_needed == 0 {
-            logger::log(&format!(
-                "UNLOCK {:?} — no locking processes found",
-                dir_path
-            ));
-            return;
+    // Step 3: Query for locking processes
+    let processes = match query.processes() {
+        Ok(p) => p,
+        Err(e) => {
+            rm_error(dir_path, e, &mut report);
+            return report;
         }
+    };
+    report.locking = processes.iter().map(|p| (p.pid, p.name.clone())).collect();
 
-        if result != ERROR_MORE_DATA && result != 0 {
-            logger::log(&format!(
-                "UNLOCK {:?} — RmGetList failed (error {})",
-                dir_path, result
-            ));
-            return;
+    if processes.is_empty() {
+        Record::new(Level::Info, "UNLOCK", dir_path, "summary")
+            .message("no locking processes found")
+            .emit();
+        return report;
+    }
+
+    if options.who_details {
+        report_handles(dir_path, dir, &processes);
+    }
+
+    // Other users' processes on a multi-user server stay untouched
+    let current = process_tree::session_id(std::process::id());
+    let (processes, foreign): (Vec<_>, Vec<_>) = processes
+        .into_iter()
+        .partition(|p| options.session.allows(Some(p.session), current));
+    for process in &foreign {
+        Record::new(Level::Info, "UNLOCK", dir_path, "skip-session")
+            .message(format!(
+                "{} (pid {}) runs in session {}, not terminated (see --session)",
+                process.name, process.pid, process.session
+            ))
+            .emit();
+    }
+
+    // Programs started from the payload itself need `--allow-running`
+    let long_dir = paths::long_path(dir);
+    let from_payload = |pid: u32| {
+        !options.allow_running
+            && process_tree::image_path(pid)
+                .is_some_and(|image| imports::inside(&image, &long_dir))
+    };
+    let (processes, payload): (Vec<_>, Vec<_>) =
+        processes.into_iter().partition(|p| !from_payload(p.pid));
+    for process in &payload {
+        Record::new(Level::Warn, "UNLOCK", dir_path, "skip-running")
+            .message(format!(
+                "{} (pid {}) runs from this directory, not terminated (see --allow-running)",
+                process.name, process.pid
+            ))
+            .emit();
+    }
+
+    // The kill policy, for processes `--pid` / `--name` would pick
+    let picked = |p: &LockingProcess| match target {
+        Target::All => true,
+        Target::Pid(pid) => p.pid == *pid,
+        Target::Name(n) => name_matches(&p.name, n),
+    };
+    let (processes, withheld): (Vec<_>, Vec<_>) = processes.into_iter().partition(|p| {
+        match options.policy.decide(&p.name) {
+            _ if !picked(p) => true,
+            Decision::Auto => true,
+            Decision::Never => false,
+            Decision::Ask => {
+                approve(dir_path, p.pid, &p.name, Some(p.session), None, &options.policy)
+            }
         }
+    });
+    for process in &withheld {
+        let reason = match options.policy.decide(&process.name) {
+            Decision::Never => "never terminated by the kill policy",
+            _ => "termination not approved",
+        };
+        Record::new(Level::Info, "UNLOCK", dir_path, "skip-policy")
+            .message(format!("{} (pid {}): {}", process.name, process.pid, reason))
+            .emit();
+    }
+    if processes.is_empty() {
+        Record::new(Level::Warn, "UNLOCK", dir_path, "summary")
+            .message("all locking processes were skipped, nothing terminated")
+            .emit();
+        return report;
+    }
+    let allowed = |pid: u32| {
+        options.session.allows(process_tree::session_id(pid), current) && !from_payload(pid)
+    };
 
-        let count = n_proc_info_needed;
+    let untouched = foreign.is_empty() && payload.is_empty() && withheld.is_empty();
+    if *target == Target::All && untouched {
+        let count = processes.len();
+        let tree = if options.kill_tree {
+            collect_tree(dir_path, &processes, &allowed, &options.policy)
+        } else {
+            Vec::new()
+        };
 
         // Step 4: RmShutdown — let Restart Manager terminate all locking processes
-        // Flag 1 = RmForceShutdown: graceful first, then force if needed
-        let result = RmShutdown(
-            session_handle,
-            RM_FORCE_SHUTDOWN,
-            std::ptr::null(),
-        );
-
-        if result == 0 {
-            logger::log(&format!(
-                "UNLOCK {:?} — terminated {} locking process(es)",
-                dir_path, count
-            ));
-        } else {
-            logger::log(&format!(
-                "UNLOCK {:?} — RmShutdown failed (error {}), {} process(es) may still be locking",
-                dir_path, result, count
-            ));
+        // RmForceShutdown: graceful first, then force if needed
+        match query.terminate_all() {
+            Ok(()) => {
+                Record::new(Level::Info, "UNLOCK", dir_path, "summary")
+                    .message(format!("terminated {} locking process(es)", count))
+                    .emit();
+                report.terminated = report.locking.clone();
+            }
+            Err(e) => {
+                let message = format!("{}, {} process(es) may still be locking", e, count);
+                Record::new(Level::Error, "UNLOCK", dir_path, "error")
+                    .code(Some(i64::from(e.code)))
+                    .message(message.as_str())
+                    .emit();
+                report.errors.push(message);
+            }
         }
+        kill_tree(dir_path, &tree, &mut report);
+        return report;
+    }
+
+    // Step 4 for `--pid` / `--name`: terminate only matching processes
+    let mut matched = Vec::new();
+    for process in processes {
+        let hit = picked(&process);
+        Record::new(Level::Debug, "UNLOCK", dir_path, "lock")
+            .message(format!(
+                "{} (pid {}) locks files{}",
+                process.name,
+                process.pid,
+                if hit { ", selected" } else { "" }
+            ))
+            .emit();
+        if hit {
+            matched.push(process);
+        }
+    }
+
+    if matched.is_empty() {
+        let wanted = match target {
+            Target::Pid(p) => format!("pid {}", p),
+            Target::Name(n) => n.clone(),
+            Target::All => String::new(),
+        };
+        Record::new(Level::Warn, "UNLOCK", dir_path, "skip")
+            .message(format!(
+                "{} does not lock files in this directory, nothing terminated",
+                wanted
+            ))
+            .emit();
+        return report;
+    }
+
+    let tree = if options.kill_tree {
+        collect_tree(dir_path, &matched, &allowed, &options.policy)
+    } else {
+        Vec::new()
+    };
+    for process in &matched {
+        let name = format!("{} (pid {})", process.name, process.pid);
+        match process.terminate() {
+            Ok(()) => {
+                audit::record("UNLOCK", dir_path, "kill", None, &name);
+                Record::new(Level::Info, "UNLOCK", dir_path, "summary")
+                    .message(format!("terminated {}", name))
+                    .emit();
+                report.terminated.push((process.pid, process.name.clone()));
+            }
+            Err(e) => {
+                let message = format!("{}, {} may still be locking", e, name);
+                Record::new(Level::Error, "UNLOCK", dir_path, "error")
+                    .code(Some(i64::from(e.code)))
+                    .message(message.as_str())
+                    .emit();
+                report.errors.push(message);
+            }
+        }
+    }
+    kill_tree(dir_path, &tree, &mut report);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("uTorrent.exe", "utorrent.exe"));
+        assert!(name_matches("uTorrent.exe", "UTORRENT"));
+        assert!(!name_matches("uTorrent.exe", "utorrent2"));
+        assert!(!name_matches("explorer.exe", "uTorrent.exe"));
+    }
+
+    #[test]
+    fn test_kill_policy() {
+        let policy = KillPolicy {
+            auto: vec!["qbittorrent".into()],
+            ask: vec!["MsMpEng.exe".into(), "explorer".into()],
+            never: vec!["explorer.exe".into()],
+            default: Decision::Ask,
+            approve_hook: None,
+        };
+        assert_eq!(policy.decide("qBittorrent.exe"), Decision::Auto);
+        assert_eq!(policy.decide("msmpeng.exe"), Decision::Ask);
+        assert_eq!(policy.decide("Explorer.EXE"), Decision::Never);
+        assert_eq!(policy.decide("vlc.exe"), Decision::Ask);
+        assert!(policy.listed_auto("qBittorrent.exe"));
+        assert!(!policy.listed_auto("vlc.exe"));
+        assert!(!KillPolicy::default().listed_auto("vlc.exe"));
+        assert!(SHELLS.iter().any(|s| name_matches("Explorer.EXE", s)));
+        assert_eq!(KillPolicy::default().decide("vlc.exe"), Decision::Auto);
+        assert_eq!(Decision::parse("NEVER"), Some(Decision::Never));
+        assert_eq!(Decision::parse("maybe"), None);
+    }
 
-        // RmEndSession is called automatically by _guard Drop
+    #[test]
+    fn test_session() {
+        assert_eq!(Session::parse("ALL"), Some(Session::All));
+        assert_eq!(Session::parse("2"), Some(Session::Id(2)));
+        assert_eq!(Session::parse("mine"), None);
+        assert!(Session::Current.allows(Some(1), Some(1)));
+        assert!(!Session::Current.allows(Some(2), Some(1)));
+        assert!(!Session::Current.allows(None, None));
+        assert!(Session::Id(0).allows(Some(0), Some(1)));
+        assert!(Session::All.allows(None, Some(1)));
     }
 }
@@ -0,0 +1,411 @@
+//! `self-update` command: replace the executable with a newer release.
+//!
+//! `--update-url` (or `update-url` in `zDirComp.toml`) points at a directory
+//! holding one manifest per channel, `<channel>.txt`:
+//!
+//! ```text
+//! version 1.2.0
+//! url https://example.org/zDirComp-1.2.0.exe
+//! sha256 <64 hex digits>
+//! ```
+//!
+//! Both the manifest and the download must be `https://`. A newer version is
+//! downloaded beside the executable as `.new` and checked against the
+//! SHA-256. If the running build is Authenticode-signed, the download must
+//! carry a valid signature (WinVerifyTrust) from the same publisher: the
+//! signing certificates' subject and issuer must match, so a renewed
+//! certificate still updates but another valid publisher does not. An
+//! unsigned (development) build has only https and the hash to go on. The
+//! running exe is then renamed to `.old` (Windows allows renaming, not
+//! deleting, a running image) and the download renamed into its place. The
+//! `.old` file is removed on the next start.
+
+use crate::http;
+use crate::logger::{Level, Record};
+use crate::paths;
+use crate::sha;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const WTD_UI_NONE: DWORD = 2;
+const WTD_REVOKE_NONE: DWORD = 0;
+const WTD_CHOICE_FILE: DWORD = 1;
+const WTD_STATEACTION_VERIFY: DWORD = 1;
+const WTD_STATEACTION_CLOSE: DWORD = 2;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct GUID {
+    Data1: u32,
+    Data2: u16,
+    Data3: u16,
+    Data4: [u8; 8],
+}
+
+/// `WINTRUST_ACTION_GENERIC_VERIFY_V2`: Authenticode policy.
+const WINTRUST_ACTION_GENERIC_VERIFY_V2: GUID = GUID {
+    Data1: 0x00AA_C56B,
+    Data2: 0xCD44,
+    Data3: 0x11D0,
+    Data4: [0x8C, 0xC2, 0x00, 0xC0, 0x4F, 0xC2, 0x95, 0xEE],
+};
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct WINTRUST_FILE_INFO {
+    cbStruct: DWORD,
+    pcwszFilePath: *const u16,
+    hFile: HANDLE,
+    pgKnownSubject: *const GUID,
+}
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct WINTRUST_DATA {
+    cbStruct: DWORD,
+    pPolicyCallbackData: *mut std::ffi::c_void,
+    pSIPClientData: *mut std::ffi::c_void,
+    dwUIChoice: DWORD,
+    fdwRevocationChecks: DWORD,
+    dwUnionChoice: DWORD,
+    pFile: *mut WINTRUST_FILE_INFO,
+    dwStateAction: DWORD,
+    hWVTStateData: HANDLE,
+    pwszURLReference: *mut u16,
+    dwProvFlags: DWORD,
+    dwUIContext: DWORD,
+    pSignatureSettings: *mut std::ffi::c_void,
+}
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct BLOB {
+    cbData: DWORD,
+    pbData: *const u8,
+}
+
+impl BLOB {
+    fn to_vec(&self) -> Vec<u8> {
+        if self.pbData.is_null() {
+            return Vec::new();
+        }
+        unsafe { std::slice::from_raw_parts(self.pbData, self.cbData as usize) }.to_vec()
+    }
+}
+
+/// Leading fields of `CERT_INFO`, up to the subject.
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct CERT_INFO {
+    dwVersion: DWORD,
+    SerialNumber: BLOB,
+    pszObjId: *const u8,
+    Parameters: BLOB,
+    Issuer: BLOB,
+    NotBefore: [DWORD; 2],
+    NotAfter: [DWORD; 2],
+    Subject: BLOB,
+}
+
+/// Leading fields of `CERT_CONTEXT`.
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct CERT_CONTEXT {
+    dwCertEncodingType: DWORD,
+    pbCertEncoded: *const u8,
+    cbCertEncoded: DWORD,
+    pCertInfo: *const CERT_INFO,
+}
+
+/// Leading fields of `CRYPT_PROVIDER_CERT`.
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct CRYPT_PROVIDER_CERT {
+    cbStruct: DWORD,
+    pCert: *const CERT_CONTEXT,
+}
+
+/// Leading fields of `CRYPT_PROVIDER_SGNR`.
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct CRYPT_PROVIDER_SGNR {
+    cbStruct: DWORD,
+    sftVerifyAsOf: [DWORD; 2],
+    csCertChain: DWORD,
+    pasCertChain: *const CRYPT_PROVIDER_CERT,
+}
+
+#[link(name = "wintrust")]
+extern "system" {
+    fn WinVerifyTrust(hwnd: HANDLE, pgActionID: *const GUID, pWVTData: *mut WINTRUST_DATA) -> i32;
+    fn WTHelperProvDataFromStateData(hStateData: HANDLE) -> *mut std::ffi::c_void;
+    fn WTHelperGetProvSignerFromChain(
+        pProvData: *mut std::ffi::c_void,
+        idxSigner: DWORD,
+        fCounterSigner: i32,
+        idxCounterSigner: DWORD,
+    ) -> *const CRYPT_PROVIDER_SGNR;
+}
+
+/// Who signed a file: subject and issuer of the signing certificate, as
+/// encoded names.
+#[derive(Debug, PartialEq, Eq)]
+struct Publisher {
+    subject: Vec<u8>,
+    issuer: Vec<u8>,
+}
+
+/// The publisher of `path` if it carries a valid Authenticode signature.
+fn signer(path: &Path) -> Option<Publisher> {
+    let wide = paths::to_wide(path);
+    let mut file = WINTRUST_FILE_INFO {
+        cbStruct: std::mem::size_of::<WINTRUST_FILE_INFO>() as DWORD,
+        pcwszFilePath: wide.as_ptr(),
+        hFile: std::ptr::null_mut(),
+        pgKnownSubject: std::ptr::null(),
+    };
+    let mut data = WINTRUST_DATA {
+        cbStruct: std::mem::size_of::<WINTRUST_DATA>() as DWORD,
+        pPolicyCallbackData: std::ptr::null_mut(),
+        pSIPClientData: std::ptr::null_mut(),
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_NONE,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        pFile: &mut file,
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        hWVTStateData: std::ptr::null_mut(),
+        pwszURLReference: std::ptr::null_mut(),
+        dwProvFlags: 0,
+        dwUIContext: 0,
+        pSignatureSettings: std::ptr::null_mut(),
+    };
+    unsafe {
+        let action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+        let status = WinVerifyTrust(std::ptr::null_mut(), &action, &mut data);
+        let publisher = if status == 0 {
+            publisher(data.hWVTStateData)
+        } else {
+            None
+        };
+        data.dwStateAction = WTD_STATEACTION_CLOSE;
+        WinVerifyTrust(std::ptr::null_mut(), &action, &mut data);
+        publisher
+    }
+}
+
+/// The signing certificate of a verified file, from the WinVerifyTrust
+/// state (valid until it is closed).
+unsafe fn publisher(state: HANDLE) -> Option<Publisher> {
+    let provider = WTHelperProvDataFromStateData(state);
+    if provider.is_null() {
+        return None;
+    }
+    let signer = WTHelperGetProvSignerFromChain(provider, 0, 0, 0).as_ref()?;
+    if signer.csCertChain == 0 {
+        return None;
+    }
+    let cert = (*signer.pasCertChain).pCert.as_ref()?;
+    let info = cert.pCertInfo.as_ref()?;
+    Some(Publisher {
+        subject: info.Subject.to_vec(),
+        issuer: info.Issuer.to_vec(),
+    })
+}
+
+/// A channel manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Manifest {
+    version: String,
+    url: String,
+    /// Lowercase hex.
+    sha256: String,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Manifest, String> {
+        let field = |name: &str| {
+            text.lines()
+                .filter_map(|line| line.trim().split_once(char::is_whitespace))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.trim().to_string())
+                .ok_or_else(|| format!("manifest has no '{}' line", name))
+        };
+        let sha256 = field("sha256")?.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("manifest sha256 is not 64 hex digits".to_string());
+        }
+        Ok(Manifest {
+            version: field("version")?,
+            url: field("url")?,
+            sha256,
+        })
+    }
+}
+
+/// Numeric components of a `1.2.3` version, for comparison.
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// `exe` with `suffix` appended to its file name.
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    exe.with_file_name(name)
+}
+
+/// Delete the executable left behind by the last update, if any.
+pub fn remove_old() {
+    if let Ok(exe) = std::env::current_exe() {
+        let _ = fs::remove_file(sibling(&exe, ".old"));
+    }
+}
+
+/// Rename `new` over the running `exe`, keeping the old one as `.old`.
+fn swap(exe: &Path, new: &Path) -> Result<(), String> {
+    let old = sibling(exe, ".old");
+    let _ = fs::remove_file(&old);
+    fs::rename(exe, &old).map_err(|e| format!("cannot rename {:?}: {}", exe, e))?;
+    if let Err(e) = fs::rename(new, exe) {
+        let _ = fs::rename(&old, exe);
+        return Err(format!("cannot move the new version into place: {}", e));
+    }
+    Ok(())
+}
+
+/// GET `url`, which must be `https://`.
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let parsed = http::Url::parse(url)?;
+    if !parsed.secure {
+        return Err(format!("{} is not https://, refused", url));
+    }
+    let response = http::get(&parsed, &[])?;
+    if response.status != 200 {
+        return Err(format!("{} returned HTTP {}", url, response.status));
+    }
+    Ok(response.body)
+}
+
+/// Check `base_url` for a newer release on `channel` and install it.
+pub fn run(base_url: &str, channel: &str) -> Result<(), String> {
+    let abort = |message: String| {
+        Record::new(Level::Error, "UPDATE", base_url, "abort")
+            .message(message.as_str())
+            .emit();
+        message
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    let manifest_url = format!("{}/{}.txt", base_url.trim_end_matches('/'), channel);
+    let text = fetch(&manifest_url).map_err(abort)?;
+    let manifest = Manifest::parse(&String::from_utf8_lossy(&text)).map_err(abort)?;
+    if version_key(&manifest.version) <= version_key(current) {
+        Record::new(Level::Info, "UPDATE", base_url, "summary")
+            .message(format!(
+                "{} is up to date ({} channel has {})",
+                current, channel, manifest.version
+            ))
+            .emit();
+        return Ok(());
+    }
+
+    let exe = std::env::current_exe()
+        .map_err(|e| abort(format!("cannot locate the executable: {}", e)))?;
+    let body = fetch(&manifest.url).map_err(abort)?;
+    if sha::hex(&sha::sha256(&body)) != manifest.sha256 {
+        return Err(abort(format!(
+            "download of {} does not match the manifest sha256, not installed",
+            manifest.version
+        )));
+    }
+    let new = sibling(&exe, ".new");
+    fs::write(&new, &body).map_err(|e| abort(format!("cannot write {:?}: {}", new, e)))?;
+    // A signed build only ever replaces itself with a build its publisher signed
+    if let Some(publisher) = signer(&exe) {
+        let problem = match signer(&new) {
+            Some(new) if new == publisher => None,
+            Some(_) => Some("is signed by a different publisher"),
+            None => Some("has no valid Authenticode signature"),
+        };
+        if let Some(problem) = problem {
+            let _ = fs::remove_file(&new);
+            return Err(abort(format!(
+                "{} {}, not installed",
+                manifest.version, problem
+            )));
+        }
+    }
+    swap(&exe, &new).map_err(|e| {
+        let _ = fs::remove_file(&new);
+        abort(e)
+    })?;
+    Record::new(Level::Info, "UPDATE", base_url, "summary")
+        .path(&exe)
+        .message(format!(
+            "updated {} to {} ({} channel)",
+            current, manifest.version, channel
+        ))
+        .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_manifest_parse() {
+        let hex = "ab".repeat(32);
+        let upper = format!(
+            "version 1.2.0\nurl https://x/a.exe\nsha256 {}",
+            hex.to_uppercase()
+        );
+        assert_eq!(Manifest::parse(&upper).unwrap().sha256, hex);
+        let text = format!(
+            "version 1.2.0\nurl https://x/zDirComp.exe\nsha256 {}\n",
+            hex
+        );
+        let manifest = Manifest::parse(&text).unwrap();
+        assert_eq!(manifest.version, "1.2.0");
+        assert_eq!(manifest.url, "https://x/zDirComp.exe");
+        assert_eq!(manifest.sha256, hex);
+        assert!(Manifest::parse("version 1.2.0\nurl https://x/a.exe\nsha256 abc").is_err());
+        assert!(Manifest::parse(&format!("version 1.2.0\nsha256 {}", hex)).is_err());
+    }
+
+    #[test]
+    fn test_https_only() {
+        let error = fetch("http://updates.example/stable.txt").unwrap_err();
+        assert!(error.contains("not https"), "{}", error);
+    }
+
+    #[test]
+    fn test_version_key() {
+        assert!(version_key("1.10.0") > version_key("1.9.3"));
+        assert!(version_key("v2.0") > version_key("1.99.99"));
+        assert_eq!(version_key("1.0.0"), version_key("1.0.0"));
+    }
+
+    #[test]
+    fn test_swap() {
+        let dir = TempDir::new("update");
+        let exe = dir.join("zDirComp.exe");
+        let new = sibling(&exe, ".new");
+        fs::write(&exe, b"v1").unwrap();
+        fs::write(&new, b"v2").unwrap();
+        swap(&exe, &new).unwrap();
+        assert_eq!(fs::read(&exe).unwrap(), b"v2");
+        assert_eq!(fs::read(sibling(&exe, ".old")).unwrap(), b"v1");
+        assert!(!new.exists());
+    }
+}
@@ -0,0 +1,312 @@
+//! NTFS change journal (USN journal) queries (raw FFI, no external crates).
+//!
+//! `verify --incremental` stores a `Checkpoint` (journal ID and next USN of
+//! the volume) after a clean full verify. The next run reads the journal
+//! from that point and only hashes files whose file ID appears in it. The
+//! journal being deleted or recreated, or wrapping past the checkpoint,
+//! changes the ID or lowest valid USN, and the run falls back to a full
+//! verify. Opening the volume usually needs an elevated process.
+
+use crate::paths;
+use crate::volume;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const GENERIC_READ: DWORD = 0x8000_0000;
+const FILE_SHARE_READ_WRITE: DWORD = 0x3;
+const FILE_SHARE_ALL: DWORD = 0x7;
+const OPEN_EXISTING: DWORD = 3;
+const FILE_FLAG_BACKUP_SEMANTICS: DWORD = 0x0200_0000;
+const FSCTL_QUERY_USN_JOURNAL: DWORD = 0x0009_00F4;
+const FSCTL_READ_USN_JOURNAL: DWORD = 0x0009_00BB;
+const ERROR_HANDLE_EOF: DWORD = 38;
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct USN_JOURNAL_DATA_V0 {
+    UsnJournalID: u64,
+    FirstUsn: i64,
+    NextUsn: i64,
+    LowestValidUsn: i64,
+    MaxUsn: i64,
+    MaximumSize: u64,
+    AllocationDelta: u64,
+}
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct READ_USN_JOURNAL_DATA_V0 {
+    StartUsn: i64,
+    ReasonMask: DWORD,
+    ReturnOnlyOnClose: DWORD,
+    Timeout: u64,
+    BytesToWaitFor: u64,
+    UsnJournalID: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct BY_HANDLE_FILE_INFORMATION {
+    dwFileAttributes: DWORD,
+    ftCreationTime: [DWORD; 2],
+    ftLastAccessTime: [DWORD; 2],
+    ftLastWriteTime: [DWORD; 2],
+    dwVolumeSerialNumber: DWORD,
+    nFileSizeHigh: DWORD,
+    nFileSizeLow: DWORD,
+    nNumberOfLinks: DWORD,
+    nFileIndexHigh: DWORD,
+    nFileIndexLow: DWORD,
+}
+
+extern "system" {
+    fn CreateFileW(
+        lpFileName: *const u16,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: *const std::ffi::c_void,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn DeviceIoControl(
+        hDevice: HANDLE,
+        dwIoControlCode: DWORD,
+        lpInBuffer: *const std::ffi::c_void,
+        nInBufferSize: DWORD,
+        lpOutBuffer: *mut std::ffi::c_void,
+        nOutBufferSize: DWORD,
+        lpBytesReturned: *mut DWORD,
+        lpOverlapped: *mut std::ffi::c_void,
+    ) -> i32;
+    fn GetFileInformationByHandle(
+        hFile: HANDLE,
+        lpFileInformation: *mut BY_HANDLE_FILE_INFORMATION,
+    ) -> i32;
+    fn GetLastError() -> DWORD;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+}
+
+/// Position in a volume's change journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub journal_id: u64,
+    pub next_usn: i64,
+}
+
+impl Checkpoint {
+    /// Parse the `journal_id next_usn` form written by `Display`.
+    pub fn parse(s: &str) -> Option<Checkpoint> {
+        let (id, usn) = s.split_once(' ')?;
+        Some(Checkpoint {
+            journal_id: u64::from_str_radix(id, 16).ok()?,
+            next_usn: usn.parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x} {}", self.journal_id, self.next_usn)
+    }
+}
+
+/// Open volume handle, closed on drop.
+struct Volume(HANDLE);
+
+impl Drop for Volume {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+impl Volume {
+    /// Open the volume holding `path` (drive letters only).
+    fn open(path: &Path) -> Result<Volume, String> {
+        let root = volume::volume_root(path).ok_or("cannot find the volume")?;
+        let bytes = root.as_bytes();
+        if bytes.len() < 2 || !bytes[0].is_ascii_alphabetic() || bytes[1] != b':' {
+            return Err(format!("{} has no drive letter", root));
+        }
+        let device = paths::to_wide(format!("\\\\.\\{}:", bytes[0] as char));
+        let handle = unsafe {
+            CreateFileW(
+                device.as_ptr(),
+                GENERIC_READ,
+                FILE_SHARE_READ_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let code = unsafe { GetLastError() };
+            return Err(format!("cannot open volume {} (error {})", root, code));
+        }
+        Ok(Volume(handle))
+    }
+
+    fn query(&self) -> Result<USN_JOURNAL_DATA_V0, String> {
+        let mut data = USN_JOURNAL_DATA_V0::default();
+        let mut returned: DWORD = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                self.0,
+                FSCTL_QUERY_USN_JOURNAL,
+                std::ptr::null(),
+                0,
+                &mut data as *mut _ as *mut std::ffi::c_void,
+                std::mem::size_of::<USN_JOURNAL_DATA_V0>() as DWORD,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            let code = unsafe { GetLastError() };
+            return Err(format!("no change journal on this volume (error {})", code));
+        }
+        Ok(data)
+    }
+}
+
+/// Current end of the change journal of the volume holding `path`.
+pub fn checkpoint(path: &Path) -> Result<Checkpoint, String> {
+    let data = Volume::open(path)?.query()?;
+    Ok(Checkpoint {
+        journal_id: data.UsnJournalID,
+        next_usn: data.NextUsn,
+    })
+}
+
+/// File reference numbers from a `FSCTL_READ_USN_JOURNAL` output buffer
+/// (a next-USN followed by `USN_RECORD_V2` records), and that next USN.
+fn parse_records(buf: &[u8], ids: &mut HashSet<u64>) -> Option<i64> {
+    let next = i64::from_le_bytes(buf.get(..8)?.try_into().ok()?);
+    let mut pos = 8;
+    while pos + 16 <= buf.len() {
+        let length = u32::from_le_bytes(buf[pos..pos + 4].try_into().ok()?) as usize;
+        if length == 0 || pos + length > buf.len() {
+            break;
+        }
+        ids.insert(u64::from_le_bytes(buf[pos + 8..pos + 16].try_into().ok()?));
+        pos += length;
+    }
+    Some(next)
+}
+
+/// IDs of files with journal records between `since` and now on the volume
+/// holding `path`. Fails if the journal no longer covers `since`.
+pub fn changed_since(path: &Path, since: Checkpoint) -> Result<HashSet<u64>, String> {
+    let volume = Volume::open(path)?;
+    let data = volume.query()?;
+    if data.UsnJournalID != since.journal_id {
+        return Err("change journal was recreated".to_string());
+    }
+    if since.next_usn < data.LowestValidUsn {
+        return Err("change journal no longer reaches the last verify".to_string());
+    }
+
+    let mut ids = HashSet::new();
+    let mut start = since.next_usn;
+    let mut buf = vec![0u8; 64 * 1024];
+    while start < data.NextUsn {
+        let request = READ_USN_JOURNAL_DATA_V0 {
+            StartUsn: start,
+            ReasonMask: 0xFFFF_FFFF,
+            ReturnOnlyOnClose: 0,
+            Timeout: 0,
+            BytesToWaitFor: 0,
+            UsnJournalID: since.journal_id,
+        };
+        let mut returned: DWORD = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                volume.0,
+                FSCTL_READ_USN_JOURNAL,
+                &request as *const _ as *const std::ffi::c_void,
+                std::mem::size_of::<READ_USN_JOURNAL_DATA_V0>() as DWORD,
+                buf.as_mut_ptr() as *mut std::ffi::c_void,
+                buf.len() as DWORD,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            let code = unsafe { GetLastError() };
+            if code == ERROR_HANDLE_EOF {
+                break;
+            }
+            return Err(format!("cannot read the change journal (error {})", code));
+        }
+        match parse_records(&buf[..returned as usize], &mut ids) {
+            Some(next) if next > start => start = next,
+            _ => break,
+        }
+    }
+    Ok(ids)
+}
+
+/// NTFS file ID of `path`, as it appears in journal records.
+pub fn file_id(path: &Path) -> Option<u64> {
+    let wide = paths::to_wide(path);
+    unsafe {
+        let handle = CreateFileW(
+            wide.as_ptr(),
+            0,
+            FILE_SHARE_ALL,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        let mut info = BY_HANDLE_FILE_INFORMATION::default();
+        let ok = GetFileInformationByHandle(handle, &mut info) != 0;
+        CloseHandle(handle);
+        ok.then(|| (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let checkpoint = Checkpoint {
+            journal_id: 0x01d9_0000_abcd,
+            next_usn: 123_456,
+        };
+        assert_eq!(Checkpoint::parse(&checkpoint.to_string()), Some(checkpoint));
+        assert_eq!(Checkpoint::parse("zz 1"), None);
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let mut buf = 500i64.to_le_bytes().to_vec();
+        for id in [7u64, 9] {
+            let mut record = vec![0u8; 64];
+            record[..4].copy_from_slice(&64u32.to_le_bytes());
+            record[8..16].copy_from_slice(&id.to_le_bytes());
+            buf.extend(record);
+        }
+        let mut ids = HashSet::new();
+        assert_eq!(parse_records(&buf, &mut ids), Some(500));
+        assert_eq!(ids, [7, 9].into_iter().collect());
+    }
+}
@@ -0,0 +1,233 @@
+//! Verify verdicts, per piece and per file.
+//!
+//! A piece is judged from its digest; a file from the pieces holding its
+//! bytes (`PieceMap::file_pieces`). A piece spanning several files counts
+//! against every one of them, as nothing tells which part is wrong.
+//! Zero-length files hold no bytes and can never fail, wherever they sit.
+//!
+//! Files set to "don't download" need care at the edges: a piece shared
+//! with one is wanted, but the client keeps the unwanted part elsewhere
+//! (qBittorrent in a `.parts` file), so what is on disk need not match. Such
+//! a piece is `Unverifiable` rather than `Bad` and fails no file.
+
+use crate::bencode::TorrentMeta;
+use crate::json::Json;
+use crate::piecemap::PieceMap;
+
+/// Outcome for one piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PieceVerdict {
+    /// Digest matched.
+    Good,
+    /// Not hashed: all its files are vouched for (`--trust-sfv`,
+    /// `--incremental`).
+    Trusted,
+    /// Not hashed: lies only in files not downloaded.
+    NotWanted,
+    /// Missing, short or corrupt data.
+    Bad,
+    /// Did not match, but shares bytes with a file not downloaded.
+    Unverifiable,
+}
+
+impl PieceVerdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PieceVerdict::Good => "good",
+            PieceVerdict::Trusted => "trusted",
+            PieceVerdict::NotWanted => "not-wanted",
+            PieceVerdict::Bad => "bad",
+            PieceVerdict::Unverifiable => "unverifiable",
+        }
+    }
+}
+
+/// Outcome for one file, from the pieces holding its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileVerdict {
+    /// Pieces holding bytes of the file; 0 for zero-length files.
+    pub pieces: usize,
+    /// Of those, `Bad` ones.
+    pub failed: usize,
+    /// Of those, `Unverifiable` ones.
+    pub unverified: usize,
+}
+
+impl FileVerdict {
+    pub fn passed(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Verdicts of a whole torrent.
+#[derive(Debug, Clone, Default)]
+pub struct Verdicts {
+    pub pieces: Vec<PieceVerdict>,
+    /// In torrent file order.
+    pub files: Vec<FileVerdict>,
+}
+
+impl Verdicts {
+    /// Judge every piece from `digests` (as from `hashing::hash_pieces`,
+    /// `None` for unread pieces) and `trusted`, then every file.
+    pub fn judge(
+        meta: &TorrentMeta,
+        map: &PieceMap,
+        digests: &[Option<Vec<u8>>],
+        trusted: &[bool],
+    ) -> Verdicts {
+        let pieces: Vec<PieceVerdict> = (0..map.piece_count())
+            .map(|piece| {
+                let spans = map.spans(piece);
+                if spans.iter().all(|span| meta.files[span.file].skip) {
+                    return PieceVerdict::NotWanted;
+                }
+                if trusted.get(piece) == Some(&true) {
+                    return PieceVerdict::Trusted;
+                }
+                let digest = digests.get(piece).and_then(Option::as_deref);
+                if digest.is_some() && digest == meta.piece_hashes.get(piece).map(|h| &h[..]) {
+                    PieceVerdict::Good
+                } else if spans.iter().any(|span| meta.files[span.file].skip) {
+                    PieceVerdict::Unverifiable
+                } else {
+                    PieceVerdict::Bad
+                }
+            })
+            .collect();
+        let files = (0..meta.files.len())
+            .map(|file| {
+                let range = map.file_pieces(file);
+                let count = |verdict| range.clone().filter(|&p| pieces[p] == verdict).count();
+                FileVerdict {
+                    pieces: range.len(),
+                    failed: count(PieceVerdict::Bad),
+                    unverified: count(PieceVerdict::Unverifiable),
+                }
+            })
+            .collect();
+        Verdicts { pieces, files }
+    }
+
+    /// Number of pieces with `verdict`.
+    pub fn count(&self, verdict: PieceVerdict) -> usize {
+        self.pieces.iter().filter(|&&v| v == verdict).count()
+    }
+
+    /// Per-piece and per-file verdicts for `--format json`: every piece
+    /// that did not pass, with the files it touches, and every wanted file.
+    pub fn to_json(&self, meta: &TorrentMeta, map: &PieceMap) -> Json {
+        let path = |file: usize| Json::from(meta.files[file].path.to_string_lossy().into_owned());
+        let failed = self
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| matches!(v, PieceVerdict::Bad | PieceVerdict::Unverifiable))
+            .map(|(piece, verdict)| {
+                let files = map
+                    .spans(piece)
+                    .iter()
+                    .map(|span| path(span.file))
+                    .collect();
+                Json::object()
+                    .with("piece", piece as i64)
+                    .with("verdict", verdict.as_str())
+                    .with("files", Json::Array(files))
+            });
+        let files = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(file, _)| !meta.files[*file].skip)
+            .map(|(file, verdict)| {
+                Json::object()
+                    .with("path", path(file))
+                    .with("verdict", if verdict.passed() { "good" } else { "bad" })
+                    .with("pieces", verdict.pieces as i64)
+                    .with("failed_pieces", verdict.failed as i64)
+                    .with("unverified_pieces", verdict.unverified as i64)
+            });
+        let count = |verdict| self.count(verdict) as i64;
+        Json::object()
+            .with("pieces", self.pieces.len() as i64)
+            .with("good_pieces", count(PieceVerdict::Good))
+            .with("trusted_pieces", count(PieceVerdict::Trusted))
+            .with("bad_pieces", count(PieceVerdict::Bad))
+            .with("unverifiable_pieces", count(PieceVerdict::Unverifiable))
+            .with("failed", Json::Array(failed.collect()))
+            .with("files", Json::Array(files.collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::TorrentFile;
+    use crate::sha;
+    use std::path::PathBuf;
+
+    /// Torrent over `files` (length, skip) with pieces of 8 bytes, hashed
+    /// over `data`, and the digests of `disk` as read back.
+    fn judge(files: &[(u64, bool)], data: &[u8], disk: &[u8], trusted: &[bool]) -> Verdicts {
+        let meta = TorrentMeta {
+            files: files
+                .iter()
+                .enumerate()
+                .map(|(i, &(length, skip))| TorrentFile {
+                    path: PathBuf::from(format!("f{}", i)),
+                    length,
+                    skip,
+                })
+                .collect(),
+            piece_length: 8,
+            total_size: data.len() as u64,
+            piece_hashes: data.chunks(8).map(sha::sha1).collect(),
+            ..Default::default()
+        };
+        let map = PieceMap::new(&meta).unwrap();
+        let digests: Vec<Option<Vec<u8>>> = disk
+            .chunks(8)
+            .map(|c| Some(sha::sha1(c).to_vec()))
+            .collect();
+        Verdicts::judge(&meta, &map, &digests, trusted)
+    }
+
+    #[test]
+    fn test_piece_across_files() {
+        // f0: 0..10, f1: empty, f2: 10..25; piece 1 (8..16) spans f0 and f2
+        let data: Vec<u8> = (0..25).collect();
+        let mut disk = data.clone();
+        disk[12] ^= 1;
+        let verdicts = judge(&[(10, false), (0, false), (15, false)], &data, &disk, &[]);
+        use PieceVerdict::*;
+        assert_eq!(verdicts.pieces, vec![Good, Bad, Good, Good]);
+        // The bad piece fails both files it touches, not the empty one
+        let failed: Vec<usize> = verdicts.files.iter().map(|f| f.failed).collect();
+        assert_eq!(failed, vec![1, 0, 1]);
+        assert_eq!(verdicts.files[1], FileVerdict::default());
+        assert_eq!(verdicts.files[2].pieces, 3);
+    }
+
+    #[test]
+    fn test_not_downloaded_neighbour() {
+        // f1 is not downloaded: piece 1 is shared with it, piece 2 is its own
+        let data: Vec<u8> = (0..24).collect();
+        let mut disk = data.clone();
+        disk[8..24].fill(0);
+        let verdicts = judge(&[(10, false), (14, true)], &data, &disk, &[]);
+        use PieceVerdict::*;
+        assert_eq!(verdicts.pieces, vec![Good, Unverifiable, NotWanted]);
+        assert!(verdicts.files[0].passed());
+        assert_eq!(verdicts.files[0].unverified, 1);
+
+        // Trusted pieces are not judged from digests
+        let verdicts = judge(
+            &[(10, false), (14, false)],
+            &data,
+            &disk,
+            &[false, true, true],
+        );
+        assert_eq!(verdicts.pieces, vec![Good, Trusted, Trusted]);
+        assert_eq!(verdicts.count(Trusted), 2);
+    }
+}
@@ -0,0 +1,404 @@
+//! Mode 3: Verify — check downloaded data against the torrent's piece hashes.
+//!
+//! Steps:
+//! 1. Parse .torrent → `TorrentMeta` (paths relocated by `--map`) and piece map
+//! 2. Hash all pieces through the multi-threaded pipeline
+//! 3. Report every file that has a missing or mismatching piece, with its
+//!    size on disk and, for sparse files, how many bytes are allocated
+//!
+//! Files set to "don't download" (`--priorities`) are not reported, and
+//! pieces that lie only in such files do not count as failed. A piece
+//! shared with one cannot be judged from disk (see `verdict`).
+//!
+//! With `--trust-sfv`, files listed in an unchanged checksum file are taken
+//! as good, and pieces lying only in such files are not hashed. The same
+//! goes with `--incremental` for files the NTFS change journal shows
+//! untouched since the last clean verify of this torrent and directory
+//! (see `usn`); the checkpoint is kept in `state`.
+//!
+//! With `--skip-unchanged`, a directory that looks as it did after the last
+//! clean verify is not hashed at all (see `cache`).
+//!
+//! With `--export`, checksum files are written for the files that passed
+//! (see `checksum`); otherwise nothing on disk is changed. `--format json`
+//! prints the per-piece and per-file verdicts. Exits with code 1 if any
+//! piece fails.
+
+use crate::bencode;
+use crate::cache;
+use crate::checksum;
+use crate::dir_index::DirIndex;
+use crate::hashing::{self, Algorithm};
+use crate::json::Json;
+use crate::logger::{Level, Record};
+use crate::pathmap::PathMap;
+use crate::piecemap::PieceMap;
+use crate::priorities::Priorities;
+use crate::sha;
+use crate::sparse;
+use crate::state;
+use crate::usn;
+use crate::verdict::{PieceVerdict, Verdicts};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// What is on disk for one torrent file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileState {
+    Missing,
+    /// Logical size differs from the torrent's.
+    WrongSize(u64),
+    /// Full logical size, but only this many bytes allocated (sparse).
+    Preallocated(u64),
+    /// Full size and fully allocated.
+    Complete,
+}
+
+fn classify(size: Option<u64>, allocated: Option<u64>, expected: u64) -> FileState {
+    match (size, allocated) {
+        (None, _) => FileState::Missing,
+        (Some(size), _) if size != expected => FileState::WrongSize(size),
+        (Some(_), Some(allocated)) if allocated < expected => FileState::Preallocated(allocated),
+        _ => FileState::Complete,
+    }
+}
+
+fn describe(state: FileState, expected: u64) -> String {
+    match state {
+        FileState::Missing => "file missing".to_string(),
+        FileState::WrongSize(size) => format!("size {} bytes, expected {}", size, expected),
+        FileState::Preallocated(allocated) => format!(
+            "full size but sparse: {} of {} bytes allocated ({}%)",
+            allocated,
+            expected,
+            allocated * 100 / expected.max(1)
+        ),
+        FileState::Complete => format!("{} bytes, fully allocated", expected),
+    }
+}
+
+/// Settings beyond the two positional arguments.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Hasher threads (`--threads`, default from the volume type).
+    pub threads: usize,
+    /// Torrent paths relocated on disk (`--map`).
+    pub path_map: PathMap,
+    /// Files marked "don't download" (`--priorities`).
+    pub priorities: Priorities,
+    /// Write checksum files for the files that passed (`--export`).
+    pub export: Option<checksum::Format>,
+    /// One checksum file per directory instead of per torrent (`--export-per-dir`).
+    pub export_per_dir: bool,
+    /// Skip files vouched for by an unchanged checksum file (`--trust-sfv`).
+    pub trust_sfv: bool,
+    /// Skip files the change journal shows unchanged (`--incremental`).
+    pub incremental: bool,
+    /// Skip a directory unchanged since the last clean verify (`--skip-unchanged`).
+    pub skip_unchanged: bool,
+}
+
+/// Outcome of a verify run.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Skipped by `--skip-unchanged`; nothing was hashed.
+    pub unchanged: bool,
+    pub verdicts: Verdicts,
+    /// `Verdicts::to_json`, which needs the torrent.
+    details: Option<Json>,
+}
+
+impl VerifyReport {
+    /// Whether no piece failed.
+    pub fn passed(&self) -> bool {
+        self.verdicts.count(PieceVerdict::Bad) == 0
+    }
+
+    /// Result object for `--format json`.
+    pub fn to_json(&self) -> Json {
+        self.details
+            .clone()
+            .unwrap_or_else(Json::object)
+            .with("unchanged", self.unchanged)
+    }
+}
+
+/// Log an `abort` record; its message becomes the run's error.
+fn abort(target: &str, message: impl Into<String>) -> String {
+    let message = message.into();
+    Record::new(Level::Error, "VERIFY", target, "abort")
+        .message(message.as_str())
+        .emit();
+    message
+}
+
+/// `state` key of the journal checkpoint for a torrent in a directory.
+fn checkpoint_key(info_hash: &[u8], dir: &Path) -> String {
+    let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    format!(
+        "usn {} {}",
+        sha::hex(info_hash),
+        dir.to_string_lossy().to_lowercase()
+    )
+}
+
+/// `--incremental`: files with no change journal record since the stored
+/// checkpoint. Empty (full verify) when there is no usable checkpoint.
+fn unchanged_files(dir: &Path, dir_path: &str, key: &str, files: &[PathBuf]) -> HashSet<PathBuf> {
+    let Some(since) = state::get(key).as_deref().and_then(usn::Checkpoint::parse) else {
+        Record::new(Level::Info, "VERIFY", dir_path, "incremental")
+            .message("no previous clean verify recorded, full verify")
+            .emit();
+        return HashSet::new();
+    };
+    match usn::changed_since(dir, since) {
+        Ok(changed) => files
+            .iter()
+            .filter(|f| usn::file_id(&dir.join(f)).is_some_and(|id| !changed.contains(&id)))
+            .cloned()
+            .collect(),
+        Err(e) => {
+            Record::new(Level::Info, "VERIFY", dir_path, "incremental")
+                .message(format!("{}, full verify", e))
+                .emit();
+            HashSet::new()
+        }
+    }
+}
+
+/// Run the verify operation. Errors are aborts, already logged.
+pub fn run(torrent_path: &str, dir_path: &str, options: &Options) -> Result<VerifyReport, String> {
+    let dir = Path::new(dir_path);
+
+    let mut meta = bencode::parse_torrent_file(Path::new(torrent_path))
+        .map_err(|e| abort(torrent_path, e))?;
+    let relocated = options.path_map.apply(&mut meta.files);
+    if relocated > 0 {
+        Record::new(Level::Debug, "VERIFY", torrent_path, "map")
+            .message(format!("{} torrent paths relocated by --map", relocated))
+            .emit();
+    }
+
+    let map = match PieceMap::new(&meta) {
+        Some(map) if map.piece_count() == meta.piece_hashes.len() => map,
+        _ => {
+            return Err(abort(
+                torrent_path,
+                "torrent piece data is missing or inconsistent, aborted",
+            ))
+        }
+    };
+
+    if !dir.is_dir() {
+        return Err(abort(dir_path, "directory does not exist, aborted"));
+    }
+
+    // Sizes and times for every step below, from one walk
+    let tree = DirIndex::build(dir, Path::new("")).unwrap_or_else(|e| {
+        Record::new(Level::Warn, "VERIFY", dir_path, "index")
+            .message(format!("{}; file metadata read one file at a time", e))
+            .emit();
+        DirIndex::partial(dir)
+    });
+
+    // Read-only, so the fingerprint before hashing is also the one after
+    let fingerprint = (options.skip_unchanged && tree.is_complete())
+        .then(|| cache::fingerprint_index(dir, &tree));
+    if fingerprint.is_some_and(|fp| cache::is_clean("verify", &meta.info_hash, dir, fp)) {
+        Record::new(Level::Info, "VERIFY", dir_path, "summary")
+            .message("unchanged since the last clean verify, skipped")
+            .emit();
+        return Ok(VerifyReport {
+            unchanged: true,
+            ..VerifyReport::default()
+        });
+    }
+
+    let skipped = options.priorities.apply(&mut meta.files);
+    // Pieces touching at least one wanted file
+    let mut wanted = vec![false; map.piece_count()];
+    for (index, file) in meta.files.iter().enumerate() {
+        if !file.skip {
+            map.file_pieces(index).for_each(|p| wanted[p] = true);
+        }
+    }
+
+    // Taken before hashing, so changes made during the run show up next time
+    let key = checkpoint_key(&meta.info_hash, dir);
+    let checkpoint = match options.incremental.then(|| usn::checkpoint(dir)) {
+        Some(Err(e)) => {
+            Record::new(Level::Warn, "VERIFY", dir_path, "incremental")
+                .message(format!("change journal unavailable: {}", e))
+                .emit();
+            None
+        }
+        other => other.and_then(Result::ok),
+    };
+
+    // Pieces made up only of files vouched for by a checksum file or the
+    // change journal
+    let mut trusted_pieces = vec![false; map.piece_count()];
+    if options.trust_sfv || checkpoint.is_some() {
+        let files: Vec<PathBuf> =
+            meta.files.iter().filter(|f| !f.skip).map(|f| f.path.clone()).collect();
+        let mut trusted = HashSet::new();
+        if options.trust_sfv {
+            trusted = checksum::trusted(dir, &files);
+        }
+        if checkpoint.is_some() {
+            trusted.extend(unchanged_files(dir, dir_path, &key, &files));
+        }
+        let is_trusted = |index: usize| {
+            let file = &meta.files[index];
+            trusted.contains(&file.path)
+                && tree.get(&file.path).is_some_and(|m| m.size == file.length)
+        };
+        let trusted_files: Vec<bool> = (0..meta.files.len()).map(is_trusted).collect();
+        for (piece, trusted_piece) in trusted_pieces.iter_mut().enumerate() {
+            *trusted_piece = map.spans(piece).iter().all(|span| trusted_files[span.file]);
+        }
+        Record::new(Level::Info, "VERIFY", dir_path, "trust")
+            .message(format!(
+                "{} files and {} of {} pieces trusted without hashing",
+                trusted_files.iter().filter(|&&t| t).count(),
+                trusted_pieces.iter().filter(|&&t| t).count(),
+                trusted_pieces.len()
+            ))
+            .emit();
+    }
+
+    let selected: Vec<bool> = wanted
+        .iter()
+        .zip(&trusted_pieces)
+        .map(|(&wanted, &trusted)| wanted && !trusted)
+        .collect();
+    let digests =
+        hashing::hash_pieces(dir, &meta, &map, Algorithm::Sha1, options.threads, &selected);
+    let verdicts = Verdicts::judge(&meta, &map, &digests, &trusted_pieces);
+    let bad_pieces = verdicts.count(PieceVerdict::Bad);
+    let unverifiable = verdicts.count(PieceVerdict::Unverifiable);
+    if unverifiable > 0 {
+        Record::new(Level::Warn, "VERIFY", dir_path, "unverifiable")
+            .message(format!(
+                "{} pieces shared with files not downloaded did not match; the client \
+                 may keep those bytes elsewhere, so no file is blamed",
+                unverifiable
+            ))
+            .emit();
+    }
+
+    let mut bad_files = 0usize;
+    let mut preallocated = 0usize;
+    let mut passed = Vec::new();
+    for (index, file) in meta.files.iter().enumerate() {
+        if file.skip {
+            continue;
+        }
+        let path = dir.join(&file.path);
+        let size = tree.get(&file.path).filter(|m| !m.is_dir).map(|m| m.size);
+        let state = classify(size, sparse::allocated_bytes(&path, file.length), file.length);
+        if matches!(state, FileState::Preallocated(_)) {
+            preallocated += 1;
+        }
+        let verdict = verdicts.files[index];
+        if !verdict.passed() {
+            bad_files += 1;
+            Record::new(Level::Warn, "VERIFY", dir_path, "mismatch")
+                .path(&file.path)
+                .message(format!(
+                    "{:?}: {} of {} pieces missing or corrupt; {}",
+                    file.path,
+                    verdict.failed,
+                    verdict.pieces,
+                    describe(state, file.length)
+                ))
+                .emit();
+            continue;
+        }
+        if state != FileState::Complete {
+            Record::new(Level::Debug, "VERIFY", dir_path, "sparse")
+                .path(&file.path)
+                .message(format!("{:?}: {}", file.path, describe(state, file.length)))
+                .emit();
+        }
+        if state != FileState::Missing {
+            passed.push(file.path.clone());
+        }
+    }
+
+    if let Some(format) = options.export {
+        match checksum::export(dir, &passed, format, options.export_per_dir, &meta.name) {
+            Ok(written) => Record::new(Level::Info, "VERIFY", dir_path, "export")
+                .message(format!(
+                    "{} checksums of {} verified files written to {} file(s)",
+                    format.extension(),
+                    passed.len(),
+                    written.len()
+                ))
+                .emit(),
+            Err(e) => Record::new(Level::Warn, "VERIFY", dir_path, "export")
+                .message(e)
+                .emit(),
+        }
+    }
+
+    let skip_note = match skipped {
+        0 => String::new(),
+        n => format!(", {} files not downloaded", n),
+    };
+    if fingerprint.is_some() {
+        let clean = fingerprint.filter(|_| bad_pieces == 0);
+        if let Err(e) = cache::record("verify", &meta.info_hash, dir, clean) {
+            Record::new(Level::Warn, "VERIFY", dir_path, "cache")
+                .message(e)
+                .emit();
+        }
+    }
+    if bad_pieces == 0 {
+        if let Some(checkpoint) = checkpoint {
+            if let Err(e) = state::set(&key, Some(&checkpoint.to_string())) {
+                Record::new(Level::Warn, "VERIFY", dir_path, "incremental")
+                    .message(e)
+                    .emit();
+            }
+        }
+        Record::new(Level::Info, "VERIFY", dir_path, "summary")
+            .message(format!("all {} pieces OK{}", verdicts.pieces.len(), skip_note))
+            .emit();
+    } else {
+        Record::new(Level::Error, "VERIFY", dir_path, "summary")
+            .message(format!(
+                "{} of {} pieces failed, {} files affected ({} preallocated sparse){}",
+                bad_pieces,
+                verdicts.pieces.len(),
+                bad_files,
+                preallocated,
+                skip_note
+            ))
+            .emit();
+    }
+    Ok(VerifyReport {
+        unchanged: false,
+        details: Some(verdicts.to_json(&meta, &map)),
+        verdicts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(None, None, 10), FileState::Missing);
+        assert_eq!(classify(Some(4), None, 10), FileState::WrongSize(4));
+        assert_eq!(classify(Some(10), Some(3), 10), FileState::Preallocated(3));
+        assert_eq!(classify(Some(10), Some(10), 10), FileState::Complete);
+        assert_eq!(classify(Some(10), None, 10), FileState::Complete);
+        assert_eq!(
+            describe(FileState::Preallocated(25), 100),
+            "full size but sparse: 25 of 100 bytes allocated (25%)"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Storage type of the volume holding a path (raw FFI, no external crates).
+//!
+//! Network shares come from the drive type; for fixed disks the seek
+//! penalty reported by the storage driver (`IOCTL_STORAGE_QUERY_PROPERTY`)
+//! separates HDDs from SSDs. Used to pick I/O defaults: parallel hashing on
+//! SSDs, one hasher on HDDs so reads stay sequential.
+
+use crate::paths;
+
+use std::fmt;
+use std::path::Path;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut std::ffi::c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const DRIVE_REMOVABLE: u32 = 2;
+const DRIVE_FIXED: u32 = 3;
+const DRIVE_REMOTE: u32 = 4;
+const FILE_SHARE_READ_WRITE: DWORD = 0x3;
+const OPEN_EXISTING: DWORD = 3;
+const IOCTL_STORAGE_QUERY_PROPERTY: DWORD = 0x002D_1400;
+const STORAGE_DEVICE_SEEK_PENALTY_PROPERTY: i32 = 7;
+const PROPERTY_STANDARD_QUERY: i32 = 0;
+const FILE_READ_ONLY_VOLUME: DWORD = 0x0008_0000;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct STORAGE_PROPERTY_QUERY {
+    PropertyId: i32,
+    QueryType: i32,
+    AdditionalParameters: [u8; 1],
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct DEVICE_SEEK_PENALTY_DESCRIPTOR {
+    Version: DWORD,
+    Size: DWORD,
+    IncursSeekPenalty: u8,
+}
+
+extern "system" {
+    fn GetVolumePathNameW(
+        lpszFileName: *const u16,
+        lpszVolumePathName: *mut u16,
+        cch: DWORD,
+    ) -> i32;
+    fn GetDriveTypeW(lpRootPathName: *const u16) -> u32;
+    fn CreateFileW(
+        lpFileName: *const u16,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: *const std::ffi::c_void,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn DeviceIoControl(
+        hDevice: HANDLE,
+        dwIoControlCode: DWORD,
+        lpInBuffer: *const std::ffi::c_void,
+        nInBufferSize: DWORD,
+        lpOutBuffer: *mut std::ffi::c_void,
+        nOutBufferSize: DWORD,
+        lpBytesReturned: *mut DWORD,
+        lpOverlapped: *mut std::ffi::c_void,
+    ) -> i32;
+    fn CloseHandle(hObject: HANDLE) -> i32;
+    fn GetVolumeInformationW(
+        lpRootPathName: *const u16,
+        lpVolumeNameBuffer: *mut u16,
+        nVolumeNameSize: DWORD,
+        lpVolumeSerialNumber: *mut DWORD,
+        lpMaximumComponentLength: *mut DWORD,
+        lpFileSystemFlags: *mut DWORD,
+        lpFileSystemNameBuffer: *mut u16,
+        nFileSystemNameSize: DWORD,
+    ) -> i32;
+    fn GetDiskFreeSpaceExW(
+        lpDirectoryName: *const u16,
+        lpFreeBytesAvailableToCaller: *mut u64,
+        lpTotalNumberOfBytes: *mut u64,
+        lpTotalNumberOfFreeBytes: *mut u64,
+    ) -> i32;
+}
+
+/// Default `--delete-rate` on network drives.
+pub const DEFAULT_NETWORK_DELETE_RATE: u32 = 200;
+
+/// Kind of storage behind a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Ssd,
+    Hdd,
+    Network,
+    Removable,
+    Unknown,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Ssd => "SSD",
+            Kind::Hdd => "HDD",
+            Kind::Network => "network",
+            Kind::Removable => "removable",
+            Kind::Unknown => "unknown",
+        })
+    }
+}
+
+impl Kind {
+    /// Hasher threads for `verify` when `--threads` is not given.
+    #[cfg(any(feature = "verify", test))]
+    pub fn hash_threads(self, cpus: usize) -> usize {
+        match self {
+            // Parallel readers would make the disk seek between pieces
+            Kind::Hdd | Kind::Removable => 1,
+            Kind::Ssd | Kind::Network | Kind::Unknown => cpus,
+        }
+    }
+
+    /// File operations per second for sync when `--delete-rate` is not
+    /// given; `None` for no limit.
+    pub fn delete_rate(self) -> Option<u32> {
+        match self {
+            // One SMB round trip per rename or delete
+            Kind::Network => Some(DEFAULT_NETWORK_DELETE_RATE),
+            Kind::Ssd | Kind::Hdd | Kind::Removable | Kind::Unknown => None,
+        }
+    }
+}
+
+/// Mount point of the volume holding `path` (e.g. `E:\` or `\\server\share\`).
+pub fn volume_root(path: &Path) -> Option<String> {
+    let wide = paths::to_wide(path);
+    let mut buf = [0u16; 1024];
+    let ok = unsafe { GetVolumePathNameW(wide.as_ptr(), buf.as_mut_ptr(), buf.len() as DWORD) };
+    if ok == 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Some(String::from_utf16_lossy(&buf[..len]))
+}
+
+/// Which volume a path leads to, to notice a drive letter that now points
+/// elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Mount point, as from `volume_root`.
+    pub root: String,
+    /// Serial number given when the volume was formatted.
+    pub serial: u32,
+    pub read_only: bool,
+}
+
+/// Identity of the volume holding `path`; `None` if it is not mounted.
+pub fn identity(path: &Path) -> Option<Identity> {
+    let root = volume_root(path)?;
+    let (mut serial, mut flags) = (0, 0);
+    let ok = unsafe {
+        GetVolumeInformationW(
+            paths::to_wide(&root).as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            &mut serial,
+            std::ptr::null_mut(),
+            &mut flags,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    (ok != 0).then_some(Identity {
+        root,
+        serial,
+        read_only: flags & FILE_READ_ONLY_VOLUME != 0,
+    })
+}
+
+/// Bytes free for this user on the volume holding the existing `path`.
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    let wide = paths::to_wide(path);
+    let mut free = 0u64;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut free,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    (ok != 0).then_some(free)
+}
+
+/// `\\.\E:` device path for a drive-letter root, `None` for anything else.
+fn device_path(root: &str) -> Option<String> {
+    let bytes = root.as_bytes();
+    (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+        .then(|| format!("\\\\.\\{}:", bytes[0] as char))
+}
+
+/// Whether the disk behind a drive letter root has a seek penalty.
+fn seek_penalty(root: &str) -> Option<bool> {
+    let device = paths::to_wide(&device_path(root)?);
+    unsafe {
+        // No access rights are needed for the property query
+        let handle = CreateFileW(
+            device.as_ptr(),
+            0,
+            FILE_SHARE_READ_WRITE,
+            std::ptr::null(),
+            OPEN_EXISTING,
+            0,
+            std::ptr::null_mut(),
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        let query = STORAGE_PROPERTY_QUERY {
+            PropertyId: STORAGE_DEVICE_SEEK_PENALTY_PROPERTY,
+            QueryType: PROPERTY_STANDARD_QUERY,
+            AdditionalParameters: [0],
+        };
+        let mut descriptor = DEVICE_SEEK_PENALTY_DESCRIPTOR::default();
+        let mut returned: DWORD = 0;
+        let ok = DeviceIoControl(
+            handle,
+            IOCTL_STORAGE_QUERY_PROPERTY,
+            &query as *const _ as *const std::ffi::c_void,
+            std::mem::size_of::<STORAGE_PROPERTY_QUERY>() as DWORD,
+            &mut descriptor as *mut _ as *mut std::ffi::c_void,
+            std::mem::size_of::<DEVICE_SEEK_PENALTY_DESCRIPTOR>() as DWORD,
+            &mut returned,
+            std::ptr::null_mut(),
+        ) != 0;
+        CloseHandle(handle);
+        (ok && returned as usize >= std::mem::size_of::<DEVICE_SEEK_PENALTY_DESCRIPTOR>())
+            .then_some(descriptor.IncursSeekPenalty != 0)
+    }
+}
+
+/// Detect the storage kind of the volume holding `path`.
+pub fn detect(path: &Path) -> Kind {
+    let Some(root) = volume_root(path) else {
+        return Kind::Unknown;
+    };
+    let drive_type = unsafe { GetDriveTypeW(paths::to_wide(&root).as_ptr()) };
+    match drive_type {
+        DRIVE_REMOTE => Kind::Network,
+        DRIVE_REMOVABLE => Kind::Removable,
+        DRIVE_FIXED => match seek_penalty(&root) {
+            Some(true) => Kind::Hdd,
+            Some(false) => Kind::Ssd,
+            None => Kind::Unknown,
+        },
+        _ => Kind::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_path() {
+        assert_eq!(device_path("E:\\").as_deref(), Some("\\\\.\\E:"));
+        assert_eq!(device_path("\\\\server\\share\\"), None);
+    }
+
+    #[test]
+    fn test_hash_threads() {
+        assert_eq!(Kind::Hdd.hash_threads(8), 1);
+        assert_eq!(Kind::Ssd.hash_threads(8), 8);
+    }
+}
@@ -0,0 +1,403 @@
+//! Volume Shadow Copy snapshots before large deletions (`--snapshot-over`).
+//!
+//! Calls WMI `Win32_ShadowCopy.Create` with the `ClientAccessible` context
+//! (raw COM), so the pre-deletion state shows up in Explorer's Previous
+//! Versions until Windows recycles the shadow storage. Needs an elevated
+//! process.
+
+use crate::paths;
+
+use std::ffi::c_void;
+use std::ptr::null_mut;
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HRESULT = i32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type BSTR = *mut u16;
+
+const COINIT_MULTITHREADED: u32 = 0;
+const RPC_C_AUTHN_WINNT: u32 = 10;
+const RPC_C_AUTHZ_NONE: u32 = 0;
+const RPC_C_AUTHN_LEVEL_CALL: u32 = 3;
+const RPC_C_IMP_LEVEL_IMPERSONATE: u32 = 3;
+const EOAC_NONE: u32 = 0;
+const CLSCTX_INPROC_SERVER: u32 = 1;
+const VT_BSTR: u16 = 8;
+const VT_I4: u16 = 3;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct GUID {
+    Data1: u32,
+    Data2: u16,
+    Data3: u16,
+    Data4: [u8; 8],
+}
+
+const CLSID_WBEM_LOCATOR: GUID = GUID {
+    Data1: 0x4590_F811,
+    Data2: 0x1D3A,
+    Data3: 0x11D0,
+    Data4: [0x89, 0x1F, 0x00, 0xAA, 0x00, 0x4B, 0x2E, 0x24],
+};
+const IID_IWBEM_LOCATOR: GUID = GUID {
+    Data1: 0xDC12_A687,
+    Data2: 0x737F,
+    Data3: 0x11CF,
+    Data4: [0x88, 0x4D, 0x00, 0xAA, 0x00, 0x4B, 0x2E, 0x24],
+};
+
+/// VARIANT with the value union as two pointer-sized words.
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct VARIANT {
+    vt: u16,
+    wReserved: [u16; 3],
+    value: usize,
+    extra: usize,
+}
+
+impl VARIANT {
+    fn empty() -> VARIANT {
+        VARIANT {
+            vt: 0,
+            wReserved: [0; 3],
+            value: 0,
+            extra: 0,
+        }
+    }
+}
+
+/// A COM object: a pointer to its vtable of function pointers.
+type Object = *mut *const usize;
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoInitializeEx(pvReserved: *mut c_void, dwCoInit: u32) -> HRESULT;
+    fn CoUninitialize();
+    fn CoInitializeSecurity(
+        pSecDesc: *mut c_void,
+        cAuthSvc: i32,
+        asAuthSvc: *mut c_void,
+        pReserved1: *mut c_void,
+        dwAuthnLevel: u32,
+        dwImpLevel: u32,
+        pAuthList: *mut c_void,
+        dwCapabilities: u32,
+        pReserved3: *mut c_void,
+    ) -> HRESULT;
+    fn CoCreateInstance(
+        rclsid: *const GUID,
+        pUnkOuter: *mut c_void,
+        dwClsContext: u32,
+        riid: *const GUID,
+        ppv: *mut Object,
+    ) -> HRESULT;
+    fn CoSetProxyBlanket(
+        pProxy: Object,
+        dwAuthnSvc: u32,
+        dwAuthzSvc: u32,
+        pServerPrincName: *mut u16,
+        dwAuthnLevel: u32,
+        dwImpLevel: u32,
+        pAuthInfo: *mut c_void,
+        dwCapabilities: u32,
+    ) -> HRESULT;
+}
+
+#[link(name = "oleaut32")]
+extern "system" {
+    fn SysAllocString(psz: *const u16) -> BSTR;
+    fn SysFreeString(bstrString: BSTR);
+    fn VariantClear(pvarg: *mut VARIANT) -> HRESULT;
+}
+
+// Vtable slots used below (IUnknown takes 0-2)
+const RELEASE: usize = 2;
+const LOCATOR_CONNECT_SERVER: usize = 3;
+const SERVICES_GET_OBJECT: usize = 6;
+const SERVICES_EXEC_METHOD: usize = 24;
+const OBJECT_GET: usize = 4;
+const OBJECT_PUT: usize = 5;
+const OBJECT_SPAWN_INSTANCE: usize = 15;
+const OBJECT_GET_METHOD: usize = 19;
+
+type ConnectServerFn = unsafe extern "system" fn(
+    Object,
+    BSTR,
+    BSTR,
+    BSTR,
+    BSTR,
+    i32,
+    BSTR,
+    *mut c_void,
+    *mut Object,
+) -> HRESULT;
+type GetObjectFn =
+    unsafe extern "system" fn(Object, BSTR, i32, *mut c_void, *mut Object, *mut c_void) -> HRESULT;
+type ExecMethodFn = unsafe extern "system" fn(
+    Object,
+    BSTR,
+    BSTR,
+    i32,
+    *mut c_void,
+    Object,
+    *mut Object,
+    *mut c_void,
+) -> HRESULT;
+type GetFn =
+    unsafe extern "system" fn(Object, *const u16, i32, *mut VARIANT, *mut i32, *mut i32) -> HRESULT;
+type PutFn = unsafe extern "system" fn(Object, *const u16, i32, *const VARIANT, i32) -> HRESULT;
+type SpawnInstanceFn = unsafe extern "system" fn(Object, i32, *mut Object) -> HRESULT;
+type GetMethodFn =
+    unsafe extern "system" fn(Object, *const u16, i32, *mut Object, *mut Object) -> HRESULT;
+type ReleaseFn = unsafe extern "system" fn(Object) -> u32;
+
+/// Function pointer at `slot` of `object`'s vtable.
+unsafe fn slot<F: Copy>(object: Object, slot: usize) -> F {
+    let entry = (*object).add(slot);
+    std::mem::transmute_copy(&*entry)
+}
+
+/// Owned COM reference, released on drop.
+struct Com(Object);
+
+impl Drop for Com {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { slot::<ReleaseFn>(self.0, RELEASE)(self.0) };
+        }
+    }
+}
+
+/// Owned BSTR, freed on drop.
+struct Bstr(BSTR);
+
+impl Bstr {
+    fn new(s: &str) -> Bstr {
+        Bstr(unsafe { SysAllocString(paths::to_wide(s).as_ptr()) })
+    }
+}
+
+impl Drop for Bstr {
+    fn drop(&mut self) {
+        unsafe { SysFreeString(self.0) };
+    }
+}
+
+fn check(hr: HRESULT, what: &str) -> Result<(), String> {
+    if hr < 0 {
+        return Err(format!("{} failed (HRESULT 0x{:08X})", what, hr as u32));
+    }
+    Ok(())
+}
+
+/// Meaning of a `Win32_ShadowCopy.Create` return value.
+fn create_error(code: i32) -> &'static str {
+    match code {
+        1 => "access denied (run elevated)",
+        2 => "invalid parameter",
+        3 => "volume not found",
+        4 => "volume not supported",
+        5 => "unsupported shadow copy context",
+        6 => "insufficient shadow storage",
+        7 => "volume is in use",
+        8 => "maximum number of shadow copies reached",
+        9 => "another shadow copy operation is in progress",
+        10 => "shadow copy provider vetoed the operation",
+        11 => "shadow copy provider not registered",
+        12 => "shadow copy provider failure",
+        _ => "unknown error",
+    }
+}
+
+/// Snapshot the volume with root `volume` (e.g. `E:\`); returns the shadow ID.
+pub fn create(volume: &str) -> Result<String, String> {
+    unsafe {
+        let init = CoInitializeEx(null_mut(), COINIT_MULTITHREADED);
+        // Already set by an earlier call in this process; keep going
+        CoInitializeSecurity(
+            null_mut(),
+            -1,
+            null_mut(),
+            null_mut(),
+            RPC_C_AUTHN_LEVEL_CALL,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            null_mut(),
+            EOAC_NONE,
+            null_mut(),
+        );
+        let result = create_in_com(volume);
+        if init >= 0 {
+            CoUninitialize();
+        }
+        result
+    }
+}
+
+unsafe fn create_in_com(volume: &str) -> Result<String, String> {
+    let mut locator: Object = null_mut();
+    check(
+        CoCreateInstance(
+            &CLSID_WBEM_LOCATOR,
+            null_mut(),
+            CLSCTX_INPROC_SERVER,
+            &IID_IWBEM_LOCATOR,
+            &mut locator,
+        ),
+        "creating the WMI locator",
+    )?;
+    let locator = Com(locator);
+
+    let mut services: Object = null_mut();
+    let namespace = Bstr::new("ROOT\\CIMV2");
+    check(
+        slot::<ConnectServerFn>(locator.0, LOCATOR_CONNECT_SERVER)(
+            locator.0,
+            namespace.0,
+            null_mut(),
+            null_mut(),
+            null_mut(),
+            0,
+            null_mut(),
+            null_mut(),
+            &mut services,
+        ),
+        "connecting to ROOT\\CIMV2",
+    )?;
+    let services = Com(services);
+    check(
+        CoSetProxyBlanket(
+            services.0,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            null_mut(),
+            RPC_C_AUTHN_LEVEL_CALL,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            null_mut(),
+            EOAC_NONE,
+        ),
+        "setting WMI security",
+    )?;
+
+    // In-parameters of Win32_ShadowCopy.Create
+    let class_name = Bstr::new("Win32_ShadowCopy");
+    let mut class: Object = null_mut();
+    check(
+        slot::<GetObjectFn>(services.0, SERVICES_GET_OBJECT)(
+            services.0,
+            class_name.0,
+            0,
+            null_mut(),
+            &mut class,
+            null_mut(),
+        ),
+        "loading Win32_ShadowCopy",
+    )?;
+    let class = Com(class);
+    let method = Bstr::new("Create");
+    let mut signature: Object = null_mut();
+    check(
+        slot::<GetMethodFn>(class.0, OBJECT_GET_METHOD)(
+            class.0,
+            paths::to_wide("Create").as_ptr(),
+            0,
+            &mut signature,
+            null_mut(),
+        ),
+        "reading Win32_ShadowCopy.Create",
+    )?;
+    let signature = Com(signature);
+    let mut params: Object = null_mut();
+    check(
+        slot::<SpawnInstanceFn>(signature.0, OBJECT_SPAWN_INSTANCE)(signature.0, 0, &mut params),
+        "preparing Create parameters",
+    )?;
+    let params = Com(params);
+    for (name, value) in [("Volume", volume), ("Context", "ClientAccessible")] {
+        let value = Bstr::new(value);
+        let mut variant = VARIANT::empty();
+        variant.vt = VT_BSTR;
+        variant.value = value.0 as usize;
+        check(
+            slot::<PutFn>(params.0, OBJECT_PUT)(
+                params.0,
+                paths::to_wide(name).as_ptr(),
+                0,
+                &variant,
+                0,
+            ),
+            "setting Create parameters",
+        )?;
+    }
+
+    let mut out: Object = null_mut();
+    check(
+        slot::<ExecMethodFn>(services.0, SERVICES_EXEC_METHOD)(
+            services.0,
+            class_name.0,
+            method.0,
+            0,
+            null_mut(),
+            params.0,
+            &mut out,
+            null_mut(),
+        ),
+        "calling Win32_ShadowCopy.Create",
+    )?;
+    let out = Com(out);
+    let get = |name: &str| {
+        let mut variant = VARIANT::empty();
+        let hr = slot::<GetFn>(out.0, OBJECT_GET)(
+            out.0,
+            paths::to_wide(name).as_ptr(),
+            0,
+            &mut variant,
+            null_mut(),
+            null_mut(),
+        );
+        (hr, variant)
+    };
+
+    let (hr, mut code) = get("ReturnValue");
+    check(hr, "reading the Create result")?;
+    let code_value = if code.vt == VT_I4 {
+        code.value as i32
+    } else {
+        -1
+    };
+    VariantClear(&mut code);
+    if code_value != 0 {
+        return Err(format!(
+            "shadow copy not created: {} ({})",
+            create_error(code_value),
+            code_value
+        ));
+    }
+    let (hr, mut id) = get("ShadowID");
+    check(hr, "reading the shadow copy ID")?;
+    let shadow_id = if id.vt == VT_BSTR && id.value != 0 {
+        let ptr = id.value as *const u16;
+        let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len))
+    } else {
+        String::new()
+    };
+    VariantClear(&mut id);
+    Ok(shadow_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_layout() {
+        // Must match the Windows VARIANT: 16 bytes on 32-bit, 24 on 64-bit
+        assert_eq!(
+            std::mem::size_of::<VARIANT>(),
+            8 + 2 * std::mem::size_of::<usize>()
+        );
+        assert_eq!(create_error(6), "insufficient shadow storage");
+    }
+}
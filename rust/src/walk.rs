@@ -0,0 +1,269 @@
+//! Depth-first directory walks that hand out entries as they are found
+//! (raw FFI, no external crates).
+//!
+//! `Walker::new(dir).on_entry(|entry| ...)` visits every file and directory
+//! under `dir`, children before parents, so a visitor may delete files and
+//! then remove their directory once it is empty. Only the entries of the
+//! directories on the current path are held in memory, not the whole tree.
+//! The walk stops at the first error from the visitor or at the `safety`
+//! walk limits.
+//!
+//! Directories are listed with `FindFirstFileExW` (basic info, large
+//! fetch), which returns each entry's attributes, size and write time with
+//! its name, so no entry needs a metadata call of its own. Where that
+//! fails (paths beyond `MAX_PATH`), `fs::read_dir` is used instead.
+
+use crate::paths;
+use crate::safety;
+
+use std::ffi::c_void;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type DWORD = u32;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+type HANDLE = *mut c_void;
+
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const FILE_ATTRIBUTE_DIRECTORY: DWORD = 0x10;
+const FILE_ATTRIBUTE_REPARSE_POINT: DWORD = 0x400;
+const FIND_EX_INFO_BASIC: i32 = 1;
+const FIND_EX_SEARCH_NAME_MATCH: i32 = 0;
+const FIND_FIRST_EX_LARGE_FETCH: DWORD = 2;
+/// 100 ns ticks from 1601-01-01 (FILETIME) to the Unix epoch.
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+#[repr(C)]
+#[allow(non_snake_case, non_camel_case_types, clippy::upper_case_acronyms)]
+struct WIN32_FIND_DATAW {
+    dwFileAttributes: DWORD,
+    /// FILETIMEs as (low, high) pairs.
+    ftCreationTime: [DWORD; 2],
+    ftLastAccessTime: [DWORD; 2],
+    ftLastWriteTime: [DWORD; 2],
+    nFileSizeHigh: DWORD,
+    nFileSizeLow: DWORD,
+    dwReserved0: DWORD,
+    dwReserved1: DWORD,
+    cFileName: [u16; 260],
+    cAlternateFileName: [u16; 14],
+}
+
+extern "system" {
+    fn FindFirstFileExW(
+        lpFileName: *const u16,
+        fInfoLevelId: i32,
+        lpFindFileData: *mut c_void,
+        fSearchOp: i32,
+        lpSearchFilter: *const c_void,
+        dwAdditionalFlags: DWORD,
+    ) -> HANDLE;
+    fn FindNextFileW(hFindFile: HANDLE, lpFindFileData: *mut WIN32_FIND_DATAW) -> i32;
+    fn FindClose(hFindFile: HANDLE) -> i32;
+}
+
+/// One file or directory met by a walk.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Size in bytes; 0 for directories.
+    pub size: u64,
+    /// Last write time, if known.
+    pub modified: Option<SystemTime>,
+    /// Win32 file attributes; `None` when listed without `FindFirstFileExW`.
+    pub attributes: Option<u32>,
+    /// Junction or symbolic link.
+    reparse_point: bool,
+}
+
+impl Entry {
+    fn from_find(path: PathBuf, data: &WIN32_FIND_DATAW) -> Entry {
+        let attributes = data.dwFileAttributes;
+        let is_dir = attributes & FILE_ATTRIBUTE_DIRECTORY != 0;
+        let size = (u64::from(data.nFileSizeHigh) << 32) | u64::from(data.nFileSizeLow);
+        Entry {
+            path,
+            is_dir,
+            size: if is_dir { 0 } else { size },
+            modified: filetime(data.ftLastWriteTime),
+            attributes: Some(attributes),
+            reparse_point: attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0,
+        }
+    }
+
+    fn from_dir_entry(entry: fs::DirEntry) -> Entry {
+        let path = entry.path();
+        let metadata = fs::metadata(&path).or_else(|_| entry.metadata()).ok();
+        let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+        Entry {
+            is_dir,
+            size: metadata.as_ref().filter(|_| !is_dir).map_or(0, |m| m.len()),
+            modified: metadata.and_then(|m| m.modified().ok()),
+            attributes: None,
+            reparse_point: entry.file_type().is_ok_and(|t| t.is_symlink()),
+            path,
+        }
+    }
+}
+
+/// A FILETIME as a `SystemTime`; `None` before 1970.
+fn filetime(time: [DWORD; 2]) -> Option<SystemTime> {
+    let ticks = (u64::from(time[1]) << 32) | u64::from(time[0]);
+    let since_epoch = ticks.checked_sub(FILETIME_UNIX_EPOCH)?;
+    let duration = Duration::new(
+        since_epoch / 10_000_000,
+        (since_epoch % 10_000_000) as u32 * 100,
+    );
+    UNIX_EPOCH.checked_add(duration)
+}
+
+/// The entries of `dir` from `FindFirstFileExW`; `None` if it fails.
+fn list_native(dir: &Path) -> Option<Vec<Entry>> {
+    let pattern = paths::to_wide(dir.join("*"));
+    let mut entries = Vec::new();
+    unsafe {
+        let mut data: WIN32_FIND_DATAW = std::mem::zeroed();
+        let handle = FindFirstFileExW(
+            pattern.as_ptr(),
+            FIND_EX_INFO_BASIC,
+            &mut data as *mut WIN32_FIND_DATAW as *mut c_void,
+            FIND_EX_SEARCH_NAME_MATCH,
+            std::ptr::null(),
+            FIND_FIRST_EX_LARGE_FETCH,
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        loop {
+            let len = data.cFileName.iter().position(|&c| c == 0).unwrap_or(260);
+            let name = String::from_utf16_lossy(&data.cFileName[..len]);
+            if name != "." && name != ".." {
+                entries.push(Entry::from_find(dir.join(name), &data));
+            }
+            if FindNextFileW(handle, &mut data) == 0 {
+                break;
+            }
+        }
+        FindClose(handle);
+    }
+    Some(entries)
+}
+
+/// The entries of `dir`; `None` if it cannot be listed.
+fn list(dir: &Path) -> Option<Vec<Entry>> {
+    list_native(dir).or_else(|| {
+        let entries = fs::read_dir(dir).ok()?;
+        Some(entries.flatten().map(Entry::from_dir_entry).collect())
+    })
+}
+
+/// A depth-first walk of one directory tree.
+#[derive(Debug, Clone)]
+pub struct Walker {
+    root: PathBuf,
+    follow_reparse_points: bool,
+}
+
+impl Walker {
+    pub fn new(root: &Path) -> Walker {
+        Walker {
+            root: root.to_path_buf(),
+            follow_reparse_points: true,
+        }
+    }
+
+    /// Report junctions and directory symlinks without walking into them.
+    pub fn skip_reparse_points(mut self) -> Walker {
+        self.follow_reparse_points = false;
+        self
+    }
+
+    /// Walk, calling `visit` for every entry in post-order (files of a
+    /// directory, then its subdirectories, each after its own contents).
+    /// Returns the number of entries visited.
+    pub fn on_entry<F>(self, mut visit: F) -> Result<usize, String>
+    where
+        F: FnMut(&Entry) -> Result<(), String>,
+    {
+        let mut count = 0;
+        self.walk(&self.root, 0, &mut count, &mut visit)?;
+        Ok(count)
+    }
+
+    fn walk<F>(
+        &self,
+        dir: &Path,
+        depth: usize,
+        count: &mut usize,
+        visit: &mut F,
+    ) -> Result<(), String>
+    where
+        F: FnMut(&Entry) -> Result<(), String>,
+    {
+        safety::check_walk(*count, depth)?;
+        let Some(entries) = list(dir) else {
+            return Ok(());
+        };
+        let (dirs, files): (Vec<Entry>, Vec<Entry>) = entries.into_iter().partition(|e| e.is_dir);
+
+        // Subdirectories first (depth-first), then files, then the
+        // subdirectories themselves after their contents
+        for sub in &dirs {
+            if self.follow_reparse_points || !sub.reparse_point {
+                self.walk(&sub.path, depth + 1, count, visit)?;
+            }
+        }
+        for entry in files.iter().chain(&dirs) {
+            *count += 1;
+            visit(entry)?;
+        }
+        safety::check_walk(*count, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn test_walk_order() {
+        let root = TempDir::new("walk");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a").join("x"), b"x").unwrap();
+        fs::write(root.join("b.txt"), b"xyz").unwrap();
+
+        let mut seen = Vec::new();
+        let count = Walker::new(&root)
+            .on_entry(|entry| {
+                seen.push((entry.path.clone(), entry.is_dir, entry.size));
+                Ok(())
+            })
+            .unwrap();
+        let stopped = Walker::new(&root).on_entry(|_| Err("stop".to_string()));
+        assert_eq!(count, 3);
+        assert_eq!(
+            seen,
+            vec![
+                (root.join("a").join("x"), false, 1),
+                (root.join("b.txt"), false, 3),
+                (root.join("a"), true, 0),
+            ]
+        );
+        assert_eq!(stopped.unwrap_err(), "stop");
+    }
+
+    #[test]
+    fn test_filetime() {
+        assert_eq!(filetime([0xD53E_8000, 0x019D_B1DE]), Some(UNIX_EPOCH));
+        let second = FILETIME_UNIX_EPOCH + 10_000_000;
+        assert_eq!(
+            filetime([second as u32, (second >> 32) as u32]),
+            Some(UNIX_EPOCH + Duration::from_secs(1))
+        );
+        assert_eq!(filetime([0, 0]), None);
+    }
+}